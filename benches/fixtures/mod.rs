@@ -0,0 +1,200 @@
+//! Generated log fixtures shared by the benchmarks.
+//!
+//! Logs are written into a temporary chat log directory laid out the same way
+//! as irclogger's: one directory per channel containing `<date slug>.log` files.
+
+// Each benchmark target only uses part of this module
+#![allow(dead_code)]
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{Date, NaiveDate, Utc};
+use tempfile::TempDir;
+
+pub const CHANNEL: &str = "bench";
+
+pub struct Fixture {
+    pub name: &'static str,
+    pub date_slug: &'static str,
+    pub lines: usize,
+    pub encoding: FixtureEncoding,
+}
+
+#[derive(Clone, Copy)]
+pub enum FixtureEncoding {
+    Utf8,
+    Latin1,      // Legacy clients sending ISO-8859-1 bytes
+    InvalidUtf8, // Truncated multi-byte sequences mixed into UTF-8
+    BomCrlf,     // Byte order mark and Windows line endings
+}
+
+pub const FIXTURES: &[Fixture] = &[
+    Fixture {
+        name: "small",
+        date_slug: "2021-01-04,Mon",
+        lines: 200,
+        encoding: FixtureEncoding::Utf8,
+    },
+    Fixture {
+        name: "medium",
+        date_slug: "2021-01-05,Tue",
+        lines: 10_000,
+        encoding: FixtureEncoding::Utf8,
+    },
+    Fixture {
+        name: "huge",
+        date_slug: "2021-01-06,Wed",
+        lines: 250_000,
+        encoding: FixtureEncoding::Utf8,
+    },
+    Fixture {
+        name: "latin1",
+        date_slug: "2021-01-07,Thu",
+        lines: 10_000,
+        encoding: FixtureEncoding::Latin1,
+    },
+    Fixture {
+        name: "invalid_utf8",
+        date_slug: "2021-01-08,Fri",
+        lines: 10_000,
+        encoding: FixtureEncoding::InvalidUtf8,
+    },
+    Fixture {
+        name: "bom_crlf",
+        date_slug: "2021-01-09,Sat",
+        lines: 10_000,
+        encoding: FixtureEncoding::BomCrlf,
+    },
+];
+
+const NICKNAMES: &[&str] = &["alice", "bob", "carol", "dave", "eve_", "[mallory]"];
+
+const WORDS: &[&str] = &[
+    "the",
+    "archive",
+    "warrior",
+    "tracker",
+    "upload",
+    "rsync",
+    "item",
+    "project",
+    "claim",
+    "done",
+    "error",
+    "retry",
+    "https://example.com/item/12345",
+    "ok",
+    "thanks",
+    "ArchiveBot",
+    "ignore",
+    "pipeline",
+    "queue",
+    "#archiveteam",
+];
+
+/// Keyword present on a fixed fraction of message lines, for search benchmarks.
+pub const SEARCH_TERM: &str = "needle";
+
+pub struct FixtureDirectory {
+    pub directory: TempDir,
+}
+
+impl FixtureDirectory {
+    pub fn create() -> std::io::Result<Self> {
+        let directory = tempfile::tempdir()?;
+        let channel_dir = directory.path().join(CHANNEL);
+
+        std::fs::create_dir(&channel_dir)?;
+
+        for fixture in FIXTURES {
+            write_fixture(&channel_dir, fixture)?;
+        }
+
+        Ok(Self { directory })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.directory.path()
+    }
+
+    pub fn log_path(&self, fixture: &Fixture) -> PathBuf {
+        self.path()
+            .join(CHANNEL)
+            .join(format!("{}.log", fixture.date_slug))
+    }
+}
+
+pub fn fixture_date(fixture: &Fixture) -> Date<Utc> {
+    let date_string = fixture.date_slug.split_once(',').unwrap().0;
+    Date::from_utc(
+        NaiveDate::parse_from_str(date_string, "%Y-%m-%d").unwrap(),
+        Utc,
+    )
+}
+
+fn write_fixture(channel_dir: &Path, fixture: &Fixture) -> std::io::Result<()> {
+    let path = channel_dir.join(format!("{}.log", fixture.date_slug));
+    let mut file = BufWriter::new(File::create(path)?);
+    let line_ending: &[u8] = match fixture.encoding {
+        FixtureEncoding::BomCrlf => b"\r\n",
+        _ => b"\n",
+    };
+
+    if let FixtureEncoding::BomCrlf = fixture.encoding {
+        file.write_all(b"\xef\xbb\xbf")?;
+    }
+
+    // Small deterministic LCG so runs are comparable without a rand dependency
+    let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+    let mut next = move || {
+        seed = seed
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        (seed >> 33) as usize
+    };
+
+    for index in 0..fixture.lines {
+        let minutes = index * 24 * 60 / fixture.lines;
+        let timestamp = format!("[{:02}:{:02}] ", minutes / 60, minutes % 60);
+        let nickname = NICKNAMES[next() % NICKNAMES.len()];
+
+        file.write_all(timestamp.as_bytes())?;
+
+        if next() % 10 == 0 {
+            file.write_all(b"*** ")?;
+            file.write_all(nickname.as_bytes())?;
+            file.write_all(b" has joined #bench")?;
+        } else {
+            file.write_all(format!("<{}> ", nickname).as_bytes())?;
+
+            for word_index in 0..(3 + next() % 15) {
+                if word_index > 0 {
+                    file.write_all(b" ")?;
+                }
+
+                file.write_all(WORDS[next() % WORDS.len()].as_bytes())?;
+            }
+
+            if next() % 50 == 0 {
+                file.write_all(b" ")?;
+                file.write_all(SEARCH_TERM.as_bytes())?;
+            }
+
+            match fixture.encoding {
+                FixtureEncoding::Latin1 => file.write_all(b" caf\xe9 na\xefve")?,
+                FixtureEncoding::InvalidUtf8 if next() % 4 == 0 => {
+                    file.write_all(b" caf\xc3 \xe2\x82")?
+                }
+                _ => file.write_all(" café 日本語".as_bytes())?,
+            }
+        }
+
+        file.write_all(line_ending)?;
+    }
+
+    file.flush()
+}
@@ -0,0 +1,50 @@
+mod fixtures;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use fixtures::{FixtureDirectory, FIXTURES};
+
+fn bench_read_lines(c: &mut Criterion) {
+    let fixture_dir = FixtureDirectory::create().unwrap();
+    let mut group = c.benchmark_group("read_lines");
+
+    for fixture in FIXTURES {
+        let path = fixture_dir.log_path(fixture);
+        let date = fixtures::fixture_date(fixture);
+
+        group.throughput(Throughput::Bytes(std::fs::metadata(&path).unwrap().len()));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(fixture.name),
+            &path,
+            |b, path| b.iter(|| irclogger_viewer::reader::read_lines(path, &date).unwrap()),
+        );
+    }
+
+    group.finish();
+}
+
+fn bench_count_message_lines(c: &mut Criterion) {
+    let fixture_dir = FixtureDirectory::create().unwrap();
+    let mut group = c.benchmark_group("count_message_lines");
+
+    for fixture in FIXTURES {
+        let path = fixture_dir.log_path(fixture);
+        let date = fixtures::fixture_date(fixture);
+
+        group.throughput(Throughput::Bytes(std::fs::metadata(&path).unwrap().len()));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(fixture.name),
+            &path,
+            |b, path| {
+                b.iter(|| {
+                    irclogger_viewer::reader::count_message_lines(path, &date, false).unwrap()
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_read_lines, bench_count_message_lines);
+criterion_main!(benches);
@@ -0,0 +1,78 @@
+mod fixtures;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use irclogger_viewer::{app::build_app_state, config::Config, search::SearchOptions};
+
+use fixtures::{FixtureDirectory, CHANNEL, SEARCH_TERM};
+
+fn build_config(fixture_dir: &FixtureDirectory, search_backend: &str) -> Config {
+    serde_json::from_value(serde_json::json!({
+        "chat_log_directory": fixture_dir.path(),
+        "apache_password_file": fixture_dir.path().join("htpasswd"),
+        "custom_message_html_file": fixture_dir.path().join("message.html"),
+        "web_server_port_number": 0,
+        "search_backend": search_backend,
+    }))
+    .unwrap()
+}
+
+fn is_agrep_installed() -> bool {
+    std::process::Command::new("agrep")
+        .arg("-V")
+        .output()
+        .is_ok()
+}
+
+fn bench_search_channel(c: &mut Criterion) {
+    let fixture_dir = FixtureDirectory::create().unwrap();
+    let mut group = c.benchmark_group("search_channel");
+    group.sample_size(10);
+
+    let mut backends = vec!["native"];
+
+    if is_agrep_installed() {
+        backends.push("agrep");
+    }
+
+    // (name, case sensitive, verbatim, whole word)
+    let variants = [
+        ("default", false, false, false),
+        ("case_sensitive", true, false, false),
+        ("verbatim", false, true, false),
+        ("whole_word", false, false, true),
+    ];
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    for backend in backends {
+        let app_state = build_app_state(&build_config(&fixture_dir, backend));
+
+        for (name, case_sensitive, verbatim, whole_word) in variants.iter() {
+            group.bench_function(BenchmarkId::new(backend, name), |b| {
+                b.iter(|| {
+                    runtime
+                        .block_on(app_state.search_channel(
+                            CHANNEL,
+                            SEARCH_TERM,
+                            SearchOptions {
+                                case_sensitive: *case_sensitive,
+                                verbatim: *verbatim,
+                                whole_word: *whole_word,
+                                text_only: false,
+                                messages_only: false,
+                            },
+                            None,
+                            None,
+                            usize::MAX,
+                        ))
+                        .unwrap()
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_search_channel);
+criterion_main!(benches);
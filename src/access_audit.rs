@@ -0,0 +1,84 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{Date, Utc};
+
+use crate::notify::{Notification, NotificationConfig};
+
+/// Tells the owners of private channels when someone logs in to read them,
+/// through the webhook named by `access_webhook` in the channel's
+/// `METADATA`. Each user is reported once a day per channel.
+#[derive(Clone, Default)]
+pub struct AccessAudit {
+    reported: Arc<Mutex<ReportedLogins>>,
+}
+
+#[derive(Default)]
+struct ReportedLogins {
+    date: Option<Date<Utc>>,
+    logins: HashSet<(String, String)>, // Channel and user, for the date above
+}
+
+impl AccessAudit {
+    pub fn record_login(&self, webhook_url: &str, channel: &str, user: &str) {
+        let today = Utc::today();
+
+        if !self.is_first_login(today, channel, user) {
+            return;
+        }
+
+        let config = NotificationConfig {
+            webhook_url: Some(webhook_url.to_string()),
+            email: None,
+        };
+        let notification = Notification {
+            subject: format!("Login to {}", channel),
+            text: format!(
+                "{} logged in to read the logs of {} on {}.",
+                user,
+                channel,
+                today.format("%Y-%m-%d")
+            ),
+        };
+        let channel = channel.to_string();
+
+        tokio::spawn(async move {
+            if let Err(error) = crate::notify::send(&config, &notification).await {
+                tracing::warn!("Access webhook of {} failed: {:#}", channel, error);
+            }
+        });
+    }
+
+    fn is_first_login(&self, date: Date<Utc>, channel: &str, user: &str) -> bool {
+        let mut reported = self.reported.lock().unwrap();
+
+        if reported.date != Some(date) {
+            reported.date = Some(date);
+            reported.logins.clear();
+        }
+
+        reported
+            .logins
+            .insert((channel.to_string(), user.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::AccessAudit;
+
+    #[test]
+    fn reports_each_login_once_a_day() {
+        let audit = AccessAudit::default();
+        let day = Utc.ymd(2021, 1, 4);
+
+        assert!(audit.is_first_login(day, "channel", "channel"));
+        assert!(!audit.is_first_login(day, "channel", "channel"));
+        assert!(audit.is_first_login(day, "other", "channel"));
+        assert!(audit.is_first_login(day.succ(), "channel", "channel"));
+    }
+}
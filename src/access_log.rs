@@ -0,0 +1,262 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, FixedOffset, Local};
+use futures_util::FutureExt;
+use gotham::{
+    handler::HandlerFuture,
+    hyper::{body::HttpBody, HeaderMap, Method, StatusCode, Uri, Version},
+    middleware::{Middleware, NewMiddleware},
+    state::{FromState, State},
+};
+use http_auth_basic::Credentials;
+use serde::Deserialize;
+
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+    Common, // Apache's common log format
+    #[default]
+    Combined, // Common log format with the referer and user agent
+}
+
+/// A file of one line per request in Apache's log formats, for tools such
+/// as GoAccess or AWStats.
+pub struct AccessLog {
+    path: PathBuf,
+    format: AccessLogFormat,
+    file: Mutex<File>,
+}
+
+impl AccessLog {
+    pub fn open(path: &Path, format: AccessLogFormat) -> anyhow::Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            format,
+            file: Mutex::new(open_file(path)?),
+        })
+    }
+
+    /// Opens the file again, so a rotated log is written to its new file.
+    pub fn reopen(&self) -> anyhow::Result<()> {
+        let file = open_file(&self.path)?;
+        *self.file.lock().unwrap() = file;
+
+        Ok(())
+    }
+
+    fn write_entry(&self, entry: &AccessLogEntry) {
+        let line = format_entry(entry, self.format);
+
+        if let Err(error) = self.file.lock().unwrap().write_all(line.as_bytes()) {
+            tracing::warn!("Couldn't write to {}: {}", self.path.display(), error);
+        }
+    }
+}
+
+fn open_file(path: &Path) -> anyhow::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|error| anyhow::anyhow!("Access log {}: {}", path.display(), error))
+}
+
+/// Writes each response, including errors and rate limited requests, to the
+/// access log when there is one.
+#[derive(Clone)]
+pub struct AccessLogMiddleware {
+    access_log: Option<Arc<AccessLog>>,
+}
+
+impl AccessLogMiddleware {
+    pub fn new(access_log: Option<Arc<AccessLog>>) -> Self {
+        Self { access_log }
+    }
+}
+
+impl Middleware for AccessLogMiddleware {
+    fn call<Chain>(self, state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>> + Send + 'static,
+    {
+        let access_log = match self.access_log {
+            Some(access_log) => access_log,
+            None => return chain(state),
+        };
+        let time = Local::now().into();
+
+        chain(state)
+            .map(move |result| {
+                match &result {
+                    Ok((state, response)) => {
+                        access_log.write_entry(&build_entry(
+                            state,
+                            time,
+                            response.status(),
+                            response.body().size_hint().exact(),
+                        ));
+                    }
+                    Err((state, error)) => {
+                        access_log.write_entry(&build_entry(state, time, error.status(), None));
+                    }
+                }
+
+                result
+            })
+            .boxed()
+    }
+}
+
+impl NewMiddleware for AccessLogMiddleware {
+    type Instance = Self;
+
+    fn new_middleware(&self) -> anyhow::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+struct AccessLogEntry {
+    client: Option<IpAddr>,
+    user: Option<String>, // As sent by the client, whether or not the password matched
+    time: DateTime<FixedOffset>,
+    request_line: String,
+    status: u16,
+    size: Option<u64>, // Unknown for streamed responses
+    referer: Option<String>,
+    user_agent: Option<String>,
+}
+
+fn build_entry(
+    state: &State,
+    time: DateTime<FixedOffset>,
+    status: StatusCode,
+    size: Option<u64>,
+) -> AccessLogEntry {
+    let headers = HeaderMap::borrow_from(state);
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+    };
+    let user = headers
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| Credentials::from_header(value.to_string()).ok())
+        .map(|credentials| credentials.user_id);
+    let uri = Uri::borrow_from(state);
+    let target = uri
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or_else(|| uri.path());
+
+    AccessLogEntry {
+        client: crate::ratelimit::client_ip(state),
+        user,
+        time,
+        request_line: format!(
+            "{} {} {:?}",
+            Method::borrow_from(state),
+            target,
+            Version::borrow_from(state)
+        ),
+        status: status.as_u16(),
+        size,
+        referer: header("Referer"),
+        user_agent: header("User-Agent"),
+    }
+}
+
+fn format_entry(entry: &AccessLogEntry, format: AccessLogFormat) -> String {
+    let mut line = format!(
+        "{} - {} [{}] \"{}\" {} {}",
+        entry
+            .client
+            .map_or_else(|| "-".to_string(), |client| client.to_string()),
+        entry
+            .user
+            .as_deref()
+            .map_or_else(|| "-".to_string(), escape),
+        entry.time.format("%d/%b/%Y:%H:%M:%S %z"),
+        escape(&entry.request_line),
+        entry.status,
+        match entry.size {
+            Some(size) if size > 0 => size.to_string(),
+            _ => "-".to_string(),
+        }
+    );
+
+    if format == AccessLogFormat::Combined {
+        line.push_str(&format!(
+            " \"{}\" \"{}\"",
+            entry
+                .referer
+                .as_deref()
+                .map_or_else(|| "-".to_string(), escape),
+            entry
+                .user_agent
+                .as_deref()
+                .map_or_else(|| "-".to_string(), escape)
+        ));
+    }
+
+    line.push('\n');
+    line
+}
+
+/// Escapes quotes, backslashes and control characters the way Apache does,
+/// so a header can't end a field or forge a line.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{FixedOffset, TimeZone};
+
+    use super::{format_entry, AccessLogEntry, AccessLogFormat};
+
+    #[test]
+    fn entries_are_formatted_like_apache() {
+        let entry = AccessLogEntry {
+            client: Some("192.0.2.7".parse().unwrap()),
+            user: None,
+            time: FixedOffset::west(7 * 3600)
+                .ymd(2021, 10, 9)
+                .and_hms(13, 55, 36),
+            request_line: "GET /bin/irclogger_log/rust?date=2021-10-09,Sat HTTP/1.1".to_string(),
+            status: 200,
+            size: Some(2326),
+            referer: Some("https://example.org/".to_string()),
+            user_agent: Some("Bot \"quoted\"\nfake line".to_string()),
+        };
+
+        assert_eq!(
+            format_entry(&entry, AccessLogFormat::Common),
+            "192.0.2.7 - - [09/Oct/2021:13:55:36 -0700] \"GET /bin/irclogger_log/rust?date=2021-10-09,Sat HTTP/1.1\" 200 2326\n"
+        );
+        assert_eq!(
+            format_entry(&entry, AccessLogFormat::Combined),
+            "192.0.2.7 - - [09/Oct/2021:13:55:36 -0700] \"GET /bin/irclogger_log/rust?date=2021-10-09,Sat HTTP/1.1\" 200 2326 \"https://example.org/\" \"Bot \\\"quoted\\\"\\nfake line\"\n"
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_db::AppDatabase,
+    datafile::{read_json, write_json},
+};
+
+pub const MAX_NOTE_LENGTH: usize = 1000;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Annotation {
+    pub line_number: u64,
+    pub author: String,
+    pub note: String,
+    pub created: DateTime<Utc>,
+}
+
+/// Stores notes attached to log lines as one JSON file per channel day, or
+/// in the app database if there is one.
+#[derive(Clone)]
+pub struct AnnotationStore {
+    directory: Option<PathBuf>,
+    database: Option<AppDatabase>,
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl AnnotationStore {
+    pub fn new(directory: Option<PathBuf>, database: Option<AppDatabase>) -> Self {
+        Self {
+            directory,
+            database,
+            write_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.directory.is_some() || self.database.is_some()
+    }
+
+    pub fn get_annotations(
+        &self,
+        channel: &str,
+        date_slug: &str,
+    ) -> anyhow::Result<Vec<Annotation>> {
+        if let Some(database) = &self.database {
+            return database.get_annotations(channel, date_slug);
+        }
+
+        let path = match self.get_path(channel, date_slug) {
+            Some(path) => path,
+            None => return Ok(Vec::new()),
+        };
+
+        read_json(&path)
+    }
+
+    pub fn add_annotation(
+        &self,
+        channel: &str,
+        date_slug: &str,
+        annotation: Annotation,
+    ) -> anyhow::Result<()> {
+        if let Some(database) = &self.database {
+            return database.add_annotation(channel, date_slug, &annotation);
+        }
+
+        let path = self
+            .get_path(channel, date_slug)
+            .ok_or_else(|| anyhow::anyhow!("Annotations are not enabled"))?;
+        let _guard = self.write_lock.lock().unwrap();
+
+        let mut annotations = self.get_annotations(channel, date_slug)?;
+        annotations.push(annotation);
+
+        write_json(&path, &annotations)
+    }
+
+    fn get_path(&self, channel: &str, date_slug: &str) -> Option<PathBuf> {
+        let directory = self.directory.as_ref()?;
+
+        Some(
+            directory
+                .join("annotations")
+                .join(channel)
+                .join(format!("{}.json", date_slug)),
+        )
+    }
+}
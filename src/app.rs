@@ -1,89 +1,919 @@
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::{
+    collections::HashMap, future::Future, net::SocketAddr, path::Path, pin::Pin, sync::Arc,
+    time::Duration,
+};
 
+use chrono::Locale;
+use chrono_tz::Tz;
 use gotham::{
-    handler::IntoResponse,
+    handler::{Handler, HandlerFuture, HandlerResult, IntoResponse, NewHandler},
     helpers::http::response::create_empty_response,
-    hyper::{Body, Response, StatusCode},
-    middleware::state::StateMiddleware,
-    pipeline::{single::single_pipeline, single_middleware},
+    hyper::{Body, Method, Response, StatusCode, Uri},
+    middleware::Middleware,
     router::{
-        builder::{build_router, DefineSingleRoute, DrawRoutes},
+        builder::{build_simple_router, DefineSingleRoute, DrawRoutes},
         Router,
     },
-    state::State,
+    state::{FromState, State},
+};
+
+use crate::{
+    access_audit::AccessAudit,
+    access_log::{AccessLog, AccessLogMiddleware},
+    annotation::AnnotationStore,
+    app_db::AppDatabase,
+    bookmark::BookmarkStore,
+    cache::{LruMap, TimedCache, TimedMap},
+    config::{site_path_prefix, Config, ConfigSource},
+    day_cache::DayCache,
+    hidden_channel::HiddenChannelStore,
+    hydrate::LogHydrator,
+    ip_filter::IpFilterMiddleware,
+    message::MessageSources,
+    page_template::PageTemplates,
+    parse_report::ParseProblemReport,
+    preferences::PreferenceSigner,
+    preview::LinkPreviewer,
+    proxy::ImageProxy,
+    ratelimit::{RateLimitMiddleware, RateLimiter},
+    scheduler::SchedulerStatus,
+    script_parser::ScriptParserCache,
+    search_history::SearchHistoryStore,
+    search_index::SearchIndex,
+    state::AppState,
+    tenant::{TenantConfig, TenantMiddleware},
 };
 
-use crate::{config::Config, state::AppState};
+const CHANNEL_ACTIVITY_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+const ARCHIVE_SUMMARY_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+const AUTH_CACHE_TTL: Duration = Duration::from_secs(60);
+const AUTH_CACHE_CAPACITY: usize = 10_000;
 
+/// Serves the config's sites. With the source of the config, the config is
+/// read again on SIGHUP.
 #[tokio::main]
-pub async fn run(config: Config) -> anyhow::Result<()> {
+pub async fn run(config: Config, config_source: Option<ConfigSource>) -> anyhow::Result<()> {
+    crate::logging::init_logging(&config.log_level, config.log_format)?;
+
     let addr = SocketAddr::new(
-        IpAddr::V4(Ipv4Addr::LOCALHOST),
+        config.web_server_bind_address,
         config.web_server_port_number,
     );
-    gotham::init_server(addr, build_routes(&config))
-        .await
-        .map_err(|_| anyhow::anyhow!("Couldn't start server"))?;
+    let app_state = build_app_state(&config);
+    let tenants = config
+        .tenants
+        .iter()
+        .map(|(host, tenant)| {
+            (
+                crate::tenant::normalize_host(host),
+                build_tenant_app_state(&app_state, tenant),
+            )
+        })
+        .collect();
+
+    let middleware = TenantMiddleware::new(app_state, tenants);
+    let tasks = crate::scheduler::build_tasks(&config)?;
+
+    if !tasks.is_empty() {
+        tokio::spawn(crate::scheduler::run_scheduler(middleware.clone(), tasks));
+    }
+
+    let access_log = match &config.access_log_file {
+        Some(path) => Some(Arc::new(AccessLog::open(path, config.access_log_format)?)),
+        None => None,
+    };
+
+    #[cfg(unix)]
+    if let Some(config_source) = config_source {
+        tokio::spawn(reload_on_hangup(
+            config_source,
+            middleware.clone(),
+            access_log.clone(),
+        ));
+    }
+
+    #[cfg(not(unix))]
+    let _ = config_source;
+
+    let router = build_routes(
+        middleware,
+        AccessLogMiddleware::new(access_log),
+        IpFilterMiddleware::new(config.ip_allow_list.clone(), config.ip_deny_list.clone()),
+    );
+    let result = match (&config.tls_certificate_path, &config.tls_key_path) {
+        (Some(certificate_path), Some(key_path)) => {
+            let tls_config = crate::tls::load_server_config(certificate_path, key_path)?;
+
+            gotham::tls::init_server(addr, router, tls_config).await
+        }
+        _ => gotham::init_server(addr, router).await,
+    };
+
+    result.map_err(|_| anyhow::anyhow!("Couldn't start server"))?;
 
     Ok(())
 }
 
-fn build_routes(config: &Config) -> Router {
-    let app_state = AppState {
+pub fn build_app_state(config: &Config) -> AppState {
+    // Demo instances don't keep any per-user data
+    let data_directory = if config.demo_mode {
+        None
+    } else {
+        config.data_directory.clone()
+    };
+    let database = if config.demo_mode {
+        None
+    } else {
+        config.app_database_file.as_ref().and_then(|path| {
+            match AppDatabase::open(path, data_directory.as_deref()) {
+                Ok(database) => Some(database),
+                Err(error) => {
+                    tracing::warn!("App database unavailable: {:#}", error);
+                    None
+                }
+            }
+        })
+    };
+
+    AppState {
         chat_log_directory: config.chat_log_directory.clone(),
         apache_password_file: config.apache_password_file.clone(),
         custom_message_html_file: config.custom_message_html_file.clone(),
+        message_sources: MessageSources::new(
+            config.global_message_html_file.clone(),
+            config.message_html.clone(),
+            config.sanitize_custom_messages,
+        ),
+        theme_file: config.theme.clone(),
+        static_directory: config.static_directory.clone(),
+        page_templates: config.template_directory.as_ref().and_then(|directory| {
+            match PageTemplates::load(directory) {
+                Ok(page_templates) => Some(page_templates),
+                Err(error) => {
+                    tracing::warn!("Page templates unavailable: {:#}", error);
+                    None
+                }
+            }
+        }),
+        widget_embed_origins: config.widget_embed_origins.clone(),
+        public_url: config.public_url.clone(),
+        path_prefix: site_path_prefix(config.path_prefix.as_deref(), config.public_url.as_deref()),
+        link_previewer: LinkPreviewer::new(config.link_preview_hosts.clone()),
+        image_proxy: ImageProxy::new(
+            config.image_proxy_hosts.clone(),
+            config.image_proxy_cache_directory.clone(),
+            config.image_proxy_max_size,
+            Duration::from_secs(config.image_proxy_timeout),
+        ),
+        mirror_upstream_url: config.mirror_upstream_url.clone(),
+        mirror_max_day_size: config.mirror_max_day_size,
+        annotation_store: AnnotationStore::new(data_directory.clone(), database.clone()),
+        bookmark_store: BookmarkStore::new(data_directory.clone(), database.clone()),
+        search_history_store: SearchHistoryStore::new(data_directory.clone(), database.clone()),
+        saved_search_alerts: config.saved_search_alert.is_some(),
+        hidden_channels: HiddenChannelStore::new(data_directory, database),
+        access_audit: AccessAudit::default(),
+        channel_activity_cache: TimedCache::new(CHANNEL_ACTIVITY_CACHE_TTL),
+        archive_summary_cache: TimedCache::new(ARCHIVE_SUMMARY_CACHE_TTL),
+        channel_privacy_cache: TimedMap::new(AUTH_CACHE_TTL, AUTH_CACHE_CAPACITY),
+        credential_cache: TimedMap::new(AUTH_CACHE_TTL, AUTH_CACHE_CAPACITY),
+        admin_users: if config.demo_mode {
+            Vec::new()
+        } else {
+            config.admin_users.clone()
+        },
+        scheduler_status: SchedulerStatus::default(),
+        slow_request_threshold: config.slow_request_threshold.map(Duration::from_millis),
+        search_defaults: config.search_defaults.clone(),
+        search_budget: config.search_budget.clone(),
+        search_backend: config.search_backend,
+        search_index: config.search_index.as_ref().and_then(
+            |search_index| match SearchIndex::open(search_index) {
+                Ok(search_index) => Some(Arc::new(search_index)),
+                Err(error) => {
+                    tracing::warn!("Search index unavailable: {:#}", error);
+                    None
+                }
+            },
+        ),
+        day_density: config.day_density,
+        color_theme: config.color_theme,
+        day_view_page_lines: config.day_view_page_lines,
+        past_day_max_age: Duration::from_secs(config.past_day_max_age),
+        day_json_files: config.day_json_files,
+        count_status_lines: config.count_status_lines,
+        lenient_parsing: config.lenient_parsing,
+        parse_problems: ParseProblemReport::default(),
+        parsed_day_cache: LruMap::new(config.parsed_day_cache_entries),
+        script_parsers: ScriptParserCache::default(),
+        locale: crate::locale::parse_locale(&config.locale).unwrap_or(Locale::en_US),
+        timezone: crate::locale::parse_timezone(&config.timezone).unwrap_or(Tz::UTC),
+        day_cache: config
+            .day_cache_file
+            .as_ref()
+            .and_then(|path| match DayCache::open(path) {
+                Ok(day_cache) => Some(day_cache),
+                Err(error) => {
+                    tracing::warn!("Day cache unavailable: {:#}", error);
+                    None
+                }
+            }),
+        log_hydrator: config.cold_storage.as_ref().map(LogHydrator::new),
+        channel_count_files: config.channel_count_files,
+        public_stats: config.public_stats.clone(),
+        preference_signer: PreferenceSigner::new(config.preferences_secret.clone()),
+        demo_mode: config.demo_mode,
+        rate_limiter: match &config.rate_limit {
+            Some(rate_limit) => Some(RateLimiter::new(
+                rate_limit.requests_per_minute,
+                rate_limit.burst,
+            )),
+            None if config.demo_mode => Some(RateLimiter::new(
+                config.demo_rate_limit,
+                config.demo_rate_limit,
+            )),
+            None => None,
+        },
+        search_rate_limiter: config.rate_limit.as_ref().and_then(|rate_limit| {
+            rate_limit
+                .search_requests_per_minute
+                .map(|requests_per_minute| {
+                    RateLimiter::new(requests_per_minute, rate_limit.search_burst)
+                })
+        }),
+        trusted_proxies: config.trusted_proxies.clone(),
+    }
+}
+
+/// Builds the state of a tenant, sharing the network facing services of the
+/// main site but none of its logs or stored data.
+fn build_tenant_app_state(app_state: &AppState, tenant: &TenantConfig) -> AppState {
+    let data_directory = if app_state.demo_mode {
+        None
+    } else {
+        tenant.data_directory.clone()
+    };
+
+    AppState {
+        chat_log_directory: tenant.chat_log_directory.clone(),
+        apache_password_file: tenant.apache_password_file.clone(),
+        custom_message_html_file: tenant.custom_message_html_file.clone(),
+        message_sources: app_state
+            .message_sources
+            .with_inline_html(tenant.message_html.clone()),
+        theme_file: tenant.theme.clone(),
+        public_url: tenant.public_url.clone(),
+        path_prefix: site_path_prefix(tenant.path_prefix.as_deref(), tenant.public_url.as_deref()),
+        mirror_upstream_url: None,
+        mirror_max_day_size: app_state.mirror_max_day_size,
+        annotation_store: AnnotationStore::new(data_directory.clone(), None),
+        bookmark_store: BookmarkStore::new(data_directory.clone(), None),
+        search_history_store: SearchHistoryStore::new(data_directory.clone(), None),
+        saved_search_alerts: false, // The scheduler only checks the main site
+        hidden_channels: HiddenChannelStore::new(data_directory, None),
+        access_audit: AccessAudit::default(),
+        channel_activity_cache: TimedCache::new(CHANNEL_ACTIVITY_CACHE_TTL),
+        archive_summary_cache: TimedCache::new(ARCHIVE_SUMMARY_CACHE_TTL),
+        channel_privacy_cache: TimedMap::new(AUTH_CACHE_TTL, AUTH_CACHE_CAPACITY),
+        credential_cache: TimedMap::new(AUTH_CACHE_TTL, AUTH_CACHE_CAPACITY),
+        admin_users: Vec::new(),
+        search_index: None,
+        day_cache: None,
+        log_hydrator: None,
+        parse_problems: ParseProblemReport::default(),
+        parsed_day_cache: LruMap::new(app_state.parsed_day_cache.capacity()),
+        ..app_state.clone()
+    }
+}
+
+/// Reads the config file again on each SIGHUP, with the same command line
+/// overrides, and points the sites at its log directories, password files,
+/// custom messages and themes. The access log is opened again for log
+/// rotation. Scheduled tasks run on the reloaded main site, but other
+/// settings, new tenants and changed tasks need a restart.
+#[cfg(unix)]
+async fn reload_on_hangup(
+    config_source: ConfigSource,
+    middleware: TenantMiddleware,
+    access_log: Option<Arc<AccessLog>>,
+) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(error) => {
+            tracing::warn!("Config reloading unavailable: {}", error);
+            return;
+        }
     };
 
-    let middleware = StateMiddleware::new(app_state);
-    let pipeline = single_middleware(middleware);
-    let (chain, pipelines) = single_pipeline(pipeline);
+    while hangups.recv().await.is_some() {
+        if let Some(access_log) = &access_log {
+            if let Err(error) = access_log.reopen() {
+                tracing::error!("{:#}", error);
+            }
+        }
+
+        match read_valid_config(&config_source) {
+            Ok(config) => {
+                middleware.update(|default, tenants| reload_app_states(&config, default, tenants));
+                tracing::info!("Reloaded {}", config_source.path.display());
+            }
+            Err(error) => tracing::error!("Keeping the current config: {:#}", error),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn read_valid_config(config_source: &ConfigSource) -> anyhow::Result<Config> {
+    let config = config_source.read()?;
+    let problems = config.validate();
+
+    if !problems.is_empty() {
+        let problems: Vec<String> = problems.iter().map(ToString::to_string).collect();
+        anyhow::bail!("{}", problems.join("; "));
+    }
+
+    Ok(config)
+}
+
+#[cfg(unix)]
+fn reload_app_states(
+    config: &Config,
+    default: &mut AppState,
+    tenants: &mut HashMap<String, AppState>,
+) {
+    reload_paths(
+        default,
+        &config.chat_log_directory,
+        &config.apache_password_file,
+        &config.custom_message_html_file,
+        config.theme.as_deref(),
+    );
+
+    for (host, tenant) in &config.tenants {
+        match tenants.get_mut(&crate::tenant::normalize_host(host)) {
+            Some(app_state) => reload_paths(
+                app_state,
+                &tenant.chat_log_directory,
+                &tenant.apache_password_file,
+                &tenant.custom_message_html_file,
+                tenant.theme.as_deref(),
+            ),
+            None => tracing::warn!("New tenant {} needs a restart", host),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn reload_paths(
+    app_state: &mut AppState,
+    chat_log_directory: &Path,
+    apache_password_file: &Path,
+    custom_message_html_file: &Path,
+    theme_file: Option<&Path>,
+) {
+    if app_state.chat_log_directory != chat_log_directory {
+        app_state.chat_log_directory = chat_log_directory.to_path_buf();
+        app_state.channel_activity_cache = TimedCache::new(CHANNEL_ACTIVITY_CACHE_TTL);
+        app_state.archive_summary_cache = TimedCache::new(ARCHIVE_SUMMARY_CACHE_TTL);
+        app_state.parsed_day_cache = LruMap::new(app_state.parsed_day_cache.capacity());
+    }
+
+    app_state.apache_password_file = apache_password_file.to_path_buf();
+    app_state.channel_privacy_cache = TimedMap::new(AUTH_CACHE_TTL, AUTH_CACHE_CAPACITY);
+    app_state.credential_cache = TimedMap::new(AUTH_CACHE_TTL, AUTH_CACHE_CAPACITY);
+    app_state.custom_message_html_file = custom_message_html_file.to_path_buf();
+    app_state.theme_file = theme_file.map(Path::to_path_buf);
+}
+
+/// Passes every request through the middleware before routing it, so
+/// refused and rate limited clients get the same answer on paths no route
+/// matches, and those requests are logged too.
+#[derive(Clone)]
+pub(crate) struct FilteredRouter {
+    router: Router,
+    access_log_middleware: AccessLogMiddleware,
+    tenant_middleware: TenantMiddleware,
+    ip_filter_middleware: IpFilterMiddleware,
+    rate_limit_middleware: RateLimitMiddleware,
+}
+
+impl NewHandler for FilteredRouter {
+    type Instance = Self;
+
+    fn new_handler(&self) -> anyhow::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+impl Handler for FilteredRouter {
+    fn handle(self, state: State) -> Pin<Box<HandlerFuture>> {
+        let Self {
+            router,
+            access_log_middleware,
+            tenant_middleware,
+            ip_filter_middleware,
+            rate_limit_middleware,
+        } = self;
 
-    build_router(chain, pipelines, |route| {
+        access_log_middleware.call(state, move |state| {
+            tenant_middleware.call(state, move |state| {
+                ip_filter_middleware.call(state, move |state| {
+                    rate_limit_middleware.call(state, move |state| router.handle(state))
+                })
+            })
+        })
+    }
+}
+
+pub(crate) fn build_routes(
+    middleware: TenantMiddleware,
+    access_log_middleware: AccessLogMiddleware,
+    ip_filter_middleware: IpFilterMiddleware,
+) -> FilteredRouter {
+    FilteredRouter {
+        router: build_router_table(),
+        access_log_middleware,
+        tenant_middleware: middleware,
+        ip_filter_middleware,
+        rate_limit_middleware: RateLimitMiddleware,
+    }
+}
+
+fn build_router_table() -> Router {
+    build_simple_router(|route| {
+        route
+            .get("/admin")
+            .to_async(|state| error_wrapper(state, crate::route::admin_index));
+        route
+            .get("/admin/parse_problems.json")
+            .to_async(|state| error_wrapper(state, crate::route::admin_parse_problems));
+        route
+            .get("/admin/selftest")
+            .to_async(|state| async_error_wrapper(state, crate::route::admin_self_test));
+        route
+            .post("/admin/reindex")
+            .to_async(|state| async_error_wrapper(state, crate::route::admin_reindex));
+        route
+            .post("/admin/hidden_channels")
+            .to_async(|state| async_error_wrapper(state, crate::route::update_hidden_channel));
         route
             .get("/bin/irclogger_logs")
-            .to(|state| error_wrapper(state, crate::route::index));
+            .to_async(|state| error_wrapper(state, crate::route::index));
+        route
+            .get("/bin/irclogger_search")
+            .with_query_string_extractor::<crate::route::GlobalSearchQuery>()
+            .to_async(|state| async_error_wrapper(state, crate::route::global_search));
+        route
+            .get("/.well-known/irclog-archive.json")
+            .to_async(|state| error_wrapper(state, crate::route::api::archive_description));
+        route
+            .get("/api/channels")
+            .to_async(|state| error_wrapper(state, crate::route::api::channels));
+        route
+            .get("/api/channels/:channel:[a-z0-9._-]+/days")
+            .with_path_extractor::<crate::route::ChannelParams>()
+            .to_async(|state| error_wrapper(state, crate::route::api::channel_days));
+        route
+            .get("/api/channels/:channel:[a-z0-9._-]+/stats")
+            .with_path_extractor::<crate::route::ChannelParams>()
+            .to_async(|state| error_wrapper(state, crate::route::api::channel_stats));
+        route
+            .get("/api/channels/:channel:[a-z0-9._-]+/log")
+            .with_path_extractor::<crate::route::ChannelParams>()
+            .with_query_string_extractor::<crate::route::api::ApiLogQuery>()
+            .to_async(|state| error_wrapper(state, crate::route::api::channel_log));
+        route
+            .get("/api/channels/:channel:[a-z0-9._-]+/nicks")
+            .with_path_extractor::<crate::route::ChannelParams>()
+            .with_query_string_extractor::<crate::route::api::ApiNicksQuery>()
+            .to_async(|state| error_wrapper(state, crate::route::api::channel_nicks));
+        route
+            .get("/api/v1/channels/:channel:[a-z0-9._-]+/tail")
+            .with_path_extractor::<crate::route::ChannelParams>()
+            .with_query_string_extractor::<crate::route::api::ApiTailQuery>()
+            .to_async(|state| error_wrapper(state, crate::route::api::channel_tail));
+        route
+            .get("/api/grafana")
+            .to_async(|state| error_wrapper(state, crate::route::api::grafana_status));
+        route
+            .post("/api/grafana/search")
+            .to_async(|state| error_wrapper(state, crate::route::api::grafana_search));
+        route
+            .post("/api/grafana/query")
+            .to_async(|state| async_error_wrapper(state, crate::route::api::grafana_query));
+        route
+            .get("/bin/irclogger_navigation.js")
+            .to_async(|state| error_wrapper(state, crate::route::navigation_script));
+        route
+            .get("/bin/irclogger_nick_card.js")
+            .to_async(|state| error_wrapper(state, crate::route::nick_card_script));
+        route
+            .get("/bin/irclogger_nick/:channel:[a-z0-9._-]+")
+            .with_path_extractor::<crate::route::ChannelParams>()
+            .with_query_string_extractor::<crate::route::NickCardQuery>()
+            .to_async(|state| error_wrapper(state, crate::route::nick_card));
+        route
+            .get("/bin/irclogger_live.js")
+            .to_async(|state| error_wrapper(state, crate::route::live_script));
+        route
+            .get("/bin/irclogger_log_live/:channel:[a-z0-9._-]+")
+            .with_path_extractor::<crate::route::ChannelParams>()
+            .with_query_string_extractor::<crate::route::ChannelLiveQuery>()
+            .to_async(|state| error_wrapper(state, crate::route::channel_live));
+        route
+            .get("/bin/irclogger_log_ws/:channel:[a-z0-9._-]+")
+            .with_path_extractor::<crate::route::ChannelParams>()
+            .with_query_string_extractor::<crate::route::ChannelWebSocketQuery>()
+            .to_async(|state| error_wrapper(state, crate::route::channel_websocket));
+        route
+            .get("/bin/irclogger_widget/:channel:[a-z0-9._-]+")
+            .with_path_extractor::<crate::route::ChannelParams>()
+            .with_query_string_extractor::<crate::route::ChannelWidgetQuery>()
+            .to_async(|state| error_wrapper(state, crate::route::channel_widget));
+        route
+            .get("/bin/irclogger_badge/:channel:[a-z0-9._-]+/:kind")
+            .with_path_extractor::<crate::route::BadgeParams>()
+            .to_async(|state| error_wrapper(state, crate::route::channel_badge));
+        route
+            .get("/static/:name")
+            .with_path_extractor::<crate::route::StaticAssetParams>()
+            .to_async(|state| async_error_wrapper(state, crate::route::static_asset));
+        route
+            .get("/bin/irclogger_theme.css")
+            .to_async(|state| async_error_wrapper(state, crate::route::theme_stylesheet));
+        route
+            .get("/bin/irclogger_channels.rss")
+            .to_async(|state| error_wrapper(state, crate::route::channels_feed));
         route
             .get("/bin/irclogger_logs/:channel:[a-z0-9._-]+")
             .with_path_extractor::<crate::route::ChannelParams>()
-            .to(|state| error_wrapper(state, crate::route::channel_daily_index));
+            .to_async(|state| error_wrapper(state, crate::route::channel_daily_index));
+        route
+            .get("/bin/irclogger_calendar/:channel:[a-z0-9._-]+")
+            .with_path_extractor::<crate::route::ChannelParams>()
+            .with_query_string_extractor::<crate::route::ChannelCalendarQuery>()
+            .to_async(|state| error_wrapper(state, crate::route::channel_calendar));
+        route
+            .get("/bin/irclogger_compare/:channel:[a-z0-9._-]+")
+            .with_path_extractor::<crate::route::ChannelParams>()
+            .with_query_string_extractor::<crate::route::ChannelCompareQuery>()
+            .to_async(|state| error_wrapper(state, crate::route::channel_compare));
+        route
+            .get("/bin/irclogger_digest/:channel:[a-z0-9._-]+")
+            .with_path_extractor::<crate::route::ChannelParams>()
+            .to_async(|state| error_wrapper(state, crate::route::channel_digest_feed));
         route
             .get("/bin/irclogger_log/:channel:[a-z0-9._-]+")
             .with_path_extractor::<crate::route::ChannelParams>()
             .with_query_string_extractor::<crate::route::ChannelLinesQuery>()
-            .to(|state| error_wrapper(state, crate::route::channel_lines));
+            .to_async(|state| error_wrapper(state, crate::route::channel_lines));
+        route
+            .post("/bin/irclogger_log_search/:channel:[a-z0-9._-]+/saved")
+            .with_path_extractor::<crate::route::ChannelParams>()
+            .to_async(|state| async_error_wrapper(state, crate::route::update_saved_search));
+        route
+            .get("/bin/irclogger_log_continuous/:channel:[a-z0-9._-]+")
+            .with_path_extractor::<crate::route::ChannelParams>()
+            .with_query_string_extractor::<crate::route::ChannelContinuousQuery>()
+            .to_async(|state| error_wrapper(state, crate::route::channel_continuous));
         route
             .get("/bin/irclogger_log_search/:channel:[a-z0-9._-]+")
             .with_path_extractor::<crate::route::ChannelParams>()
             .with_query_string_extractor::<crate::route::ChannelSearchQuery>()
-            .to(|state| error_wrapper(state, crate::route::channel_search));
+            .to_async(|state| async_error_wrapper(state, crate::route::channel_search));
+        route
+            .post("/bin/irclogger_annotate/:channel:[a-z0-9._-]+")
+            .with_path_extractor::<crate::route::ChannelParams>()
+            .to_async(|state| async_error_wrapper(state, crate::route::annotate_line));
+        route
+            .get("/bin/irclogger_bookmarks")
+            .to_async(|state| error_wrapper(state, crate::route::bookmarks));
+        route
+            .post("/bin/irclogger_bookmarks")
+            .to_async(|state| async_error_wrapper(state, crate::route::add_bookmark));
+        route
+            .post("/bin/irclogger_bookmarks/delete")
+            .to_async(|state| async_error_wrapper(state, crate::route::delete_bookmark));
+        route
+            .get("/bin/irclogger_log_diff/:channel:[a-z0-9._-]+")
+            .with_path_extractor::<crate::route::ChannelParams>()
+            .with_query_string_extractor::<crate::route::ChannelDiffQuery>()
+            .to_async(|state| async_error_wrapper(state, crate::route::channel_diff));
+        route
+            .get("/bin/irclogger_preferences")
+            .to_async(|state| error_wrapper(state, crate::route::preferences));
+        route
+            .post("/bin/irclogger_preferences")
+            .to_async(|state| async_error_wrapper(state, crate::route::update_preferences));
+        route
+            .get("/bin/irclogger_preferences.json")
+            .to_async(|state| error_wrapper(state, crate::route::export_preferences));
+        route
+            .post("/bin/irclogger_preferences/import")
+            .to_async(|state| async_error_wrapper(state, crate::route::import_preferences));
+        route
+            .get("/proxy/image")
+            .with_query_string_extractor::<crate::route::ProxyImageQuery>()
+            .to_async(|state| async_error_wrapper(state, crate::route::proxy_image));
         route
             .get("bin/irclogger_logs_a/:channel:[a-z0-9._-]+")
             .with_path_extractor::<crate::route::ChannelParams>()
-            .to(|state| error_wrapper(state, crate::route::redirect_channel_daily_index));
+            .to_async(|state| error_wrapper(state, crate::route::redirect_channel_daily_index));
         route
             .get("bin/irclogger_log_a/:channel:[a-z0-9._-]+")
             .with_path_extractor::<crate::route::ChannelParams>()
-            .to(|state| error_wrapper(state, crate::route::redirect_channel_lines));
+            .to_async(|state| error_wrapper(state, crate::route::redirect_channel_lines));
         route
             .get("bin/irclogger_log_search_a/:channel:[a-z0-9._-]+")
             .with_path_extractor::<crate::route::ChannelParams>()
-            .to(|state| error_wrapper(state, crate::route::redirect_channel_search));
+            .to_async(|state| error_wrapper(state, crate::route::redirect_channel_search));
     })
 }
 
-fn error_wrapper<F, R>(mut state: State, func: F) -> (State, Response<Body>)
+/// Runs a synchronous handler on the blocking thread pool. These handlers
+/// read logs through the synchronous caches, so this keeps a slow disk from
+/// holding up the runtime's workers.
+async fn error_wrapper<F, R>(state: State, func: F) -> HandlerResult
+where
+    F: FnOnce(&mut State) -> anyhow::Result<R> + Send + 'static,
+    R: IntoResponse,
+{
+    Ok(run_blocking(state, |state| run_handler(state, func)).await)
+}
+
+fn run_handler<F, R>(state: &mut State, func: F) -> Response<Body>
 where
     F: FnOnce(&mut State) -> anyhow::Result<R>,
     R: IntoResponse,
 {
-    let response = match func(&mut state) {
+    crate::timing::start_request(state);
+
+    if let Some(response) = early_response(state) {
+        return response;
+    }
+
+    let mut response = match func(state) {
+        Ok(response) => response.into_response(state),
+        Err(error) => {
+            log_handler_error(state, &error);
+            create_empty_response(state, StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    };
+
+    crate::route::append_color_theme_cookie(state, &mut response);
+    crate::timing::finish_request(state, &response);
+
+    response
+}
+
+/// Runs the synchronous part of a handler on the blocking thread pool,
+/// handing the request's state over and back. Async handlers use this for
+/// reading files, checking passwords and rendering between what they await.
+pub(crate) async fn run_blocking<F, T>(mut state: State, func: F) -> (State, T)
+where
+    F: FnOnce(&mut State) -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let result = tokio::task::spawn_blocking(move || {
+        let output = func(&mut state);
+        (state, output)
+    })
+    .await;
+
+    match result {
+        Ok(result) => result,
+        Err(error) => std::panic::resume_unwind(error.into_panic()),
+    }
+}
+
+async fn async_error_wrapper<F, Fut, R>(mut state: State, func: F) -> HandlerResult
+where
+    F: FnOnce(State) -> Fut,
+    Fut: Future<Output = (State, anyhow::Result<R>)>,
+    R: IntoResponse,
+{
+    crate::timing::start_request(&mut state);
+
+    // Hidden channels are read from disk
+    let (state, response) = run_blocking(state, early_response).await;
+
+    if let Some(response) = response {
+        return Ok((state, response));
+    }
+
+    let (state, result) = func(state).await;
+    let mut response = match result {
         Ok(response) => response.into_response(&state),
         Err(error) => {
-            dbg!(error);
+            log_handler_error(&state, &error);
             create_empty_response(&state, StatusCode::INTERNAL_SERVER_ERROR)
         }
     };
 
-    (state, response)
+    crate::route::append_color_theme_cookie(&state, &mut response);
+    crate::timing::finish_request(&state, &response);
+
+    Ok((state, response))
+}
+
+/// Answers requests for pages of hidden channels, which shouldn't reach
+/// their handler.
+fn early_response(state: &mut State) -> Option<Response<Body>> {
+    match crate::route::hidden_channel_response(state) {
+        Ok(response) => response,
+        Err(error) => {
+            log_handler_error(state, &error);
+            Some(create_empty_response(
+                state,
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ))
+        }
+    }
+}
+
+/// Logs the error with its chain of causes, such as the file that couldn't
+/// be read under the day that was being parsed.
+fn log_handler_error(state: &State, error: &anyhow::Error) {
+    tracing::error!(
+        method = %Method::borrow_from(state),
+        path = Uri::borrow_from(state).path(),
+        error = %format!("{:#}", error),
+        "Request failed"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::TryFrom, path::Path};
+
+    use gotham::{hyper::StatusCode, test::TestServer};
+
+    use super::{build_app_state, build_routes};
+    use crate::{
+        access_log::AccessLogMiddleware,
+        hidden_channel::HiddenChannel,
+        ip_filter::IpFilterMiddleware,
+        ratelimit::{IpNetwork, RateLimiter},
+        state::AppState,
+        tenant::TenantMiddleware,
+    };
+
+    fn build_app_state_in(directory: &Path) -> AppState {
+        std::fs::create_dir_all(directory.join("log/archiveteam")).unwrap();
+        std::fs::write(directory.join("htpasswd"), "").unwrap();
+
+        let mut value = crate::config::minimal_config_value(&directory.join("log"), directory);
+        value["data_directory"] = serde_json::json!(directory);
+
+        build_app_state(&crate::config::config_from_value(value).unwrap())
+    }
+
+    fn build_test_server(app_state: AppState, ip_filter: IpFilterMiddleware) -> TestServer {
+        TestServer::new(build_routes(
+            TenantMiddleware::new(app_state, Default::default()),
+            AccessLogMiddleware::new(None),
+            ip_filter,
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn hidden_channel_badges_are_gone() {
+        let directory = tempfile::tempdir().unwrap();
+        let app_state = build_app_state_in(directory.path());
+        app_state
+            .hidden_channels
+            .hide_channel(HiddenChannel {
+                channel: "archiveteam".to_string(),
+                reason: "Takedown request".to_string(),
+                hidden_by: "admin".to_string(),
+                hidden_at: chrono::Utc::now(),
+            })
+            .unwrap();
+        let server = build_test_server(app_state, IpFilterMiddleware::default());
+
+        let response = server
+            .client()
+            .get("http://localhost/bin/irclogger_badge/archiveteam/days.svg")
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::GONE);
+    }
+
+    #[test]
+    fn calendar_years_past_the_last_are_refused() {
+        let directory = tempfile::tempdir().unwrap();
+        let server = build_test_server(
+            build_app_state_in(directory.path()),
+            IpFilterMiddleware::default(),
+        );
+
+        let response = server
+            .client()
+            .get("http://localhost/bin/irclogger_calendar/archiveteam?year=2147483647&month=12")
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn tail_is_under_api_v1() {
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(directory.path().join("log/archiveteam")).unwrap();
+        std::fs::write(
+            directory.path().join("log/archiveteam/2021-01-04,Mon.log"),
+            "[00:01] <alice> hello\n[00:02] <bob> hi\n",
+        )
+        .unwrap();
+        let server = build_test_server(
+            build_app_state_in(directory.path()),
+            IpFilterMiddleware::default(),
+        );
+
+        let response = server
+            .client()
+            .get("http://localhost/api/v1/channels/archiveteam/tail?n=1")
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let lines: serde_json::Value =
+            serde_json::from_slice(&response.read_body().unwrap()).unwrap();
+        assert_eq!(lines.as_array().unwrap().len(), 1);
+        assert_eq!(lines[0]["date_slug"], "2021-01-04,Mon");
+    }
+
+    #[test]
+    fn oversized_grafana_queries_are_refused() {
+        let directory = tempfile::tempdir().unwrap();
+        let server = build_test_server(
+            build_app_state_in(directory.path()),
+            IpFilterMiddleware::default(),
+        );
+
+        let response = server
+            .client()
+            .post(
+                "http://localhost/api/grafana/query",
+                vec![b' '; 100 * 1024],
+                mime::APPLICATION_JSON,
+            )
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[test]
+    fn denied_clients_are_refused_on_unknown_paths() {
+        let directory = tempfile::tempdir().unwrap();
+        let app_state = build_app_state_in(directory.path());
+        let deny_list = vec![IpNetwork::try_from("127.0.0.0/8".to_string()).unwrap()];
+
+        let server = build_test_server(app_state.clone(), IpFilterMiddleware::default());
+        let response = server
+            .client()
+            .get("http://localhost/no-such-page")
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let server = build_test_server(app_state, IpFilterMiddleware::new(Vec::new(), deny_list));
+        let response = server
+            .client()
+            .get("http://localhost/no-such-page")
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn unknown_paths_count_against_rate_limits() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut app_state = build_app_state_in(directory.path());
+        app_state.rate_limiter = Some(RateLimiter::new(1, 1));
+        let server = build_test_server(app_state, IpFilterMiddleware::default());
+
+        let statuses = (0..2)
+            .map(|_| {
+                server
+                    .client()
+                    .get("http://localhost/no-such-page")
+                    .perform()
+                    .unwrap()
+                    .status()
+            })
+            .collect::<Vec<StatusCode>>();
+
+        assert_eq!(
+            statuses,
+            vec![StatusCode::NOT_FOUND, StatusCode::TOO_MANY_REQUESTS]
+        );
+    }
 }
@@ -0,0 +1,621 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, Row, Transaction};
+
+use crate::{
+    annotation::Annotation,
+    bookmark::Bookmark,
+    datafile::read_json,
+    hidden_channel::HiddenChannel,
+    search_history::{SavedSearch, SearchParameters, MAX_RECENT_SEARCHES},
+};
+
+// Each migration upgrades the schema by one version, tracked in `PRAGMA user_version`
+const MIGRATIONS: &[&str] = &["CREATE TABLE annotations (
+        channel TEXT NOT NULL,
+        date_slug TEXT NOT NULL,
+        line_number INTEGER NOT NULL,
+        author TEXT NOT NULL,
+        note TEXT NOT NULL,
+        created TEXT NOT NULL
+    );
+    CREATE INDEX annotations_day ON annotations (channel, date_slug);
+    CREATE TABLE bookmarks (
+        user TEXT NOT NULL,
+        channel TEXT NOT NULL,
+        date_slug TEXT NOT NULL,
+        line_number INTEGER NOT NULL,
+        label TEXT NOT NULL,
+        created TEXT NOT NULL,
+        PRIMARY KEY (user, channel, date_slug, line_number)
+    );
+    CREATE TABLE saved_searches (
+        user TEXT NOT NULL,
+        channel TEXT NOT NULL,
+        query TEXT NOT NULL,
+        case_sensitive INTEGER NOT NULL,
+        verbatim INTEGER NOT NULL,
+        whole_word INTEGER NOT NULL,
+        text_only INTEGER NOT NULL,
+        messages_only INTEGER NOT NULL,
+        pinned INTEGER NOT NULL,
+        last_used TEXT NOT NULL,
+        PRIMARY KEY (user, channel, query, case_sensitive, verbatim, whole_word, text_only, messages_only)
+    );",
+    "CREATE TABLE hidden_channels (
+        channel TEXT PRIMARY KEY NOT NULL,
+        reason TEXT NOT NULL,
+        hidden_by TEXT NOT NULL,
+        hidden_at TEXT NOT NULL
+    );",
+    "ALTER TABLE saved_searches ADD COLUMN alert INTEGER NOT NULL DEFAULT 0;",
+];
+
+/// Viewer data kept in one SQLite file instead of JSON files in the data
+/// directory: annotations, bookmarks, saved searches and hidden channels.
+#[derive(Clone)]
+pub struct AppDatabase {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl AppDatabase {
+    /// Opens the database and upgrades its schema. When the database is
+    /// created, the JSON files of a data directory are imported into it.
+    pub fn open(path: &Path, data_directory: Option<&Path>) -> anyhow::Result<Self> {
+        let mut connection = Connection::open(path)?;
+        let version: i64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            let transaction = connection.transaction()?;
+            transaction.execute_batch(migration)?;
+            transaction.pragma_update(None, "user_version", &(index as i64 + 1))?;
+
+            // A new database is filled once all of its tables exist
+            if version == 0 && index == MIGRATIONS.len() - 1 {
+                if let Some(directory) = data_directory {
+                    import_data_directory(&transaction, directory)?;
+                }
+            }
+
+            transaction.commit()?;
+        }
+
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    pub fn get_annotations(
+        &self,
+        channel: &str,
+        date_slug: &str,
+    ) -> anyhow::Result<Vec<Annotation>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(
+            "SELECT line_number, author, note, created FROM annotations
+            WHERE channel = ?1 AND date_slug = ?2 ORDER BY rowid",
+        )?;
+        let annotations = statement
+            .query_map(params![channel, date_slug], |row| {
+                Ok(Annotation {
+                    line_number: row.get::<_, i64>(0)? as u64,
+                    author: row.get(1)?,
+                    note: row.get(2)?,
+                    created: row.get(3)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+
+        Ok(annotations)
+    }
+
+    pub fn add_annotation(
+        &self,
+        channel: &str,
+        date_slug: &str,
+        annotation: &Annotation,
+    ) -> anyhow::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        insert_annotation(&connection, channel, date_slug, annotation)
+    }
+
+    pub fn get_bookmarks(&self, user: &str) -> anyhow::Result<Vec<Bookmark>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(
+            "SELECT channel, date_slug, line_number, label, created FROM bookmarks
+            WHERE user = ?1 ORDER BY rowid",
+        )?;
+        let bookmarks = statement
+            .query_map(params![user], |row| {
+                Ok(Bookmark {
+                    channel: row.get(0)?,
+                    date_slug: row.get(1)?,
+                    line_number: row.get::<_, i64>(2)? as u64,
+                    label: row.get(3)?,
+                    created: row.get(4)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+
+        Ok(bookmarks)
+    }
+
+    /// Adds a bookmark, replacing one of the same line.
+    pub fn add_bookmark(&self, user: &str, bookmark: &Bookmark) -> anyhow::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        insert_bookmark(&connection, user, bookmark)
+    }
+
+    pub fn remove_bookmark(
+        &self,
+        user: &str,
+        channel: &str,
+        date_slug: &str,
+        line_number: u64,
+    ) -> anyhow::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "DELETE FROM bookmarks
+            WHERE user = ?1 AND channel = ?2 AND date_slug = ?3 AND line_number = ?4",
+            params![user, channel, date_slug, line_number as i64],
+        )?;
+
+        Ok(())
+    }
+
+    /// Returns the user's searches for a channel, pinned first, most recent first.
+    pub fn get_searches(&self, user: &str, channel: &str) -> anyhow::Result<Vec<SavedSearch>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(
+            "SELECT channel, query, case_sensitive, verbatim, whole_word, text_only,
+                messages_only, pinned, last_used, alert
+            FROM saved_searches WHERE user = ?1 AND channel = ?2
+            ORDER BY pinned DESC, last_used DESC",
+        )?;
+        let searches = statement
+            .query_map(params![user, channel], read_saved_search)?
+            .collect::<Result<_, _>>()?;
+
+        Ok(searches)
+    }
+
+    /// Records a search as used now, keeping only the user's most recent
+    /// searches that aren't pinned.
+    pub fn record_search(&self, user: &str, parameters: &SearchParameters) -> anyhow::Result<()> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+
+        insert_saved_search(
+            &transaction,
+            user,
+            &SavedSearch {
+                parameters: parameters.clone(),
+                pinned: false,
+                last_used: Utc::now(),
+                alert: false,
+            },
+        )?;
+        transaction.execute(
+            "DELETE FROM saved_searches WHERE user = ?1 AND NOT pinned AND rowid NOT IN (
+                SELECT rowid FROM saved_searches WHERE user = ?1 AND NOT pinned
+                ORDER BY last_used DESC LIMIT ?2
+            )",
+            params![user, MAX_RECENT_SEARCHES as i64],
+        )?;
+
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    pub fn set_pinned(
+        &self,
+        user: &str,
+        parameters: &SearchParameters,
+        pinned: bool,
+    ) -> anyhow::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "UPDATE saved_searches SET pinned = ?9, alert = alert AND ?9
+            WHERE user = ?1 AND channel = ?2 AND query = ?3 AND case_sensitive = ?4
+                AND verbatim = ?5 AND whole_word = ?6 AND text_only = ?7 AND messages_only = ?8",
+            params![
+                user,
+                parameters.channel,
+                parameters.query,
+                parameters.case_sensitive,
+                parameters.verbatim,
+                parameters.whole_word,
+                parameters.text_only,
+                parameters.messages_only,
+                pinned,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn set_alert(
+        &self,
+        user: &str,
+        parameters: &SearchParameters,
+        alert: bool,
+    ) -> anyhow::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "UPDATE saved_searches SET alert = ?9 AND pinned
+            WHERE user = ?1 AND channel = ?2 AND query = ?3 AND case_sensitive = ?4
+                AND verbatim = ?5 AND whole_word = ?6 AND text_only = ?7 AND messages_only = ?8",
+            params![
+                user,
+                parameters.channel,
+                parameters.query,
+                parameters.case_sensitive,
+                parameters.verbatim,
+                parameters.whole_word,
+                parameters.text_only,
+                parameters.messages_only,
+                alert,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Returns every user's pinned searches that have alerts.
+    pub fn get_alert_searches(&self) -> anyhow::Result<Vec<(String, SearchParameters)>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(
+            "SELECT channel, query, case_sensitive, verbatim, whole_word, text_only,
+                messages_only, pinned, last_used, alert, user
+            FROM saved_searches WHERE pinned AND alert",
+        )?;
+        let searches = statement
+            .query_map([], |row| {
+                Ok((row.get(10)?, read_saved_search(row)?.parameters))
+            })?
+            .collect::<Result<_, _>>()?;
+
+        Ok(searches)
+    }
+
+    pub fn remove_search(&self, user: &str, parameters: &SearchParameters) -> anyhow::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "DELETE FROM saved_searches
+            WHERE user = ?1 AND channel = ?2 AND query = ?3 AND case_sensitive = ?4
+                AND verbatim = ?5 AND whole_word = ?6 AND text_only = ?7 AND messages_only = ?8",
+            params![
+                user,
+                parameters.channel,
+                parameters.query,
+                parameters.case_sensitive,
+                parameters.verbatim,
+                parameters.whole_word,
+                parameters.text_only,
+                parameters.messages_only,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_hidden_channels(&self) -> anyhow::Result<Vec<HiddenChannel>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(
+            "SELECT channel, reason, hidden_by, hidden_at FROM hidden_channels ORDER BY channel",
+        )?;
+        let channels = statement
+            .query_map([], |row| {
+                Ok(HiddenChannel {
+                    channel: row.get(0)?,
+                    reason: row.get(1)?,
+                    hidden_by: row.get(2)?,
+                    hidden_at: row.get(3)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+
+        Ok(channels)
+    }
+
+    /// Hides a channel, replacing the reason if it's already hidden.
+    pub fn hide_channel(&self, hidden: &HiddenChannel) -> anyhow::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        insert_hidden_channel(&connection, hidden)
+    }
+
+    pub fn restore_channel(&self, channel: &str) -> anyhow::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "DELETE FROM hidden_channels WHERE channel = ?1",
+            params![channel],
+        )?;
+
+        Ok(())
+    }
+}
+
+fn insert_annotation(
+    connection: &Connection,
+    channel: &str,
+    date_slug: &str,
+    annotation: &Annotation,
+) -> anyhow::Result<()> {
+    connection.execute(
+        "INSERT INTO annotations (channel, date_slug, line_number, author, note, created)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            channel,
+            date_slug,
+            annotation.line_number as i64,
+            annotation.author,
+            annotation.note,
+            annotation.created,
+        ],
+    )?;
+
+    Ok(())
+}
+
+fn insert_bookmark(connection: &Connection, user: &str, bookmark: &Bookmark) -> anyhow::Result<()> {
+    connection.execute(
+        "INSERT OR REPLACE INTO bookmarks (user, channel, date_slug, line_number, label, created)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            user,
+            bookmark.channel,
+            bookmark.date_slug,
+            bookmark.line_number as i64,
+            bookmark.label,
+            bookmark.created,
+        ],
+    )?;
+
+    Ok(())
+}
+
+/// Inserts a saved search, or updates when it was last used and keeps
+/// whether it's pinned or has alerts.
+fn insert_saved_search(
+    connection: &Connection,
+    user: &str,
+    search: &SavedSearch,
+) -> anyhow::Result<()> {
+    let parameters = &search.parameters;
+
+    connection.execute(
+        "INSERT INTO saved_searches (user, channel, query, case_sensitive, verbatim, whole_word,
+            text_only, messages_only, pinned, last_used, alert)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+        ON CONFLICT (user, channel, query, case_sensitive, verbatim, whole_word, text_only,
+            messages_only)
+        DO UPDATE SET last_used = excluded.last_used",
+        params![
+            user,
+            parameters.channel,
+            parameters.query,
+            parameters.case_sensitive,
+            parameters.verbatim,
+            parameters.whole_word,
+            parameters.text_only,
+            parameters.messages_only,
+            search.pinned,
+            search.last_used,
+            search.alert,
+        ],
+    )?;
+
+    Ok(())
+}
+
+fn insert_hidden_channel(connection: &Connection, hidden: &HiddenChannel) -> anyhow::Result<()> {
+    connection.execute(
+        "INSERT OR REPLACE INTO hidden_channels (channel, reason, hidden_by, hidden_at)
+        VALUES (?1, ?2, ?3, ?4)",
+        params![
+            hidden.channel,
+            hidden.reason,
+            hidden.hidden_by,
+            hidden.hidden_at,
+        ],
+    )?;
+
+    Ok(())
+}
+
+fn read_saved_search(row: &Row) -> rusqlite::Result<SavedSearch> {
+    Ok(SavedSearch {
+        parameters: SearchParameters {
+            channel: row.get(0)?,
+            query: row.get(1)?,
+            case_sensitive: row.get(2)?,
+            verbatim: row.get(3)?,
+            whole_word: row.get(4)?,
+            text_only: row.get(5)?,
+            messages_only: row.get(6)?,
+        },
+        pinned: row.get(7)?,
+        last_used: row.get::<_, DateTime<Utc>>(8)?,
+        alert: row.get(9)?,
+    })
+}
+
+/// Copies the annotations, bookmarks, saved searches and hidden channels from
+/// the JSON files of a data directory.
+fn import_data_directory(transaction: &Transaction, directory: &Path) -> anyhow::Result<()> {
+    let annotations_directory = directory.join("annotations");
+
+    if annotations_directory.is_dir() {
+        for channel_entry in std::fs::read_dir(&annotations_directory)? {
+            let channel_entry = channel_entry?;
+            let channel = channel_entry.file_name().to_string_lossy().to_string();
+
+            if !channel_entry.file_type()?.is_dir() {
+                continue;
+            }
+
+            for day_entry in std::fs::read_dir(channel_entry.path())? {
+                let path = day_entry?.path();
+                let date_slug = match path.file_name().and_then(|name| name.to_str()) {
+                    Some(name) => match name.strip_suffix(".json") {
+                        Some(date_slug) => date_slug.to_string(),
+                        None => continue,
+                    },
+                    None => continue,
+                };
+
+                for annotation in read_json::<Vec<Annotation>>(&path)? {
+                    insert_annotation(transaction, &channel, &date_slug, &annotation)?;
+                }
+            }
+        }
+    }
+
+    let bookmarks: HashMap<String, Vec<Bookmark>> = read_json(&directory.join("bookmarks.json"))?;
+
+    for (user, bookmarks) in bookmarks {
+        for bookmark in bookmarks {
+            insert_bookmark(transaction, &user, &bookmark)?;
+        }
+    }
+
+    let history: HashMap<String, Vec<SavedSearch>> =
+        read_json(&directory.join("search_history.json"))?;
+
+    for (user, searches) in history {
+        for search in searches {
+            insert_saved_search(transaction, &user, &search)?;
+        }
+    }
+
+    let hidden_channels: Vec<HiddenChannel> = read_json(&directory.join("hidden_channels.json"))?;
+
+    for hidden in hidden_channels {
+        insert_hidden_channel(transaction, &hidden)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::AppDatabase;
+    use crate::{
+        annotation::{Annotation, AnnotationStore},
+        search_history::SearchParameters,
+    };
+
+    fn parameters(query: &str) -> SearchParameters {
+        SearchParameters {
+            channel: "archiveteam".to_string(),
+            query: query.to_string(),
+            case_sensitive: false,
+            verbatim: false,
+            whole_word: false,
+            text_only: false,
+            messages_only: false,
+        }
+    }
+
+    #[test]
+    fn imports_data_files_when_created() {
+        let directory = tempfile::tempdir().unwrap();
+        let files = AnnotationStore::new(Some(directory.path().to_path_buf()), None);
+        files
+            .add_annotation(
+                "archiveteam",
+                "2021-01-04,Mon",
+                Annotation {
+                    line_number: 2,
+                    author: "alice".to_string(),
+                    note: "see the wiki".to_string(),
+                    created: Utc::now(),
+                },
+            )
+            .unwrap();
+
+        let path = directory.path().join("app.sqlite3");
+        let database = AppDatabase::open(&path, Some(directory.path())).unwrap();
+        let annotations = database
+            .get_annotations("archiveteam", "2021-01-04,Mon")
+            .unwrap();
+
+        assert_eq!(annotations.len(), 1);
+        assert_eq!(annotations[0].note, "see the wiki");
+
+        // Opening again runs no migrations, so nothing is imported twice
+        drop(database);
+        let database = AppDatabase::open(&path, Some(directory.path())).unwrap();
+
+        assert_eq!(
+            database
+                .get_annotations("archiveteam", "2021-01-04,Mon")
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn keeps_pinned_and_recent_searches() {
+        let directory = tempfile::tempdir().unwrap();
+        let database = AppDatabase::open(&directory.path().join("app.sqlite3"), None).unwrap();
+
+        database
+            .record_search("alice", &parameters("pinned"))
+            .unwrap();
+        database
+            .set_pinned("alice", &parameters("pinned"), true)
+            .unwrap();
+
+        for index in 0..25 {
+            database
+                .record_search("alice", &parameters(&index.to_string()))
+                .unwrap();
+        }
+
+        let searches = database.get_searches("alice", "archiveteam").unwrap();
+
+        assert_eq!(searches.len(), 21);
+        assert_eq!(searches[0].parameters.query, "pinned");
+        assert!(searches[0].pinned);
+        assert!(database
+            .get_searches("bob", "archiveteam")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn alerts_need_pinned_searches() {
+        let directory = tempfile::tempdir().unwrap();
+        let database = AppDatabase::open(&directory.path().join("app.sqlite3"), None).unwrap();
+
+        database.record_search("alice", &parameters("a")).unwrap();
+        database.record_search("alice", &parameters("b")).unwrap();
+        database
+            .set_pinned("alice", &parameters("a"), true)
+            .unwrap();
+        database.set_alert("alice", &parameters("a"), true).unwrap();
+        database.set_alert("alice", &parameters("b"), true).unwrap();
+
+        let alert_searches = database.get_alert_searches().unwrap();
+
+        assert_eq!(alert_searches.len(), 1);
+        assert_eq!(alert_searches[0].0, "alice");
+        assert_eq!(alert_searches[0].1.query, "a");
+
+        database
+            .set_pinned("alice", &parameters("a"), false)
+            .unwrap();
+
+        assert!(database.get_alert_searches().unwrap().is_empty());
+    }
+}
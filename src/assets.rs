@@ -0,0 +1,93 @@
+use std::path::Path;
+
+use mime::Mime;
+
+/// Stylesheets and scripts built into the binary, served under `/static/`.
+const EMBEDDED_ASSETS: &[(&str, &[u8])] = &[
+    ("live.js", include_bytes!("../static/live.js")),
+    ("navigation.js", include_bytes!("../static/navigation.js")),
+    ("nick_card.js", include_bytes!("../static/nick_card.js")),
+    ("style.css", include_bytes!("../static/style.css")),
+    (
+        "theme-classic-irclogger.css",
+        include_bytes!("../static/theme-classic-irclogger.css"),
+    ),
+    ("theme-dark.css", include_bytes!("../static/theme-dark.css")),
+    (
+        "theme-light.css",
+        include_bytes!("../static/theme-light.css"),
+    ),
+];
+
+/// Returns a static file by name, preferring one in the override directory
+/// over the built-in one.
+pub async fn read_asset(
+    override_directory: Option<&Path>,
+    name: &str,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    if !is_asset_name_ok(name) {
+        return Ok(None);
+    }
+
+    if let Some(directory) = override_directory {
+        let path = directory.join(name);
+
+        if tokio::fs::metadata(&path)
+            .await
+            .is_ok_and(|metadata| metadata.is_file())
+        {
+            return Ok(Some(tokio::fs::read(path).await?));
+        }
+    }
+
+    Ok(EMBEDDED_ASSETS
+        .iter()
+        .find(|(asset_name, _)| *asset_name == name)
+        .map(|(_, content)| content.to_vec()))
+}
+
+/// Names are single file names, so the override directory can't be left.
+fn is_asset_name_ok(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "._-".contains(c))
+}
+
+pub fn asset_mime(name: &str) -> Mime {
+    match name.rsplit('.').next() {
+        Some("css") => mime::TEXT_CSS_UTF_8,
+        Some("js") => mime::APPLICATION_JAVASCRIPT_UTF_8,
+        Some("svg") => mime::IMAGE_SVG,
+        Some("png") => mime::IMAGE_PNG,
+        Some("ico") => "image/x-icon".parse().unwrap(),
+        Some("woff2") => "font/woff2".parse().unwrap(),
+        _ => mime::APPLICATION_OCTET_STREAM,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::read_asset;
+
+    #[tokio::test]
+    async fn override_directory_replaces_built_in_assets() {
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::write(directory.path().join("style.css"), "body {}").unwrap();
+
+        assert!(read_asset(None, "style.css").await.unwrap().unwrap().len() > 7);
+        assert_eq!(
+            read_asset(Some(directory.path()), "style.css")
+                .await
+                .unwrap(),
+            Some(b"body {}".to_vec())
+        );
+        assert!(read_asset(Some(directory.path()), "live.js")
+            .await
+            .unwrap()
+            .is_some());
+        assert_eq!(read_asset(None, "missing.css").await.unwrap(), None);
+        assert_eq!(read_asset(None, "../Cargo.toml").await.unwrap(), None);
+    }
+}
@@ -0,0 +1,390 @@
+use std::{
+    ffi::OsStr,
+    fs::File,
+    path::{Component, Path, PathBuf},
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use rusqlite::{params, Connection, OpenFlags};
+
+use crate::config::{parse_config, Config, ConfigFormat};
+
+const CONFIG_ENTRY_STEM: &str = "config"; // With the extension of the config's format
+const APP_DATABASE_ENTRY: &str = "app_database.sqlite3";
+const DAY_CACHE_ENTRY: &str = "day_cache.sqlite3";
+const DATA_ENTRY: &str = "data";
+const TENANTS_ENTRY: &str = "tenants";
+
+pub struct BackupOptions {
+    pub config_path: PathBuf,
+    pub config_format: ConfigFormat,
+    pub archive_path: PathBuf,
+}
+
+pub struct RestoreOptions {
+    pub config_path: PathBuf,
+    pub config_format: ConfigFormat, // Of an existing config file
+    pub archive_path: PathBuf,
+    pub force: bool, // Overwrite existing files
+}
+
+/// Writes the config, app database, day cache and data directories to a
+/// gzipped tar archive for moving an install to another host.
+///
+/// Databases are copied with `VACUUM INTO`, so the server can keep running.
+/// Logs and password files aren't included.
+pub fn run_backup(options: BackupOptions) -> anyhow::Result<()> {
+    let config_content = std::fs::read(&options.config_path)?;
+    let config = parse_config(&config_content, options.config_format)?;
+
+    let file = File::create(&options.archive_path)?;
+    let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(config_content.len() as u64);
+    header.set_mode(0o600);
+    header.set_cksum();
+    builder.append_data(
+        &mut header,
+        format!(
+            "{}.{}",
+            CONFIG_ENTRY_STEM,
+            options.config_format.extension()
+        ),
+        config_content.as_slice(),
+    )?;
+
+    let databases = [
+        (APP_DATABASE_ENTRY, &config.app_database_file),
+        (DAY_CACHE_ENTRY, &config.day_cache_file),
+    ];
+
+    for (entry_name, path) in databases.iter() {
+        if let Some(path) = path.as_ref().filter(|path| path.exists()) {
+            let copy_path = options.archive_path.with_extension("sqlite3.tmp");
+            copy_database(path, &copy_path)?;
+            let result = builder.append_path_with_name(&copy_path, entry_name);
+            std::fs::remove_file(&copy_path)?;
+            result?;
+            println!("Added {}", path.display());
+        }
+    }
+
+    if let Some(directory) = config.data_directory.as_ref().filter(|path| path.is_dir()) {
+        builder.append_dir_all(DATA_ENTRY, directory)?;
+        println!("Added {}", directory.display());
+    }
+
+    for (host, tenant) in &config.tenants {
+        if let Some(directory) = tenant.data_directory.as_ref().filter(|path| path.is_dir()) {
+            builder.append_dir_all(
+                Path::new(TENANTS_ENTRY).join(host).join(DATA_ENTRY),
+                directory,
+            )?;
+            println!("Added {}", directory.display());
+        }
+    }
+
+    builder.into_inner()?.finish()?;
+    println!("Wrote {}", options.archive_path.display());
+
+    Ok(())
+}
+
+/// Puts the files of a backup archive where the config says they go.
+///
+/// An existing config file is kept so the paths of the new host are used;
+/// otherwise the config from the archive is written first. Nothing is
+/// written if a file already exists, unless forced. The server should be
+/// stopped while restoring.
+pub fn run_restore(options: RestoreOptions) -> anyhow::Result<()> {
+    let config = if options.config_path.exists() {
+        println!("Keeping existing {}", options.config_path.display());
+        parse_config(&std::fs::read(&options.config_path)?, options.config_format)?
+    } else {
+        let mut archived_config = None;
+
+        for entry in open_archive(&options.archive_path)?.entries()? {
+            let mut entry = entry?;
+            let entry_path = entry.path()?.into_owned();
+
+            if is_regular_file(&entry) && is_config_entry(&entry_path) {
+                let mut content = Vec::new();
+                std::io::Read::read_to_end(&mut entry, &mut content)?;
+                archived_config = Some((ConfigFormat::from_path(&entry_path), content));
+                break;
+            }
+        }
+
+        let (format, content) =
+            archived_config.ok_or_else(|| anyhow::anyhow!("Archive has no config"))?;
+        std::fs::write(&options.config_path, &content)?;
+        println!("Wrote {}", options.config_path.display());
+
+        parse_config(&content, format)?
+    };
+
+    let mut destinations = Vec::new();
+
+    for entry in open_archive(&options.archive_path)?.entries()? {
+        let entry = entry?;
+
+        if entry.header().entry_type().is_dir() {
+            continue;
+        }
+
+        let entry_path = entry.path()?.into_owned();
+
+        if !is_regular_file(&entry) {
+            println!("Skipping {}: not a regular file", entry_path.display());
+            continue;
+        }
+
+        match restore_destination(&config, &entry_path)? {
+            Some((_, destination)) => {
+                if destination.exists() && !options.force {
+                    anyhow::bail!(
+                        "{} already exists; restore with --force to overwrite it",
+                        destination.display()
+                    );
+                }
+
+                destinations.push(destination);
+            }
+            None if is_config_entry(&entry_path) => {}
+            None => println!(
+                "Skipping {}: nowhere to put it in this config",
+                entry_path.display()
+            ),
+        }
+    }
+
+    for entry in open_archive(&options.archive_path)?.entries()? {
+        let mut entry = entry?;
+
+        if !is_regular_file(&entry) {
+            continue;
+        }
+
+        let entry_path = entry.path()?.into_owned();
+
+        if let Some((directory, destination)) = restore_destination(&config, &entry_path)? {
+            check_destination(&directory, &destination)?;
+            entry.unpack(&destination)?;
+            println!("Wrote {}", destination.display());
+        }
+    }
+
+    println!(
+        "Restored {} file(s) from {}",
+        destinations.len(),
+        options.archive_path.display()
+    );
+
+    Ok(())
+}
+
+/// Links and devices are never written by a backup and could point a
+/// restore outside the configured directories.
+fn is_regular_file<R: std::io::Read>(entry: &tar::Entry<R>) -> bool {
+    entry.header().entry_type() == tar::EntryType::Regular
+}
+
+/// Creates the directories of a destination and checks that, with any
+/// symlinks already on disk resolved, it is still inside the configured
+/// directory and isn't itself a symlink.
+fn check_destination(directory: &Path, destination: &Path) -> anyhow::Result<()> {
+    let parent = destination
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    std::fs::create_dir_all(parent)?;
+
+    if !parent
+        .canonicalize()?
+        .starts_with(directory.canonicalize()?)
+    {
+        anyhow::bail!(
+            "{} is outside {}",
+            destination.display(),
+            directory.display()
+        );
+    }
+
+    if std::fs::symlink_metadata(destination)
+        .is_ok_and(|metadata| metadata.file_type().is_symlink())
+    {
+        anyhow::bail!("{} is a symlink", destination.display());
+    }
+
+    Ok(())
+}
+
+fn is_config_entry(entry_path: &Path) -> bool {
+    entry_path.parent() == Some(Path::new(""))
+        && entry_path.file_stem() == Some(OsStr::new(CONFIG_ENTRY_STEM))
+}
+
+fn open_archive(path: &Path) -> anyhow::Result<tar::Archive<GzDecoder<File>>> {
+    Ok(tar::Archive::new(GzDecoder::new(File::open(path)?)))
+}
+
+fn copy_database(source: &Path, destination: &Path) -> anyhow::Result<()> {
+    let connection = Connection::open_with_flags(source, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let _ = std::fs::remove_file(destination);
+
+    connection.execute(
+        "VACUUM INTO ?1",
+        params![destination.to_string_lossy().into_owned()],
+    )?;
+
+    Ok(())
+}
+
+/// Maps an archive entry to the file it's restored to, if the config has a
+/// place for it, along with the configured directory the file must stay in.
+fn restore_destination(
+    config: &Config,
+    entry_path: &Path,
+) -> anyhow::Result<Option<(PathBuf, PathBuf)>> {
+    let components = entry_path
+        .components()
+        .map(|component| match component {
+            Component::Normal(name) => Ok(name.to_string_lossy().into_owned()),
+            _ => Err(anyhow::anyhow!(
+                "Unsafe path in archive: {}",
+                entry_path.display()
+            )),
+        })
+        .collect::<anyhow::Result<Vec<String>>>()?;
+    let components: Vec<&str> = components.iter().map(String::as_str).collect();
+
+    let destination = match components.as_slice() {
+        [APP_DATABASE_ENTRY] => config.app_database_file.as_deref().map(file_in_parent),
+        [DAY_CACHE_ENTRY] => config.day_cache_file.as_deref().map(file_in_parent),
+        [DATA_ENTRY, rest @ ..] if !rest.is_empty() => {
+            config.data_directory.as_ref().map(|directory| {
+                (
+                    directory.clone(),
+                    directory.join(rest.iter().collect::<PathBuf>()),
+                )
+            })
+        }
+        [TENANTS_ENTRY, host, DATA_ENTRY, rest @ ..] if !rest.is_empty() => config
+            .tenants
+            .get(*host)
+            .and_then(|tenant| tenant.data_directory.as_ref())
+            .map(|directory| {
+                (
+                    directory.clone(),
+                    directory.join(rest.iter().collect::<PathBuf>()),
+                )
+            }),
+        _ => None,
+    };
+
+    Ok(destination)
+}
+
+fn file_in_parent(path: &Path) -> (PathBuf, PathBuf) {
+    let directory = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+
+    (directory.to_path_buf(), path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{run_backup, run_restore, BackupOptions, RestoreOptions};
+    use crate::{app_db::AppDatabase, config::ConfigFormat};
+
+    fn write_config(directory: &Path) -> PathBuf {
+        let path = directory.join("config.json");
+        let config = serde_json::json!({
+            "chat_log_directory": directory.join("log"),
+            "apache_password_file": directory.join("passwords"),
+            "custom_message_html_file": directory.join("message.html"),
+            "web_server_port_number": 8033,
+            "data_directory": directory.join("data"),
+            "app_database_file": directory.join("app.sqlite3"),
+        });
+        std::fs::write(&path, serde_json::to_vec(&config).unwrap()).unwrap();
+
+        path
+    }
+
+    #[test]
+    fn restores_to_paths_of_new_config() {
+        let old = tempfile::tempdir().unwrap();
+        let new = tempfile::tempdir().unwrap();
+        let archive_path = old.path().join("backup.tar.gz");
+
+        std::fs::create_dir(old.path().join("data")).unwrap();
+        std::fs::write(old.path().join("data/bookmarks.json"), b"{}").unwrap();
+        AppDatabase::open(&old.path().join("app.sqlite3"), None).unwrap();
+
+        run_backup(BackupOptions {
+            config_path: write_config(old.path()),
+            config_format: ConfigFormat::Json,
+            archive_path: archive_path.clone(),
+        })
+        .unwrap();
+
+        let restore = |force| {
+            run_restore(RestoreOptions {
+                config_path: write_config(new.path()),
+                config_format: ConfigFormat::Json,
+                archive_path: archive_path.clone(),
+                force,
+            })
+        };
+
+        restore(false).unwrap();
+        assert!(new.path().join("data/bookmarks.json").exists());
+        assert!(new.path().join("app.sqlite3").exists());
+
+        assert!(restore(false).is_err());
+        restore(true).unwrap();
+    }
+
+    #[test]
+    fn links_in_archives_are_skipped() {
+        let outside = tempfile::tempdir().unwrap();
+        let new = tempfile::tempdir().unwrap();
+        let archive_path = new.path().join("backup.tar.gz");
+        let file = std::fs::File::create(&archive_path).unwrap();
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            file,
+            flate2::Compression::default(),
+        ));
+
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "data/escape", outside.path())
+            .unwrap();
+
+        let mut header = tar::Header::new_gnu();
+        header.set_size(2);
+        builder
+            .append_data(&mut header, "data/escape/owned.json", &b"{}"[..])
+            .unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+
+        run_restore(RestoreOptions {
+            config_path: write_config(new.path()),
+            config_format: ConfigFormat::Json,
+            archive_path,
+            force: false,
+        })
+        .unwrap();
+
+        assert!(!outside.path().join("owned.json").exists());
+        assert!(new.path().join("data/escape/owned.json").exists());
+    }
+}
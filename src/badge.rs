@@ -0,0 +1,62 @@
+//! Small SVG badges in the style of shields.io for project READMEs.
+
+pub const COLOR_ACTIVE: &str = "#007ec6";
+pub const COLOR_INACTIVE: &str = "#9f9f9f";
+
+const CHARACTER_WIDTH: usize = 7; // Close enough for 11px Verdana
+const TEXT_PADDING: usize = 10;
+
+/// Draws a gray label next to a value on a colored background.
+pub fn render_badge(label: &str, value: &str, color: &str) -> String {
+    let label_width = text_width(label);
+    let value_width = text_width(value);
+    let width = label_width + value_width;
+
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="20" role="img" aria-label="{label}: {value}">
+<title>{label}: {value}</title>
+<linearGradient id="s" x2="0" y2="100%"><stop offset="0" stop-color="#bbb" stop-opacity=".1"/><stop offset="1" stop-opacity=".1"/></linearGradient>
+<clipPath id="r"><rect width="{width}" height="20" rx="3" fill="#fff"/></clipPath>
+<g clip-path="url(#r)"><rect width="{label_width}" height="20" fill="#555"/><rect x="{label_width}" width="{value_width}" height="20" fill="{color}"/><rect width="{width}" height="20" fill="url(#s)"/></g>
+<g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11">
+<text x="{label_x}" y="15" fill="#010101" fill-opacity=".3">{label}</text><text x="{label_x}" y="14">{label}</text>
+<text x="{value_x}" y="15" fill="#010101" fill-opacity=".3">{value}</text><text x="{value_x}" y="14">{value}</text>
+</g>
+</svg>
+"##,
+        width = width,
+        label_width = label_width,
+        value_width = value_width,
+        label_x = label_width / 2,
+        value_x = label_width + value_width / 2,
+        label = escape_xml(label),
+        value = escape_xml(value),
+        color = escape_xml(color),
+    )
+}
+
+fn text_width(text: &str) -> usize {
+    text.chars().count() * CHARACTER_WIDTH + TEXT_PADDING
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render_badge, COLOR_ACTIVE};
+
+    #[test]
+    fn badge_fits_and_escapes_text() {
+        let badge = render_badge("logged days", "1,234 <&>", COLOR_ACTIVE);
+
+        assert!(badge.starts_with(r#"<svg xmlns="http://www.w3.org/2000/svg" width="160""#));
+        assert!(badge.contains("<title>logged days: 1,234 &lt;&amp;&gt;</title>"));
+        assert!(!badge.contains("<&>"));
+    }
+}
@@ -0,0 +1,121 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_db::AppDatabase,
+    datafile::{read_json, write_json},
+};
+
+pub const MAX_LABEL_LENGTH: usize = 200;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub channel: String,
+    pub date_slug: String,
+    pub line_number: u64,
+    pub label: String,
+    pub created: DateTime<Utc>,
+}
+
+/// Stores each user's bookmarked line permalinks in a single JSON file, or
+/// in the app database if there is one.
+#[derive(Clone)]
+pub struct BookmarkStore {
+    path: Option<PathBuf>,
+    database: Option<AppDatabase>,
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl BookmarkStore {
+    pub fn new(data_directory: Option<PathBuf>, database: Option<AppDatabase>) -> Self {
+        Self {
+            path: data_directory.map(|directory| directory.join("bookmarks.json")),
+            database,
+            write_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.path.is_some() || self.database.is_some()
+    }
+
+    pub fn get_bookmarks(&self, user: &str) -> anyhow::Result<Vec<Bookmark>> {
+        if let Some(database) = &self.database {
+            return database.get_bookmarks(user);
+        }
+
+        Ok(self.read_all()?.remove(user).unwrap_or_default())
+    }
+
+    pub fn add_bookmark(&self, user: &str, bookmark: Bookmark) -> anyhow::Result<()> {
+        if let Some(database) = &self.database {
+            return database.add_bookmark(user, &bookmark);
+        }
+
+        let _guard = self.write_lock.lock().unwrap();
+        let mut bookmarks = self.read_all()?;
+        let user_bookmarks = bookmarks.entry(user.to_string()).or_default();
+
+        user_bookmarks.retain(|item| !item.is_same_line(&bookmark));
+        user_bookmarks.push(bookmark);
+
+        self.write_all(&bookmarks)
+    }
+
+    pub fn remove_bookmark(
+        &self,
+        user: &str,
+        channel: &str,
+        date_slug: &str,
+        line_number: u64,
+    ) -> anyhow::Result<()> {
+        if let Some(database) = &self.database {
+            return database.remove_bookmark(user, channel, date_slug, line_number);
+        }
+
+        let _guard = self.write_lock.lock().unwrap();
+        let mut bookmarks = self.read_all()?;
+
+        if let Some(user_bookmarks) = bookmarks.get_mut(user) {
+            user_bookmarks.retain(|item| {
+                !(item.channel == channel
+                    && item.date_slug == date_slug
+                    && item.line_number == line_number)
+            });
+        }
+
+        self.write_all(&bookmarks)
+    }
+
+    fn read_all(&self) -> anyhow::Result<HashMap<String, Vec<Bookmark>>> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(HashMap::new()),
+        };
+
+        read_json(path)
+    }
+
+    fn write_all(&self, bookmarks: &HashMap<String, Vec<Bookmark>>) -> anyhow::Result<()> {
+        let path = self
+            .path
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Bookmarks are not enabled"))?;
+
+        write_json(path, bookmarks)
+    }
+}
+
+impl Bookmark {
+    fn is_same_line(&self, other: &Bookmark) -> bool {
+        self.channel == other.channel
+            && self.date_slug == other.date_slug
+            && self.line_number == other.line_number
+    }
+}
@@ -0,0 +1,76 @@
+use std::{
+    net::{Ipv4Addr, TcpListener},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::config::Config;
+
+/// Gives the server a moment to start listening before the browser connects.
+const BROWSER_OPEN_DELAY: Duration = Duration::from_millis(500);
+
+pub struct BrowseOptions {
+    pub directory: PathBuf,
+    pub port: Option<u16>, // None picks a free port
+    pub open_browser: bool,
+}
+
+/// Serves a local log directory with every channel public, for reading
+/// copied archives without writing a config file.
+pub fn run_browse(options: BrowseOptions) -> anyhow::Result<()> {
+    if !options.directory.is_dir() {
+        anyhow::bail!("{} is not a directory", options.directory.display());
+    }
+
+    let port = match options.port {
+        Some(port) => port,
+        None => find_free_port()?,
+    };
+    let config = build_config(&options.directory, port)?;
+    let url = format!("http://127.0.0.1:{}/bin/irclogger_logs", port);
+
+    println!("Browsing {} at {}", options.directory.display(), url);
+    println!("Press Ctrl+C to stop.");
+
+    if options.open_browser {
+        std::thread::spawn(move || {
+            std::thread::sleep(BROWSER_OPEN_DELAY);
+
+            if let Err(error) = webbrowser::open(&url) {
+                eprintln!("Couldn't open a browser: {}", error);
+            }
+        });
+    }
+
+    crate::app::run(config, None)
+}
+
+fn find_free_port() -> anyhow::Result<u16> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+
+    Ok(listener.local_addr()?.port())
+}
+
+/// Builds a config whose password file is empty, so no channel is private.
+fn build_config(directory: &Path, port: u16) -> anyhow::Result<Config> {
+    let support_directory =
+        std::env::temp_dir().join(format!("irclogger-viewer-browse-{}", std::process::id()));
+    let password_path = support_directory.join("passwords");
+    let message_path = support_directory.join("message.html");
+
+    std::fs::create_dir_all(&support_directory)?;
+    std::fs::write(&password_path, b"")?;
+    std::fs::write(
+        &message_path,
+        "<p>Browsing a local log directory. Each subdirectory is a channel.</p>\n",
+    )?;
+
+    let config = serde_json::json!({
+        "chat_log_directory": directory.canonicalize()?,
+        "apache_password_file": password_path,
+        "custom_message_html_file": message_path,
+        "web_server_port_number": port,
+    });
+
+    crate::config::config_from_value(config)
+}
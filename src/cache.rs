@@ -0,0 +1,274 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+type TimedEntry<T> = (Instant, Arc<T>); // When the value was computed
+
+/// Holds a single computed value that is recomputed once it's older than the TTL.
+pub struct TimedCache<T> {
+    ttl: Duration,
+    entry: Arc<Mutex<Option<TimedEntry<T>>>>,
+}
+
+impl<T> TimedCache<T> {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entry: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn get_or_try_insert_with<F>(&self, func: F) -> anyhow::Result<Arc<T>>
+    where
+        F: FnOnce() -> anyhow::Result<T>,
+    {
+        let mut entry = self.entry.lock().unwrap();
+
+        if let Some((created, value)) = entry.as_ref() {
+            if created.elapsed() < self.ttl {
+                return Ok(value.clone());
+            }
+        }
+
+        let value = Arc::new(func()?);
+        *entry = Some((Instant::now(), value.clone()));
+
+        Ok(value)
+    }
+
+    /// Drops the value so the next use recomputes it.
+    pub fn clear(&self) {
+        *self.entry.lock().unwrap() = None;
+    }
+}
+
+impl<T> Clone for TimedCache<T> {
+    fn clone(&self) -> Self {
+        Self {
+            ttl: self.ttl,
+            entry: self.entry.clone(),
+        }
+    }
+}
+
+/// Holds values by key, each recomputed once it's older than the TTL.
+///
+/// Expired entries are dropped when the map grows past its capacity, so
+/// keys taken from requests can't grow it without bound.
+pub struct TimedMap<K, V> {
+    ttl: Duration,
+    capacity: usize,
+    entries: Arc<Mutex<HashMap<K, (Instant, V)>>>,
+}
+
+impl<K: Eq + Hash, V: Clone> TimedMap<K, V> {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let entries = self.entries.lock().unwrap();
+
+        entries
+            .get(key)
+            .filter(|(created, _)| created.elapsed() < self.ttl)
+            .map(|(_, value)| value.clone())
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        let mut entries = self.entries.lock().unwrap();
+
+        if entries.len() >= self.capacity {
+            let ttl = self.ttl;
+            entries.retain(|_, (created, _)| created.elapsed() < ttl);
+
+            // Still full of fresh entries, so start over rather than scan
+            // the map on every insert
+            if entries.len() >= self.capacity {
+                entries.clear();
+            }
+        }
+
+        entries.insert(key, (Instant::now(), value));
+    }
+
+    pub fn get_or_try_insert_with<F>(&self, key: K, func: F) -> anyhow::Result<V>
+    where
+        F: FnOnce() -> anyhow::Result<V>,
+    {
+        if let Some(value) = self.get(&key) {
+            return Ok(value);
+        }
+
+        let value = func()?;
+        self.insert(key, value.clone());
+
+        Ok(value)
+    }
+}
+
+impl<K, V> Clone for TimedMap<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            ttl: self.ttl,
+            capacity: self.capacity,
+            entries: self.entries.clone(),
+        }
+    }
+}
+
+/// Holds values by key, dropping the least recently used once there are
+/// more than the capacity. A capacity of 0 holds nothing.
+pub struct LruMap<K, V> {
+    capacity: usize,
+    entries: Arc<Mutex<LruEntries<K, V>>>,
+}
+
+struct LruEntries<K, V> {
+    values: HashMap<K, (u64, V)>, // Value by key, with when it was last used
+    uses: BTreeMap<u64, K>,       // Keys by when they were last used
+    use_count: u64,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> LruMap<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Arc::new(Mutex::new(LruEntries {
+                values: HashMap::new(),
+                uses: BTreeMap::new(),
+                use_count: 0,
+            })),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.use_count += 1;
+        let use_count = entries.use_count;
+
+        let (last_use, value) = entries.values.get_mut(key)?;
+        let previous_use = std::mem::replace(last_use, use_count);
+        let value = value.clone();
+        entries.uses.remove(&previous_use);
+        entries.uses.insert(use_count, key.clone());
+
+        Some(value)
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.use_count += 1;
+        let use_count = entries.use_count;
+
+        if let Some((previous_use, _)) = entries.values.insert(key.clone(), (use_count, value)) {
+            entries.uses.remove(&previous_use);
+        }
+
+        entries.uses.insert(use_count, key);
+
+        while entries.values.len() > self.capacity {
+            let oldest_use = *entries.uses.keys().next().unwrap();
+            let oldest_key = entries.uses.remove(&oldest_use).unwrap();
+            entries.values.remove(&oldest_key);
+        }
+    }
+
+    pub fn get_or_try_insert_with<F>(&self, key: K, func: F) -> anyhow::Result<V>
+    where
+        F: FnOnce() -> anyhow::Result<V>,
+    {
+        if let Some(value) = self.get(&key) {
+            return Ok(value);
+        }
+
+        let value = func()?;
+        self.insert(key, value.clone());
+
+        Ok(value)
+    }
+
+    /// Drops the values whose keys don't pass the filter.
+    pub fn retain<F>(&self, keep: F)
+    where
+        F: Fn(&K) -> bool,
+    {
+        let mut entries = self.entries.lock().unwrap();
+        let entries = &mut *entries;
+
+        entries.values.retain(|key, _| keep(key));
+        entries.uses.retain(|_, key| keep(key));
+    }
+}
+
+impl<K, V> Clone for LruMap<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            capacity: self.capacity,
+            entries: self.entries.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{LruMap, TimedMap};
+
+    #[test]
+    fn timed_map_expires_and_stays_bounded() {
+        let map = TimedMap::new(Duration::from_secs(60), 2);
+
+        assert_eq!(map.get_or_try_insert_with("a", || Ok(1)).unwrap(), 1);
+        assert_eq!(map.get_or_try_insert_with("a", || Ok(2)).unwrap(), 1);
+        map.insert("b", 3);
+        map.insert("c", 4);
+        assert_eq!(map.get(&"c"), Some(4));
+        assert!(map.entries.lock().unwrap().len() <= 2);
+
+        let expired = TimedMap::new(Duration::from_secs(0), 10);
+        expired.insert("a", 1);
+        assert_eq!(expired.get(&"a"), None);
+    }
+
+    #[test]
+    fn lru_map_drops_least_recently_used() {
+        let map = LruMap::new(2);
+
+        map.insert("a", 1);
+        map.insert("b", 2);
+        assert_eq!(map.get(&"a"), Some(1));
+        map.insert("c", 3);
+        assert_eq!(map.get(&"b"), None);
+        assert_eq!(map.get(&"a"), Some(1));
+        assert_eq!(map.get_or_try_insert_with("c", || Ok(4)).unwrap(), 3);
+        map.insert("a", 5);
+        assert_eq!(map.get(&"a"), Some(5));
+        assert_eq!(map.entries.lock().unwrap().uses.len(), 2);
+
+        map.retain(|key| *key != "a");
+        assert_eq!(map.get(&"a"), None);
+        assert_eq!(map.get(&"c"), Some(3));
+        assert_eq!(map.entries.lock().unwrap().uses.len(), 1);
+
+        let disabled = LruMap::new(0);
+        disabled.insert("a", 1);
+        assert_eq!(disabled.get(&"a"), None);
+    }
+}
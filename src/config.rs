@@ -1,11 +1,1008 @@
-use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+};
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    access_log::AccessLogFormat,
+    digest::DailyDigestConfig,
+    hydrate::ColdStorageConfig,
+    logging::LogFormat,
+    monitor::{SavedSearchAlertConfig, StaleChannelAlertConfig},
+    page_template::PageTemplates,
+    purge::CachePurgeConfig,
+    ratelimit::{IpNetwork, RateLimitConfig},
+    scheduler::Schedule,
+    search::{SearchBackend, SearchBudget},
+    search_engine::SearchEngineKind,
+    search_index::SearchIndexConfig,
+    stats::PublicStatsConfig,
+    tenant::TenantConfig,
+};
 
 #[derive(Deserialize)]
 pub struct Config {
     pub chat_log_directory: PathBuf, // Directory containing channel-named directories
     pub apache_password_file: PathBuf, // Password file in htpasswd format,
     pub custom_message_html_file: PathBuf,
+    pub global_message_html_file: Option<PathBuf>, // Shown above the message of the site and of every tenant
+    pub message_html: Option<String>,              // Shown below the site's message file
+    #[serde(default)]
+    pub sanitize_custom_messages: bool, // Remove scripts, styles and event handlers from every message
     pub web_server_port_number: u16,
+    #[serde(default = "default_web_server_bind_address")]
+    pub web_server_bind_address: IpAddr, // Such as "0.0.0.0" or "::" to listen on all interfaces
+    pub tls_certificate_path: Option<PathBuf>, // PEM certificate chain; serves HTTPS with tls_key_path
+    pub tls_key_path: Option<PathBuf>,         // PEM private key
+    pub theme: Option<PathBuf>,                // CSS file served after the built-in styles
+    pub static_directory: Option<PathBuf>,     // Files replacing the built-in ones in /static/
+    pub template_directory: Option<PathBuf>,   // Tera templates replacing built-in pages
+    pub public_url: Option<String>,            // Such as "https://example.org/irc" for feed links
+    pub path_prefix: Option<String>, // Such as "/irc" when a proxy serves the site under a path
+    #[serde(default)]
+    pub tenants: HashMap<String, TenantConfig>, // Hostname to tenant; other hosts use the settings above
+    #[serde(default)]
+    pub link_preview_hosts: Vec<String>, // Hosts whose page titles are fetched for day views
+    #[serde(default)]
+    pub image_proxy_hosts: Vec<String>, // Hosts that image thumbnails may be proxied from
+    pub image_proxy_cache_directory: Option<PathBuf>,
+    #[serde(default = "default_image_proxy_max_size")]
+    pub image_proxy_max_size: usize, // Bytes
+    #[serde(default = "default_image_proxy_timeout")]
+    pub image_proxy_timeout: u64, // Seconds
+    #[serde(default)]
+    pub widget_embed_origins: Vec<String>, // Origins such as "https://example.org" allowed to embed the activity widget
+    pub mirror_upstream_url: Option<String>, // Raw log URL with {channel} and {date} placeholders
+    #[serde(default = "default_mirror_max_day_size")]
+    pub mirror_max_day_size: usize, // Bytes of an upstream day log
+    pub data_directory: Option<PathBuf>, // Writable directory for annotations and other viewer data
+    pub app_database_file: Option<PathBuf>, // SQLite file for the main site's viewer data instead of JSON files in data_directory
+    pub stale_channel_alert: Option<StaleChannelAlertConfig>,
+    pub saved_search_alert: Option<SavedSearchAlertConfig>, // Lets users subscribe pinned searches to new matches
+    pub daily_digest: Option<DailyDigestConfig>,
+    pub cache_purge: Option<CachePurgeConfig>, // Purge a reverse proxy cache when logs change
+    #[serde(default)]
+    pub admin_users: Vec<String>, // Users in the password file allowed to access the admin pages
+    pub access_log_file: Option<PathBuf>,      // One line per request in Apache's log format
+    #[serde(default)]
+    pub access_log_format: AccessLogFormat, // "combined" or "common"
+    #[serde(default = "default_log_level")]
+    pub log_level: String, // Such as "info" or "warn,irclogger_viewer=debug"
+    #[serde(default)]
+    pub log_format: LogFormat, // "text" or "json"
+    pub slow_request_threshold: Option<u64>, // Milliseconds; slower requests are logged with timings
+    #[serde(default)]
+    pub search_defaults: SearchDefaults, // Options the search form starts with
+    pub search_budget: Option<SearchBudget>, // Limits how much of the logs one search may scan
+    #[serde(default)]
+    pub search_backend: SearchBackend, // "native" or "agrep"
+    pub search_index: Option<SearchIndexConfig>, // Full-text index for faster searches of the main site
+    #[serde(default)]
+    pub day_density: Density, // Default rendering of the day view
+    #[serde(default)]
+    pub color_theme: ColorTheme, // "light", "dark" or "classic-irclogger"
+    #[serde(default = "default_day_view_page_lines")]
+    pub day_view_page_lines: usize, // Days with more lines are split into pages
+    #[serde(default)]
+    pub day_json_files: bool, // Keep each finished day's log API response in a .json file next to the log
+    #[serde(default = "default_past_day_max_age")]
+    pub past_day_max_age: u64, // Seconds that pages of finished days may be cached; 0 to not
+    #[serde(default = "default_parsed_day_cache_entries")]
+    pub parsed_day_cache_entries: usize, // Parsed days kept in memory per site; 0 to not
+    #[serde(default)]
+    pub count_status_lines: bool, // Include joins, quits and other status lines in daily message counts
+    #[serde(default)]
+    pub lenient_parsing: bool, // Show unparsable lines as status lines and report them on the admin page
+    #[serde(default = "default_locale")]
+    pub locale: String, // Such as "en_US", for dates when the browser's languages have no locale data
+    #[serde(default = "default_timezone")]
+    pub timezone: String, // Such as "Europe/Paris", for log times when the viewer hasn't chosen one
+    pub day_cache_file: Option<PathBuf>, // SQLite file caching each day's message count for the main site
+    #[serde(default)]
+    pub channel_count_files: bool, // Without day_cache_file, keep counts in each channel's counts.json
+    pub cold_storage: Option<ColdStorageConfig>, // Local copies of the main site's logs on slow storage
+    pub public_stats: Option<PublicStatsConfig>, // Enables noisy daily counts of channels that opt in
+    pub preferences_secret: Option<String>, // Key for signing the preferences cookie; enables the preferences page
+    #[serde(default)]
+    pub demo_mode: bool, // Public demo: no logins, no private channels, no custom HTML, rate limited
+    #[serde(default = "default_demo_rate_limit")]
+    pub demo_rate_limit: u32, // Requests per minute per client in demo mode, without rate_limit
+    pub rate_limit: Option<RateLimitConfig>, // Requests per client, with a stricter limit of searches
+    #[serde(default = "crate::ratelimit::default_trusted_proxies")]
+    pub trusted_proxies: Vec<IpNetwork>, // Reverse proxies whose X-Forwarded-For header is used, such as "10.0.0.0/8"
+    #[serde(default)]
+    pub ip_allow_list: Vec<IpNetwork>, // Only these addresses and networks may use the viewer, if any are given
+    #[serde(default)]
+    pub ip_deny_list: Vec<IpNetwork>, // Addresses and networks refused even if allowed
+}
+
+#[derive(Clone, Default, Deserialize)]
+pub struct SearchDefaults {
+    #[serde(default)]
+    pub case_sensitive: bool,
+    #[serde(default)]
+    pub verbatim: bool,
+    #[serde(default)]
+    pub whole_word: bool,
+    #[serde(default)]
+    pub text_only: bool, // Match message text only, not timestamps, nicknames or status lines
+    #[serde(default)]
+    pub messages_only: bool, // Leave out joins, parts, quits and other status lines
+    pub days: Option<u32>, // Only search the most recent days unless "all dates" is chosen
+    pub per_day_limit: Option<u32>, // Collapse matches beyond this many per day into one row
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Density {
+    #[default]
+    Normal, // One grid row per line with permalinks, previews and notes
+    Compact, // Plain text lines, about half the HTML of a normal day
+}
+
+/// Bundled color schemes, each a stylesheet served after `style.css`.
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize, Serialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorTheme {
+    #[default]
+    Light,
+    Dark,
+    ClassicIrclogger, // Plain white pages like the original irclogger's
+}
+
+impl ColorTheme {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Light => "light",
+            Self::Dark => "dark",
+            Self::ClassicIrclogger => "classic-irclogger",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "light" => Some(Self::Light),
+            "dark" => Some(Self::Dark),
+            "classic-irclogger" => Some(Self::ClassicIrclogger),
+            _ => None,
+        }
+    }
+}
+
+fn default_web_server_bind_address() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::LOCALHOST)
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+fn default_locale() -> String {
+    "en_US".to_string()
+}
+
+fn default_timezone() -> String {
+    "UTC".to_string()
+}
+
+fn default_day_view_page_lines() -> usize {
+    5000
+}
+
+fn default_past_day_max_age() -> u64 {
+    86400
+}
+
+fn default_parsed_day_cache_entries() -> usize {
+    64
+}
+
+fn default_demo_rate_limit() -> u32 {
+    30
+}
+
+fn default_image_proxy_max_size() -> usize {
+    5 * 1024 * 1024
+}
+
+fn default_image_proxy_timeout() -> u64 {
+    10
+}
+
+fn default_mirror_max_day_size() -> usize {
+    16 * 1024 * 1024
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(Self::Json),
+            "toml" => Some(Self::Toml),
+            "yaml" | "yml" => Some(Self::Yaml),
+            _ => None,
+        }
+    }
+
+    /// Guesses the format from the file extension, defaulting to JSON.
+    pub fn from_path(path: &Path) -> Self {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(|extension| Self::from_name(&extension.to_lowercase()))
+            .unwrap_or(Self::Json)
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Toml => "toml",
+            Self::Yaml => "yaml",
+        }
+    }
+}
+
+const ENV_PREFIX: &str = "IRCLOGGER_VIEWER_";
+
+/// Parses the config and overrides its fields with `IRCLOGGER_VIEWER_*`
+/// environment variables, naming the field that failed to deserialize.
+pub fn parse_config(content: &[u8], format: ConfigFormat) -> anyhow::Result<Config> {
+    let mut value: serde_json::Value = match format {
+        ConfigFormat::Json => serde_json::from_slice(content)?,
+        ConfigFormat::Toml => toml::from_str(std::str::from_utf8(content)?)?,
+        ConfigFormat::Yaml => serde_yaml::from_slice(content)?,
+    };
+
+    // Variables that aren't Unicode can't name a field and are skipped
+    let vars = std::env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)));
+    let guesses = apply_env_overrides(&mut value, vars)?;
+
+    config_from_overridden_value(value, guesses)
+}
+
+/// The required fields of a config, with the password and message files in
+/// `directory`. Tests add to it.
+#[cfg(test)]
+pub(crate) fn minimal_config_value(
+    chat_log_directory: &Path,
+    directory: &Path,
+) -> serde_json::Value {
+    serde_json::json!({
+        "chat_log_directory": chat_log_directory,
+        "apache_password_file": directory.join("htpasswd"),
+        "custom_message_html_file": directory.join("message.html"),
+        "web_server_port_number": 0,
+    })
+}
+
+/// Deserializes a config, naming the field that failed to deserialize.
+pub fn config_from_value(value: serde_json::Value) -> anyhow::Result<Config> {
+    serde_path_to_error::deserialize(value)
+        .map_err(|error| anyhow::anyhow!("Config field '{}': {}", error.path(), error.inner()))
+}
+
+/// Deserializes a config whose fields were overridden, using the raw string
+/// of a guessed value instead when its field turns out to be a string.
+fn config_from_overridden_value(
+    mut value: serde_json::Value,
+    mut guesses: Vec<(String, String)>,
+) -> anyhow::Result<Config> {
+    loop {
+        let error = match serde_path_to_error::deserialize(value.clone()) {
+            Ok(config) => return Ok(config),
+            Err(error) => error,
+        };
+        let path = error.path().to_string();
+
+        match guesses
+            .iter()
+            .position(|(guess_path, _)| guess_path.replace("__", ".") == path)
+        {
+            Some(index) => {
+                let (guess_path, raw_value) = guesses.remove(index);
+                *field_mut(&mut value, &guess_path)? = serde_json::Value::String(raw_value);
+            }
+            None => anyhow::bail!("Config field '{}': {}", path, error.inner()),
+        }
+    }
+}
+
+/// Sets the fields named by environment variables such as
+/// `IRCLOGGER_VIEWER_WEB_SERVER_PORT_NUMBER`, with `__` between the names of
+/// nested fields, like `IRCLOGGER_VIEWER_SEARCH_DEFAULTS__DAYS`.
+///
+/// A field that is a string in the config stays one. Otherwise values that
+/// parse as JSON, such as numbers, booleans and arrays, are used as such and
+/// anything else is a string. Returns the paths and raw values of fields
+/// missing from the config that were given JSON values, since only the
+/// config's types can tell whether they were meant as strings.
+fn apply_env_overrides<I>(
+    value: &mut serde_json::Value,
+    vars: I,
+) -> anyhow::Result<Vec<(String, String)>>
+where
+    I: Iterator<Item = (String, String)>,
+{
+    let mut guesses = Vec::new();
+
+    for (name, raw_value) in vars {
+        let path = match name.strip_prefix(ENV_PREFIX) {
+            Some(path) if !path.is_empty() => path.to_lowercase(),
+            _ => continue,
+        };
+        let target = field_mut(value, &path).map_err(|_| {
+            anyhow::anyhow!("Environment variable {} names a field inside a value", name)
+        })?;
+
+        *target = if target.is_string() {
+            serde_json::Value::String(raw_value)
+        } else {
+            match serde_json::from_str::<serde_json::Value>(&raw_value) {
+                Ok(parsed) => {
+                    if target.is_null() && !parsed.is_string() {
+                        guesses.push((path, raw_value));
+                    }
+
+                    parsed
+                }
+                Err(_) => serde_json::Value::String(raw_value),
+            }
+        };
+    }
+
+    Ok(guesses)
+}
+
+/// The field at a path of names separated by `__`, added as null when
+/// missing.
+fn field_mut<'a>(
+    value: &'a mut serde_json::Value,
+    path: &str,
+) -> anyhow::Result<&'a mut serde_json::Value> {
+    let mut target = value;
+
+    for key in path.split("__") {
+        if target.is_null() {
+            *target = serde_json::Value::Object(Default::default());
+        }
+
+        target = target
+            .as_object_mut()
+            .ok_or_else(|| anyhow::anyhow!("Field {} is inside a value", path))?
+            .entry(key)
+            .or_insert(serde_json::Value::Null);
+    }
+
+    Ok(target)
+}
+
+/// Reads and parses a config file.
+pub fn read_config(path: &Path, format: ConfigFormat) -> anyhow::Result<Config> {
+    parse_config(&std::fs::read(path)?, format)
+}
+
+/// Values given on the command line that replace those of the config file.
+#[derive(Clone, Default)]
+pub struct ConfigOverrides {
+    pub web_server_port_number: Option<u16>,
+    pub chat_log_directory: Option<PathBuf>,
+    pub apache_password_file: Option<PathBuf>,
+    pub custom_message_html_file: Option<PathBuf>,
+}
+
+impl ConfigOverrides {
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(port) = self.web_server_port_number {
+            config.web_server_port_number = port;
+        }
+
+        if let Some(path) = &self.chat_log_directory {
+            config.chat_log_directory = path.clone();
+        }
+
+        if let Some(path) = &self.apache_password_file {
+            config.apache_password_file = path.clone();
+        }
+
+        if let Some(path) = &self.custom_message_html_file {
+            config.custom_message_html_file = path.clone();
+        }
+    }
+}
+
+/// Where the config came from, so it can be read again the same way.
+#[derive(Clone)]
+pub struct ConfigSource {
+    pub path: PathBuf,
+    pub format: ConfigFormat,
+    pub overrides: ConfigOverrides,
+}
+
+impl ConfigSource {
+    pub fn read(&self) -> anyhow::Result<Config> {
+        let mut config = read_config(&self.path, self.format)?;
+        self.overrides.apply(&mut config);
+
+        Ok(config)
+    }
+}
+
+pub struct ConfigProblem {
+    pub field: String,
+    pub message: String,
+}
+
+impl Display for ConfigProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+impl Config {
+    /// Checks paths and values that deserialized fine but would fail at
+    /// runtime, returning every problem found.
+    pub fn validate(&self) -> Vec<ConfigProblem> {
+        let mut problems = Vec::new();
+
+        check_directory(
+            &mut problems,
+            "chat_log_directory",
+            &self.chat_log_directory,
+        );
+        check_file(
+            &mut problems,
+            "apache_password_file",
+            &self.apache_password_file,
+        );
+        check_file(
+            &mut problems,
+            "custom_message_html_file",
+            &self.custom_message_html_file,
+        );
+
+        if !crate::logging::is_log_level_ok(&self.log_level) {
+            add_problem(&mut problems, "log_level", "is not a valid log filter");
+        }
+
+        if self.web_server_port_number == 0 {
+            add_problem(&mut problems, "web_server_port_number", "must not be 0");
+        }
+
+        if let Some(path) = &self.global_message_html_file {
+            check_file(&mut problems, "global_message_html_file", path);
+        }
+
+        if let Some(path) = &self.theme {
+            check_file(&mut problems, "theme", path);
+        }
+
+        if let Some(path) = &self.static_directory {
+            check_directory(&mut problems, "static_directory", path);
+        }
+
+        if let Some(path) = &self.template_directory {
+            check_directory(&mut problems, "template_directory", path);
+
+            if path.is_dir() {
+                if let Err(error) = PageTemplates::load(path) {
+                    add_problem(&mut problems, "template_directory", &error.to_string());
+                }
+            }
+        }
+
+        if let Some(url) = &self.public_url {
+            check_public_url(&mut problems, "public_url", url);
+        }
+
+        if let Some(prefix) = &self.path_prefix {
+            check_path_prefix(&mut problems, "path_prefix", prefix);
+        }
+
+        for origin in &self.widget_embed_origins {
+            let scheme_ok = origin.starts_with("https://") || origin.starts_with("http://");
+            let host = origin.split_once("://").map_or("", |(_, host)| host);
+
+            if !scheme_ok
+                || host.is_empty()
+                || host
+                    .contains(|c: char| "/;,'\"".contains(c) || c.is_whitespace() || c.is_control())
+            {
+                add_problem(
+                    &mut problems,
+                    "widget_embed_origins",
+                    &format!("{:?} is not an origin like \"https://example.org\"", origin),
+                );
+            }
+        }
+
+        match (&self.tls_certificate_path, &self.tls_key_path) {
+            (Some(certificate_path), Some(key_path)) => {
+                check_file(&mut problems, "tls_certificate_path", certificate_path);
+                check_file(&mut problems, "tls_key_path", key_path);
+            }
+            (Some(_), None) => add_problem(
+                &mut problems,
+                "tls_key_path",
+                "must be set with tls_certificate_path",
+            ),
+            (None, Some(_)) => add_problem(
+                &mut problems,
+                "tls_certificate_path",
+                "must be set with tls_key_path",
+            ),
+            (None, None) => {}
+        }
+
+        if let Some(path) = &self.image_proxy_cache_directory {
+            check_writable_directory(&mut problems, "image_proxy_cache_directory", path);
+        }
+
+        if let Some(path) = &self.data_directory {
+            check_writable_directory(&mut problems, "data_directory", path);
+        }
+
+        if let Some(url) = &self.mirror_upstream_url {
+            if !url.contains("{channel}") || !url.contains("{date}") {
+                add_problem(
+                    &mut problems,
+                    "mirror_upstream_url",
+                    "must contain {channel} and {date} placeholders",
+                );
+            }
+        }
+
+        if let Some(alert) = &self.stale_channel_alert {
+            check_schedule(
+                &mut problems,
+                "stale_channel_alert.schedule",
+                &alert.schedule,
+            );
+
+            if alert.threshold_hours == 0 {
+                add_problem(
+                    &mut problems,
+                    "stale_channel_alert.threshold_hours",
+                    "must be at least 1",
+                );
+            }
+        }
+
+        if let Some(alert) = &self.saved_search_alert {
+            check_schedule(
+                &mut problems,
+                "saved_search_alert.schedule",
+                &alert.schedule,
+            );
+
+            if self.data_directory.is_none() && self.app_database_file.is_none() {
+                add_problem(
+                    &mut problems,
+                    "saved_search_alert",
+                    "needs data_directory or app_database_file to keep saved searches",
+                );
+            }
+        }
+
+        if let Some(digest) = &self.daily_digest {
+            check_schedule(&mut problems, "daily_digest.schedule", &digest.schedule);
+        }
+
+        if let Some(purge) = &self.cache_purge {
+            check_schedule(&mut problems, "cache_purge.schedule", &purge.schedule);
+
+            if reqwest::Url::parse(&purge.cache_url).is_err() {
+                add_problem(&mut problems, "cache_purge.cache_url", "is not a valid URL");
+            }
+        }
+
+        if let Some(search_index) = &self.search_index {
+            check_schedule(
+                &mut problems,
+                "search_index.schedule",
+                &search_index.schedule,
+            );
+
+            if search_index.directory.exists() {
+                check_writable_directory(
+                    &mut problems,
+                    "search_index.directory",
+                    &search_index.directory,
+                );
+            }
+
+            let is_external = matches!(
+                search_index.engine,
+                SearchEngineKind::Meilisearch | SearchEngineKind::Elasticsearch
+            );
+
+            match &search_index.engine_url {
+                Some(url) => check_public_url(&mut problems, "search_index.engine_url", url),
+                None if is_external => add_problem(
+                    &mut problems,
+                    "search_index.engine_url",
+                    "is required for MeiliSearch and Elasticsearch",
+                ),
+                None => {}
+            }
+        }
+
+        if let Some(search_budget) = &self.search_budget {
+            if search_budget.max_bytes == 0 {
+                add_problem(
+                    &mut problems,
+                    "search_budget.max_bytes",
+                    "must be at least 1",
+                );
+            }
+        }
+
+        if self.day_view_page_lines == 0 {
+            add_problem(&mut problems, "day_view_page_lines", "must be at least 1");
+        }
+
+        if crate::locale::parse_locale(&self.locale).is_none() {
+            add_problem(&mut problems, "locale", "is not a known locale");
+        }
+
+        if crate::locale::parse_timezone(&self.timezone).is_none() {
+            add_problem(&mut problems, "timezone", "is not a known timezone");
+        }
+
+        if let Some(path) = &self.day_cache_file {
+            check_database_file(&mut problems, "day_cache_file", path);
+        }
+
+        if let Some(cold_storage) = &self.cold_storage {
+            check_writable_directory(
+                &mut problems,
+                "cold_storage.cache_directory",
+                &cold_storage.cache_directory,
+            );
+
+            if cold_storage.concurrency == 0 {
+                add_problem(
+                    &mut problems,
+                    "cold_storage.concurrency",
+                    "must be at least 1",
+                );
+            }
+        }
+
+        if let Some(path) = &self.access_log_file {
+            check_database_file(&mut problems, "access_log_file", path);
+        }
+
+        if let Some(path) = &self.app_database_file {
+            check_database_file(&mut problems, "app_database_file", path);
+        }
+
+        if let Some(public_stats) = &self.public_stats {
+            if public_stats.noise_secret.len() < 16 {
+                add_problem(
+                    &mut problems,
+                    "public_stats.noise_secret",
+                    "must be at least 16 characters",
+                );
+            }
+
+            if !(public_stats.epsilon > 0.0 && public_stats.epsilon.is_finite()) {
+                add_problem(
+                    &mut problems,
+                    "public_stats.epsilon",
+                    "must be a positive number",
+                );
+            }
+        }
+
+        if let Some(secret) = &self.preferences_secret {
+            if secret.len() < 16 {
+                add_problem(
+                    &mut problems,
+                    "preferences_secret",
+                    "must be at least 16 characters",
+                );
+            }
+        }
+
+        if self.demo_mode && self.demo_rate_limit == 0 {
+            add_problem(&mut problems, "demo_rate_limit", "must not be 0");
+        }
+
+        if let Some(rate_limit) = &self.rate_limit {
+            if rate_limit.requests_per_minute == 0 {
+                add_problem(
+                    &mut problems,
+                    "rate_limit.requests_per_minute",
+                    "must not be 0",
+                );
+            }
+
+            if rate_limit.search_requests_per_minute == Some(0) {
+                add_problem(
+                    &mut problems,
+                    "rate_limit.search_requests_per_minute",
+                    "must not be 0",
+                );
+            }
+        }
+
+        let mut tenant_hosts = self.tenants.keys().collect::<Vec<&String>>();
+        tenant_hosts.sort_unstable();
+
+        for host in tenant_hosts {
+            let tenant = &self.tenants[host];
+            let field = |name: &str| format!("tenants.\"{}\".{}", host, name);
+
+            check_directory(
+                &mut problems,
+                &field("chat_log_directory"),
+                &tenant.chat_log_directory,
+            );
+            check_file(
+                &mut problems,
+                &field("apache_password_file"),
+                &tenant.apache_password_file,
+            );
+            check_file(
+                &mut problems,
+                &field("custom_message_html_file"),
+                &tenant.custom_message_html_file,
+            );
+
+            if let Some(path) = &tenant.theme {
+                check_file(&mut problems, &field("theme"), path);
+            }
+
+            if let Some(url) = &tenant.public_url {
+                check_public_url(&mut problems, &field("public_url"), url);
+            }
+
+            if let Some(prefix) = &tenant.path_prefix {
+                check_path_prefix(&mut problems, &field("path_prefix"), prefix);
+            }
+
+            if let Some(path) = &tenant.data_directory {
+                check_writable_directory(&mut problems, &field("data_directory"), path);
+            }
+        }
+
+        problems
+    }
+}
+
+fn add_problem(problems: &mut Vec<ConfigProblem>, field: &str, message: &str) {
+    problems.push(ConfigProblem {
+        field: field.to_string(),
+        message: message.to_string(),
+    });
+}
+
+fn check_directory(problems: &mut Vec<ConfigProblem>, field: &str, path: &Path) {
+    if !path.is_dir() {
+        add_problem(
+            problems,
+            field,
+            &format!("directory {} does not exist", path.display()),
+        );
+    } else if let Err(error) = std::fs::read_dir(path) {
+        add_problem(
+            problems,
+            field,
+            &format!("directory {} is not readable: {}", path.display(), error),
+        );
+    }
+}
+
+fn check_file(problems: &mut Vec<ConfigProblem>, field: &str, path: &Path) {
+    if !path.is_file() {
+        add_problem(
+            problems,
+            field,
+            &format!("file {} does not exist", path.display()),
+        );
+    } else if let Err(error) = std::fs::File::open(path) {
+        add_problem(
+            problems,
+            field,
+            &format!("file {} is not readable: {}", path.display(), error),
+        );
+    }
+}
+
+/// Public URLs are joined with paths starting with `/`, so they can't end
+/// with one.
+fn check_public_url(problems: &mut Vec<ConfigProblem>, field: &str, url: &str) {
+    let scheme_ok = url.starts_with("https://") || url.starts_with("http://");
+    let rest = url.split_once("://").map_or("", |(_, rest)| rest);
+
+    if !scheme_ok
+        || rest.is_empty()
+        || rest.starts_with('/')
+        || rest.ends_with('/')
+        || rest.contains(|c: char| "?#".contains(c) || c.is_whitespace() || c.is_control())
+    {
+        add_problem(
+            problems,
+            field,
+            &format!(
+                "{:?} is not a URL like \"https://example.org\" or \"https://example.org/irc\"",
+                url
+            ),
+        );
+    }
+}
+
+/// Path prefixes are joined with paths starting with `/` too.
+fn check_path_prefix(problems: &mut Vec<ConfigProblem>, field: &str, prefix: &str) {
+    if !prefix.starts_with('/')
+        || prefix.ends_with('/')
+        || prefix.contains(|c: char| "?#".contains(c) || c.is_whitespace() || c.is_control())
+    {
+        add_problem(
+            problems,
+            field,
+            &format!("{:?} is not a path like \"/irc\"", prefix),
+        );
+    }
+}
+
+/// The path that site links start with: the configured prefix, or else the
+/// path of the public URL, which is empty at the root of a host.
+pub fn site_path_prefix(path_prefix: Option<&str>, public_url: Option<&str>) -> String {
+    if let Some(prefix) = path_prefix {
+        return prefix.to_string();
+    }
+
+    public_url
+        .and_then(|url| url.split_once("://"))
+        .and_then(|(_, rest)| rest.find('/').map(|index| rest[index..].to_string()))
+        .unwrap_or_default()
+}
+
+/// Writable directories are created on demand, so a missing one only needs
+/// an existing parent.
+fn check_writable_directory(problems: &mut Vec<ConfigProblem>, field: &str, path: &Path) {
+    let existing = if path.exists() {
+        path
+    } else {
+        match path.parent() {
+            Some(parent) if parent.as_os_str().is_empty() => Path::new("."),
+            Some(parent) => parent,
+            None => path,
+        }
+    };
+
+    match std::fs::metadata(existing) {
+        Ok(metadata) if !metadata.is_dir() => add_problem(
+            problems,
+            field,
+            &format!("{} is not a directory", existing.display()),
+        ),
+        Ok(metadata) if metadata.permissions().readonly() => add_problem(
+            problems,
+            field,
+            &format!("directory {} is not writable", existing.display()),
+        ),
+        Ok(_) => {}
+        Err(error) => add_problem(
+            problems,
+            field,
+            &format!("directory {} is not usable: {}", existing.display(), error),
+        ),
+    }
+}
+
+fn check_database_file(problems: &mut Vec<ConfigProblem>, field: &str, path: &Path) {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => {
+            check_writable_directory(problems, field, parent)
+        }
+        _ => check_writable_directory(problems, field, Path::new(".")),
+    }
+}
+
+fn check_schedule(problems: &mut Vec<ConfigProblem>, field: &str, schedule: &str) {
+    if let Err(error) = Schedule::parse(schedule) {
+        add_problem(problems, field, &error.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_env_overrides, config_from_overridden_value, site_path_prefix};
+
+    #[test]
+    fn env_vars_override_fields() {
+        let mut value = serde_json::json!({
+            "chat_log_directory": "log/",
+            "web_server_port_number": 8033,
+        });
+        let vars = vec![
+            ("IRCLOGGER_VIEWER_CHAT_LOG_DIRECTORY", "/srv/logs"),
+            ("IRCLOGGER_VIEWER_WEB_SERVER_PORT_NUMBER", "8080"),
+            ("IRCLOGGER_VIEWER_SEARCH_DEFAULTS__DAYS", "30"),
+            ("IRCLOGGER_VIEWER_ADMIN_USERS", r#"["alice"]"#),
+            ("HOME", "/root"),
+        ];
+
+        apply_env_overrides(
+            &mut value,
+            vars.into_iter()
+                .map(|(name, value)| (name.to_string(), value.to_string())),
+        )
+        .unwrap();
+
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "chat_log_directory": "/srv/logs",
+                "web_server_port_number": 8080,
+                "search_defaults": { "days": 30 },
+                "admin_users": ["alice"],
+            })
+        );
+    }
+
+    #[test]
+    fn env_vars_keep_strings_that_look_like_numbers() {
+        let mut value = serde_json::json!({
+            "chat_log_directory": "log/",
+            "web_server_port_number": 8033,
+            "apache_password_file": "htpasswd",
+            "custom_message_html_file": "message.html",
+            "public_url": "https://example.org",
+        });
+        let vars = vec![
+            ("IRCLOGGER_VIEWER_PUBLIC_URL", "2021"),
+            ("IRCLOGGER_VIEWER_PREFERENCES_SECRET", "12345"),
+            ("IRCLOGGER_VIEWER_SEARCH_DEFAULTS__DAYS", "30"),
+        ];
+
+        let guesses = apply_env_overrides(
+            &mut value,
+            vars.into_iter()
+                .map(|(name, value)| (name.to_string(), value.to_string())),
+        )
+        .unwrap();
+        let config = config_from_overridden_value(value, guesses).unwrap();
+
+        assert_eq!(config.public_url.as_deref(), Some("2021"));
+        assert_eq!(config.preferences_secret.as_deref(), Some("12345"));
+        assert_eq!(config.search_defaults.days, Some(30));
+    }
+
+    #[test]
+    fn path_prefix_defaults_to_public_url_path() {
+        assert_eq!(site_path_prefix(None, None), "");
+        assert_eq!(site_path_prefix(None, Some("https://example.org")), "");
+        assert_eq!(
+            site_path_prefix(None, Some("https://example.org/irc")),
+            "/irc"
+        );
+        assert_eq!(
+            site_path_prefix(Some("/logs"), Some("https://example.org/irc")),
+            "/logs"
+        );
+    }
+
+    #[test]
+    fn env_vars_cannot_reach_inside_values() {
+        let mut value = serde_json::json!({ "web_server_port_number": 8033 });
+        let vars = vec![(
+            "IRCLOGGER_VIEWER_WEB_SERVER_PORT_NUMBER__X".to_string(),
+            "1".to_string(),
+        )];
+
+        assert!(apply_env_overrides(&mut value, vars.into_iter()).is_err());
+    }
 }
@@ -0,0 +1,127 @@
+use std::{collections::BTreeMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::day_cache::DaySummary;
+
+pub const COUNT_FILE_NAME: &str = "counts.json";
+
+/// Day summaries of a channel kept in a JSON file in its log directory, for
+/// sites without a day cache database. Like the day cache, entries are keyed
+/// by the log file's ETag, so only new and changed days are read again.
+#[derive(Default, Serialize, Deserialize)]
+pub struct CountFile {
+    days: BTreeMap<String, CountFileEntry>, // Date slug to entry
+    #[serde(skip)]
+    changed: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CountFileEntry {
+    etag: String,
+    count_status_lines: bool,
+    message_count: u64,
+    first_message: Option<String>,
+    last_message: Option<String>,
+}
+
+impl CountFile {
+    /// Reads the file, starting over if it's missing or can't be read.
+    pub fn load(path: &Path) -> Self {
+        std::fs::read(path)
+            .ok()
+            .and_then(|content| serde_json::from_slice(&content).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn get(&self, date_slug: &str, etag: &str, count_status_lines: bool) -> Option<DaySummary> {
+        self.days
+            .get(date_slug)
+            .filter(|entry| entry.etag == etag && entry.count_status_lines == count_status_lines)
+            .map(|entry| DaySummary {
+                message_count: entry.message_count,
+                first_message: entry.first_message.clone(),
+                last_message: entry.last_message.clone(),
+            })
+    }
+
+    pub fn insert(
+        &mut self,
+        date_slug: &str,
+        etag: &str,
+        count_status_lines: bool,
+        summary: &DaySummary,
+    ) {
+        self.days.insert(
+            date_slug.to_string(),
+            CountFileEntry {
+                etag: etag.to_string(),
+                count_status_lines,
+                message_count: summary.message_count,
+                first_message: summary.first_message.clone(),
+                last_message: summary.last_message.clone(),
+            },
+        );
+        self.changed = true;
+    }
+
+    /// Drops the entry of a day so it's summarized again.
+    pub fn remove_day(&mut self, date_slug: &str) {
+        self.changed |= self.days.remove(date_slug).is_some();
+    }
+
+    /// Drops the entries of days whose logs are gone.
+    pub fn retain_days(&mut self, date_slugs: &[String]) {
+        let count = self.days.len();
+        self.days
+            .retain(|date_slug, _| date_slugs.contains(date_slug));
+        self.changed |= self.days.len() != count;
+    }
+
+    /// Writes the file if any entry changed since it was loaded, through a
+    /// temporary file so it's never read half written.
+    pub fn save(&mut self, path: &Path) -> anyhow::Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+
+        let temp_path = path.with_extension("json.tmp");
+        std::fs::write(&temp_path, serde_json::to_vec(self)?)?;
+        std::fs::rename(temp_path, path)?;
+        self.changed = false;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CountFile;
+    use crate::day_cache::DaySummary;
+
+    #[test]
+    fn keeps_summaries_until_the_log_changes() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("counts.json");
+        let summary = DaySummary {
+            message_count: 3,
+            first_message: Some("<alice> hello".to_string()),
+            last_message: None,
+        };
+
+        let mut count_file = CountFile::load(&path);
+        count_file.insert("2021-01-04,Mon", "\"1-2.3\"", false, &summary);
+        count_file.insert("2021-01-05,Tue", "\"4-5.6\"", false, &summary);
+        count_file.retain_days(&["2021-01-04,Mon".to_string()]);
+        count_file.save(&path).unwrap();
+
+        let count_file = CountFile::load(&path);
+        assert_eq!(
+            count_file.get("2021-01-04,Mon", "\"1-2.3\"", false),
+            Some(summary)
+        );
+        assert_eq!(count_file.get("2021-01-04,Mon", "\"1-2.4\"", false), None);
+        assert_eq!(count_file.get("2021-01-04,Mon", "\"1-2.3\"", true), None);
+        assert_eq!(count_file.get("2021-01-05,Tue", "\"4-5.6\"", false), None);
+    }
+}
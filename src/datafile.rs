@@ -0,0 +1,25 @@
+use std::path::Path;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Reads a JSON data file, returning the default value if it doesn't exist.
+pub fn read_json<T: DeserializeOwned + Default>(path: &Path) -> anyhow::Result<T> {
+    match std::fs::read(path) {
+        Ok(content) => Ok(serde_json::from_slice(&content)?),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(T::default()),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Writes a JSON data file by replacing it so readers never see partial content.
+pub fn write_json<T: Serialize>(path: &Path, value: &T) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let temp_path = path.with_extension("json.tmp");
+    std::fs::write(&temp_path, serde_json::to_vec_pretty(value)?)?;
+    std::fs::rename(&temp_path, path)?;
+
+    Ok(())
+}
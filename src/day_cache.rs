@@ -0,0 +1,236 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+/// What the channel index shows about a day.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DaySummary {
+    pub message_count: u64,
+    pub first_message: Option<String>,
+    pub last_message: Option<String>,
+}
+
+/// Caches each day's summary and messages per nickname in SQLite so the
+/// channel index and nick cards don't reread every log file. Entries are keyed by the log file's ETag, so a day is
+/// summarized again once its file changes.
+#[derive(Clone)]
+pub struct DayCache {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl DayCache {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let connection = Connection::open(path)?;
+
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS day_summaries (
+                channel TEXT NOT NULL,
+                date_slug TEXT NOT NULL,
+                etag TEXT NOT NULL,
+                count_status_lines INTEGER NOT NULL,
+                message_count INTEGER NOT NULL,
+                first_message TEXT,
+                last_message TEXT,
+                PRIMARY KEY (channel, date_slug)
+            );
+            CREATE TABLE IF NOT EXISTS day_nick_counts (
+                channel TEXT NOT NULL,
+                date_slug TEXT NOT NULL,
+                etag TEXT NOT NULL,
+                counts TEXT NOT NULL,
+                PRIMARY KEY (channel, date_slug)
+            );",
+        )?;
+
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    pub fn get(
+        &self,
+        channel: &str,
+        date_slug: &str,
+        etag: &str,
+        count_status_lines: bool,
+    ) -> anyhow::Result<Option<DaySummary>> {
+        let connection = self.connection.lock().unwrap();
+        let summary = connection
+            .query_row(
+                "SELECT message_count, first_message, last_message FROM day_summaries
+                WHERE channel = ?1 AND date_slug = ?2 AND etag = ?3 AND count_status_lines = ?4",
+                params![channel, date_slug, etag, count_status_lines],
+                |row| {
+                    Ok(DaySummary {
+                        message_count: row.get::<_, i64>(0)? as u64,
+                        first_message: row.get(1)?,
+                        last_message: row.get(2)?,
+                    })
+                },
+            )
+            .optional()?;
+
+        Ok(summary)
+    }
+
+    pub fn insert(
+        &self,
+        channel: &str,
+        date_slug: &str,
+        etag: &str,
+        count_status_lines: bool,
+        summary: &DaySummary,
+    ) -> anyhow::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "INSERT OR REPLACE INTO day_summaries
+            (channel, date_slug, etag, count_status_lines, message_count, first_message, last_message)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                channel,
+                date_slug,
+                etag,
+                count_status_lines,
+                summary.message_count as i64,
+                summary.first_message,
+                summary.last_message,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Drops a day's summary and nickname counts so they're made again.
+    pub fn remove_day(&self, channel: &str, date_slug: &str) -> anyhow::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "DELETE FROM day_summaries WHERE channel = ?1 AND date_slug = ?2",
+            params![channel, date_slug],
+        )?;
+        connection.execute(
+            "DELETE FROM day_nick_counts WHERE channel = ?1 AND date_slug = ?2",
+            params![channel, date_slug],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_nick_counts(
+        &self,
+        channel: &str,
+        date_slug: &str,
+        etag: &str,
+    ) -> anyhow::Result<Option<HashMap<String, u64>>> {
+        let connection = self.connection.lock().unwrap();
+        let counts = connection
+            .query_row(
+                "SELECT counts FROM day_nick_counts
+                WHERE channel = ?1 AND date_slug = ?2 AND etag = ?3",
+                params![channel, date_slug, etag],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()?;
+
+        match counts {
+            Some(counts) => Ok(Some(serde_json::from_str(&counts)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn insert_nick_counts(
+        &self,
+        channel: &str,
+        date_slug: &str,
+        etag: &str,
+        counts: &HashMap<String, u64>,
+    ) -> anyhow::Result<()> {
+        let counts = serde_json::to_string(counts)?;
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "INSERT OR REPLACE INTO day_nick_counts (channel, date_slug, etag, counts)
+            VALUES (?1, ?2, ?3, ?4)",
+            params![channel, date_slug, etag, counts],
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{DayCache, DaySummary};
+
+    #[test]
+    fn summaries_are_keyed_by_etag() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache = DayCache::open(&directory.path().join("days.sqlite3")).unwrap();
+        let summary = DaySummary {
+            message_count: 3,
+            first_message: Some("<alice> hello".to_string()),
+            last_message: None,
+        };
+
+        cache
+            .insert("archiveteam", "2021-01-04,Mon", "\"a\"", false, &summary)
+            .unwrap();
+
+        assert_eq!(
+            cache
+                .get("archiveteam", "2021-01-04,Mon", "\"a\"", false)
+                .unwrap(),
+            Some(summary)
+        );
+        assert_eq!(
+            cache
+                .get("archiveteam", "2021-01-04,Mon", "\"b\"", false)
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            cache
+                .get("archiveteam", "2021-01-04,Mon", "\"a\"", true)
+                .unwrap(),
+            None
+        );
+
+        cache.remove_day("archiveteam", "2021-01-04,Mon").unwrap();
+        assert_eq!(
+            cache
+                .get("archiveteam", "2021-01-04,Mon", "\"a\"", false)
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn nick_counts_are_keyed_by_etag() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache = DayCache::open(&directory.path().join("days.sqlite3")).unwrap();
+        let mut counts = HashMap::new();
+        counts.insert("alice".to_string(), 2);
+        counts.insert("[bob]".to_string(), 1);
+
+        cache
+            .insert_nick_counts("archiveteam", "2021-01-04,Mon", "\"a\"", &counts)
+            .unwrap();
+
+        assert_eq!(
+            cache
+                .get_nick_counts("archiveteam", "2021-01-04,Mon", "\"a\"")
+                .unwrap(),
+            Some(counts)
+        );
+        assert_eq!(
+            cache
+                .get_nick_counts("archiveteam", "2021-01-04,Mon", "\"b\"")
+                .unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use chrono::{Date, Utc};
+use serde::Deserialize;
+
+use crate::{
+    notify::{Notification, NotificationConfig},
+    preview::extract_urls,
+    reader::{LogLine, LogLineContent},
+    state::AppState,
+};
+
+const TOP_NICKS: usize = 10;
+const TOP_LINKS: usize = 5;
+
+#[derive(Clone, Deserialize)]
+pub struct DailyDigestConfig {
+    pub channels: Vec<String>,
+    #[serde(default = "default_schedule")]
+    pub schedule: String, // Cron-like schedule (UTC) for sending the previous day's digest
+    pub notification: NotificationConfig,
+}
+
+fn default_schedule() -> String {
+    "0 1 * * *".to_string()
+}
+
+pub struct DailyDigest {
+    pub channel: String,
+    pub date_slug: String,
+    pub date: Date<Utc>,
+    pub message_count: u64,
+    pub active_nicks: Vec<(String, u64)>,
+    pub top_links: Vec<(String, u64)>,
+    pub first_line: Option<String>,
+    pub last_line: Option<String>,
+}
+
+impl DailyDigest {
+    pub fn build(app_state: &AppState, channel: &str, date_slug: &str) -> anyhow::Result<Self> {
+        let date = crate::state::parse_date_slug(date_slug)?;
+        let lines = app_state.get_log_lines(channel, date_slug)?;
+        let mut message_count = 0;
+        let mut nick_counts = HashMap::new();
+        let mut link_counts = HashMap::new();
+
+        for line in &lines {
+            if let LogLineContent::Message { nickname, text } = &line.content {
+                message_count += 1;
+                *nick_counts.entry(nickname.clone()).or_insert(0) += 1;
+
+                for url in extract_urls(text) {
+                    *link_counts.entry(url.to_string()).or_insert(0) += 1;
+                }
+            }
+        }
+
+        let mut messages = lines
+            .iter()
+            .filter(|line| matches!(line.content, LogLineContent::Message { .. }));
+
+        Ok(Self {
+            channel: channel.to_string(),
+            date_slug: date_slug.to_string(),
+            date,
+            message_count,
+            active_nicks: top_entries(nick_counts, TOP_NICKS),
+            top_links: top_entries(link_counts, TOP_LINKS),
+            first_line: messages.clone().next().map(format_line),
+            last_line: messages.next_back().map(format_line),
+        })
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "#{} on {}: {} message(s)\n",
+            self.channel,
+            self.date.format("%Y-%m-%d"),
+            self.message_count
+        );
+
+        if !self.active_nicks.is_empty() {
+            let nicks = self
+                .active_nicks
+                .iter()
+                .map(|(nick, count)| format!("{} ({})", nick, count))
+                .collect::<Vec<String>>()
+                .join(", ");
+            text.push_str(&format!("\nMost active: {}\n", nicks));
+        }
+
+        if !self.top_links.is_empty() {
+            text.push_str("\nTop links:\n");
+
+            for (url, count) in &self.top_links {
+                text.push_str(&format!("  {} ({})\n", url, count));
+            }
+        }
+
+        if let Some(line) = &self.first_line {
+            text.push_str(&format!("\nFirst message: {}\n", line));
+        }
+
+        if let Some(line) = &self.last_line {
+            text.push_str(&format!("Last message: {}\n", line));
+        }
+
+        text
+    }
+}
+
+fn top_entries(counts: HashMap<String, u64>, limit: usize) -> Vec<(String, u64)> {
+    let mut entries = counts.into_iter().collect::<Vec<(String, u64)>>();
+    entries.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    entries.truncate(limit);
+
+    entries
+}
+
+fn format_line(line: &LogLine) -> String {
+    match &line.content {
+        LogLineContent::Message { nickname, text } => {
+            format!("[{}] <{}> {}", line.date.format("%H:%M"), nickname, text)
+        }
+        LogLineContent::Status(text) => format!("[{}] *** {}", line.date.format("%H:%M"), text),
+    }
+}
+
+pub async fn send_daily_digests(
+    app_state: &AppState,
+    config: &DailyDigestConfig,
+) -> anyhow::Result<()> {
+    let yesterday = Utc::today().pred();
+
+    for channel in &config.channels {
+        let date_slug = match app_state.find_date_slug(channel, yesterday)? {
+            Some(date_slug) => date_slug,
+            None => continue,
+        };
+        let digest = DailyDigest::build(app_state, channel, &date_slug)?;
+        let notification = Notification {
+            subject: format!("#{} digest for {}", channel, yesterday.format("%Y-%m-%d")),
+            text: digest.to_text(),
+        };
+
+        crate::notify::send(&config.notification, &notification).await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,133 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_db::AppDatabase,
+    datafile::{read_json, write_json},
+};
+
+pub const MAX_REASON_LENGTH: usize = 1000;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HiddenChannel {
+    pub channel: String,
+    pub reason: String, // Shown on the channel's pages
+    pub hidden_by: String,
+    pub hidden_at: DateTime<Utc>,
+}
+
+/// Channels an admin hid without deleting their files, such as while a
+/// takedown request is decided. Stored in a single JSON file, or in the app
+/// database if there is one.
+#[derive(Clone)]
+pub struct HiddenChannelStore {
+    path: Option<PathBuf>,
+    database: Option<AppDatabase>,
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl HiddenChannelStore {
+    pub fn new(data_directory: Option<PathBuf>, database: Option<AppDatabase>) -> Self {
+        Self {
+            path: data_directory.map(|directory| directory.join("hidden_channels.json")),
+            database,
+            write_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.path.is_some() || self.database.is_some()
+    }
+
+    pub fn get_hidden_channels(&self) -> anyhow::Result<Vec<HiddenChannel>> {
+        if let Some(database) = &self.database {
+            return database.get_hidden_channels();
+        }
+
+        match &self.path {
+            Some(path) => read_json(path),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub fn get_hidden_channel(&self, channel: &str) -> anyhow::Result<Option<HiddenChannel>> {
+        Ok(self
+            .get_hidden_channels()?
+            .into_iter()
+            .find(|hidden| hidden.channel == channel))
+    }
+
+    /// Hides a channel, replacing the reason if it's already hidden.
+    pub fn hide_channel(&self, hidden: HiddenChannel) -> anyhow::Result<()> {
+        if let Some(database) = &self.database {
+            return database.hide_channel(&hidden);
+        }
+
+        self.update(|channels| {
+            channels.retain(|existing| existing.channel != hidden.channel);
+            channels.push(hidden);
+        })
+    }
+
+    pub fn restore_channel(&self, channel: &str) -> anyhow::Result<()> {
+        if let Some(database) = &self.database {
+            return database.restore_channel(channel);
+        }
+
+        self.update(|channels| channels.retain(|existing| existing.channel != channel))
+    }
+
+    fn update<F>(&self, func: F) -> anyhow::Result<()>
+    where
+        F: FnOnce(&mut Vec<HiddenChannel>),
+    {
+        let path = self
+            .path
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Hiding channels is disabled"))?;
+        let _guard = self.write_lock.lock().unwrap();
+
+        let mut channels: Vec<HiddenChannel> = read_json(path)?;
+        func(&mut channels);
+
+        write_json(path, &channels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::{HiddenChannel, HiddenChannelStore};
+
+    fn hidden(reason: &str) -> HiddenChannel {
+        HiddenChannel {
+            channel: "archiveteam".to_string(),
+            reason: reason.to_string(),
+            hidden_by: "admin".to_string(),
+            hidden_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn hides_and_restores_channels() {
+        let directory = tempfile::tempdir().unwrap();
+        let store = HiddenChannelStore::new(Some(directory.path().to_path_buf()), None);
+
+        store.hide_channel(hidden("takedown request")).unwrap();
+        store.hide_channel(hidden("still deciding")).unwrap();
+
+        let channels = store.get_hidden_channels().unwrap();
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].reason, "still deciding");
+        assert!(store.get_hidden_channel("archivebot").unwrap().is_none());
+
+        store.restore_channel("archiveteam").unwrap();
+        assert!(store.get_hidden_channel("archiveteam").unwrap().is_none());
+    }
+}
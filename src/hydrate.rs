@@ -0,0 +1,144 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use serde::Deserialize;
+use tokio::sync::Semaphore;
+
+#[derive(Clone, Deserialize)]
+pub struct ColdStorageConfig {
+    pub cache_directory: PathBuf, // Writable local directory for copies of logs
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize, // Logs copied at once
+}
+
+fn default_concurrency() -> usize {
+    4
+}
+
+/// Local copies of logs kept on slow or network storage.
+///
+/// A finished day that hasn't been copied yet is copied in the background,
+/// a limited number at a time, while its page asks the reader to wait.
+/// Copies are used until the log is changed.
+#[derive(Clone)]
+pub struct LogHydrator {
+    cache_directory: PathBuf,
+    permits: Arc<Semaphore>,
+    copying: Arc<Mutex<HashSet<PathBuf>>>, // Logs being copied
+}
+
+impl LogHydrator {
+    pub fn new(config: &ColdStorageConfig) -> Self {
+        Self {
+            cache_directory: config.cache_directory.clone(),
+            permits: Arc::new(Semaphore::new(config.concurrency.max(1))),
+            copying: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    /// Returns the local copy of a log if it's as new as the log.
+    pub fn get_copy(&self, channel: &str, log_path: &Path) -> Option<PathBuf> {
+        let copy_path = self.copy_path(channel, log_path)?;
+        let log_metadata = std::fs::metadata(log_path).ok()?;
+        let copy_metadata = std::fs::metadata(&copy_path).ok()?;
+        let is_fresh = copy_metadata.len() == log_metadata.len()
+            && match (copy_metadata.modified(), log_metadata.modified()) {
+                (Ok(copy_modified), Ok(log_modified)) => copy_modified >= log_modified,
+                _ => false,
+            };
+
+        Some(copy_path).filter(|_| is_fresh)
+    }
+
+    /// Starts copying a log unless it's already being copied.
+    pub fn start_copy(&self, channel: &str, log_path: &Path) {
+        let copy_path = match self.copy_path(channel, log_path) {
+            Some(copy_path) => copy_path,
+            None => return,
+        };
+
+        if !self.copying.lock().unwrap().insert(log_path.to_path_buf()) {
+            return;
+        }
+
+        let log_path = log_path.to_path_buf();
+        let permits = self.permits.clone();
+        let copying = self.copying.clone();
+
+        tokio::spawn(async move {
+            let _permit = permits.acquire().await;
+
+            if let Err(error) = copy_log(&log_path, &copy_path).await {
+                tracing::warn!("Couldn't copy {}: {:#}", log_path.display(), error);
+            }
+
+            copying.lock().unwrap().remove(&log_path);
+        });
+    }
+
+    fn copy_path(&self, channel: &str, log_path: &Path) -> Option<PathBuf> {
+        Some(
+            self.cache_directory
+                .join(channel)
+                .join(log_path.file_name()?),
+        )
+    }
+}
+
+/// Copies through a temporary file, so a copy is never read half written.
+async fn copy_log(log_path: &Path, copy_path: &Path) -> anyhow::Result<()> {
+    let temp_path = copy_path.with_extension("tmp");
+
+    if let Some(parent) = copy_path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    tokio::fs::copy(log_path, &temp_path).await?;
+    tokio::fs::rename(temp_path, copy_path).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{ColdStorageConfig, LogHydrator};
+
+    #[tokio::test]
+    async fn copies_logs_in_background() {
+        let directory = tempfile::tempdir().unwrap();
+        let log_path = directory.path().join("2021-01-04,Mon.log");
+        std::fs::write(&log_path, "[00:01] <alice> hello\n").unwrap();
+
+        let hydrator = LogHydrator::new(&ColdStorageConfig {
+            cache_directory: directory.path().join("cache"),
+            concurrency: 1,
+        });
+
+        assert_eq!(hydrator.get_copy("channel", &log_path), None);
+
+        hydrator.start_copy("channel", &log_path);
+
+        for _ in 0..100 {
+            if hydrator.get_copy("channel", &log_path).is_some() {
+                break;
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let copy_path = hydrator.get_copy("channel", &log_path).unwrap();
+        assert_eq!(
+            copy_path,
+            directory.path().join("cache/channel/2021-01-04,Mon.log")
+        );
+        assert_eq!(
+            std::fs::read(copy_path).unwrap(),
+            b"[00:01] <alice> hello\n"
+        );
+    }
+}
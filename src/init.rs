@@ -0,0 +1,104 @@
+use std::{
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+const EXAMPLE_MESSAGE: &str = "<p>These are the logs of our IRC channels.</p>\n\
+<p>Password-protected channels use the channel name as the username.</p>\n";
+
+pub struct InitOptions {
+    pub directory: PathBuf,
+    pub port: u16,
+    pub interactive: bool,
+    pub force: bool, // Overwrite existing files
+}
+
+/// Creates a config file, log directory, empty password file and example
+/// custom message so a new install can be started right away.
+pub fn run_init(mut options: InitOptions) -> anyhow::Result<()> {
+    if options.interactive {
+        let stdin = std::io::stdin();
+        let mut input = stdin.lock();
+
+        let directory = prompt(
+            &mut input,
+            "Directory for config and logs",
+            &options.directory.display().to_string(),
+        )?;
+        options.directory = PathBuf::from(directory);
+
+        let port = prompt(&mut input, "Web server port", &options.port.to_string())?;
+        options.port = port
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid port number: {}", port))?;
+    }
+
+    let directory = &options.directory;
+    let log_directory = directory.join("log");
+    let config_path = directory.join("config.json");
+    let password_path = directory.join("passwords");
+    let message_path = directory.join("message.html");
+
+    std::fs::create_dir_all(&log_directory)?;
+    std::fs::create_dir_all(directory.join("data"))?;
+
+    let config = serde_json::json!({
+        "chat_log_directory": "log/",
+        "apache_password_file": "passwords",
+        "custom_message_html_file": "message.html",
+        "web_server_port_number": options.port,
+        "data_directory": "data/",
+    });
+
+    write_new_file(
+        &config_path,
+        &serde_json::to_vec_pretty(&config)?,
+        options.force,
+    )?;
+    write_new_file(&password_path, b"", options.force)?;
+    write_new_file(&message_path, EXAMPLE_MESSAGE.as_bytes(), options.force)?;
+
+    println!("Created {}", directory.display());
+    println!();
+    println!(
+        "Put each channel's logs in {}/<channel>/<date slug>.log",
+        log_directory.display()
+    );
+    println!(
+        "Protect a channel with: htpasswd -B {} <channel>",
+        password_path.display()
+    );
+    println!(
+        "Start the server from {} with: irclogger-viewer config.json",
+        directory.display()
+    );
+
+    Ok(())
+}
+
+fn prompt<R: BufRead>(input: &mut R, question: &str, default: &str) -> anyhow::Result<String> {
+    print!("{} [{}]: ", question, default);
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    let answer = answer.trim();
+
+    if answer.is_empty() {
+        Ok(default.to_string())
+    } else {
+        Ok(answer.to_string())
+    }
+}
+
+fn write_new_file(path: &Path, content: &[u8], force: bool) -> anyhow::Result<()> {
+    if path.exists() && !force {
+        println!("Keeping existing {}", path.display());
+        return Ok(());
+    }
+
+    std::fs::write(path, content)?;
+    println!("Wrote {}", path.display());
+
+    Ok(())
+}
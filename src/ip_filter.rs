@@ -0,0 +1,103 @@
+use std::{net::IpAddr, pin::Pin, sync::Arc};
+
+use futures_util::FutureExt;
+use gotham::{
+    handler::HandlerFuture,
+    helpers::http::response::create_empty_response,
+    hyper::StatusCode,
+    middleware::{Middleware, NewMiddleware},
+    state::State,
+};
+
+use crate::ratelimit::IpNetwork;
+
+/// Refuses clients by address with `403 Forbidden` before their request
+/// reaches a handler. A client in the deny list is refused, and with an
+/// allow list, so is every client not in it.
+///
+/// Addresses are those found by [`crate::ratelimit::client_ip`], so it goes
+/// after the middleware that puts the `AppState` with the trusted proxies.
+#[derive(Clone, Default)]
+pub struct IpFilterMiddleware {
+    allow_list: Arc<Vec<IpNetwork>>,
+    deny_list: Arc<Vec<IpNetwork>>,
+}
+
+impl IpFilterMiddleware {
+    pub fn new(allow_list: Vec<IpNetwork>, deny_list: Vec<IpNetwork>) -> Self {
+        Self {
+            allow_list: Arc::new(allow_list),
+            deny_list: Arc::new(deny_list),
+        }
+    }
+
+    fn is_allowed(&self, client: Option<IpAddr>) -> bool {
+        let is_listed = |list: &[IpNetwork]| {
+            client.is_some_and(|client| list.iter().any(|network| network.contains(client)))
+        };
+
+        !is_listed(&self.deny_list) && (self.allow_list.is_empty() || is_listed(&self.allow_list))
+    }
+}
+
+impl Middleware for IpFilterMiddleware {
+    fn call<Chain>(self, state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>> + Send + 'static,
+    {
+        if self.allow_list.is_empty() && self.deny_list.is_empty() {
+            return chain(state);
+        }
+
+        if self.is_allowed(crate::ratelimit::client_ip(&state)) {
+            return chain(state);
+        }
+
+        let response = create_empty_response(&state, StatusCode::FORBIDDEN);
+
+        async move { Ok((state, response)) }.boxed()
+    }
+}
+
+impl NewMiddleware for IpFilterMiddleware {
+    type Instance = Self;
+
+    fn new_middleware(&self) -> anyhow::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::IpFilterMiddleware;
+    use crate::ratelimit::IpNetwork;
+
+    fn networks(values: &[&str]) -> Vec<IpNetwork> {
+        values
+            .iter()
+            .map(|value| IpNetwork::try_from(value.to_string()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn deny_list_wins_over_allow_list() {
+        let filter = IpFilterMiddleware::new(
+            networks(&["10.0.0.0/8", "fd00::/8"]),
+            networks(&["10.6.6.0/24"]),
+        );
+
+        assert!(filter.is_allowed(Some("10.1.2.3".parse().unwrap())));
+        assert!(filter.is_allowed(Some("fd12::1".parse().unwrap())));
+        assert!(!filter.is_allowed(Some("10.6.6.6".parse().unwrap())));
+        assert!(!filter.is_allowed(Some("192.0.2.1".parse().unwrap())));
+        assert!(!filter.is_allowed(None));
+
+        let filter = IpFilterMiddleware::new(Vec::new(), networks(&["192.0.2.0/24"]));
+
+        assert!(!filter.is_allowed(Some("192.0.2.1".parse().unwrap())));
+        assert!(filter.is_allowed(Some("198.51.100.1".parse().unwrap())));
+        assert!(filter.is_allowed(None));
+    }
+}
@@ -0,0 +1,51 @@
+pub mod access_audit;
+pub mod access_log;
+pub mod annotation;
+pub mod app;
+pub mod app_db;
+pub mod assets;
+pub mod backup;
+pub mod badge;
+pub mod bookmark;
+pub mod browse;
+pub mod cache;
+pub mod config;
+pub mod count_file;
+pub mod cursor;
+pub mod datafile;
+pub mod day_cache;
+pub mod digest;
+pub mod hidden_channel;
+pub mod hydrate;
+pub mod init;
+pub mod ip_filter;
+pub mod live;
+pub mod locale;
+pub mod logging;
+pub mod message;
+pub mod metadata;
+pub mod mirror;
+pub mod monitor;
+pub mod notify;
+pub mod page_template;
+pub mod parse_report;
+pub mod preferences;
+pub mod preview;
+pub mod proxy;
+pub mod purge;
+pub mod ratelimit;
+pub mod reader;
+pub mod route;
+pub mod scheduler;
+pub mod script_parser;
+pub mod search;
+pub mod search_engine;
+pub mod search_history;
+pub mod search_index;
+pub mod selftest;
+pub mod state;
+pub mod stats;
+pub mod tenant;
+pub mod timing;
+pub mod tls;
+pub mod tombstone;
@@ -0,0 +1,194 @@
+use std::{
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::{Date, Utc};
+use futures_util::{SinkExt, StreamExt};
+use gotham::hyper::{
+    body::{Bytes, Sender},
+    upgrade::Upgraded,
+};
+use serde::Serialize;
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt},
+    sync::mpsc,
+};
+use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+
+use crate::{reader::LogLine, script_parser::ScriptParser};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(30);
+const EVENT_BUFFER: usize = 100;
+
+#[derive(Serialize)]
+pub struct LiveLine {
+    pub line_number: u64,
+    #[serde(flatten)]
+    pub line: LogLine,
+}
+
+pub enum LiveEvent {
+    Line(LiveLine),
+    End, // The day is over and its log stopped growing
+}
+
+/// Follows a day's log file in a task and returns the lines appended to it.
+///
+/// Lines are numbered like the day view, skipping blank lines. Lines after
+/// `after_line` that are already in the file are returned first; without it,
+/// only lines written from now on are returned. The task stops once the day
+/// is over and the file has stopped growing, or when the receiver is dropped.
+/// Channels with their own script parse lines with it.
+pub fn follow_log(
+    path: PathBuf,
+    log_date: Date<Utc>,
+    after_line: Option<u64>,
+    script_parser: Option<Arc<ScriptParser>>,
+) -> mpsc::Receiver<LiveEvent> {
+    let (sender, receiver) = mpsc::channel(EVENT_BUFFER);
+
+    tokio::spawn(async move {
+        if let Err(error) =
+            read_new_lines(&path, log_date, after_line, script_parser, &sender).await
+        {
+            tracing::warn!("Live tail of {} stopped: {:#}", path.display(), error);
+        }
+    });
+
+    receiver
+}
+
+async fn read_new_lines(
+    path: &Path,
+    log_date: Date<Utc>,
+    after_line: Option<u64>,
+    script_parser: Option<Arc<ScriptParser>>,
+    sender: &mpsc::Sender<LiveEvent>,
+) -> anyhow::Result<()> {
+    let mut offset = 0;
+    let mut line_number = 0;
+    let mut send_after = after_line;
+
+    while !sender.is_closed() {
+        let mut file = tokio::fs::File::open(path).await?;
+        let length = file.metadata().await?.len();
+
+        // Rewritten files start over
+        if length < offset {
+            offset = 0;
+            line_number = 0;
+            send_after = Some(0);
+        }
+
+        let mut buffer = Vec::new();
+        file.seek(SeekFrom::Start(offset)).await?;
+        file.read_to_end(&mut buffer).await?;
+
+        // Partial lines are read again once they are complete
+        let complete_length = buffer
+            .iter()
+            .rposition(|&byte| byte == b'\n')
+            .map_or(0, |position| position + 1);
+        buffer.truncate(complete_length);
+        offset += complete_length as u64;
+
+        for raw_line in crate::reader::read_raw_lines(buffer.as_slice())? {
+            if raw_line.is_empty() {
+                continue;
+            }
+
+            line_number += 1;
+
+            if send_after.is_none_or(|after_line| line_number <= after_line) {
+                continue;
+            }
+
+            // The day view fails on unparsable lines; here they are skipped
+            let line = match &script_parser {
+                Some(script_parser) => script_parser.parse_line(&raw_line, &log_date),
+                None => crate::reader::parse_line(&raw_line, &log_date),
+            };
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => continue,
+            };
+
+            if sender
+                .send(LiveEvent::Line(LiveLine { line_number, line }))
+                .await
+                .is_err()
+            {
+                return Ok(());
+            }
+        }
+
+        // Without a starting line, everything before the first read is old
+        if send_after.is_none() {
+            send_after = Some(line_number);
+        }
+
+        if complete_length == 0 && Utc::today() > log_date {
+            let _ = sender.send(LiveEvent::End).await;
+            return Ok(());
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+
+    Ok(())
+}
+
+/// Sends followed lines as server-sent events until the client disconnects.
+pub async fn send_events(mut events: mpsc::Receiver<LiveEvent>, mut sender: Sender) {
+    loop {
+        let chunk = tokio::select! {
+            event = events.recv() => match event {
+                Some(LiveEvent::Line(line)) => match serde_json::to_string(&line) {
+                    Ok(data) => format!("id: {}\nevent: line\ndata: {}\n\n", line.line_number, data),
+                    Err(_) => continue,
+                },
+                Some(LiveEvent::End) => "event: end\ndata:\n\n".to_string(),
+                None => return,
+            },
+            _ = tokio::time::sleep(KEEP_ALIVE_INTERVAL) => ": keep-alive\n\n".to_string(),
+        };
+
+        if sender.send_data(Bytes::from(chunk)).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Sends followed lines as JSON text messages until either side closes the
+/// connection.
+pub async fn send_websocket_messages(
+    mut events: mpsc::Receiver<LiveEvent>,
+    mut websocket: WebSocketStream<Upgraded>,
+) {
+    loop {
+        let message = tokio::select! {
+            event = events.recv() => match event {
+                Some(LiveEvent::Line(line)) => match serde_json::to_string(&line) {
+                    Ok(data) => Message::Text(data),
+                    Err(_) => continue,
+                },
+                Some(LiveEvent::End) | None => Message::Close(None),
+            },
+            // Reading also answers pings; anything the client sends is ignored
+            incoming = websocket.next() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => continue,
+            },
+            _ = tokio::time::sleep(KEEP_ALIVE_INTERVAL) => Message::Ping(Vec::new()),
+        };
+        let is_close = matches!(message, Message::Close(_));
+
+        if websocket.send(message).await.is_err() || is_close {
+            return;
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use std::{borrow::Borrow, convert::TryFrom};
+
+use chrono::{Date, Duration, Locale, TimeZone, Utc};
+use chrono_tz::Tz;
+
+// Locales of languages whose own code isn't also a region code
+const LANGUAGE_LOCALES: &[(&str, &str)] = &[
+    ("cs", "cs_CZ"),
+    ("da", "da_DK"),
+    ("el", "el_GR"),
+    ("en", "en_US"),
+    ("ja", "ja_JP"),
+    ("ko", "ko_KR"),
+    ("nb", "nb_NO"),
+    ("sv", "sv_SE"),
+    ("uk", "uk_UA"),
+    ("zh", "zh_CN"),
+];
+
+/// Parses a locale name such as `fr_FR`, or a language tag such as `fr-FR`
+/// or `fr`.
+pub fn parse_locale(name: &str) -> Option<Locale> {
+    let name = name.trim().replace('-', "_");
+    let (language, region) = match name.split_once('_') {
+        Some((language, region)) => (language.to_lowercase(), Some(region.to_uppercase())),
+        None => (name.to_lowercase(), None),
+    };
+
+    if language.is_empty() {
+        return None;
+    }
+
+    if let Some(region) = region {
+        if let Ok(locale) = Locale::try_from(format!("{}_{}", language, region).as_str()) {
+            return Some(locale);
+        }
+    }
+
+    if let Ok(locale) =
+        Locale::try_from(format!("{}_{}", language, language.to_uppercase()).as_str())
+    {
+        return Some(locale);
+    }
+
+    LANGUAGE_LOCALES
+        .iter()
+        .find(|(candidate, _)| *candidate == language)
+        .and_then(|(_, locale)| Locale::try_from(*locale).ok())
+}
+
+/// Parses an IANA timezone name such as `Europe/Paris`.
+pub fn parse_timezone(name: &str) -> Option<Tz> {
+    name.trim().parse::<Tz>().ok()
+}
+
+pub struct WeekdayName {
+    pub short: String, // Such as "Mon"
+    pub long: String,
+}
+
+/// Formats dates for display in the reader's language.
+#[derive(Clone, Copy)]
+pub struct DateFormatter {
+    locale: Locale,
+}
+
+impl DateFormatter {
+    pub fn new(locale: Locale) -> Self {
+        Self { locale }
+    }
+
+    /// Uses the most preferred language of an `Accept-Language` header that
+    /// has locale data, or the default locale.
+    pub fn negotiate(accept_language: Option<&str>, default: Locale) -> Self {
+        let mut languages = accept_language
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|entry| {
+                let mut parts = entry.split(';');
+                let tag = parts.next()?.trim();
+                let quality = parts
+                    .filter_map(|part| part.trim().strip_prefix("q="))
+                    .find_map(|quality| quality.parse::<f32>().ok())
+                    .unwrap_or(1.0);
+
+                Some((tag, quality))
+            })
+            .filter(|(tag, quality)| !tag.is_empty() && *tag != "*" && *quality > 0.0)
+            .collect::<Vec<(&str, f32)>>();
+
+        // Stable, so equally preferred languages keep their order
+        languages.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+        let locale = languages
+            .into_iter()
+            .find_map(|(tag, _)| parse_locale(tag))
+            .unwrap_or(default);
+
+        Self::new(locale)
+    }
+
+    /// Such as "Monday 4 January 2021".
+    pub fn long_date<D: Borrow<Date<Utc>>>(&self, date: D) -> String {
+        date.borrow()
+            .format_localized("%A %-d %B %Y", self.locale)
+            .to_string()
+    }
+
+    /// The locale's numeric date, such as "01/04/21".
+    pub fn date<D: Borrow<Date<Utc>>>(&self, date: D) -> String {
+        date.borrow()
+            .format_localized("%x", self.locale)
+            .to_string()
+    }
+
+    /// Such as "January 2021".
+    pub fn month_year<D: Borrow<Date<Utc>>>(&self, date: D) -> String {
+        date.borrow()
+            .format_localized("%B %Y", self.locale)
+            .to_string()
+    }
+
+    /// Such as "January".
+    pub fn month<D: Borrow<Date<Utc>>>(&self, date: D) -> String {
+        date.borrow()
+            .format_localized("%B", self.locale)
+            .to_string()
+    }
+
+    /// Weekday names from Monday to Sunday.
+    pub fn weekday_names(&self) -> Vec<WeekdayName> {
+        // 2021-01-04 is a Monday
+        let monday = Utc.ymd(2021, 1, 4);
+
+        (0..7)
+            .map(|offset| {
+                let date = monday + Duration::days(offset);
+
+                WeekdayName {
+                    short: date.format_localized("%a", self.locale).to_string(),
+                    long: date.format_localized("%A", self.locale).to_string(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Locale, TimeZone, Utc};
+
+    use super::{parse_locale, parse_timezone, DateFormatter};
+
+    #[test]
+    fn parses_locale_names_and_language_tags() {
+        assert!(matches!(parse_locale("fr_FR"), Some(Locale::fr_FR)));
+        assert!(matches!(parse_locale("fr-ca"), Some(Locale::fr_CA)));
+        assert!(matches!(parse_locale("de"), Some(Locale::de_DE)));
+        assert!(matches!(parse_locale("en"), Some(Locale::en_US)));
+        assert!(parse_locale("xx").is_none());
+        assert!(parse_locale("").is_none());
+    }
+
+    #[test]
+    fn parses_timezone_names() {
+        assert_eq!(
+            parse_timezone(" Europe/Paris "),
+            Some(chrono_tz::Europe::Paris)
+        );
+        assert_eq!(parse_timezone("UTC"), Some(chrono_tz::UTC));
+        assert!(parse_timezone("Mars/Olympus_Mons").is_none());
+    }
+
+    #[test]
+    fn negotiates_most_preferred_known_language() {
+        let date = Utc.ymd(2021, 1, 4);
+        let format = |header| DateFormatter::negotiate(header, Locale::en_US).month_year(date);
+
+        assert_eq!(format(None), "January 2021");
+        assert_eq!(format(Some("de-DE,de;q=0.9,en;q=0.8")), "Januar 2021");
+        assert_eq!(format(Some("en;q=0.5, fr;q=0.9")), "janvier 2021");
+        assert_eq!(format(Some("xx, *;q=0.1")), "January 2021");
+        assert_eq!(format(Some("fr;q=0")), "January 2021");
+    }
+
+    #[test]
+    fn formats_long_dates() {
+        let formatter = DateFormatter::new(Locale::en_US);
+
+        assert_eq!(
+            formatter.long_date(Utc.ymd(2021, 1, 4)),
+            "Monday 4 January 2021"
+        );
+        assert_eq!(formatter.weekday_names()[6].long, "Sunday");
+    }
+}
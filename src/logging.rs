@@ -0,0 +1,34 @@
+use serde::Deserialize;
+use tracing_subscriber::EnvFilter;
+
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text, // One readable line per event
+    Json, // One JSON object per event, for log collectors
+}
+
+/// Sends requests, errors and other events to standard error. The
+/// `RUST_LOG` environment variable, when set, replaces the config's level.
+pub fn init_logging(level: &str, format: LogFormat) -> anyhow::Result<()> {
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        Err(_) => EnvFilter::try_new(level)?,
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    let result = match format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().flatten_event(true).try_init(),
+    };
+
+    result.map_err(|error| anyhow::anyhow!("Couldn't set up logging: {}", error))
+}
+
+/// Whether the level is a valid filter, such as `info` or
+/// `warn,irclogger_viewer=debug`.
+pub fn is_log_level_ok(level: &str) -> bool {
+    EnvFilter::try_new(level).is_ok()
+}
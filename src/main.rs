@@ -1,25 +1,238 @@
-mod app;
-mod config;
-mod reader;
-mod route;
-mod state;
+use std::path::{Path, PathBuf};
 
-use clap::{App, Arg};
-use config::Config;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use irclogger_viewer::{
+    backup::{BackupOptions, RestoreOptions},
+    browse::BrowseOptions,
+    config::{ConfigFormat, ConfigOverrides, ConfigSource},
+    init::InitOptions,
+};
 
 fn main() -> anyhow::Result<()> {
-    let args = App::new("irclogger-viewer").arg(
-        Arg::with_name("config_path")
-            .required(true)
-            .value_name("CONFIG")
-            .help("Path to JSON config file."),
-    );
+    let args = App::new("irclogger-viewer")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .arg(
+            Arg::with_name("config_path")
+                .required(true)
+                .value_name("CONFIG")
+                .help("Path to JSON, TOML or YAML config file."),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .value_name("FORMAT")
+                .possible_values(&["json", "toml", "yaml"])
+                .help("Config file format. Defaults to the one of the file extension, or JSON."),
+        )
+        .arg(
+            Arg::with_name("port")
+                .long("port")
+                .value_name("PORT")
+                .help("Web server port number, instead of the config's."),
+        )
+        .arg(
+            Arg::with_name("log_dir")
+                .long("log-dir")
+                .value_name("DIR")
+                .help("Directory containing channel-named directories, instead of the config's."),
+        )
+        .arg(
+            Arg::with_name("password_file")
+                .long("password-file")
+                .value_name("FILE")
+                .help("Password file in htpasswd format, instead of the config's."),
+        )
+        .arg(
+            Arg::with_name("message_file")
+                .long("message-file")
+                .value_name("FILE")
+                .help("Custom message HTML file, instead of the config's."),
+        )
+        .subcommand(
+            SubCommand::with_name("init")
+                .about("Creates a config file, log directory and password file.")
+                .arg(
+                    Arg::with_name("directory")
+                        .long("directory")
+                        .value_name("DIR")
+                        .default_value("irclogger")
+                        .help("Directory to set up."),
+                )
+                .arg(
+                    Arg::with_name("port")
+                        .long("port")
+                        .value_name("PORT")
+                        .default_value("8033")
+                        .help("Web server port number."),
+                )
+                .arg(
+                    Arg::with_name("yes")
+                        .long("yes")
+                        .short("y")
+                        .help("Use the given or default values without asking."),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("Overwrite existing files."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("browse")
+                .about("Serves a local log directory without a config file or passwords.")
+                .arg(
+                    Arg::with_name("directory")
+                        .required(true)
+                        .value_name("DIR")
+                        .help("Directory containing a subdirectory of logs per channel."),
+                )
+                .arg(
+                    Arg::with_name("port")
+                        .long("port")
+                        .value_name("PORT")
+                        .help("Web server port number. Defaults to a free port."),
+                )
+                .arg(
+                    Arg::with_name("no_open")
+                        .long("no-open")
+                        .help("Don't open a web browser."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("backup")
+                .about("Archives the config, app database, day cache and data directories.")
+                .arg(
+                    Arg::with_name("archive")
+                        .required(true)
+                        .value_name("ARCHIVE")
+                        .help("Path of the .tar.gz archive to write."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("restore")
+                .about("Puts the files of a backup archive where the config says they go.")
+                .arg(
+                    Arg::with_name("archive")
+                        .required(true)
+                        .value_name("ARCHIVE")
+                        .help("Path of the .tar.gz archive to read."),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("Overwrite existing files."),
+                ),
+        );
 
     let matches = args.get_matches();
-    let config_content = std::fs::read(matches.value_of("config_path").unwrap())?;
-    let config: Config = serde_json::from_slice(&config_content)?;
 
-    crate::app::run(config)?;
+    if let Some(matches) = matches.subcommand_matches("init") {
+        return run_init(matches);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("browse") {
+        return run_browse(matches);
+    }
+
+    if let Some(subcommand_matches) = matches.subcommand_matches("backup") {
+        let config_path = required_config_path(&matches)?;
+
+        return irclogger_viewer::backup::run_backup(BackupOptions {
+            config_format: config_format(&matches, &config_path),
+            config_path,
+            archive_path: PathBuf::from(subcommand_matches.value_of("archive").unwrap()),
+        });
+    }
+
+    if let Some(subcommand_matches) = matches.subcommand_matches("restore") {
+        let config_path = required_config_path(&matches)?;
+
+        return irclogger_viewer::backup::run_restore(RestoreOptions {
+            config_format: config_format(&matches, &config_path),
+            config_path,
+            archive_path: PathBuf::from(subcommand_matches.value_of("archive").unwrap()),
+            force: subcommand_matches.is_present("force"),
+        });
+    }
+
+    let config_path = PathBuf::from(matches.value_of("config_path").unwrap());
+    let config_source = ConfigSource {
+        format: config_format(&matches, &config_path),
+        path: config_path,
+        overrides: config_overrides(&matches)?,
+    };
+    let config = config_source.read()?;
+    let problems = config.validate();
+
+    if !problems.is_empty() {
+        for problem in &problems {
+            eprintln!("{}", problem);
+        }
+
+        anyhow::bail!("Config has {} problem(s)", problems.len());
+    }
+
+    irclogger_viewer::app::run(config, Some(config_source))?;
 
     Ok(())
 }
+
+fn run_init(matches: &ArgMatches) -> anyhow::Result<()> {
+    let port = matches.value_of("port").unwrap();
+
+    irclogger_viewer::init::run_init(InitOptions {
+        directory: PathBuf::from(matches.value_of("directory").unwrap()),
+        port: port
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid port number: {}", port))?,
+        interactive: !matches.is_present("yes"),
+        force: matches.is_present("force"),
+    })
+}
+
+fn run_browse(matches: &ArgMatches) -> anyhow::Result<()> {
+    let port = match matches.value_of("port") {
+        Some(port) => Some(
+            port.parse()
+                .map_err(|_| anyhow::anyhow!("Invalid port number: {}", port))?,
+        ),
+        None => None,
+    };
+
+    irclogger_viewer::browse::run_browse(BrowseOptions {
+        directory: PathBuf::from(matches.value_of("directory").unwrap()),
+        port,
+        open_browser: !matches.is_present("no_open"),
+    })
+}
+
+fn required_config_path(matches: &ArgMatches) -> anyhow::Result<PathBuf> {
+    matches
+        .value_of("config_path")
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow::anyhow!("Give the config path before the subcommand"))
+}
+
+fn config_format(matches: &ArgMatches, config_path: &Path) -> ConfigFormat {
+    matches
+        .value_of("format")
+        .and_then(ConfigFormat::from_name)
+        .unwrap_or_else(|| ConfigFormat::from_path(config_path))
+}
+
+fn config_overrides(matches: &ArgMatches) -> anyhow::Result<ConfigOverrides> {
+    let port = match matches.value_of("port") {
+        Some(port) => Some(
+            port.parse()
+                .map_err(|_| anyhow::anyhow!("Invalid port number: {}", port))?,
+        ),
+        None => None,
+    };
+
+    Ok(ConfigOverrides {
+        web_server_port_number: port,
+        chat_log_directory: matches.value_of("log_dir").map(PathBuf::from),
+        apache_password_file: matches.value_of("password_file").map(PathBuf::from),
+        custom_message_html_file: matches.value_of("message_file").map(PathBuf::from),
+    })
+}
@@ -0,0 +1,125 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+type CachedFile = (SystemTime, u64, Arc<String>); // Modification time, size and content
+
+/// The parts of a site's homepage message other than its own file: a file
+/// shared by every site and HTML written in the config.
+///
+/// Files are read again only when their modification time or size changes,
+/// and with `sanitize`, scripts, styles and event handlers are removed from
+/// every part.
+#[derive(Clone)]
+pub struct MessageSources {
+    global_file: Option<PathBuf>,
+    inline_html: Option<String>, // Already sanitized
+    sanitize: bool,
+    files: Arc<Mutex<HashMap<PathBuf, CachedFile>>>,
+}
+
+impl MessageSources {
+    pub fn new(global_file: Option<PathBuf>, inline_html: Option<String>, sanitize: bool) -> Self {
+        Self {
+            global_file,
+            inline_html: inline_html.map(|html| clean_html(html, sanitize)),
+            sanitize,
+            files: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Sources of another site that shares the global file and the cache of
+    /// files read.
+    pub fn with_inline_html(&self, inline_html: Option<String>) -> Self {
+        Self {
+            inline_html: inline_html.map(|html| clean_html(html, self.sanitize)),
+            ..self.clone()
+        }
+    }
+
+    /// Joins the global file, the site's file and the inline HTML, leaving
+    /// out empty parts.
+    pub fn assemble(&self, site_file: &Path) -> anyhow::Result<String> {
+        let mut parts = Vec::new();
+
+        if let Some(path) = &self.global_file {
+            parts.push(self.read_file(path)?);
+        }
+
+        parts.push(self.read_file(site_file)?);
+
+        if let Some(html) = &self.inline_html {
+            parts.push(Arc::new(html.clone()));
+        }
+
+        Ok(parts
+            .iter()
+            .map(|part| part.trim())
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<&str>>()
+            .join("\n"))
+    }
+
+    fn read_file(&self, path: &Path) -> anyhow::Result<Arc<String>> {
+        let metadata = std::fs::metadata(path)?;
+        let modified = metadata.modified()?;
+
+        if let Some((cached_modified, cached_len, content)) = self.files.lock().unwrap().get(path) {
+            if *cached_modified == modified && *cached_len == metadata.len() {
+                return Ok(content.clone());
+            }
+        }
+
+        let content = Arc::new(clean_html(std::fs::read_to_string(path)?, self.sanitize));
+        self.files.lock().unwrap().insert(
+            path.to_path_buf(),
+            (modified, metadata.len(), content.clone()),
+        );
+
+        Ok(content)
+    }
+}
+
+fn clean_html(html: String, sanitize: bool) -> String {
+    if sanitize {
+        ammonia::clean(&html)
+    } else {
+        html
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MessageSources;
+
+    #[test]
+    fn parts_are_joined_and_sanitized() {
+        let directory = tempfile::tempdir().unwrap();
+        let global_path = directory.path().join("global.html");
+        let site_path = directory.path().join("message.html");
+        std::fs::write(&global_path, "<p>Maintenance tonight</p>\n").unwrap();
+        std::fs::write(&site_path, "").unwrap();
+
+        let sources = MessageSources::new(
+            Some(global_path),
+            Some("<p onclick=\"steal()\">Welcome</p><script>steal()</script>".to_string()),
+            true,
+        );
+
+        assert_eq!(
+            sources.assemble(&site_path).unwrap(),
+            "<p>Maintenance tonight</p>\n<p>Welcome</p>"
+        );
+
+        std::fs::write(&site_path, "<p>Our channels</p>").unwrap();
+        let tenant_sources = sources.with_inline_html(None);
+
+        assert_eq!(
+            tenant_sources.assemble(&site_path).unwrap(),
+            "<p>Maintenance tonight</p>\n<p>Our channels</p>"
+        );
+    }
+}
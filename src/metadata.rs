@@ -0,0 +1,75 @@
+/// Per-channel settings read from a `METADATA` file in the channel's log
+/// directory. Each line is a `key=value` pair; lines starting with `#` are
+/// comments.
+#[derive(Clone)]
+pub struct ChannelMetadata {
+    pub searchable: bool,   // When false, the channel is excluded from every search
+    pub public_stats: bool, // When true, noisy daily counts are published even if the channel is private
+    pub parser: Option<String>, // File name of a Rhai script in the channel's directory that parses its lines
+    pub access_webhook: Option<String>, // Receives a POST when a reader logs in, once a day per reader
+}
+
+impl Default for ChannelMetadata {
+    fn default() -> Self {
+        Self {
+            searchable: true,
+            public_stats: false,
+            parser: None,
+            access_webhook: None,
+        }
+    }
+}
+
+impl ChannelMetadata {
+    pub fn parse(content: &str) -> anyhow::Result<Self> {
+        let mut metadata = Self::default();
+
+        for line in content.lines() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Channel metadata line without value: {}", line))?;
+
+            // Unknown keys are ignored so newer metadata files work with older viewers
+            match key.trim() {
+                "searchable" => metadata.searchable = parse_bool(value.trim())?,
+                "public_stats" => metadata.public_stats = parse_bool(value.trim())?,
+                "parser" => metadata.parser = Some(parse_file_name(value.trim())?),
+                "access_webhook" => metadata.access_webhook = Some(parse_url(value.trim())?),
+                _ => {}
+            }
+        }
+
+        Ok(metadata)
+    }
+}
+
+/// Accepts only a file in the channel's directory, not a path elsewhere.
+fn parse_file_name(value: &str) -> anyhow::Result<String> {
+    if value.is_empty() || value.starts_with('.') || value.contains(['/', '\\']) {
+        anyhow::bail!("Invalid file name in channel metadata: {}", value);
+    }
+
+    Ok(value.to_string())
+}
+
+fn parse_url(value: &str) -> anyhow::Result<String> {
+    if !(value.starts_with("https://") || value.starts_with("http://")) {
+        anyhow::bail!("Invalid URL in channel metadata: {}", value);
+    }
+
+    Ok(value.to_string())
+}
+
+fn parse_bool(value: &str) -> anyhow::Result<bool> {
+    match value {
+        "true" | "yes" | "on" => Ok(true),
+        "false" | "no" | "off" => Ok(false),
+        _ => anyhow::bail!("Invalid boolean in channel metadata: {}", value),
+    }
+}
@@ -0,0 +1,146 @@
+use std::{collections::HashMap, time::Duration};
+
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Serialize)]
+pub struct DayDiffReport {
+    pub channel: String,
+    pub date_slug: String,
+    pub upstream_url: String,
+    pub local: Option<DaySummary>,
+    pub upstream: Option<DaySummary>,
+    pub diverged: bool,
+    pub local_only_lines: u64,
+    pub upstream_only_lines: u64,
+}
+
+#[derive(Serialize)]
+pub struct DaySummary {
+    pub line_count: u64,
+    pub sha256: String,
+}
+
+impl DaySummary {
+    fn new(content: &[u8]) -> Self {
+        Self {
+            line_count: content
+                .split(|&byte| byte == b'\n')
+                .filter(|line| !line.is_empty())
+                .count() as u64,
+            sha256: format!("{:x}", Sha256::digest(content)),
+        }
+    }
+}
+
+/// Builds the upstream raw log URL from a template containing `{channel}`
+/// and `{date}` placeholders.
+pub fn format_upstream_url(url_template: &str, channel: &str, date_slug: &str) -> String {
+    url_template
+        .replace(
+            "{channel}",
+            &utf8_percent_encode(channel, NON_ALPHANUMERIC).to_string(),
+        )
+        .replace(
+            "{date}",
+            &utf8_percent_encode(date_slug, NON_ALPHANUMERIC).to_string(),
+        )
+}
+
+/// Fetches a day's raw log from the upstream, or `None` if it has no such
+/// day. Redirects aren't followed, and logs larger than `max_size` bytes are
+/// an error.
+pub async fn fetch_upstream_day(
+    upstream_url: &str,
+    max_size: usize,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let client = reqwest::Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?;
+    let response = client.get(upstream_url).send().await?;
+
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+
+    let mut response = response.error_for_status()?;
+
+    if response.status().is_redirection() {
+        anyhow::bail!("Upstream redirected {}", upstream_url);
+    }
+
+    if response.content_length().unwrap_or_default() > max_size as u64 {
+        anyhow::bail!("Upstream day too large: {}", upstream_url);
+    }
+
+    let mut content = Vec::new();
+
+    while let Some(chunk) = response.chunk().await? {
+        content.extend_from_slice(&chunk);
+
+        if content.len() > max_size {
+            anyhow::bail!("Upstream day too large: {}", upstream_url);
+        }
+    }
+
+    Ok(Some(content))
+}
+
+pub fn compare_day(
+    channel: &str,
+    date_slug: &str,
+    upstream_url: &str,
+    local: Option<&[u8]>,
+    upstream: Option<&[u8]>,
+) -> DayDiffReport {
+    let local_lines = count_lines(local.unwrap_or_default());
+    let upstream_lines = count_lines(upstream.unwrap_or_default());
+    let mut local_only_lines = 0;
+    let mut upstream_only_lines = 0;
+
+    for (line, count) in &local_lines {
+        let upstream_count = upstream_lines.get(line).copied().unwrap_or_default();
+        local_only_lines += count.saturating_sub(upstream_count);
+    }
+
+    for (line, count) in &upstream_lines {
+        let local_count = local_lines.get(line).copied().unwrap_or_default();
+        upstream_only_lines += count.saturating_sub(local_count);
+    }
+
+    let local = local.map(DaySummary::new);
+    let upstream = upstream.map(DaySummary::new);
+    let diverged = match (&local, &upstream) {
+        (Some(local), Some(upstream)) => local.sha256 != upstream.sha256,
+        _ => true,
+    };
+
+    DayDiffReport {
+        channel: channel.to_string(),
+        date_slug: date_slug.to_string(),
+        upstream_url: upstream_url.to_string(),
+        local,
+        upstream,
+        diverged,
+        local_only_lines,
+        upstream_only_lines,
+    }
+}
+
+fn count_lines(content: &[u8]) -> HashMap<&[u8], u64> {
+    let mut lines = HashMap::new();
+
+    for line in content.split(|&byte| byte == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        if !line.is_empty() {
+            *lines.entry(line).or_insert(0) += 1;
+        }
+    }
+
+    lines
+}
@@ -0,0 +1,186 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, SystemTime},
+};
+
+use chrono::Utc;
+use serde::Deserialize;
+
+use crate::{
+    notify::{Notification, NotificationConfig},
+    search_history::SearchParameters,
+    state::AppState,
+};
+
+/// Channels whose newest log is older than this are considered dormant
+/// rather than broken and are not alerted on.
+const ACTIVE_CHANNEL_WINDOW: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+#[derive(Clone, Deserialize)]
+pub struct StaleChannelAlertConfig {
+    pub threshold_hours: u64, // Alert when an active channel has no new lines for this long
+    #[serde(default = "default_schedule")]
+    pub schedule: String, // Cron-like schedule of the check
+    #[serde(default)]
+    pub channels: Vec<String>, // Channels to watch; empty watches all recently active channels
+    pub notification: NotificationConfig,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct SavedSearchAlertConfig {
+    #[serde(default = "default_schedule")]
+    pub schedule: String, // Cron-like schedule of the check
+    pub notification: NotificationConfig, // Receives the new matches of every user's subscribed searches
+}
+
+fn default_schedule() -> String {
+    "*/15 * * * *".to_string()
+}
+
+/// Date slug and line number of the newest match of each user's saved search
+/// that was already seen.
+pub type SeenMatches = HashMap<(String, SearchParameters), Option<(String, u64)>>;
+
+pub async fn check_stale_channels(
+    app_state: &AppState,
+    config: &StaleChannelAlertConfig,
+    alerted_channels: &mut HashSet<String>,
+) -> anyhow::Result<()> {
+    let threshold = Duration::from_secs(config.threshold_hours.saturating_mul(60 * 60));
+    let now = SystemTime::now();
+
+    for channel in app_state.get_channels()? {
+        if !config.channels.is_empty() && !config.channels.contains(&channel.name) {
+            continue;
+        }
+
+        let last_modified = match app_state.get_channel_last_modified(&channel.name)? {
+            Some(last_modified) => last_modified,
+            None => continue,
+        };
+        let idle = now.duration_since(last_modified).unwrap_or_default();
+
+        if idle < threshold {
+            alerted_channels.remove(&channel.name);
+            continue;
+        }
+
+        let is_watched = !config.channels.is_empty() || idle < ACTIVE_CHANNEL_WINDOW;
+
+        if is_watched && !alerted_channels.contains(&channel.name) {
+            let notification = Notification {
+                subject: format!("#{} stopped receiving logs", channel.name),
+                text: format!(
+                    "The log of #{} hasn't changed for {} hours (threshold {} hours). The logger may have stopped.",
+                    channel.name,
+                    idle.as_secs() / 3600,
+                    config.threshold_hours
+                ),
+            };
+
+            crate::notify::send(&config.notification, &notification).await?;
+            alerted_channels.insert(channel.name);
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends the new matches of the pinned searches that users subscribed to
+/// alerts. Yesterday's and today's logs are searched, and a search's first
+/// check only notes its newest match, so subscribing doesn't alert on old
+/// lines.
+pub async fn check_saved_searches(
+    app_state: &AppState,
+    config: &SavedSearchAlertConfig,
+    seen_matches: &mut SeenMatches,
+) -> anyhow::Result<()> {
+    let searches = app_state.search_history_store.get_alert_searches()?;
+
+    seen_matches.retain(|key, _| searches.contains(key));
+
+    for (user, parameters) in searches {
+        let channel = &parameters.channel;
+
+        if app_state
+            .hidden_channels
+            .get_hidden_channel(channel)?
+            .is_some()
+            || !app_state.get_channel_metadata(channel)?.searchable
+        {
+            continue;
+        }
+
+        let matches = app_state
+            .search_channel(
+                channel,
+                &parameters.query,
+                parameters.to_options(),
+                Some(Utc::today().pred()),
+                None,
+                usize::MAX,
+            )
+            .await?;
+        let positions = matches
+            .entries
+            .iter()
+            .map(|entry| (entry.date_slug.clone(), entry.line_number))
+            .collect::<Vec<(String, u64)>>();
+        let newest = positions
+            .iter()
+            .max_by(|a, b| compare_positions(a, b))
+            .cloned();
+        let key = (user, parameters);
+
+        let previous = match seen_matches.get(&key) {
+            Some(previous) => previous.clone(),
+            None => {
+                seen_matches.insert(key, newest);
+                continue;
+            }
+        };
+        let new_count = positions
+            .iter()
+            .filter(|position| {
+                previous.as_ref().is_none_or(|previous| {
+                    compare_positions(position, previous) == std::cmp::Ordering::Greater
+                })
+            })
+            .count();
+
+        if new_count > 0 {
+            let (user, parameters) = &key;
+            let base_url = app_state
+                .public_url
+                .as_deref()
+                .unwrap_or(&app_state.path_prefix);
+            let notification = Notification {
+                subject: format!("New matches for \"{}\" in #{}", parameters.query, parameters.channel),
+                text: format!(
+                    "{} new lines in #{} match the search \"{}\" saved by {}: {}/bin/irclogger_log_search/{}?{}",
+                    new_count,
+                    parameters.channel,
+                    parameters.query,
+                    user,
+                    base_url,
+                    parameters.channel,
+                    parameters.to_query_string()
+                ),
+            };
+
+            crate::notify::send(&config.notification, &notification).await?;
+        }
+
+        let newest = newest.into_iter().chain(previous).max_by(compare_positions);
+        seen_matches.insert(key, newest);
+    }
+
+    Ok(())
+}
+
+/// Orders positions by the date of their slug, then by line number.
+fn compare_positions(a: &(String, u64), b: &(String, u64)) -> std::cmp::Ordering {
+    let date = |slug: &str| slug.split(',').next().unwrap_or_default().to_string();
+
+    date(&a.0).cmp(&date(&b.0)).then(a.1.cmp(&b.1))
+}
@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use lettre::{
+    transport::smtp::authentication::Credentials, AsyncSmtpTransport, AsyncTransport, Message,
+    Tokio1Executor,
+};
+use serde::{Deserialize, Serialize};
+
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where operator notifications such as alerts are delivered.
+#[derive(Clone, Default, Deserialize)]
+pub struct NotificationConfig {
+    pub webhook_url: Option<String>, // Receives a JSON POST with "subject" and "text"
+    pub email: Option<EmailConfig>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct EmailConfig {
+    pub smtp_server: String,
+    pub smtp_port: Option<u16>, // Defaults to the standard port of the security mode
+    #[serde(default)]
+    pub smtp_security: SmtpSecurity,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    #[serde(default = "default_subject_template")]
+    pub subject_template: String, // Supports {subject} placeholder
+    #[serde(default = "default_body_template")]
+    pub body_template: String, // Supports {subject} and {text} placeholders
+}
+
+#[derive(Clone, Copy, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    Tls,
+    #[default]
+    Starttls,
+    None,
+}
+
+fn default_subject_template() -> String {
+    "[irclogger-viewer] {subject}".to_string()
+}
+
+fn default_body_template() -> String {
+    "{text}".to_string()
+}
+
+#[derive(Serialize)]
+pub struct Notification {
+    pub subject: String,
+    pub text: String,
+}
+
+pub async fn send(config: &NotificationConfig, notification: &Notification) -> anyhow::Result<()> {
+    if let Some(url) = &config.webhook_url {
+        send_webhook(url, notification).await?;
+    }
+
+    if let Some(email_config) = &config.email {
+        send_email(email_config, notification).await?;
+    }
+
+    Ok(())
+}
+
+async fn send_webhook(url: &str, notification: &Notification) -> anyhow::Result<()> {
+    let client = reqwest::Client::builder().timeout(SEND_TIMEOUT).build()?;
+    let body = serde_json::to_vec(notification)?;
+
+    client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
+
+async fn send_email(config: &EmailConfig, notification: &Notification) -> anyhow::Result<()> {
+    let subject = config
+        .subject_template
+        .replace("{subject}", &notification.subject);
+    let body = config
+        .body_template
+        .replace("{subject}", &notification.subject)
+        .replace("{text}", &notification.text);
+
+    let mut builder = match config.smtp_security {
+        SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.smtp_server)?,
+        SmtpSecurity::Starttls => {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.smtp_server)?
+        }
+        SmtpSecurity::None => {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.smtp_server)
+        }
+    };
+
+    builder = builder.timeout(Some(SEND_TIMEOUT));
+
+    if let Some(port) = config.smtp_port {
+        builder = builder.port(port);
+    }
+
+    if let (Some(username), Some(password)) = (&config.smtp_username, &config.smtp_password) {
+        builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+    }
+
+    let mailer = builder.build();
+
+    for recipient in &config.to {
+        let message = Message::builder()
+            .from(config.from.parse()?)
+            .to(recipient.parse()?)
+            .subject(subject.clone())
+            .body(body.clone())?;
+
+        mailer.send(message).await?;
+    }
+
+    Ok(())
+}
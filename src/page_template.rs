@@ -0,0 +1,74 @@
+use std::{path::Path, sync::Arc};
+
+use serde::Serialize;
+use tera::Tera;
+
+/// Operators' own versions of pages, written as [Tera](https://tera.netlify.app)
+/// templates in a directory and read at startup.
+///
+/// A file named like a built-in template, such as `index.html`, replaces it
+/// and gets the same fields. Other files can be used with `{% extends %}`
+/// and `{% include %}`.
+#[derive(Clone)]
+pub struct PageTemplates {
+    tera: Arc<Tera>,
+}
+
+impl PageTemplates {
+    pub fn load(directory: &Path) -> anyhow::Result<Self> {
+        let pattern = directory.join("**").join("*.html");
+        let tera = Tera::new(&pattern.to_string_lossy())
+            .map_err(|error| anyhow::anyhow!("Templates in {}: {}", directory.display(), error))?;
+
+        Ok(Self {
+            tera: Arc::new(tera),
+        })
+    }
+
+    /// Renders a page if the directory has a template of that name.
+    pub fn render<T: Serialize>(&self, name: &str, fields: &T) -> anyhow::Result<Option<String>> {
+        if !self
+            .tera
+            .get_template_names()
+            .any(|template_name| template_name == name)
+        {
+            return Ok(None);
+        }
+
+        let context = tera::Context::from_serialize(fields)?;
+
+        Ok(Some(self.tera.render(name, &context)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PageTemplates;
+
+    #[test]
+    fn renders_templates_in_directory() {
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::write(
+            directory.path().join("layout.html"),
+            "<main>{% block content %}{% endblock %}</main>",
+        )
+        .unwrap();
+        std::fs::write(
+            directory.path().join("index.html"),
+            "{% extends \"layout.html\" %}{% block content %}{{ message }}{% endblock %}",
+        )
+        .unwrap();
+
+        let templates = PageTemplates::load(directory.path()).unwrap();
+        let fields = serde_json::json!({ "message": "<b>hi</b>" });
+
+        assert_eq!(
+            templates.render("index.html", &fields).unwrap().as_deref(),
+            Some("<main>&lt;b&gt;hi&lt;&#x2F;b&gt;</main>")
+        );
+        assert_eq!(
+            templates.render("channel_index.html", &fields).unwrap(),
+            None
+        );
+    }
+}
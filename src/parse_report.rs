@@ -0,0 +1,119 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::reader::ParseProblem;
+
+const MAX_PROBLEMS: usize = 1000;
+
+/// A line that failed to parse, for fixing the logger or the parser.
+#[derive(Clone, Serialize)]
+pub struct ParseProblemRecord {
+    pub channel: String,
+    pub date_slug: String,
+    pub line_number: u64,
+    pub reason: String,
+    pub text: String,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// The most recently seen parse problems, newest first.
+///
+/// Days are parsed again on every view, so a problem seen again moves to the
+/// front instead of being repeated. The oldest problems are dropped once the
+/// report is full.
+#[derive(Clone, Default)]
+pub struct ParseProblemReport {
+    problems: Arc<Mutex<VecDeque<ParseProblemRecord>>>,
+}
+
+impl ParseProblemReport {
+    pub fn record(&self, channel: &str, date_slug: &str, problems: Vec<ParseProblem>) {
+        let mut records = self.problems.lock().unwrap();
+        let now = Utc::now();
+
+        for problem in problems {
+            let existing = records.iter().position(|record| {
+                record.line_number == problem.line_number
+                    && record.date_slug == date_slug
+                    && record.channel == channel
+            });
+
+            if let Some(index) = existing {
+                records.remove(index);
+            } else {
+                tracing::warn!(
+                    "Parse problem in {} {} line {}: {}",
+                    channel,
+                    date_slug,
+                    problem.line_number,
+                    problem.reason
+                );
+            }
+
+            records.push_front(ParseProblemRecord {
+                channel: channel.to_string(),
+                date_slug: date_slug.to_string(),
+                line_number: problem.line_number,
+                reason: problem.reason,
+                text: problem.text,
+                last_seen: now,
+            });
+            records.truncate(MAX_PROBLEMS);
+        }
+    }
+
+    pub fn get_problems(&self) -> Vec<ParseProblemRecord> {
+        self.problems.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ParseProblemReport, MAX_PROBLEMS};
+    use crate::reader::ParseProblem;
+
+    fn problem(line_number: u64) -> ParseProblem {
+        ParseProblem {
+            line_number,
+            reason: "no timestamp and nickname".to_string(),
+            text: "garbage".to_string(),
+        }
+    }
+
+    #[test]
+    fn repeated_problems_move_to_front() {
+        let report = ParseProblemReport::default();
+        report.record(
+            "archiveteam",
+            "2021-01-04,Mon",
+            vec![problem(1), problem(2)],
+        );
+        report.record("archiveteam", "2021-01-04,Mon", vec![problem(1)]);
+
+        let problems = report.get_problems();
+
+        assert_eq!(problems.len(), 2);
+        assert_eq!(problems[0].line_number, 1);
+        assert_eq!(problems[1].line_number, 2);
+    }
+
+    #[test]
+    fn oldest_problems_are_dropped() {
+        let report = ParseProblemReport::default();
+        report.record(
+            "archiveteam",
+            "2021-01-04,Mon",
+            (1..=MAX_PROBLEMS as u64 + 1).map(problem).collect(),
+        );
+
+        let problems = report.get_problems();
+
+        assert_eq!(problems.len(), MAX_PROBLEMS);
+        assert_eq!(problems.last().unwrap().line_number, 2);
+    }
+}
@@ -0,0 +1,153 @@
+use hmac::{Hmac, Mac, NewMac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+pub const PREFERENCES_COOKIE: &str = "prefs";
+pub const MAX_HIDDEN_NICKS: usize = 100;
+const MAX_NICK_LENGTH: usize = 64;
+const MAX_COOKIE_VALUE_BYTES: usize = 4000; // Browsers drop cookies of more than 4096 bytes
+
+/// Viewer settings kept in the browser so they work without an account.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preferences {
+    pub thumbnails: Option<bool>, // None follows the page default
+    pub hide_status: bool,        // Hide joins, parts and other status lines
+    pub hidden_nicks: Vec<String>,
+    pub timezone: Option<String>, // Such as "Europe/Paris"; None uses the site's timezone
+}
+
+impl Preferences {
+    /// Trims and deduplicates user supplied values so the cookie stays small.
+    pub fn normalize(&mut self) {
+        let mut hidden_nicks: Vec<String> = Vec::new();
+
+        for nick in &self.hidden_nicks {
+            let nick = nick.trim();
+
+            if nick.is_empty()
+                || nick.chars().count() > MAX_NICK_LENGTH
+                || hidden_nicks
+                    .iter()
+                    .any(|other| other.eq_ignore_ascii_case(nick))
+            {
+                continue;
+            }
+
+            hidden_nicks.push(nick.to_string());
+        }
+
+        hidden_nicks.truncate(MAX_HIDDEN_NICKS);
+        self.hidden_nicks = hidden_nicks;
+        self.timezone = self
+            .timezone
+            .as_deref()
+            .and_then(crate::locale::parse_timezone)
+            .map(|timezone| timezone.name().to_string());
+    }
+
+    /// Whether a line should be left out of the day view. Status lines have
+    /// an empty nickname.
+    pub fn is_line_hidden(&self, nickname: &str) -> bool {
+        if nickname.is_empty() {
+            self.hide_status
+        } else {
+            self.hidden_nicks
+                .iter()
+                .any(|hidden_nick| hidden_nick.eq_ignore_ascii_case(nickname))
+        }
+    }
+}
+
+/// Signs preferences into a cookie value and verifies them on the way back.
+#[derive(Clone)]
+pub struct PreferenceSigner {
+    secret: Option<Vec<u8>>,
+}
+
+impl PreferenceSigner {
+    pub fn new(secret: Option<String>) -> Self {
+        Self {
+            secret: secret.map(String::into_bytes),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.secret.is_some()
+    }
+
+    /// Hidden nicknames are dropped from the end of the list until the value
+    /// fits in a cookie.
+    pub fn encode(&self, preferences: &Preferences) -> anyhow::Result<String> {
+        let mut preferences = preferences.clone();
+
+        loop {
+            let value = self.encode_value(&preferences)?;
+
+            if value.len() <= MAX_COOKIE_VALUE_BYTES || preferences.hidden_nicks.pop().is_none() {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn encode_value(&self, preferences: &Preferences) -> anyhow::Result<String> {
+        let payload =
+            base64::encode_config(serde_json::to_vec(preferences)?, base64::URL_SAFE_NO_PAD);
+        let signature = base64::encode_config(
+            self.build_mac(&payload)?.finalize().into_bytes(),
+            base64::URL_SAFE_NO_PAD,
+        );
+
+        Ok(format!("{}.{}", payload, signature))
+    }
+
+    /// Returns `None` for cookies that are malformed or were not signed with
+    /// the current secret.
+    pub fn decode(&self, value: &str) -> Option<Preferences> {
+        let (payload, signature) = value.split_once('.')?;
+        let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD).ok()?;
+
+        self.build_mac(payload).ok()?.verify(&signature).ok()?;
+
+        let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()?;
+
+        serde_json::from_slice(&payload).ok()
+    }
+
+    fn build_mac(&self, payload: &str) -> anyhow::Result<Hmac<Sha256>> {
+        let secret = self
+            .secret
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Preferences secret not configured"))?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret)
+            .map_err(|_| anyhow::anyhow!("Invalid preferences secret"))?;
+        mac.update(payload.as_bytes());
+
+        Ok(mac)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PreferenceSigner, Preferences, MAX_COOKIE_VALUE_BYTES, MAX_HIDDEN_NICKS};
+
+    #[test]
+    fn cookies_are_trimmed_to_fit() {
+        let signer = PreferenceSigner::new(Some("secret".to_string()));
+        let mut preferences = Preferences {
+            hidden_nicks: (0..MAX_HIDDEN_NICKS)
+                .map(|number| format!("{}{}", "é".repeat(60), number))
+                .collect(),
+            ..Preferences::default()
+        };
+        preferences.normalize();
+
+        let value = signer.encode(&preferences).unwrap();
+        let decoded = signer.decode(&value).unwrap();
+
+        assert!(value.len() <= MAX_COOKIE_VALUE_BYTES);
+        assert!(!decoded.hidden_nicks.is_empty());
+        assert!(decoded.hidden_nicks.len() < MAX_HIDDEN_NICKS);
+        assert_eq!(decoded.hidden_nicks[0], preferences.hidden_nicks[0]);
+    }
+}
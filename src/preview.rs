@@ -0,0 +1,299 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_BODY_SIZE: usize = 64 * 1024;
+const MAX_CACHE_ENTRIES: usize = 10000;
+const RETRY_AFTER: Duration = Duration::from_secs(60 * 60); // For links without a title
+
+type CachedTitle = (Instant, Option<String>); // When fetched and the title; None while pending or when no title
+
+#[derive(Serialize)]
+pub struct LinkPreview {
+    pub url: String,
+    pub title: String,
+}
+
+/// Fetches page titles of links to allowlisted hosts in the background.
+///
+/// The visitor's browser never contacts the linked site; titles are fetched
+/// by the server and only shown once they are cached.
+#[derive(Clone, Default)]
+pub struct LinkPreviewer {
+    allowed_hosts: Arc<Vec<String>>,
+    titles: Arc<Mutex<HashMap<String, CachedTitle>>>,
+}
+
+impl LinkPreviewer {
+    pub fn new(allowed_hosts: Vec<String>) -> Self {
+        Self {
+            allowed_hosts: Arc::new(allowed_hosts),
+            titles: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.allowed_hosts.is_empty()
+    }
+
+    pub fn previews_for_text(&self, text: &str) -> Vec<LinkPreview> {
+        let mut previews = Vec::new();
+
+        for url in extract_urls(text) {
+            if !is_host_allowed(&self.allowed_hosts, url) {
+                continue;
+            }
+
+            if let Some(title) = self.get_or_prefetch(url) {
+                previews.push(LinkPreview {
+                    url: url.to_string(),
+                    title,
+                });
+            }
+        }
+
+        previews
+    }
+
+    /// Returns the cached title of a link, or starts fetching it. Links
+    /// that failed or had no title are fetched again after a while.
+    fn get_or_prefetch(&self, url: &str) -> Option<String> {
+        let mut titles = self.titles.lock().unwrap();
+        let now = Instant::now();
+
+        match titles.get(url) {
+            Some((_, Some(title))) => return Some(title.clone()),
+            Some((fetched, None)) if now.duration_since(*fetched) < RETRY_AFTER => return None,
+            _ => {}
+        }
+
+        if titles.len() >= MAX_CACHE_ENTRIES {
+            evict_titles(&mut titles, now);
+        }
+
+        titles.insert(url.to_string(), (now, None));
+
+        let url = url.to_string();
+        let cache = self.titles.clone();
+
+        tokio::spawn(async move {
+            let title = fetch_title(&url).await.unwrap_or_default();
+            cache.lock().unwrap().insert(url, (Instant::now(), title));
+        });
+
+        None
+    }
+}
+
+/// Makes room in a full cache by dropping links due to be fetched again, or
+/// failing that, the one fetched longest ago.
+fn evict_titles(titles: &mut HashMap<String, CachedTitle>, now: Instant) {
+    titles.retain(|_, (fetched, title)| {
+        title.is_some() || now.duration_since(*fetched) < RETRY_AFTER
+    });
+
+    if titles.len() >= MAX_CACHE_ENTRIES {
+        let oldest = titles
+            .iter()
+            .min_by_key(|(_, (fetched, _))| *fetched)
+            .map(|(url, _)| url.clone());
+
+        if let Some(url) = oldest {
+            titles.remove(&url);
+        }
+    }
+}
+
+pub fn is_host_allowed(allowed_hosts: &[String], url: &str) -> bool {
+    let url = match reqwest::Url::parse(url) {
+        Ok(url) => url,
+        Err(_) => return false,
+    };
+    let host = url.host_str().unwrap_or_default();
+
+    allowed_hosts
+        .iter()
+        .any(|allowed_host| host == allowed_host || host.ends_with(&format!(".{}", allowed_host)))
+}
+
+/// Builds a client for fetching a link to an allowlisted host. The host is
+/// resolved up front and the client pinned to the address, so a host that
+/// resolves to the server's own network can't be used to reach it, and
+/// redirects, which could lead anywhere, aren't followed.
+pub async fn build_link_client(
+    url: &reqwest::Url,
+    timeout: Duration,
+) -> anyhow::Result<reqwest::Client> {
+    if url.scheme() != "http" && url.scheme() != "https" {
+        anyhow::bail!("Unsupported scheme: {}", url.scheme());
+    }
+
+    let host = url
+        .host_str()
+        .ok_or_else(|| anyhow::anyhow!("No host: {}", url))?;
+    let port = url.port_or_known_default().unwrap_or_default();
+    let address = tokio::net::lookup_host((host.trim_matches(&['[', ']'][..]), port))
+        .await?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Host not found: {}", host))?;
+
+    if !is_public_address(address.ip()) {
+        anyhow::bail!("Host resolves to a non-public address: {}", host);
+    }
+
+    Ok(reqwest::Client::builder()
+        .timeout(timeout)
+        .redirect(reqwest::redirect::Policy::none())
+        .resolve(host, address)
+        .build()?)
+}
+
+fn is_public_address(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => {
+            let octets = address.octets();
+
+            !(address.is_private()
+                || address.is_loopback()
+                || address.is_link_local()
+                || address.is_unspecified()
+                || address.is_broadcast()
+                || address.is_documentation()
+                || address.is_multicast()
+                || octets[0] == 0
+                || (octets[0] == 100 && octets[1] & 0xc0 == 64)) // Shared address space
+        }
+        IpAddr::V6(address) => {
+            if let Some(mapped) = address.to_ipv4_mapped() {
+                return is_public_address(IpAddr::V4(mapped));
+            }
+
+            let first = address.segments()[0];
+
+            !(address.is_loopback()
+                || address.is_unspecified()
+                || address.is_multicast()
+                || first & 0xfe00 == 0xfc00 // Unique local
+                || first & 0xffc0 == 0xfe80) // Link local
+        }
+    }
+}
+
+pub fn extract_urls(text: &str) -> Vec<&str> {
+    lazy_static! {
+        static ref PATTERN: Regex = Regex::new(r#"https?://[^\s<>"']+"#).unwrap();
+    }
+
+    PATTERN
+        .find_iter(text)
+        .map(|found| found.as_str().trim_end_matches(&['.', ',', ')', ']'][..]))
+        .collect()
+}
+
+async fn fetch_title(url: &str) -> anyhow::Result<Option<String>> {
+    let url = reqwest::Url::parse(url)?;
+    let client = build_link_client(&url, FETCH_TIMEOUT).await?;
+    let mut response = client.get(url).send().await?.error_for_status()?;
+    let mut body = Vec::new();
+
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+
+        if body.len() >= MAX_BODY_SIZE {
+            break;
+        }
+    }
+
+    Ok(extract_title(&String::from_utf8_lossy(&body)))
+}
+
+fn extract_title(html: &str) -> Option<String> {
+    lazy_static! {
+        static ref PATTERN: Regex = Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap();
+    }
+
+    let title = PATTERN.captures(html)?.get(1)?.as_str();
+    let title = title
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&");
+
+    if title.is_empty() {
+        None
+    } else {
+        Some(title)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        time::{Duration, Instant},
+    };
+
+    use super::{evict_titles, is_public_address, MAX_CACHE_ENTRIES, RETRY_AFTER};
+
+    #[test]
+    fn full_caches_drop_failures_then_the_oldest() {
+        let start = Instant::now();
+        let now = start + RETRY_AFTER + Duration::from_secs(1);
+        let mut titles = HashMap::new();
+
+        for number in 1..MAX_CACHE_ENTRIES {
+            titles.insert(
+                format!("https://example.com/{}", number),
+                (now, Some(number.to_string())),
+            );
+        }
+
+        titles.insert(
+            "https://example.com/oldest".to_string(),
+            (start, Some("Oldest".to_string())),
+        );
+        evict_titles(&mut titles, now);
+
+        assert_eq!(titles.len(), MAX_CACHE_ENTRIES - 1);
+        assert!(!titles.contains_key("https://example.com/oldest"));
+
+        titles.insert("https://example.com/failed".to_string(), (start, None));
+        evict_titles(&mut titles, now);
+
+        assert_eq!(titles.len(), MAX_CACHE_ENTRIES - 1);
+        assert!(!titles.contains_key("https://example.com/failed"));
+    }
+
+    #[test]
+    fn internal_addresses_are_not_public() {
+        for address in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "192.168.0.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public_address(address.parse().unwrap()), "{}", address);
+        }
+
+        assert!(is_public_address("93.184.216.34".parse().unwrap()));
+        assert!(is_public_address("2606:2800:220:1::".parse().unwrap()));
+    }
+}
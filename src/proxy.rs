@@ -0,0 +1,178 @@
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use mime::Mime;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::preview::{build_link_client, extract_urls, is_host_allowed};
+
+const CACHE_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const IMAGE_EXTENSIONS: [&str; 5] = [".png", ".jpg", ".jpeg", ".gif", ".webp"];
+
+#[derive(Serialize)]
+pub struct Thumbnail {
+    pub url: String,
+    pub proxy_url: String,
+}
+
+pub struct ProxiedImage {
+    pub content_type: Mime,
+    pub data: Vec<u8>,
+}
+
+/// Fetches images from allowlisted hosts on behalf of visitors so their
+/// addresses aren't leaked to third-party hosts.
+#[derive(Clone)]
+pub struct ImageProxy {
+    allowed_hosts: Arc<Vec<String>>,
+    cache_directory: Option<PathBuf>,
+    max_size: usize,
+    timeout: Duration,
+}
+
+impl ImageProxy {
+    pub fn new(
+        allowed_hosts: Vec<String>,
+        cache_directory: Option<PathBuf>,
+        max_size: usize,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            allowed_hosts: Arc::new(allowed_hosts),
+            cache_directory,
+            max_size,
+            timeout,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.allowed_hosts.is_empty()
+    }
+
+    pub fn is_url_allowed(&self, url: &str) -> bool {
+        is_host_allowed(&self.allowed_hosts, url)
+    }
+
+    pub fn thumbnails_for_text(&self, text: &str) -> Vec<Thumbnail> {
+        extract_urls(text)
+            .into_iter()
+            .filter(|url| is_image_url(url) && self.is_url_allowed(url))
+            .map(|url| Thumbnail {
+                url: url.to_string(),
+                proxy_url: format!(
+                    "/proxy/image?url={}",
+                    utf8_percent_encode(url, NON_ALPHANUMERIC)
+                ),
+            })
+            .collect()
+    }
+
+    pub async fn get_image(&self, url: &str) -> anyhow::Result<ProxiedImage> {
+        if !self.is_url_allowed(url) {
+            anyhow::bail!("Host not allowed: {}", url);
+        }
+
+        if let Some(image) = self.read_cached_image(url).await {
+            return Ok(image);
+        }
+
+        let image = self.fetch_image(url).await?;
+
+        if let Some(path) = self.get_cache_path(url) {
+            tokio::fs::create_dir_all(path.parent().unwrap()).await?;
+            tokio::fs::write(&path, &image.data).await?;
+        }
+
+        Ok(image)
+    }
+
+    fn get_cache_path(&self, url: &str) -> Option<PathBuf> {
+        let cache_directory = self.cache_directory.as_ref()?;
+        let key = format!("{:x}", Sha256::digest(url.as_bytes()));
+
+        Some(cache_directory.join(&key[0..2]).join(key))
+    }
+
+    async fn read_cached_image(&self, url: &str) -> Option<ProxiedImage> {
+        let path = self.get_cache_path(url)?;
+        let metadata = tokio::fs::metadata(&path).await.ok()?;
+        let age = SystemTime::now()
+            .duration_since(metadata.modified().ok()?)
+            .unwrap_or_default();
+
+        if age > CACHE_MAX_AGE {
+            return None;
+        }
+
+        let data = tokio::fs::read(&path).await.ok()?;
+        let content_type = sniff_image_type(&data)?;
+
+        Some(ProxiedImage { content_type, data })
+    }
+
+    async fn fetch_image(&self, url: &str) -> anyhow::Result<ProxiedImage> {
+        let url = reqwest::Url::parse(url)?;
+        let client = build_link_client(&url, self.timeout).await?;
+        let mut response = client.get(url).send().await?.error_for_status()?;
+
+        if response.content_length().unwrap_or_default() > self.max_size as u64 {
+            anyhow::bail!("Image too large");
+        }
+
+        let declared_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .parse::<Mime>()?;
+
+        if declared_type.type_() != mime::IMAGE {
+            anyhow::bail!("Not an image: {}", declared_type);
+        }
+
+        let mut data = Vec::new();
+
+        while let Some(chunk) = response.chunk().await? {
+            data.extend_from_slice(&chunk);
+
+            if data.len() > self.max_size {
+                anyhow::bail!("Image too large");
+            }
+        }
+
+        // Trust the content rather than the declared type so that nothing
+        // but raster images is ever served from our origin.
+        let content_type =
+            sniff_image_type(&data).ok_or_else(|| anyhow::anyhow!("Unrecognized image data"))?;
+
+        Ok(ProxiedImage { content_type, data })
+    }
+}
+
+fn is_image_url(url: &str) -> bool {
+    let path = url.split(&['?', '#'][..]).next().unwrap_or_default();
+    let path = path.to_lowercase();
+
+    IMAGE_EXTENSIONS
+        .iter()
+        .any(|extension| path.ends_with(extension))
+}
+
+fn sniff_image_type(data: &[u8]) -> Option<Mime> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some(mime::IMAGE_PNG)
+    } else if data.starts_with(b"\xff\xd8\xff") {
+        Some(mime::IMAGE_JPEG)
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some(mime::IMAGE_GIF)
+    } else if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        "image/webp".parse().ok()
+    } else {
+        None
+    }
+}
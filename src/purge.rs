@@ -0,0 +1,145 @@
+use std::{collections::HashMap, time::Duration};
+
+use serde::Deserialize;
+
+use crate::state::AppState;
+
+const PURGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Deserialize)]
+pub struct CachePurgeConfig {
+    pub cache_url: String, // Base URL of the Varnish/nginx cache, such as http://127.0.0.1:6081
+    pub host: Option<String>, // Host header for purge requests; the public hostname of the viewer
+    #[serde(default)]
+    pub method: PurgeMethod,
+    #[serde(default = "default_schedule")]
+    pub schedule: String, // Cron-like schedule of the change check
+}
+
+#[derive(Clone, Copy, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PurgeMethod {
+    #[default]
+    Purge, // One PURGE request per affected URL
+    Ban, // One BAN request per channel with an X-Ban-Url regex header
+}
+
+fn default_schedule() -> String {
+    "* * * * *".to_string()
+}
+
+/// Newest log of a channel and its ETag when last checked.
+pub struct LogFingerprint {
+    date_slug: String,
+    etag: String,
+}
+
+/// Purges cached pages of channels whose newest log changed since the last
+/// check. The first check only records the current state.
+pub async fn purge_changed_logs(
+    app_state: &AppState,
+    config: &CachePurgeConfig,
+    fingerprints: &mut HashMap<String, LogFingerprint>,
+) -> anyhow::Result<()> {
+    let client = reqwest::Client::builder().timeout(PURGE_TIMEOUT).build()?;
+
+    for channel in app_state.get_channels()? {
+        let date_slug = match app_state.get_channel_log_date_slugs(&channel.name)?.first() {
+            Some(date_slug) => date_slug.clone(),
+            None => continue,
+        };
+        let fingerprint = LogFingerprint {
+            etag: app_state.get_log_etag(&channel.name, &date_slug)?,
+            date_slug,
+        };
+
+        let previous = fingerprints.insert(channel.name.clone(), fingerprint);
+        let previous = match previous {
+            Some(previous) => previous,
+            None => continue,
+        };
+        let current = &fingerprints[&channel.name];
+
+        if previous.date_slug == current.date_slug && previous.etag == current.etag {
+            continue;
+        }
+
+        let mut date_slugs = vec![current.date_slug.as_str()];
+
+        // The previous day may have received its last lines before rollover
+        if previous.date_slug != current.date_slug {
+            date_slugs.push(previous.date_slug.as_str());
+        }
+
+        match config.method {
+            PurgeMethod::Purge => {
+                for path in affected_paths(&channel.name, &date_slugs) {
+                    send_request(&client, config, "PURGE", &path, None).await?;
+                }
+            }
+            PurgeMethod::Ban => {
+                let pattern = format!(
+                    "^/bin/irclogger_(log|logs|log_continuous|digest)/{}([/?]|$)",
+                    regex::escape(&channel.name)
+                );
+                send_request(&client, config, "BAN", "/", Some(&pattern)).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn affected_paths(channel: &str, date_slugs: &[&str]) -> Vec<String> {
+    let mut paths = vec![
+        format!("/bin/irclogger_logs/{}", channel),
+        format!("/bin/irclogger_logs/{}/", channel),
+    ];
+
+    for date_slug in date_slugs {
+        paths.push(format!("/bin/irclogger_log/{}?date={}", channel, date_slug));
+        paths.push(format!(
+            "/bin/irclogger_log/{}/?date={}",
+            channel, date_slug
+        ));
+        paths.push(format!(
+            "/bin/irclogger_log/{}/?date={}&raw=on",
+            channel, date_slug
+        ));
+    }
+
+    paths
+}
+
+async fn send_request(
+    client: &reqwest::Client,
+    config: &CachePurgeConfig,
+    method: &str,
+    path: &str,
+    ban_pattern: Option<&str>,
+) -> anyhow::Result<()> {
+    let url = format!("{}{}", config.cache_url.trim_end_matches('/'), path);
+    let mut request = client.request(reqwest::Method::from_bytes(method.as_bytes())?, &url);
+
+    if let Some(host) = &config.host {
+        request = request.header(reqwest::header::HOST, host);
+    }
+
+    if let Some(pattern) = ban_pattern {
+        request = request.header("X-Ban-Url", pattern);
+    }
+
+    let response = request.send().await?;
+
+    // Caches answer 404 when the URL wasn't cached, which is fine
+    if !response.status().is_success() && response.status() != reqwest::StatusCode::NOT_FOUND {
+        anyhow::bail!(
+            "Cache purge {} {} failed: {}",
+            method,
+            url,
+            response.status()
+        );
+    }
+
+    Ok(())
+}
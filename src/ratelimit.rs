@@ -0,0 +1,339 @@
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    net::IpAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures_util::FutureExt;
+use gotham::{
+    handler::HandlerFuture,
+    helpers::http::response::create_empty_response,
+    hyper::{header::HeaderValue, HeaderMap, Method, StatusCode, Uri},
+    middleware::{Middleware, NewMiddleware},
+    state::{client_addr, FromState, State},
+};
+use serde::Deserialize;
+
+use crate::state::AppState;
+
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+#[derive(Clone, Deserialize)]
+pub struct RateLimitConfig {
+    pub requests_per_minute: u32,
+    #[serde(default = "default_burst")]
+    pub burst: u32, // Requests a client can make at once before the rate applies
+    pub search_requests_per_minute: Option<u32>, // Stricter limit of search pages, on top of the other
+    #[serde(default = "default_search_burst")]
+    pub search_burst: u32,
+}
+
+fn default_burst() -> u32 {
+    20
+}
+
+fn default_search_burst() -> u32 {
+    5
+}
+
+/// Limits each client to a rate of requests with a token bucket, which
+/// allows a burst of requests after a quiet moment.
+#[derive(Clone)]
+pub struct RateLimiter {
+    tokens_per_second: f64,
+    burst: f64,
+    clients: Arc<Mutex<HashMap<IpAddr, (Instant, f64)>>>, // Time of the last request and tokens left after it
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_minute: u32, burst: u32) -> Self {
+        Self {
+            tokens_per_second: f64::from(requests_per_minute) / 60.0,
+            burst: f64::from(burst.max(1)),
+            clients: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Takes a token for a request of the client. Returns how long until the
+    /// next token if the client has none left.
+    pub fn check(&self, client: IpAddr) -> Result<(), Duration> {
+        self.check_at(client, Instant::now())
+    }
+
+    fn check_at(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut clients = self.clients.lock().unwrap();
+
+        if clients.len() >= MAX_TRACKED_CLIENTS {
+            // Clients whose buckets have filled up again are like new ones
+            clients.retain(|_, (last_request, tokens)| {
+                self.refill(*tokens, now.duration_since(*last_request)) < self.burst
+            });
+        }
+
+        let (last_request, tokens) = clients.entry(client).or_insert((now, self.burst));
+        let available = self.refill(*tokens, now.duration_since(*last_request));
+        *last_request = now;
+
+        if available >= 1.0 {
+            *tokens = available - 1.0;
+            Ok(())
+        } else {
+            *tokens = available;
+            Err(Duration::from_secs_f64(
+                (1.0 - available) / self.tokens_per_second,
+            ))
+        }
+    }
+
+    fn refill(&self, tokens: f64, elapsed: Duration) -> f64 {
+        (tokens + elapsed.as_secs_f64() * self.tokens_per_second).min(self.burst)
+    }
+}
+
+/// An address or a network of addresses, such as `10.0.0.0/8`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct IpNetwork {
+    address: IpAddr,
+    prefix_length: u8,
+}
+
+impl IpNetwork {
+    pub fn contains(&self, address: IpAddr) -> bool {
+        let (network, address, bits) = match (self.address, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                (u32::from(network) as u128, u32::from(address) as u128, 32)
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                (u128::from(network), u128::from(address), 128)
+            }
+            (IpAddr::V6(network), IpAddr::V4(address)) => (
+                u128::from(network),
+                u128::from(address.to_ipv6_mapped()),
+                128,
+            ),
+            (IpAddr::V4(_), IpAddr::V6(address)) => match address.to_ipv4() {
+                Some(address) => return self.contains(IpAddr::V4(address)),
+                None => return false,
+            },
+        };
+        let host_bits = bits - u32::from(self.prefix_length);
+
+        host_bits >= bits || network >> host_bits == address >> host_bits
+    }
+}
+
+impl TryFrom<String> for IpNetwork {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let error = || format!("{:?} is not an address or network like 10.0.0.0/8", value);
+        let (address, prefix_length) = match value.split_once('/') {
+            Some((address, prefix_length)) => (address, Some(prefix_length)),
+            None => (value.as_str(), None),
+        };
+        let address = address.parse::<IpAddr>().map_err(|_| error())?;
+        let max_length = if address.is_ipv4() { 32 } else { 128 };
+        let prefix_length = match prefix_length {
+            Some(prefix_length) => prefix_length.parse::<u8>().map_err(|_| error())?,
+            None => max_length,
+        };
+
+        if prefix_length > max_length {
+            return Err(error());
+        }
+
+        Ok(Self {
+            address,
+            prefix_length,
+        })
+    }
+}
+
+/// Answers clients over their rate limits with `429 Too Many Requests` and
+/// a Retry-After header before their request is routed, so requests for
+/// paths that don't exist count too.
+///
+/// The limits are those of the site's `AppState`, so it goes after the
+/// middleware that puts it.
+#[derive(Clone, Default)]
+pub struct RateLimitMiddleware;
+
+impl Middleware for RateLimitMiddleware {
+    fn call<Chain>(self, state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>> + Send + 'static,
+    {
+        let retry_after = match rate_limit_delay(&state) {
+            Some(retry_after) => retry_after,
+            None => return chain(state),
+        };
+
+        let mut response = create_empty_response(&state, StatusCode::TOO_MANY_REQUESTS);
+        let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        response
+            .headers_mut()
+            .insert("Retry-After", HeaderValue::from(seconds));
+
+        async move { Ok((state, response)) }.boxed()
+    }
+}
+
+impl NewMiddleware for RateLimitMiddleware {
+    type Instance = Self;
+
+    fn new_middleware(&self) -> anyhow::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+/// Counts the request against the client's limits and returns how long the
+/// client should wait if it's over one. Searches read every day of a
+/// channel, so they also have a limit of their own.
+fn rate_limit_delay(state: &State) -> Option<Duration> {
+    let app_state = AppState::try_borrow_from(state)?;
+    let client = client_ip(state)?;
+
+    if let Some(rate_limiter) = &app_state.rate_limiter {
+        if let Err(retry_after) = rate_limiter.check(client) {
+            return Some(retry_after);
+        }
+    }
+
+    match &app_state.search_rate_limiter {
+        Some(rate_limiter) if is_search_request(state) => rate_limiter.check(client).err(),
+        _ => None,
+    }
+}
+
+fn is_search_request(state: &State) -> bool {
+    let path = Uri::borrow_from(state).path();
+
+    Method::borrow_from(state) == Method::GET
+        && (path == "/bin/irclogger_search" || path.starts_with("/bin/irclogger_log_search/"))
+}
+
+pub fn default_trusted_proxies() -> Vec<IpNetwork> {
+    vec![
+        IpNetwork::try_from("127.0.0.0/8".to_string()).unwrap(),
+        IpNetwork::try_from("::1".to_string()).unwrap(),
+    ]
+}
+
+/// Address of the client. Connections from trusted reverse proxies use
+/// their X-Forwarded-For header; others could forge it.
+///
+/// Every X-Forwarded-For header is read, since a proxy may add its own
+/// after one sent by the client rather than appending to it.
+pub fn client_ip(state: &State) -> Option<IpAddr> {
+    let peer = client_addr(state).map(|addr| addr.ip())?;
+    let forwarded = HeaderMap::borrow_from(state)
+        .get_all("X-Forwarded-For")
+        .iter()
+        .map(|value| value.to_str().unwrap_or("invalid"))
+        .collect::<Vec<&str>>()
+        .join(",");
+
+    let trusted_proxies = AppState::try_borrow_from(state)
+        .map(|app_state| app_state.trusted_proxies.clone())
+        .unwrap_or_else(default_trusted_proxies);
+
+    Some(forwarded_client(
+        peer,
+        Some(forwarded.as_str()).filter(|forwarded| !forwarded.is_empty()),
+        &trusted_proxies,
+    ))
+}
+
+/// Follows the X-Forwarded-For header from the nearest proxy back to the
+/// first address that isn't a trusted proxy.
+fn forwarded_client(
+    peer: IpAddr,
+    forwarded: Option<&str>,
+    trusted_proxies: &[IpNetwork],
+) -> IpAddr {
+    let is_trusted = |address: IpAddr| {
+        trusted_proxies
+            .iter()
+            .any(|network| network.contains(address))
+    };
+    let mut client = peer;
+
+    for value in forwarded.unwrap_or_default().rsplit(',') {
+        if !is_trusted(client) {
+            break;
+        }
+
+        match value.trim().parse::<IpAddr>() {
+            Ok(address) => client = address,
+            Err(_) => break,
+        }
+    }
+
+    client
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::TryFrom,
+        net::IpAddr,
+        time::{Duration, Instant},
+    };
+
+    use super::{default_trusted_proxies, forwarded_client, IpNetwork, RateLimiter};
+
+    #[test]
+    fn buckets_allow_bursts_then_the_rate() {
+        let rate_limiter = RateLimiter::new(60, 2);
+        let client = "192.0.2.1".parse().unwrap();
+        let start = Instant::now();
+
+        assert_eq!(rate_limiter.check_at(client, start), Ok(()));
+        assert_eq!(rate_limiter.check_at(client, start), Ok(()));
+        assert_eq!(
+            rate_limiter.check_at(client, start),
+            Err(Duration::from_secs(1))
+        );
+        assert_eq!(
+            rate_limiter.check_at("192.0.2.2".parse().unwrap(), start),
+            Ok(())
+        );
+        assert_eq!(
+            rate_limiter.check_at(client, start + Duration::from_secs(1)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn forwarded_addresses_of_trusted_proxies_are_followed() {
+        let ip = |value: &str| value.parse::<IpAddr>().unwrap();
+        let mut trusted_proxies = default_trusted_proxies();
+        trusted_proxies.push(IpNetwork::try_from("10.0.0.0/8".to_string()).unwrap());
+
+        assert_eq!(
+            forwarded_client(ip("127.0.0.1"), Some("203.0.113.5"), &trusted_proxies),
+            ip("203.0.113.5")
+        );
+        assert_eq!(
+            forwarded_client(
+                ip("127.0.0.1"),
+                Some("198.51.100.9, 203.0.113.5, 10.1.2.3"),
+                &trusted_proxies
+            ),
+            ip("203.0.113.5")
+        );
+        assert_eq!(
+            forwarded_client(ip("192.0.2.1"), Some("203.0.113.5"), &trusted_proxies),
+            ip("192.0.2.1")
+        );
+        assert_eq!(
+            forwarded_client(ip("127.0.0.1"), None, &trusted_proxies),
+            ip("127.0.0.1")
+        );
+        assert!(IpNetwork::try_from("10.0.0.0/33".to_string()).is_err());
+    }
+}
@@ -1,28 +1,220 @@
 use encoding_rs_io::DecodeReaderBytesBuilder;
+use flate2::read::MultiGzDecoder;
 use lazy_static::lazy_static;
 use regex::Regex;
+use serde::Serialize;
 use std::{
+    ffi::OsStr,
     fs::File,
-    io::{BufRead, BufReader},
-    path::Path,
+    io::{BufRead, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
 };
 
 use chrono::{Date, DateTime, NaiveTime, Utc};
 
+const PREVIEW_HEAD_LINES: usize = 200;
+const PREVIEW_TAIL_BYTES: u64 = 8192;
+const PREVIEW_LENGTH: usize = 80;
+const TAIL_CHUNK_BYTES: u64 = 8192;
+
+lazy_static! {
+    static ref LINE_PATTERN: Regex = Regex::new(r"\[(\d\d:\d\d)\] (\S+) (.*)").unwrap();
+}
+
+#[derive(Clone, Serialize)]
 pub struct LogLine {
     pub date: DateTime<Utc>,
     pub content: LogLineContent,
 }
 
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum LogLineContent {
     Message { nickname: String, text: String },
     Status(String),
 }
 
-pub fn count_message_lines(path: &Path, _log_date: &Date<Utc>) -> anyhow::Result<u64> {
-    let mut count = 0;
+/// A line that lenient parsing kept as a status line.
+pub struct ParseProblem {
+    pub line_number: u64, // Counting non-blank lines, like the day view
+    pub reason: String,
+    pub text: String,
+}
+
+/// How the parser treats a line of a log file.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LineKind {
+    Message,
+    Status,
+    Blank,      // Skipped by the parser
+    Unparsable, // Fails the whole day's parse
+}
+
+impl LineKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Message => "message",
+            Self::Status => "status",
+            Self::Blank => "blank",
+            Self::Unparsable => "unparsable",
+        }
+    }
+}
+
+pub fn classify_line(line: &str) -> LineKind {
+    if line.is_empty() {
+        return LineKind::Blank;
+    }
+
+    match LINE_PATTERN.captures(line) {
+        Some(captures) => {
+            if NaiveTime::parse_from_str(captures.get(1).unwrap().as_str(), "%H:%M").is_err() {
+                LineKind::Unparsable
+            } else if captures.get(2).unwrap().as_str() == "***" {
+                LineKind::Status
+            } else {
+                LineKind::Message
+            }
+        }
+        None => LineKind::Unparsable,
+    }
+}
 
+/// Returns the path of a day's log file, preferring `<date slug>.log` over a
+/// compressed `<date slug>.log.gz`.
+pub fn find_log_file(channel_dir: &Path, date_slug: &str) -> PathBuf {
+    let path = channel_dir.join(format!("{}.log", date_slug));
+
+    if !path.exists() {
+        let compressed_path = channel_dir.join(format!("{}.log.gz", date_slug));
+
+        if compressed_path.exists() {
+            return compressed_path;
+        }
+    }
+
+    path
+}
+
+pub fn is_compressed(path: &Path) -> bool {
+    path.extension().and_then(OsStr::to_str) == Some("gz")
+}
+
+/// Opens a log file, decompressing it if it's gzipped.
+pub fn open_log(path: &Path) -> std::io::Result<Box<dyn Read + Send>> {
     let file = File::open(path)?;
+
+    if is_compressed(path) {
+        Ok(Box::new(MultiGzDecoder::new(file)))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+pub fn read_log(path: &Path) -> std::io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    open_log(path)?.read_to_end(&mut data)?;
+
+    Ok(data)
+}
+
+/// Returns the end of a log holding its last lines that aren't blank,
+/// reading backwards from the end of the file so a long day isn't read
+/// whole.
+pub fn read_log_tail(path: &Path, max_lines: usize) -> std::io::Result<Vec<u8>> {
+    if max_lines == 0 {
+        return Ok(Vec::new());
+    }
+
+    // Compressed files can't seek, so the whole day is read
+    if is_compressed(path) {
+        let mut data = read_log(path)?;
+        let start = find_tail_start(&data, max_lines).unwrap_or(0);
+
+        return Ok(data.split_off(start));
+    }
+
+    let mut file = File::open(path)?;
+    let mut position = file.metadata()?.len();
+    let mut tail = Vec::new();
+
+    while position > 0 {
+        let chunk_start = position.saturating_sub(TAIL_CHUNK_BYTES);
+        let mut chunk = vec![0; (position - chunk_start) as usize];
+        file.seek(SeekFrom::Start(chunk_start))?;
+        file.read_exact(&mut chunk)?;
+        chunk.extend_from_slice(&tail);
+        tail = chunk;
+        position = chunk_start;
+
+        if let Some(start) = find_tail_start(&tail, max_lines) {
+            return Ok(tail.split_off(start));
+        }
+    }
+
+    Ok(tail)
+}
+
+/// Finds where the last lines that aren't blank start, if a line break
+/// comes before them. The first line may be cut off, so it's never counted.
+fn find_tail_start(data: &[u8], max_lines: usize) -> Option<usize> {
+    let mut count = 0;
+    let mut line_end = data.len();
+
+    for index in (0..data.len()).rev() {
+        if data[index] != b'\n' {
+            continue;
+        }
+
+        let line = &data[index + 1..line_end];
+
+        if !line.is_empty() && line != b"\r" {
+            count += 1;
+
+            if count == max_lines {
+                return Some(index + 1);
+            }
+        }
+
+        line_end = index;
+    }
+
+    None
+}
+
+/// Returns the text of a message line without its timestamp and nickname.
+pub fn message_text(line: &str) -> Option<&str> {
+    if classify_line(line) != LineKind::Message {
+        return None;
+    }
+
+    LINE_PATTERN
+        .captures(line)
+        .map(|captures| captures.get(3).unwrap().as_str())
+}
+
+/// Returns who sent a message, counting an action as sent by the nickname
+/// that starts it. Status lines have no sender.
+pub fn message_sender(content: &LogLineContent) -> Option<&str> {
+    match content {
+        LogLineContent::Message { nickname, text } if nickname == "*" => {
+            Some(text.split(' ').next().unwrap_or_default())
+        }
+        LogLineContent::Message { nickname, .. } => Some(nickname),
+        LogLineContent::Status(_) => None,
+    }
+}
+
+/// Counts the lines the day view shows as messages, and also status lines
+/// if `count_status_lines` is set.
+pub fn count_message_lines(
+    path: &Path,
+    _log_date: &Date<Utc>,
+    count_status_lines: bool,
+) -> anyhow::Result<u64> {
+    let mut count = 0;
+
+    let file = open_log(path)?;
     let file = DecodeReaderBytesBuilder::new()
         .encoding(Some(encoding_rs::UTF_8))
         .build(file);
@@ -31,21 +223,143 @@ pub fn count_message_lines(path: &Path, _log_date: &Date<Utc>) -> anyhow::Result
     for raw_line in file.lines() {
         let line = raw_line?;
 
-        if !line.contains("] *** ") {
-            count += 1;
+        match classify_line(&line) {
+            LineKind::Message => count += 1,
+            LineKind::Status if count_status_lines => count += 1,
+            _ => {}
         }
     }
 
     Ok(count)
 }
 
+/// Returns previews of the first and last messages of a day, reading only
+/// the start and end of the file.
+pub fn read_message_previews(path: &Path) -> anyhow::Result<(Option<String>, Option<String>)> {
+    // Compressed files can't seek, so the whole day is read
+    if is_compressed(path) {
+        let data = read_log(path)?;
+        let tail_start = data.len().saturating_sub(PREVIEW_TAIL_BYTES as usize);
+        let first = find_first_preview(data.as_slice())?;
+        let last = find_last_preview(&data[tail_start..], tail_start > 0, &first);
+
+        return Ok((first, last));
+    }
+
+    let mut file = File::open(path)?;
+    let length = file.metadata()?.len();
+    let first = find_first_preview(&file)?;
+
+    let tail_start = length.saturating_sub(PREVIEW_TAIL_BYTES);
+    let mut tail = Vec::new();
+    file.seek(SeekFrom::Start(tail_start))?;
+    file.read_to_end(&mut tail)?;
+
+    let last = find_last_preview(&tail, tail_start > 0, &first);
+
+    Ok((first, last))
+}
+
+fn find_first_preview<R: Read>(head: R) -> anyhow::Result<Option<String>> {
+    let head = DecodeReaderBytesBuilder::new()
+        .encoding(Some(encoding_rs::UTF_8))
+        .build(head);
+
+    for raw_line in BufReader::new(head).lines().take(PREVIEW_HEAD_LINES) {
+        let preview = make_message_preview(&raw_line?);
+
+        if preview.is_some() {
+            return Ok(preview);
+        }
+    }
+
+    Ok(None)
+}
+
+fn find_last_preview(tail: &[u8], is_cut_off: bool, first: &Option<String>) -> Option<String> {
+    let tail = String::from_utf8_lossy(tail);
+    let mut tail_lines = tail.lines();
+
+    // The first line is likely cut off unless the whole file was read
+    if is_cut_off {
+        tail_lines.next();
+    }
+
+    tail_lines
+        .rev()
+        .find_map(make_message_preview)
+        .filter(|last| Some(last) != first.as_ref())
+}
+
+fn make_message_preview(line: &str) -> Option<String> {
+    if classify_line(line) != LineKind::Message {
+        return None;
+    }
+
+    let captures = LINE_PATTERN.captures(line)?;
+    let nickname = captures
+        .get(2)
+        .unwrap()
+        .as_str()
+        .trim_start_matches('<')
+        .trim_end_matches('>');
+    let text = captures.get(3).unwrap().as_str();
+
+    let preview = if nickname == "*" {
+        format!("* {}", text)
+    } else {
+        format!("<{}> {}", nickname, text)
+    };
+
+    if preview.chars().count() > PREVIEW_LENGTH {
+        let mut preview = preview.chars().take(PREVIEW_LENGTH - 1).collect::<String>();
+        preview.push('…');
+        Some(preview)
+    } else {
+        Some(preview)
+    }
+}
+
 pub fn read_lines(path: &Path, log_date: &Date<Utc>) -> anyhow::Result<Vec<LogLine>> {
-    let file = File::open(path)?;
+    parse_lines(open_log(path)?, log_date)
+}
+
+pub fn parse_lines<R: Read>(reader: R, log_date: &Date<Utc>) -> anyhow::Result<Vec<LogLine>> {
+    let (lines, _) = parse_lines_with(reader, log_date, false, parse_line)?;
+
+    Ok(lines)
+}
+
+/// Parses like `parse_lines`, but keeps unparsable lines and reports them.
+pub fn parse_lines_lenient<R: Read>(
+    reader: R,
+    log_date: &Date<Utc>,
+) -> anyhow::Result<(Vec<LogLine>, Vec<ParseProblem>)> {
+    parse_lines_with(reader, log_date, true, parse_line)
+}
+
+/// Parses each line that isn't blank with a line parser, such as a channel's
+/// script.
+///
+/// In lenient mode, an unparsable line is kept as a status line with its raw
+/// text and the time of the line before it, so line numbers stay the same and
+/// one bad line doesn't fail the day. Otherwise it fails the whole parse.
+pub fn parse_lines_with<R, F>(
+    reader: R,
+    log_date: &Date<Utc>,
+    lenient: bool,
+    parse: F,
+) -> anyhow::Result<(Vec<LogLine>, Vec<ParseProblem>)>
+where
+    R: Read,
+    F: Fn(&str, &Date<Utc>) -> Result<LogLine, String>,
+{
     let file = DecodeReaderBytesBuilder::new()
         .encoding(Some(encoding_rs::UTF_8))
-        .build(file);
+        .build(reader);
     let file = BufReader::new(file);
-    let mut lines = Vec::new();
+    let mut lines: Vec<LogLine> = Vec::new();
+    let mut problems = Vec::new();
 
     for raw_line in file.lines() {
         let line = raw_line?;
@@ -54,19 +368,44 @@ pub fn read_lines(path: &Path, log_date: &Date<Utc>) -> anyhow::Result<Vec<LogLi
             continue;
         }
 
-        let line = parse_line(line, log_date)?;
-        lines.push(line)
+        match parse(&line, log_date) {
+            Ok(parsed_line) => lines.push(parsed_line),
+            Err(reason) if !lenient => anyhow::bail!("Parse line error ({}): {}", reason, line),
+            Err(reason) => {
+                let date = lines
+                    .last()
+                    .map_or_else(|| log_date.and_hms(0, 0, 0), |previous| previous.date);
+
+                problems.push(ParseProblem {
+                    line_number: lines.len() as u64 + 1,
+                    reason,
+                    text: line.clone(),
+                });
+                lines.push(LogLine {
+                    date,
+                    content: LogLineContent::Status(line),
+                });
+            }
+        }
     }
 
-    Ok(lines)
+    Ok((lines, problems))
 }
 
-fn parse_line(line: String, log_date: &Date<Utc>) -> anyhow::Result<LogLine> {
-    lazy_static! {
-        static ref PATTERN: Regex = Regex::new(r"\[(\d\d:\d\d)\] (\S+) (.*)").unwrap();
-    }
+/// Decodes a log file into its lines without parsing them, including the
+/// blank lines that the parser skips.
+pub fn read_raw_lines<R: Read>(reader: R) -> anyhow::Result<Vec<String>> {
+    let file = DecodeReaderBytesBuilder::new()
+        .encoding(Some(encoding_rs::UTF_8))
+        .build(reader);
+    let lines = BufReader::new(file).lines().collect::<Result<_, _>>()?;
 
-    if let Some(captures) = PATTERN.captures(&line) {
+    Ok(lines)
+}
+
+/// Parses a line in irclogger's `[HH:MM] <nickname> text` format.
+pub fn parse_line(line: &str, log_date: &Date<Utc>) -> Result<LogLine, String> {
+    if let Some(captures) = LINE_PATTERN.captures(line) {
         let time_str = captures.get(1).unwrap().as_str();
         let nickname = captures
             .get(2)
@@ -76,7 +415,8 @@ fn parse_line(line: String, log_date: &Date<Utc>) -> anyhow::Result<LogLine> {
             .trim_end_matches('>');
         let text = captures.get(3).unwrap().as_str();
 
-        let time = NaiveTime::parse_from_str(time_str, "%H:%M")?;
+        let time = NaiveTime::parse_from_str(time_str, "%H:%M")
+            .map_err(|_| "invalid timestamp".to_string())?;
         let date = log_date.and_time(time).unwrap();
 
         if nickname == "***" {
@@ -94,6 +434,54 @@ fn parse_line(line: String, log_date: &Date<Utc>) -> anyhow::Result<LogLine> {
             })
         }
     } else {
-        anyhow::bail!("Parse line error: {}", line);
+        Err("no timestamp and nickname".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::{parse_lines, parse_lines_lenient, read_log_tail, LogLineContent};
+
+    const LOG: &str = "[00:01] <alice> hello\ngarbage\n\n[25:00] <bob> hi\n[00:03] <bob> bye\n";
+
+    #[test]
+    fn lenient_parsing_keeps_unparsable_lines() {
+        let date = Utc.ymd(2021, 1, 4);
+
+        assert!(parse_lines(LOG.as_bytes(), &date).is_err());
+
+        let (lines, problems) = parse_lines_lenient(LOG.as_bytes(), &date).unwrap();
+
+        assert_eq!(lines.len(), 4);
+        assert!(matches!(&lines[1].content, LogLineContent::Status(text) if text == "garbage"));
+        assert_eq!(lines[1].date, date.and_hms(0, 1, 0));
+        assert_eq!(
+            problems
+                .iter()
+                .map(|problem| (problem.line_number, problem.reason.as_str()))
+                .collect::<Vec<(u64, &str)>>(),
+            vec![(2, "no timestamp and nickname"), (3, "invalid timestamp")]
+        );
+    }
+
+    #[test]
+    fn reads_last_lines_from_end() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("2021-01-04,Mon.log");
+        let log = (0..2000)
+            .map(|index| format!("[00:00] <alice> line {}\n", index))
+            .collect::<String>();
+        std::fs::write(&path, format!("{}\n\n", log)).unwrap();
+
+        let tail = String::from_utf8(read_log_tail(&path, 3).unwrap()).unwrap();
+        assert_eq!(
+            tail,
+            "[00:00] <alice> line 1997\n[00:00] <alice> line 1998\n[00:00] <alice> line 1999\n\n\n"
+        );
+
+        let whole = read_log_tail(&path, 5000).unwrap();
+        assert_eq!(whole.len(), log.len() + 2);
     }
 }
@@ -1,247 +1,3831 @@
+use std::{
+    cmp::Ordering,
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
 use askama::Template;
-use chrono::{DateTime, Utc};
+use chrono::{Date, DateTime, Datelike, NaiveDate, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
 use gotham::{
     helpers::http::response::{create_empty_response, create_response},
-    hyper::{Body, HeaderMap, Response, StatusCode, Uri},
+    hyper::{
+        body::HttpBody, header, upgrade::OnUpgrade, Body, HeaderMap, Response, StatusCode, Uri,
+    },
     state::{FromState, State},
 };
 use gotham_derive::{StateData, StaticResponseExtender};
 use http_auth_basic::Credentials;
 use lazy_static::lazy_static;
+use mime::Mime;
 use regex::Regex;
-use serde::Deserialize;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio_tungstenite::{
+    tungstenite::{handshake::derive_accept_key, protocol::Role},
+    WebSocketStream,
+};
+
+use crate::{
+    annotation::{Annotation, MAX_NOTE_LENGTH},
+    app::run_blocking,
+    bookmark::{Bookmark, MAX_LABEL_LENGTH},
+    config::{ColorTheme, Density},
+    cursor::Cursor,
+    digest::DailyDigest,
+    hidden_channel::{HiddenChannel, MAX_REASON_LENGTH},
+    locale::{DateFormatter, WeekdayName},
+    parse_report::ParseProblemRecord,
+    preferences::{Preferences, PREFERENCES_COOKIE},
+    preview::{LinkPreview, LinkPreviewer},
+    proxy::{ImageProxy, Thumbnail},
+    reader::{LineKind, LogLine, LogLineContent},
+    search::{SearchBackend, SearchBudgetAction, SearchCoverage, SearchMatches, SearchOptions},
+    search_history::{SavedSearch, SearchParameters},
+    state::{
+        AppState, ArchiveSummary, ChannelDailyEntry, ChannelInfo, ContinuousPosition, NickCard,
+        SearchResultEntry,
+    },
+    stats::DayComparison,
+    timing::{record_phase, time_phase},
+};
+
+pub mod api;
+
+fn render_template<T: Template>(state: &mut State, template: T) -> anyhow::Result<Response<Body>> {
+    render_template_with_mime(state, template, mime::TEXT_HTML_UTF_8)
+}
+
+/// Formats dates in the client's preferred language, falling back to the
+/// configured locale.
+fn date_formatter(state: &State) -> DateFormatter {
+    let accept_language = HeaderMap::borrow_from(state)
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok());
+
+    DateFormatter::negotiate(accept_language, AppState::borrow_from(state).locale)
+}
+
+/// Renders a page from the template directory if it has one of the same
+/// name, or else from the built-in template.
+fn render_page<T: Template + Serialize>(
+    state: &mut State,
+    name: &str,
+    template: T,
+) -> anyhow::Result<Response<Body>> {
+    if let Some(page_templates) = AppState::borrow_from(state).page_templates.clone() {
+        let content = time_phase(state, "render", || page_templates.render(name, &template))?;
+
+        if let Some(content) = content {
+            return Ok(create_response(
+                state,
+                StatusCode::OK,
+                mime::TEXT_HTML_UTF_8,
+                content.into_bytes(),
+            ));
+        }
+    }
+
+    render_template(state, template)
+}
+
+fn render_template_with_mime<T: Template>(
+    state: &mut State,
+    template: T,
+    mime: Mime,
+) -> anyhow::Result<Response<Body>> {
+    let content = time_phase(state, "render", || template.render())?;
+
+    Ok(create_response(
+        state,
+        StatusCode::OK,
+        mime,
+        content.into_bytes(),
+    ))
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct ChannelParams {
+    channel: String,
+}
+
+#[derive(Template, Serialize)]
+#[template(path = "index.html")]
+struct IndexTemplate {
+    channels: Vec<ChannelInfo>,
+    message: String,
+    preferences_enabled: bool,
+    summary: Option<IndexSummary>, // Left out while the archive is empty
+    #[serde(skip)]
+    dates: DateFormatter,
+    color_theme: ColorTheme,
+    path_prefix: String,
+}
+
+#[derive(Serialize)]
+struct IndexSummary {
+    pub channel_count: String,
+    pub day_count: String,
+    pub line_count: String,
+    #[serde(serialize_with = "crate::state::serialize_date")]
+    pub oldest_date: Date<Utc>,
+    #[serde(serialize_with = "crate::state::serialize_date")]
+    pub newest_date: Date<Utc>,
+}
+
+impl IndexSummary {
+    fn new(summary: &ArchiveSummary) -> Option<Self> {
+        Some(Self {
+            channel_count: format_count(summary.channel_count as u64),
+            day_count: format_count(summary.day_count as u64),
+            line_count: format_count(summary.line_count),
+            oldest_date: summary.oldest_date?,
+            newest_date: summary.newest_date?,
+        })
+    }
+}
+
+/// Formats a number with thousands separators, such as "1,234,567".
+fn format_count(count: u64) -> String {
+    let digits = count.to_string();
+    let mut formatted = String::new();
+
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index).is_multiple_of(3) {
+            formatted.push(',');
+        }
+
+        formatted.push(digit);
+    }
+
+    formatted
+}
+
+pub fn index(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let app_state = AppState::borrow_from(state);
+    let channels = app_state.get_channels()?;
+    let message = app_state.get_custom_message()?;
+    let summary = time_phase(state, "count", || app_state.get_archive_summary())?;
+
+    let template = IndexTemplate {
+        channels,
+        message,
+        preferences_enabled: app_state.preference_signer.is_enabled(),
+        summary: IndexSummary::new(&summary),
+        dates: date_formatter(state),
+        color_theme: viewer_color_theme(state),
+        path_prefix: path_prefix(state),
+    };
+    let response = render_page(state, "index.html", template)?;
+
+    Ok(response)
+}
+
+const CHANNELS_FEED_DAYS: i64 = 90;
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct StaticAssetParams {
+    name: String,
+}
+
+pub async fn static_asset(mut state: State) -> (State, anyhow::Result<Response<Body>>) {
+    let result = static_asset_impl(&mut state).await;
+    (state, result)
+}
+
+async fn static_asset_impl(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let params = StaticAssetParams::take_from(state);
+    let app_state = AppState::borrow_from(state);
+
+    let content =
+        match crate::assets::read_asset(app_state.static_directory.as_deref(), &params.name).await?
+        {
+            Some(content) => content,
+            None => return Ok(create_empty_response(state, StatusCode::NOT_FOUND)),
+        };
+    let mut response = create_response(
+        state,
+        StatusCode::OK,
+        crate::assets::asset_mime(&params.name),
+        content,
+    );
+    response
+        .headers_mut()
+        .insert("Cache-Control", "public, max-age=300".parse()?);
+
+    Ok(response)
+}
+
+pub async fn theme_stylesheet(mut state: State) -> (State, anyhow::Result<Response<Body>>) {
+    let result = theme_stylesheet_impl(&mut state).await;
+    (state, result)
+}
+
+async fn theme_stylesheet_impl(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let app_state = AppState::borrow_from(state);
+    let theme = app_state.get_theme().await?;
+    let mut response = create_response(state, StatusCode::OK, mime::TEXT_CSS_UTF_8, theme);
+    response
+        .headers_mut()
+        .insert("Cache-Control", "public, max-age=300".parse()?);
+
+    Ok(response)
+}
+
+#[derive(Template)]
+#[template(path = "channels_feed.xml")]
+struct ChannelsFeedTemplate {
+    base_url: String,
+    items: Vec<ChannelsFeedItem>,
+}
+
+struct ChannelsFeedItem {
+    name: String,
+    resumed: bool,
+    active_since: Date<Utc>,
+    pub_date: String,
+}
+
+pub fn channels_feed(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let app_state = AppState::borrow_from(state);
+    let cutoff = Utc::today() - chrono::Duration::days(CHANNELS_FEED_DAYS);
+    let items = app_state
+        .get_channel_activity()?
+        .iter()
+        .filter(|activity| activity.active_since >= cutoff)
+        .map(|activity| ChannelsFeedItem {
+            name: activity.name.clone(),
+            resumed: activity.resumed,
+            active_since: activity.active_since,
+            pub_date: activity.active_since.and_hms(0, 0, 0).to_rfc2822(),
+        })
+        .collect();
+
+    let template = ChannelsFeedTemplate {
+        base_url: public_url(state, ""),
+        items,
+    };
+
+    render_template_with_mime(
+        state,
+        template,
+        "application/rss+xml; charset=utf-8".parse()?,
+    )
+}
+
+const DIGEST_FEED_DAYS: usize = 7;
+
+#[derive(Template)]
+#[template(path = "digest_feed.xml")]
+struct DigestFeedTemplate {
+    base_url: String,
+    channel_name: String,
+    items: Vec<DigestFeedItem>,
+    dates: DateFormatter,
+}
+
+struct DigestFeedItem {
+    digest: DailyDigest,
+    pub_date: String,
+}
+
+pub fn channel_digest_feed(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let params = ChannelParams::take_from(state);
+
+    if !user_has_access(state, &params.channel)? {
+        return Ok(build_auth_response(state));
+    }
+
+    let app_state = AppState::borrow_from(state);
+    let today = Utc::today();
+    let mut items = Vec::new();
+
+    for date_slug in app_state.get_channel_log_date_slugs(&params.channel)? {
+        if items.len() == DIGEST_FEED_DAYS {
+            break;
+        }
+
+        if crate::state::parse_date_slug(&date_slug)? >= today {
+            continue;
+        }
+
+        let digest = DailyDigest::build(app_state, &params.channel, &date_slug)?;
+
+        items.push(DigestFeedItem {
+            pub_date: digest.date.succ().and_hms(0, 0, 0).to_rfc2822(),
+            digest,
+        });
+    }
+
+    let template = DigestFeedTemplate {
+        base_url: public_url(state, ""),
+        channel_name: params.channel,
+        items,
+        // Feeds are shared between readers, so they use the configured locale
+        dates: DateFormatter::new(AppState::borrow_from(state).locale),
+    };
+
+    render_template_with_mime(
+        state,
+        template,
+        "application/rss+xml; charset=utf-8".parse()?,
+    )
+}
+
+#[derive(Template, Serialize)]
+#[template(path = "channel_index.html")]
+struct ChannelIndexTemplate {
+    channel_name: String,
+    years: Vec<ChannelIndexYear>,
+    searchable: bool,
+    #[serde(skip)]
+    dates: DateFormatter,
+    color_theme: ColorTheme,
+    path_prefix: String,
+}
+
+#[derive(Serialize)]
+struct ChannelIndexYear {
+    pub year: i32,
+    pub message_count: u64,
+    pub day_count: usize,
+    pub months: Vec<ChannelIndexMonth>,
+    pub open: bool,
+}
+
+#[derive(Serialize)]
+struct ChannelIndexMonth {
+    pub id: String,   // Such as "2021-01", for linking to a month
+    pub name: String, // Such as "January"
+    pub message_count: u64,
+    pub entries: Vec<ChannelDailyEntry>,
+    pub open: bool,
+}
+
+/// Groups daily entries, newest first, under years and months with only the
+/// newest month expanded.
+fn group_daily_entries(
+    entries: Vec<ChannelDailyEntry>,
+    dates: &DateFormatter,
+) -> Vec<ChannelIndexYear> {
+    let mut years: Vec<ChannelIndexYear> = Vec::new();
+
+    for entry in entries {
+        if years
+            .last()
+            .is_none_or(|year| year.year != entry.date.year())
+        {
+            years.push(ChannelIndexYear {
+                year: entry.date.year(),
+                message_count: 0,
+                day_count: 0,
+                months: Vec::new(),
+                open: years.is_empty(),
+            });
+        }
+
+        let year = years.last_mut().unwrap();
+        let id = entry.date.format("%Y-%m").to_string();
+
+        if year.months.last().is_none_or(|month| month.id != id) {
+            year.months.push(ChannelIndexMonth {
+                id,
+                name: dates.month(entry.date),
+                message_count: 0,
+                entries: Vec::new(),
+                open: year.open && year.months.is_empty(),
+            });
+        }
+
+        let month = year.months.last_mut().unwrap();
+        year.message_count += entry.message_count;
+        year.day_count += 1;
+        month.message_count += entry.message_count;
+        month.entries.push(entry);
+    }
+
+    years
+}
+
+#[derive(Template)]
+#[template(path = "channel_calendar.html")]
+struct ChannelCalendarTemplate {
+    channel_name: String,
+    month_label: String, // Such as "January 2021"
+    weekdays: Vec<WeekdayName>,
+    weeks: Vec<Vec<Option<CalendarDay>>>,
+    previous_month: Option<String>, // Query string of the nearest earlier month with logs
+    next_month: Option<String>,
+    color_theme: ColorTheme,
+    path_prefix: String,
+}
+
+struct CalendarDay {
+    pub day: u32,
+    pub entry: Option<ChannelDailyEntry>,
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct ChannelCalendarQuery {
+    year: Option<i32>,
+    month: Option<u32>,
+}
+
+/// Lays out a month as weeks from Monday to Sunday, placing the entries that
+/// fall in the month. Days outside the month are `None`.
+fn make_calendar_weeks(
+    first_day: NaiveDate,
+    entries: Vec<ChannelDailyEntry>,
+) -> Vec<Vec<Option<CalendarDay>>> {
+    let mut entries = entries
+        .into_iter()
+        .filter(|entry| {
+            entry.date.year() == first_day.year() && entry.date.month() == first_day.month()
+        })
+        .map(|entry| (entry.date.day(), entry))
+        .collect::<HashMap<u32, ChannelDailyEntry>>();
+    let mut weeks = Vec::new();
+    let mut week = (0..first_day.weekday().num_days_from_monday())
+        .map(|_| None)
+        .collect::<Vec<Option<CalendarDay>>>();
+    let mut date = first_day;
+
+    while date.month() == first_day.month() {
+        week.push(Some(CalendarDay {
+            day: date.day(),
+            entry: entries.remove(&date.day()),
+        }));
+
+        if week.len() == 7 {
+            weeks.push(std::mem::take(&mut week));
+        }
+
+        date = date.succ();
+    }
+
+    if !week.is_empty() {
+        week.resize_with(7, || None);
+        weeks.push(week);
+    }
+
+    weeks
+}
+
+pub fn channel_calendar(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let params = ChannelParams::take_from(state);
+
+    if !user_has_access(state, &params.channel)? {
+        return Ok(build_auth_response(state));
+    }
+
+    let query = ChannelCalendarQuery::take_from(state);
+    let app_state = AppState::borrow_from(state);
+    let entries = time_phase(state, "count", || {
+        app_state.get_channel_daily_entries(&params.channel)
+    })?;
+
+    // Entries are newest first, and the newest month is shown by default
+    let latest = entries
+        .first()
+        .map_or_else(|| Utc::today().naive_utc(), |entry| entry.date.naive_utc());
+    let year = query.year.unwrap_or_else(|| latest.year());
+    let month = query.month.unwrap_or_else(|| latest.month());
+    let (first_day, next_first_day) = match (
+        NaiveDate::from_ymd_opt(year, month, 1),
+        year.checked_add((month / 12) as i32)
+            .and_then(|next_year| NaiveDate::from_ymd_opt(next_year, month % 12 + 1, 1)),
+    ) {
+        (Some(first_day), Some(next_first_day)) => (first_day, next_first_day),
+        _ => return Ok(create_empty_response(state, StatusCode::BAD_REQUEST)),
+    };
+
+    let month_query = |date: &Date<Utc>| format!("year={}&month={}", date.year(), date.month());
+    let previous_month = entries
+        .iter()
+        .find(|entry| entry.date.naive_utc() < first_day)
+        .map(|entry| month_query(&entry.date));
+    let next_month = entries
+        .iter()
+        .rev()
+        .find(|entry| entry.date.naive_utc() >= next_first_day)
+        .map(|entry| month_query(&entry.date));
+
+    let dates = date_formatter(state);
+    let template = ChannelCalendarTemplate {
+        channel_name: params.channel,
+        month_label: dates.month_year(Utc.from_utc_date(&first_day)),
+        weekdays: dates.weekday_names(),
+        weeks: make_calendar_weeks(first_day, entries),
+        previous_month,
+        next_month,
+        color_theme: viewer_color_theme(state),
+        path_prefix: path_prefix(state),
+    };
+
+    render_template(state, template)
+}
+
+#[derive(Template)]
+#[template(path = "channel_compare.html")]
+struct ChannelCompareTemplate {
+    channel_name: String,
+    from: String,
+    to: String,
+    date_slugs: Vec<String>,           // Suggestions for the date fields
+    comparison: Option<DayComparison>, // Left out while the channel has no logs
+    message_change: String,            // Such as "+12"
+    color_theme: ColorTheme,
+    path_prefix: String,
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct ChannelCompareQuery {
+    from: Option<String>,
+    to: Option<String>,
+}
+
+/// Compares who was active on two days. Without dates, the two most recent
+/// days are compared.
+pub fn channel_compare(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let params = ChannelParams::take_from(state);
+
+    if !user_has_access(state, &params.channel)? {
+        return Ok(build_auth_response(state));
+    }
+
+    let query = ChannelCompareQuery::take_from(state);
+    let app_state = AppState::borrow_from(state);
+
+    // Newest first
+    let date_slugs = app_state.get_channel_log_date_slugs(&params.channel)?;
+    let to = query
+        .to
+        .filter(|date_slug| !date_slug.is_empty())
+        .or_else(|| date_slugs.first().cloned());
+    let from = query
+        .from
+        .filter(|date_slug| !date_slug.is_empty())
+        .or_else(|| date_slugs.get(1).cloned())
+        .or_else(|| to.clone());
+
+    let comparison = match (&from, &to) {
+        (Some(from), Some(to)) => {
+            if !date_slugs.contains(from) || !date_slugs.contains(to) {
+                return Ok(create_empty_response(state, StatusCode::NOT_FOUND));
+            }
+
+            Some(time_phase(state, "count", || {
+                Ok::<_, anyhow::Error>(DayComparison::new(
+                    &app_state.get_day_nick_counts(&params.channel, from)?,
+                    &app_state.get_day_nick_counts(&params.channel, to)?,
+                ))
+            })?)
+        }
+        _ => None,
+    };
+
+    let template = ChannelCompareTemplate {
+        channel_name: params.channel,
+        from: from.unwrap_or_default(),
+        to: to.unwrap_or_default(),
+        date_slugs,
+        message_change: comparison.as_ref().map_or_else(String::new, |comparison| {
+            format!(
+                "{:+}",
+                comparison.messages_after as i64 - comparison.messages_before as i64
+            )
+        }),
+        comparison,
+        color_theme: viewer_color_theme(state),
+        path_prefix: path_prefix(state),
+    };
+
+    render_template(state, template)
+}
+
+pub fn channel_daily_index(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let params = ChannelParams::take_from(state);
+
+    if !user_has_access(state, &params.channel)? {
+        return Ok(build_auth_response(state));
+    }
+
+    let app_state = AppState::borrow_from(state);
+    let entries = time_phase(state, "count", || {
+        app_state.get_channel_daily_entries(&params.channel)
+    })?;
+
+    let searchable = app_state.get_channel_metadata(&params.channel)?.searchable;
+    let dates = date_formatter(state);
+    let template = ChannelIndexTemplate {
+        channel_name: params.channel,
+        years: group_daily_entries(entries, &dates),
+        searchable,
+        dates,
+        color_theme: viewer_color_theme(state),
+        path_prefix: path_prefix(state),
+    };
+    let response = render_page(state, "channel_index.html", template)?;
+
+    Ok(response)
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct ChannelLinesQuery {
+    pub date: String,
+    sel: Option<String>,
+    raw: Option<String>,
+    thumbs: Option<String>,
+    view: Option<String>,
+    hide_status: Option<String>,
+    density: Option<String>,
+    page: Option<String>,
+    nick: Option<String>,        // Show only this nickname's messages
+    nick_status: Option<String>, // "on" to also show status lines mentioning the nickname
+    tz: Option<String>,          // Timezone of the times shown, such as "Europe/Paris"
+    after: Option<String>,       // Cursor; shows the page with the line after it
+    before: Option<String>,      // Cursor; shows the page with the line before it
+}
+
+/// Selects messages by who sent them. Nicknames are compared case
+/// insensitively, and actions count as messages of the nickname that starts
+/// them. Status lines have no sender, so only a `nick` filter removes them,
+/// unless it keeps those mentioning one of its nicknames.
+struct NickFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+    include_status: bool, // Keeps status lines mentioning an included nickname, such as its joins
+}
+
+impl NickFilter {
+    fn new(include: Option<&str>, exclude: Option<&str>) -> Self {
+        let parse = |nicks: Option<&str>| {
+            nicks
+                .unwrap_or_default()
+                .split(',')
+                .map(|nick| nick.trim().to_lowercase())
+                .filter(|nick| !nick.is_empty())
+                .collect::<Vec<String>>()
+        };
+
+        Self {
+            include: parse(include),
+            exclude: parse(exclude),
+            include_status: false,
+        }
+    }
+
+    fn with_status_mentions(self, include_status: bool) -> Self {
+        Self {
+            include_status,
+            ..self
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    fn is_match(&self, line: &LogLine) -> bool {
+        let nickname = match crate::reader::message_sender(&line.content) {
+            Some(nickname) => nickname.to_lowercase(),
+            None => return self.include.is_empty() || self.is_status_mention(line),
+        };
+
+        (self.include.is_empty() || self.include.contains(&nickname))
+            && !self.exclude.contains(&nickname)
+    }
+
+    fn is_status_mention(&self, line: &LogLine) -> bool {
+        let text = match &line.content {
+            LogLineContent::Status(text) if self.include_status => text,
+            _ => return false,
+        };
+
+        text.split_whitespace()
+            .map(|word| {
+                word.trim_matches(|c: char| ",.:;!?()".contains(c))
+                    .to_lowercase()
+            })
+            .any(|word| self.include.contains(&word))
+    }
+}
+
+#[derive(Template, Serialize)]
+#[template(path = "channel_lines.html")]
+struct ChannelLinesTemplate {
+    pub channel_name: String,
+    pub lines: Vec<LogOutputLine>,
+    pub date_slug: String,
+    pub date_label: String, // Such as "Monday 4 January 2021", for screen readers
+    pub selected_line_number: u64,
+    pub thumbnails_available: bool,
+    pub thumbnails_enabled: bool,
+    pub annotations_enabled: bool,
+    pub bookmarks_enabled: bool,
+    pub has_selection: bool,
+    pub searchable: bool,
+    pub previous_day: Option<String>,
+    pub next_day: Option<String>,
+    pub navigation_json: String,
+    pub compact: bool, // Plain lines without previews, thumbnails or notes
+    pub page: usize,
+    pub page_count: usize,      // Long days are split into pages
+    pub earlier_cursor: String, // Of the page's first line, for linking to the page before
+    pub later_cursor: String,   // Of the page's last line, for linking to the page after
+    #[serde(skip)]
+    pub dates: DateFormatter,
+    pub nick: Option<String>, // Only this nickname's lines are shown
+    pub nick_query: String,   // Keeps the nickname filter in links to other pages and days
+    pub timezone: String,     // Name of the timezone the times are shown in
+    pub color_theme: ColorTheme,
+    pub path_prefix: String,
+}
+
+/// Data for keyboard navigation, embedded in the day view as JSON.
+#[derive(Serialize)]
+struct DayNavigation<'a> {
+    channel: &'a str,
+    date_slug: &'a str,
+    line_numbers: Vec<u64>,
+    selected_line: Option<u64>,
+    previous_day: Option<&'a str>,
+    next_day: Option<&'a str>,
+    path_prefix: &'a str,
+    live_url: Option<String>, // Set when viewing today's log
+    utc_offset_minutes: i32,  // For showing new lines in the viewer's timezone
+}
+
+impl DayNavigation<'_> {
+    /// Serializes for a `<script>` element, which can't contain `</`.
+    fn to_script_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string(self)?.replace('<', "\\u003c"))
+    }
+}
+
+const NAVIGATION_SCRIPT: &str = include_str!("../static/navigation.js");
+
+const NICK_CARD_SCRIPT: &str = include_str!("../static/nick_card.js");
+
+const LIVE_SCRIPT: &str = include_str!("../static/live.js");
+
+pub fn live_script(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let mut response = create_response(
+        state,
+        StatusCode::OK,
+        mime::APPLICATION_JAVASCRIPT_UTF_8,
+        LIVE_SCRIPT,
+    );
+    response
+        .headers_mut()
+        .insert("Cache-Control", "public, max-age=300".parse()?);
+
+    Ok(response)
+}
+
+pub fn nick_card_script(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let mut response = create_response(
+        state,
+        StatusCode::OK,
+        mime::APPLICATION_JAVASCRIPT_UTF_8,
+        NICK_CARD_SCRIPT,
+    );
+    response
+        .headers_mut()
+        .insert("Cache-Control", "public, max-age=300".parse()?);
+
+    Ok(response)
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct NickCardQuery {
+    nick: String,
+    date: String,
+    format: Option<String>, // "json" instead of an HTML fragment
+}
+
+#[derive(Template)]
+#[template(path = "nick_card.html")]
+struct NickCardTemplate {
+    nickname: String,
+    lines_url: String, // The day showing only this nickname's lines
+    day_label: String,
+    message_count: u64,
+    first_seen: Option<String>,
+    last_seen: Option<String>,
+}
+
+impl NickCardTemplate {
+    fn new(
+        card: NickCard,
+        path_prefix: &str,
+        channel: &str,
+        date_slug: &str,
+        date: Date<Utc>,
+        dates: &DateFormatter,
+    ) -> Self {
+        Self {
+            lines_url: format!(
+                "{}/bin/irclogger_log/{}?date={}&nick={}",
+                path_prefix,
+                channel,
+                percent_encoding::utf8_percent_encode(
+                    date_slug,
+                    percent_encoding::NON_ALPHANUMERIC
+                ),
+                percent_encoding::utf8_percent_encode(
+                    &card.nickname,
+                    percent_encoding::NON_ALPHANUMERIC
+                ),
+            ),
+            nickname: card.nickname,
+            day_label: dates.date(date),
+            message_count: card.message_count,
+            first_seen: card.first_seen.map(|date| dates.date(date)),
+            last_seen: card.last_seen.map(|date| dates.date(date)),
+        }
+    }
+}
+
+/// Serves a nickname's activity for the popup card in the day view.
+pub fn nick_card(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let params = ChannelParams::take_from(state);
+
+    if !user_has_access(state, &params.channel)? {
+        return Ok(build_auth_response(state));
+    }
+
+    let query = NickCardQuery::take_from(state);
+
+    if !is_date_string_ok(&query.date) || query.nick.is_empty() {
+        return Ok(create_empty_response(state, StatusCode::BAD_REQUEST));
+    }
+
+    let app_state = AppState::borrow_from(state);
+
+    // Skips reading the channel's history for nicknames that were never seen,
+    // unless they're new on a day that isn't indexed yet
+    if let Some(false) = app_state
+        .search_index
+        .as_ref()
+        .and_then(|search_index| search_index.is_known_nickname(&params.channel, &query.nick))
+    {
+        let day_counts = app_state.get_day_nick_counts(&params.channel, &query.date)?;
+
+        if !day_counts.contains_key(&query.nick.to_lowercase()) {
+            return Ok(create_empty_response(state, StatusCode::NOT_FOUND));
+        }
+    }
+    let card = time_phase(state, "count", || {
+        app_state.get_nick_card(&params.channel, &query.nick, &query.date)
+    })?;
+
+    if let Some("json") = query.format.as_deref() {
+        return Ok(create_response(
+            state,
+            StatusCode::OK,
+            mime::APPLICATION_JSON,
+            serde_json::to_vec(&card)?,
+        ));
+    }
+
+    let date = crate::state::parse_date_slug(&query.date)?;
+    let template = NickCardTemplate::new(
+        card,
+        &AppState::borrow_from(state).path_prefix,
+        &params.channel,
+        &query.date,
+        date,
+        &date_formatter(state),
+    );
+
+    render_template(state, template)
+}
+
+pub fn navigation_script(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let mut response = create_response(
+        state,
+        StatusCode::OK,
+        mime::APPLICATION_JAVASCRIPT_UTF_8,
+        NAVIGATION_SCRIPT,
+    );
+    response
+        .headers_mut()
+        .insert("Cache-Control", "public, max-age=300".parse()?);
+
+    Ok(response)
+}
+
+const CLEAN_VIEW_PAGE_LINES: usize = 50;
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct ChannelLiveQuery {
+    after: Option<u64>, // Last line number the client has
+}
+
+/// Streams lines appended to today's log as server-sent events.
+pub fn channel_live(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let params = ChannelParams::take_from(state);
+
+    if !user_has_access(state, &params.channel)? {
+        return Ok(build_auth_response(state));
+    }
+
+    let query = ChannelLiveQuery::take_from(state);
+    let app_state = AppState::borrow_from(state);
+    let today = Utc::today();
+    let date_slug = match app_state.find_date_slug(&params.channel, today)? {
+        Some(date_slug) => date_slug,
+        None => return Ok(create_empty_response(state, StatusCode::NOT_FOUND)),
+    };
+    let path = app_state.get_log_path(&params.channel, &date_slug)?;
+    let script_parser = app_state.get_script_parser(&params.channel)?;
+
+    // Browsers send the last event ID when reconnecting
+    let after_line = HeaderMap::borrow_from(state)
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .or(query.after);
+
+    let (sender, body) = Body::channel();
+    tokio::spawn(crate::live::send_events(
+        crate::live::follow_log(path, today, after_line, script_parser),
+        sender,
+    ));
+
+    let mut response = create_response(state, StatusCode::OK, mime::TEXT_EVENT_STREAM, body);
+    response
+        .headers_mut()
+        .insert("Cache-Control", "no-cache".parse()?);
+
+    Ok(response)
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct ChannelWebSocketQuery {
+    after: Option<u64>,   // Last line number the client has
+    backlog: Option<u64>, // Number of existing lines to send first without `after`
+}
+
+/// Streams lines appended to today's log as JSON messages over a WebSocket.
+pub fn channel_websocket(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let params = ChannelParams::take_from(state);
+
+    if !user_has_access(state, &params.channel)? {
+        return Ok(build_auth_response(state));
+    }
+
+    let headers = HeaderMap::borrow_from(state);
+    let is_upgrade = headers
+        .get(header::UPGRADE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+    let key = match headers.get(header::SEC_WEBSOCKET_KEY) {
+        Some(key) if is_upgrade => key.as_bytes().to_vec(),
+        _ => return Ok(create_empty_response(state, StatusCode::BAD_REQUEST)),
+    };
+
+    let query = ChannelWebSocketQuery::take_from(state);
+    let app_state = AppState::borrow_from(state);
+    let today = Utc::today();
+    let date_slug = match app_state.find_date_slug(&params.channel, today)? {
+        Some(date_slug) => date_slug,
+        None => return Ok(create_empty_response(state, StatusCode::NOT_FOUND)),
+    };
+    let path = app_state.get_log_path(&params.channel, &date_slug)?;
+    let script_parser = app_state.get_script_parser(&params.channel)?;
+
+    let after_line = match (query.after, query.backlog) {
+        (Some(after), _) => Some(after),
+        (None, Some(backlog)) => {
+            let line_count = crate::reader::read_raw_lines(crate::reader::open_log(&path)?)?
+                .iter()
+                .filter(|line| !line.is_empty())
+                .count() as u64;
+            Some(line_count.saturating_sub(backlog))
+        }
+        (None, None) => None,
+    };
+
+    let on_upgrade = match OnUpgrade::try_take_from(state) {
+        Some(on_upgrade) => on_upgrade,
+        None => return Ok(create_empty_response(state, StatusCode::BAD_REQUEST)),
+    };
+
+    tokio::spawn(async move {
+        match on_upgrade.await {
+            Ok(upgraded) => {
+                let websocket =
+                    WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+                crate::live::send_websocket_messages(
+                    crate::live::follow_log(path, today, after_line, script_parser),
+                    websocket,
+                )
+                .await;
+            }
+            Err(error) => tracing::warn!("WebSocket upgrade failed: {}", error),
+        }
+    });
+
+    let mut response = create_empty_response(state, StatusCode::SWITCHING_PROTOCOLS);
+    let headers = response.headers_mut();
+    headers.insert(header::UPGRADE, "websocket".parse()?);
+    headers.insert(header::CONNECTION, "Upgrade".parse()?);
+    headers.insert(
+        header::SEC_WEBSOCKET_ACCEPT,
+        derive_accept_key(&key).parse()?,
+    );
+
+    Ok(response)
+}
+
+#[derive(Template)]
+#[template(path = "channel_lines_clean.html")]
+struct ChannelLinesCleanTemplate {
+    pub channel_name: String,
+    pub date_slug: String,
+    pub pages: Vec<Vec<LogOutputLine>>,
+}
+
+#[derive(Template)]
+#[template(path = "channel_lines_source.html")]
+struct ChannelLinesSourceTemplate {
+    pub channel_name: String,
+    pub path_prefix: String,
+    pub date_slug: String,
+    pub lines: Vec<SourceLine>,
+}
+
+struct SourceLine {
+    pub line_number: u64,
+    pub kind: LineKind,
+    pub text: String,
+}
+
+#[derive(Serialize)]
+struct LogOutputLine {
+    pub date: DateTime<Tz>, // In the viewer's timezone
+    pub nickname: String,
+    pub text: String,
+    pub line_number: u64,
+    pub link_previews: Vec<LinkPreview>,
+    pub thumbnails: Vec<Thumbnail>,
+    pub annotations: Vec<Annotation>,
+}
+
+#[derive(Template)]
+#[template(path = "channel_widget.html")]
+struct ChannelWidgetTemplate {
+    pub channel_name: String,
+    pub path_prefix: String,
+    pub lines: Vec<WidgetLine>,
+}
+
+struct WidgetLine {
+    pub date_slug: String,
+    pub line: LogOutputLine,
+}
+
+const DEFAULT_WIDGET_LINES: usize = 20;
+const MAX_WIDGET_LINES: usize = 100;
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct ChannelWidgetQuery {
+    n: Option<String>, // Number of lines, 20 by default
+}
+
+/// The latest lines of a public channel in a small page for other sites to
+/// show in an iframe. Only the configured origins may frame it.
+pub fn channel_widget(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let params = ChannelParams::take_from(state);
+    let query = ChannelWidgetQuery::take_from(state);
+    let app_state = AppState::borrow_from(state);
+
+    // Frames can't ask for credentials, so private channels have no widget
+    if app_state.widget_embed_origins.is_empty()
+        || !app_state.chat_log_directory.join(&params.channel).is_dir()
+        || app_state.is_channel_private(&params.channel)?
+    {
+        return Ok(create_empty_response(state, StatusCode::NOT_FOUND));
+    }
+
+    let count = match query.n.as_deref() {
+        None | Some("") => DEFAULT_WIDGET_LINES,
+        Some(count) => match count.parse::<usize>() {
+            Ok(count) if count > 0 && count <= MAX_WIDGET_LINES => count,
+            _ => return Ok(create_empty_response(state, StatusCode::BAD_REQUEST)),
+        },
+    };
+
+    let lines = time_phase(state, "parse", || {
+        app_state.get_recent_lines(&params.channel, count)
+    })?;
+    let template = ChannelWidgetTemplate {
+        channel_name: params.channel,
+        path_prefix: app_state.path_prefix.clone(),
+        lines: lines
+            .into_iter()
+            .map(|(date_slug, line)| WidgetLine {
+                line: make_output_line(&line, 0, app_state.timezone),
+                date_slug,
+            })
+            .collect(),
+    };
+    let frame_ancestors = format!(
+        "frame-ancestors {}",
+        app_state.widget_embed_origins.join(" ")
+    );
+
+    let mut response = render_template(state, template)?;
+    let headers = response.headers_mut();
+    headers.insert("Content-Security-Policy", frame_ancestors.parse()?);
+    headers.insert("Cache-Control", "public, max-age=60".parse()?);
+
+    Ok(response)
+}
+
+const BADGE_MAX_AGE: u32 = 3600; // Seconds
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct BadgeParams {
+    channel: String,
+    kind: String, // "messages-today.svg", "last-activity.svg" or "days.svg"
+}
+
+/// An SVG badge of a public channel's activity for READMEs. Each reads at
+/// most one log, or none with the day cache.
+pub fn channel_badge(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let params = BadgeParams::take_from(state);
+    let app_state = AppState::borrow_from(state);
+
+    // Badges are shown on other sites, which can't log in
+    if !app_state.chat_log_directory.join(&params.channel).is_dir()
+        || app_state.is_channel_private(&params.channel)?
+    {
+        return Ok(create_empty_response(state, StatusCode::NOT_FOUND));
+    }
+
+    // Date slugs are sorted newest first
+    let date_slugs = app_state.get_channel_log_date_slugs(&params.channel)?;
+    let (label, value, is_active) = match params.kind.as_str() {
+        "messages-today.svg" => {
+            let today = Utc::today();
+            let count = match date_slugs.first() {
+                Some(date_slug) if crate::state::parse_date_slug(date_slug)? == today => {
+                    time_phase(state, "count", || {
+                        app_state.get_day_summary(&params.channel, date_slug)
+                    })?
+                    .message_count
+                }
+                _ => 0,
+            };
+
+            ("messages today", format_count(count), count > 0)
+        }
+        "last-activity.svg" => match date_slugs.first() {
+            Some(date_slug) => (
+                "last activity",
+                crate::state::parse_date_slug(date_slug)?
+                    .format("%Y-%m-%d")
+                    .to_string(),
+                true,
+            ),
+            None => ("last activity", "never".to_string(), false),
+        },
+        "days.svg" => (
+            "logged days",
+            format_count(date_slugs.len() as u64),
+            !date_slugs.is_empty(),
+        ),
+        _ => return Ok(create_empty_response(state, StatusCode::NOT_FOUND)),
+    };
+
+    let badge = crate::badge::render_badge(
+        label,
+        &value,
+        if is_active {
+            crate::badge::COLOR_ACTIVE
+        } else {
+            crate::badge::COLOR_INACTIVE
+        },
+    );
+    let mut response = create_response(state, StatusCode::OK, mime::IMAGE_SVG, badge);
+    response.headers_mut().insert(
+        "Cache-Control",
+        format!("public, max-age={}", BADGE_MAX_AGE).parse()?,
+    );
+
+    Ok(response)
+}
+
+#[derive(Template)]
+#[template(path = "day_removed.html")]
+struct DayRemovedTemplate {
+    channel_name: String,
+    date_slug: String,
+    reason: String,
+    color_theme: ColorTheme,
+    path_prefix: String,
+}
+
+const DAY_PREPARING_REFRESH_SECONDS: u64 = 3;
+
+/// Shown while a day's log is copied off cold storage, and reloads itself.
+#[derive(Template)]
+#[template(path = "day_preparing.html")]
+struct DayPreparingTemplate {
+    channel_name: String,
+    date_slug: String,
+    refresh_seconds: u64,
+    color_theme: ColorTheme,
+    path_prefix: String,
+}
+
+pub fn channel_lines(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let params = ChannelParams::take_from(state);
+
+    if !user_has_access(state, &params.channel)? {
+        return Ok(build_auth_response(state));
+    }
+
+    let query = ChannelLinesQuery::take_from(state);
+
+    if !is_date_string_ok(&query.date) {
+        return Ok(create_empty_response(state, StatusCode::BAD_REQUEST));
+    }
+
+    let app_state = AppState::borrow_from(state);
+
+    if let Some(removed) = app_state.get_removed_day(&params.channel, &query.date)? {
+        let template = DayRemovedTemplate {
+            channel_name: params.channel,
+            date_slug: query.date,
+            reason: removed.reason,
+            color_theme: viewer_color_theme(state),
+            path_prefix: path_prefix(state),
+        };
+        let mut response = render_template(state, template)?;
+        *response.status_mut() = StatusCode::GONE;
+
+        return Ok(response);
+    }
+
+    if app_state.is_day_cold(&params.channel, &query.date)? {
+        let template = DayPreparingTemplate {
+            channel_name: params.channel,
+            date_slug: query.date,
+            refresh_seconds: DAY_PREPARING_REFRESH_SECONDS,
+            color_theme: viewer_color_theme(state),
+            path_prefix: path_prefix(state),
+        };
+        let mut response = render_template(state, template)?;
+        *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+        response.headers_mut().insert(
+            "Retry-After",
+            DAY_PREPARING_REFRESH_SECONDS.to_string().parse()?,
+        );
+
+        return Ok(response);
+    }
+
+    let cache_control = day_cache_control(state, &params.channel, &query.date)?;
+
+    if let Some("on") = query.raw.as_deref() {
+        let etag = app_state.get_log_etag(&params.channel, &query.date)?;
+
+        let mut response = if is_etag_fresh(state, &etag) {
+            create_empty_response(state, StatusCode::NOT_MODIFIED)
+        } else {
+            let raw_log = time_phase(state, "fs read", || {
+                app_state.get_raw_log(&params.channel, &query.date)
+            })?;
+
+            create_response(state, StatusCode::OK, mime::TEXT_PLAIN_UTF_8, raw_log)
+        };
+        response.headers_mut().insert("ETag", etag.parse()?);
+        response
+            .headers_mut()
+            .insert("Cache-Control", cache_control.parse()?);
+
+        return Ok(response);
+    }
+
+    let page_etag = day_page_etag(state, &params.channel, &query.date)?;
+
+    if is_etag_fresh(state, &page_etag) {
+        let mut response = create_empty_response(state, StatusCode::NOT_MODIFIED);
+        set_day_page_headers(&mut response, &page_etag, &cache_control)?;
+
+        return Ok(response);
+    }
+
+    // Shown before parsing so that lines the parser rejects can be found
+    if let Some("source") = query.view.as_deref() {
+        let raw_log = time_phase(state, "fs read", || {
+            app_state.get_raw_log(&params.channel, &query.date)
+        })?;
+        let template = ChannelLinesSourceTemplate {
+            channel_name: params.channel,
+            path_prefix: app_state.path_prefix.clone(),
+            date_slug: query.date,
+            lines: make_source_lines(&raw_log)?,
+        };
+        let mut response = render_template(state, template)?;
+        set_day_page_headers(&mut response, &page_etag, &cache_control)?;
+
+        return Ok(response);
+    }
+
+    let compact = match query.density.as_deref() {
+        None | Some("") => app_state.day_density == Density::Compact,
+        Some("compact") => true,
+        Some("normal") => false,
+        Some(_) => return Ok(create_empty_response(state, StatusCode::BAD_REQUEST)),
+    };
+
+    let mut preferences = get_preferences(state);
+    let chosen_timezone = match query.tz.as_deref().map(str::trim) {
+        None | Some("") => None,
+        Some(name) => match crate::locale::parse_timezone(name) {
+            Some(timezone) => Some(timezone),
+            None => return Ok(create_empty_response(state, StatusCode::BAD_REQUEST)),
+        },
+    };
+    let timezone = chosen_timezone.unwrap_or_else(|| viewer_timezone(state, &preferences));
+
+    let log_date = crate::state::parse_date_slug(&query.date)?;
+    let log_lines = time_phase(state, "parse", || {
+        app_state.get_log_lines(&params.channel, &query.date)
+    })?;
+    let line_count = log_lines.len();
+    let nick_filter = NickFilter::new(query.nick.as_deref(), None)
+        .with_status_mentions(query.nick_status.as_deref() == Some("on"));
+    let nick_matches = log_lines
+        .iter()
+        .map(|line| nick_filter.is_match(line))
+        .collect::<Vec<bool>>();
+    let is_nick_match = |line: &LogOutputLine| nick_matches[line.line_number as usize - 1];
+    let mut lines = make_output_lines(&log_lines, timezone);
+
+    if let Some("clean") = query.view.as_deref() {
+        let hide_status = match query.hide_status.as_deref() {
+            Some(value) => value == "on",
+            None => preferences.hide_status,
+        };
+        let mut pages: Vec<Vec<LogOutputLine>> = Vec::new();
+
+        for line in lines {
+            let is_hidden = if line.nickname.is_empty() {
+                hide_status
+            } else {
+                preferences.is_line_hidden(&line.nickname)
+            };
+
+            if is_hidden || !is_nick_match(&line) {
+                continue;
+            }
+
+            if pages
+                .last()
+                .is_none_or(|page| page.len() == CLEAN_VIEW_PAGE_LINES)
+            {
+                pages.push(Vec::new());
+            }
+
+            pages.last_mut().unwrap().push(line);
+        }
+
+        let template = ChannelLinesCleanTemplate {
+            channel_name: params.channel,
+            date_slug: query.date,
+            pages,
+        };
+        let mut response = render_template(state, template)?;
+        set_day_page_headers(&mut response, &page_etag, &cache_control)?;
+
+        return Ok(response);
+    }
+
+    let annotations_enabled = app_state.annotation_store.is_enabled() && !compact;
+
+    if annotations_enabled {
+        for annotation in app_state
+            .annotation_store
+            .get_annotations(&params.channel, &query.date)?
+        {
+            let index = annotation.line_number.saturating_sub(1) as usize;
+
+            if let Some(line) = lines.get_mut(index) {
+                line.annotations.push(annotation);
+            }
+        }
+    }
+
+    let selected_line_number = query
+        .sel
+        .as_deref()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or(u64::MAX);
+
+    lines.retain(|line| {
+        line.line_number == selected_line_number
+            || (!preferences.is_line_hidden(&line.nickname) && is_nick_match(line))
+    });
+
+    // Without a page, show the one with the selected line. A cursor shows
+    // the page with the line next to it, which stays put as the day grows.
+    let page_lines = app_state.day_view_page_lines.max(1);
+    let page_count = lines.len().div_ceil(page_lines).max(1);
+    let cursor_page = |cursor: &str, ordering: Ordering| {
+        Cursor::decode(cursor)
+            .filter(|cursor| cursor.date_slug == query.date)
+            .map(|cursor| {
+                let mut indexes = lines.iter().enumerate().filter(|(_, line)| {
+                    cursor.compare_line(&query.date, line.line_number) == ordering
+                });
+                let index = match ordering {
+                    Ordering::Greater => indexes.next().map_or(lines.len(), |(index, _)| index),
+                    _ => indexes.next_back().map_or(0, |(index, _)| index),
+                };
+
+                (index / page_lines + 1).min(page_count)
+            })
+    };
+    let page = match (
+        query.after.as_deref().filter(|value| !value.is_empty()),
+        query.before.as_deref().filter(|value| !value.is_empty()),
+        query.page.as_deref(),
+    ) {
+        (Some(after), _, _) => cursor_page(after, Ordering::Greater),
+        (_, Some(before), _) => cursor_page(before, Ordering::Less),
+        (_, _, None) | (_, _, Some("")) => Some(
+            lines
+                .iter()
+                .position(|line| line.line_number == selected_line_number)
+                .map_or(1, |index| index / page_lines + 1),
+        ),
+        (_, _, Some(page)) => page
+            .parse::<usize>()
+            .ok()
+            .filter(|page| *page >= 1 && *page <= page_count),
+    };
+    let page = match page {
+        Some(page) => page,
+        None => return Ok(create_empty_response(state, StatusCode::BAD_REQUEST)),
+    };
+    let page_cursor = |index: usize| {
+        lines.get(index).map_or_else(String::new, |line| {
+            Cursor {
+                date_slug: query.date.clone(),
+                line_number: line.line_number,
+                position: index,
+            }
+            .encode()
+        })
+    };
+    let earlier_cursor = page_cursor((page - 1) * page_lines);
+    let later_cursor = page_cursor((page * page_lines).min(lines.len()).saturating_sub(1));
+    let mut lines = lines
+        .into_iter()
+        .skip((page - 1) * page_lines)
+        .take(page_lines)
+        .collect::<Vec<LogOutputLine>>();
+
+    if !compact {
+        add_link_previews(&app_state.link_previewer, &mut lines);
+    }
+
+    let thumbnails_available = app_state.image_proxy.is_enabled() && !compact;
+    let thumbnails_enabled = thumbnails_available
+        && match query.thumbs.as_deref() {
+            Some(value) => value == "on",
+            None => preferences
+                .thumbnails
+                .unwrap_or_else(|| has_cookie(state, THUMBNAILS_COOKIE, "on")),
+        };
+
+    if thumbnails_enabled {
+        add_thumbnails(&app_state.image_proxy, &mut lines);
+    }
+
+    // Date slugs are sorted newest first
+    let date_slugs = app_state.get_channel_log_date_slugs(&params.channel)?;
+    let position = date_slugs.iter().position(|slug| *slug == query.date);
+    let previous_day = position.and_then(|position| date_slugs.get(position + 1).cloned());
+    let next_day = position
+        .and_then(|position| position.checked_sub(1))
+        .and_then(|position| date_slugs.get(position).cloned());
+    let navigation_json = DayNavigation {
+        channel: &params.channel,
+        date_slug: &query.date,
+        line_numbers: lines.iter().map(|line| line.line_number).collect(),
+        selected_line: Some(selected_line_number).filter(|number| *number != u64::MAX),
+        previous_day: previous_day.as_deref(),
+        next_day: next_day.as_deref(),
+        path_prefix: &app_state.path_prefix,
+        live_url: Some(site_url(
+            state,
+            &format!(
+                "/bin/irclogger_log_live/{}?after={}",
+                params.channel, line_count
+            ),
+        ))
+        // Only the last page can grow, and new lines aren't filtered
+        .filter(|_| log_date == Utc::today() && page == page_count && nick_filter.is_empty()),
+        utc_offset_minutes: Utc::now()
+            .with_timezone(&timezone)
+            .offset()
+            .fix()
+            .local_minus_utc()
+            / 60,
+    }
+    .to_script_json()?;
+
+    let dates = date_formatter(state);
+    let template = ChannelLinesTemplate {
+        channel_name: params.channel.clone(),
+        lines,
+        date_slug: query.date.clone(),
+        date_label: dates.long_date(log_date),
+        selected_line_number,
+        thumbnails_available,
+        thumbnails_enabled,
+        annotations_enabled,
+        bookmarks_enabled: app_state.bookmark_store.is_enabled(),
+        has_selection: selected_line_number != u64::MAX,
+        searchable: app_state.get_channel_metadata(&params.channel)?.searchable,
+        previous_day,
+        next_day,
+        navigation_json,
+        compact,
+        page,
+        page_count,
+        earlier_cursor,
+        later_cursor,
+        dates,
+        nick_query: if nick_filter.is_empty() {
+            String::new()
+        } else {
+            format!(
+                "&nick={}{}",
+                percent_encoding::utf8_percent_encode(
+                    query.nick.as_deref().unwrap_or_default().trim(),
+                    percent_encoding::NON_ALPHANUMERIC
+                ),
+                if nick_filter.include_status {
+                    "&nick_status=on"
+                } else {
+                    ""
+                }
+            )
+        },
+        nick: query
+            .nick
+            .as_deref()
+            .map(|nick| nick.trim().to_string())
+            .filter(|nick| !nick.is_empty()),
+        timezone: timezone.name().to_string(),
+        color_theme: viewer_color_theme(state),
+        path_prefix: path_prefix(state),
+    };
+
+    let preferences_enabled = app_state.preference_signer.is_enabled();
+    let mut response = render_page(state, "channel_lines.html", template)?;
+    set_day_page_headers(&mut response, &page_etag, &cache_control)?;
+    let mut preferences_changed = false;
+
+    if query.thumbs.is_some() {
+        if preferences_enabled {
+            preferences.thumbnails = Some(thumbnails_enabled);
+            preferences_changed = true;
+        } else {
+            response.headers_mut().append(
+                "Set-Cookie",
+                format!(
+                    "{}={}; Path=/; Max-Age=31536000; SameSite=Lax",
+                    THUMBNAILS_COOKIE,
+                    if thumbnails_enabled { "on" } else { "off" }
+                )
+                .parse()?,
+            );
+        }
+    }
+
+    if let Some(timezone) = chosen_timezone {
+        if preferences_enabled {
+            preferences.timezone = Some(timezone.name().to_string());
+            preferences_changed = true;
+        } else {
+            response.headers_mut().append(
+                "Set-Cookie",
+                format!(
+                    "{}={}; Path=/; Max-Age=31536000; SameSite=Lax",
+                    TIMEZONE_COOKIE,
+                    timezone.name()
+                )
+                .parse()?,
+            );
+        }
+    }
+
+    if preferences_changed {
+        append_preferences_cookie(state, &mut response, &preferences)?;
+    }
+
+    let canonical_url = public_url(
+        state,
+        &format!(
+            "/bin/irclogger_log/{}/?date={}",
+            params.channel,
+            percent_encoding::utf8_percent_encode(&query.date, percent_encoding::NON_ALPHANUMERIC)
+        ),
+    );
+
+    response.headers_mut().append(
+        "Link",
+        format!("<{}>; rel=\"canonical\"", canonical_url).parse()?,
+    );
+
+    Ok(response)
+}
+
+/// URL of a page for links read outside the site, such as in feeds, exports
+/// and Link headers. Uses the configured `public_url` so they agree behind a
+/// proxy. Without one, the link is relative to the host, since the request's
+/// Host header can't be trusted to name the site.
+fn public_url(state: &State, path: &str) -> String {
+    match &AppState::borrow_from(state).public_url {
+        Some(public_url) => format!("{}{}", public_url, path),
+        None => site_url(state, path),
+    }
+}
+
+/// Link to a page of the site, under the configured path prefix.
+fn site_url(state: &State, path: &str) -> String {
+    format!("{}{}", AppState::borrow_from(state).path_prefix, path)
+}
+
+/// Start of the site's links in templates.
+fn path_prefix(state: &State) -> String {
+    AppState::borrow_from(state).path_prefix.clone()
+}
+
+/// Lets browsers and caches keep pages of finished days, whose logs don't
+/// change, while today's page is revalidated on every visit.
+///
+/// Logs are named by the logger's local date, which can trail UTC, so a
+/// day counts as finished once the next one is over too.
+fn day_cache_control(state: &State, channel: &str, date_slug: &str) -> anyhow::Result<String> {
+    let app_state = AppState::borrow_from(state);
+    let max_age = app_state.past_day_max_age.as_secs();
+
+    if max_age == 0 || crate::state::parse_date_slug(date_slug)?.succ() >= Utc::today() {
+        return Ok("no-cache".to_string());
+    }
+
+    // Pages differ by login, so shared caches only get public ones
+    let scope = if app_state.is_channel_private(channel)? {
+        "private"
+    } else {
+        "public"
+    };
+
+    Ok(format!("{}, max-age={}", scope, max_age))
+}
+
+/// Sets the caching headers of a day page. Pages also differ by the viewer's
+/// cookies and languages, which shared caches have to key them by.
+fn set_day_page_headers(
+    response: &mut Response<Body>,
+    etag: &str,
+    cache_control: &str,
+) -> anyhow::Result<()> {
+    let headers = response.headers_mut();
+    headers.insert("ETag", etag.parse()?);
+    headers.insert("Cache-Control", cache_control.parse()?);
+    headers.insert("Vary", "Cookie, Accept-Language".parse()?);
+
+    Ok(())
+}
+
+/// A weak ETag of a rendered day page. Besides the log file, the page shows
+/// the day's notes and links to the days around it, and it depends on the
+/// request's options, cookies and login, so those are hashed in too. Tags
+/// change on each server start in case the templates or config did.
+fn day_page_etag(state: &State, channel: &str, date_slug: &str) -> anyhow::Result<String> {
+    lazy_static! {
+        static ref STARTED: u128 = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+    }
+
+    let app_state = AppState::borrow_from(state);
+    let headers = HeaderMap::borrow_from(state);
+    let mut hasher = DefaultHasher::new();
+
+    STARTED.hash(&mut hasher);
+    app_state
+        .get_log_etag(channel, date_slug)?
+        .hash(&mut hasher);
+    state.borrow::<Uri>().query().hash(&mut hasher);
+
+    for name in &["Cookie", "Authorization", "Accept-Language"] {
+        for value in headers.get_all(*name) {
+            value.as_bytes().hash(&mut hasher);
+        }
+    }
+
+    // Today's page offers live updates until the day is over
+    (crate::state::parse_date_slug(date_slug)? == Utc::today()).hash(&mut hasher);
+    app_state
+        .get_channel_metadata(channel)?
+        .searchable
+        .hash(&mut hasher);
+
+    let date_slugs = app_state.get_channel_log_date_slugs(channel)?;
+
+    if let Some(position) = date_slugs.iter().position(|slug| slug == date_slug) {
+        date_slugs[position.saturating_sub(1)..(position + 2).min(date_slugs.len())]
+            .hash(&mut hasher);
+    }
+
+    if app_state.annotation_store.is_enabled() {
+        serde_json::to_string(
+            &app_state
+                .annotation_store
+                .get_annotations(channel, date_slug)?,
+        )?
+        .hash(&mut hasher);
+    }
+
+    Ok(format!("W/\"{:x}\"", hasher.finish()))
+}
+
+/// Whether the request's `If-None-Match` has the ETag. The comparison is
+/// weak, as for any conditional GET.
+fn is_etag_fresh(state: &State, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+
+    HeaderMap::borrow_from(state)
+        .get_all("If-None-Match")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+const THUMBNAILS_COOKIE: &str = "thumbs";
+const TIMEZONE_COOKIE: &str = "tz";
+const COLOR_THEME_COOKIE: &str = "theme";
+
+/// The color theme picked with `?theme=`, then the one remembered in a
+/// cookie, then the site's default.
+fn viewer_color_theme(state: &State) -> ColorTheme {
+    requested_color_theme(state)
+        .or_else(|| {
+            get_cookie(state, COLOR_THEME_COOKIE).and_then(|name| ColorTheme::from_name(&name))
+        })
+        .unwrap_or(AppState::borrow_from(state).color_theme)
+}
+
+fn requested_color_theme(state: &State) -> Option<ColorTheme> {
+    let query = state.borrow::<Uri>().query()?;
+
+    serde_urlencoded::from_str::<Vec<(String, String)>>(query)
+        .ok()?
+        .into_iter()
+        .find(|(name, _)| name == "theme")
+        .and_then(|(_, value)| ColorTheme::from_name(&value))
+}
+
+/// Remembers a theme picked with `?theme=` on any page for later visits.
+pub fn append_color_theme_cookie(state: &State, response: &mut Response<Body>) {
+    if let Some(color_theme) = requested_color_theme(state) {
+        response.headers_mut().append(
+            "Set-Cookie",
+            format!(
+                "{}={}; Path=/; Max-Age=31536000; SameSite=Lax",
+                COLOR_THEME_COOKIE,
+                color_theme.name()
+            )
+            .parse()
+            .unwrap(),
+        );
+    }
+}
+
+fn has_cookie(state: &State, name: &str, value: &str) -> bool {
+    get_cookie(state, name).as_deref() == Some(value)
+}
+
+fn get_cookie(state: &State, name: &str) -> Option<String> {
+    let headers = HeaderMap::borrow_from(state);
+
+    headers
+        .get_all("Cookie")
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(cookie_name, _)| *cookie_name == name)
+        .map(|(_, cookie_value)| cookie_value.to_string())
+}
+
+fn get_preferences(state: &State) -> Preferences {
+    let app_state = AppState::borrow_from(state);
+
+    if !app_state.preference_signer.is_enabled() {
+        return Preferences::default();
+    }
+
+    get_cookie(state, PREFERENCES_COOKIE)
+        .and_then(|value| app_state.preference_signer.decode(&value))
+        .unwrap_or_default()
+}
+
+/// The timezone chosen by the viewer, or the site's timezone.
+fn viewer_timezone(state: &State, preferences: &Preferences) -> Tz {
+    preferences
+        .timezone
+        .clone()
+        .or_else(|| get_cookie(state, TIMEZONE_COOKIE))
+        .and_then(|name| crate::locale::parse_timezone(&name))
+        .unwrap_or_else(|| AppState::borrow_from(state).timezone)
+}
+
+fn append_preferences_cookie(
+    state: &State,
+    response: &mut Response<Body>,
+    preferences: &Preferences,
+) -> anyhow::Result<()> {
+    let app_state = AppState::borrow_from(state);
+    let value = app_state.preference_signer.encode(preferences)?;
+
+    response.headers_mut().append(
+        "Set-Cookie",
+        format!(
+            "{}={}; Path=/; Max-Age=31536000; SameSite=Lax; HttpOnly",
+            PREFERENCES_COOKIE, value
+        )
+        .parse()?,
+    );
+
+    Ok(())
+}
+
+fn is_channel_name_ok(channel: &str) -> bool {
+    lazy_static! {
+        static ref PATTERN: Regex = Regex::new(r"^[a-z0-9._-]+$").unwrap();
+    }
+
+    PATTERN.is_match(channel)
+}
+
+/// Checks a date slug from a request. Weekday names vary with the locale
+/// irclogger ran under, such as `Mon`, `lun.` or `月`.
+pub fn is_date_string_ok(date: &str) -> bool {
+    lazy_static! {
+        static ref PATTERN: Regex = Regex::new(r"^[0-9]{4}-[0-9]{2}-[0-9]{2},[\w.]+$").unwrap();
+    }
+
+    PATTERN.is_match(date) && crate::state::parse_date_slug(date).is_ok()
+}
+
+fn make_output_lines(lines: &[LogLine], timezone: Tz) -> Vec<LogOutputLine> {
+    let mut output_lines = Vec::new();
+
+    for (line_number, line) in lines.iter().enumerate() {
+        let line_number = line_number as u64 + 1;
+
+        output_lines.push(make_output_line(line, line_number, timezone));
+    }
+
+    output_lines
+}
+
+fn make_source_lines(raw_log: &[u8]) -> anyhow::Result<Vec<SourceLine>> {
+    let lines = crate::reader::read_raw_lines(raw_log)?
+        .into_iter()
+        .enumerate()
+        .map(|(index, text)| SourceLine {
+            line_number: index as u64 + 1,
+            kind: crate::reader::classify_line(&text),
+            text,
+        })
+        .collect();
+
+    Ok(lines)
+}
+
+fn add_link_previews<'a, I>(link_previewer: &LinkPreviewer, lines: I)
+where
+    I: IntoIterator<Item = &'a mut LogOutputLine>,
+{
+    if !link_previewer.is_enabled() {
+        return;
+    }
+
+    for line in lines {
+        line.link_previews = link_previewer.previews_for_text(&line.text);
+    }
+}
+
+fn add_thumbnails(image_proxy: &ImageProxy, lines: &mut [LogOutputLine]) {
+    for line in lines {
+        line.thumbnails = image_proxy.thumbnails_for_text(&line.text);
+    }
+}
+
+fn make_output_line(line: &LogLine, line_number: u64, timezone: Tz) -> LogOutputLine {
+    match &line.content {
+        LogLineContent::Status(text) => LogOutputLine {
+            date: line.date.with_timezone(&timezone),
+            nickname: String::new(),
+            text: text.clone(),
+            line_number,
+            link_previews: Vec::new(),
+            thumbnails: Vec::new(),
+            annotations: Vec::new(),
+        },
+        LogLineContent::Message { nickname, text } => LogOutputLine {
+            date: line.date.with_timezone(&timezone),
+            nickname: nickname.clone(),
+            text: text.clone(),
+            line_number,
+            link_previews: Vec::new(),
+            thumbnails: Vec::new(),
+            annotations: Vec::new(),
+        },
+    }
+}
+
+const CONTINUOUS_CHUNK_LINES: usize = 1000;
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct ChannelContinuousQuery {
+    date: String,
+    line: Option<u64>,
+}
+
+#[derive(Template)]
+#[template(path = "channel_continuous.html")]
+struct ChannelContinuousTemplate {
+    pub channel_name: String,
+    pub date_slug: String,
+    pub lines: Vec<ContinuousOutputLine>,
+    pub next: Option<ContinuousPosition>,
+    pub searchable: bool,
+    pub color_theme: ColorTheme,
+    pub path_prefix: String,
+}
+
+struct ContinuousOutputLine {
+    pub date_slug: String,
+    pub starts_day: bool,
+    pub line: LogOutputLine,
+}
+
+pub fn channel_continuous(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let params = ChannelParams::take_from(state);
+
+    if !user_has_access(state, &params.channel)? {
+        return Ok(build_auth_response(state));
+    }
+
+    let query = ChannelContinuousQuery::take_from(state);
+
+    if !is_date_string_ok(&query.date) {
+        return Ok(create_empty_response(state, StatusCode::BAD_REQUEST));
+    }
+
+    let app_state = AppState::borrow_from(state);
+    let chunk = app_state.get_continuous_lines(
+        &params.channel,
+        &query.date,
+        query.line.unwrap_or(1),
+        CONTINUOUS_CHUNK_LINES,
+    )?;
+
+    let timezone = viewer_timezone(state, &get_preferences(state));
+    let mut lines = Vec::new();
+    let mut previous_date_slug = None;
+
+    for continuous_line in chunk.lines {
+        let starts_day = previous_date_slug.as_ref() != Some(&continuous_line.date_slug);
+        previous_date_slug = Some(continuous_line.date_slug.clone());
+
+        lines.push(ContinuousOutputLine {
+            starts_day,
+            line: make_output_line(&continuous_line.line, continuous_line.line_number, timezone),
+            date_slug: continuous_line.date_slug,
+        });
+    }
+
+    add_link_previews(
+        &app_state.link_previewer,
+        lines.iter_mut().map(|entry| &mut entry.line),
+    );
+
+    let template = ChannelContinuousTemplate {
+        searchable: app_state.get_channel_metadata(&params.channel)?.searchable,
+        channel_name: params.channel,
+        date_slug: query.date,
+        lines,
+        next: chunk.next,
+        color_theme: viewer_color_theme(state),
+        path_prefix: path_prefix(state),
+    };
+    let response = render_template(state, template)?;
+
+    Ok(response)
+}
+
+#[derive(Template)]
+#[template(path = "channel_search.html")]
+struct ChannelSearchTemplate {
+    pub channel_name: String,
+    pub has_results: bool,
+    pub results: Vec<SearchResultRow>,
+    pub date: Option<String>, // Set when searching a single day
+    pub pagination: Option<SearchPagination>,
+    pub budget_notice: Option<String>, // Why the search covered fewer days or none
+    pub saved_searches: Vec<SavedSearch>,
+    pub saved_search_alerts: bool, // Pinned searches offer alerts of new matches
+    pub uses_agrep: bool,
+    pub search: String,
+    pub case_sensitive: bool,
+    pub verbatim: bool,
+    pub whole_word: bool,
+    pub text_only: bool,
+    pub messages_only: bool,
+    pub day_options: Vec<SearchDayOption>,
+    pub color_theme: ColorTheme,
+    pub path_prefix: String,
+}
+
+enum SearchResultRow {
+    Line(SearchResultEntry),
+    More(CollapsedMatches),
+}
+
+struct CollapsedMatches {
+    pub date_slug: String,
+    pub count: usize,
+    pub query_string: String, // Searches only this day
+}
+
+struct SearchPagination {
+    pub first: usize, // Position of the first line on the page from 1, or 0 if the page is empty
+    pub last: usize,
+    pub total: usize,
+    pub incomplete: bool, // The search stopped early, so the total is a lower bound
+    pub current_page: String, // Query string
+    pub previous_page: Option<String>,
+    pub next_page: Option<String>,
+}
+
+impl SearchPagination {
+    /// Links to the results around the shown ones with cursors, so the pages
+    /// don't shift as matches are added to today's log.
+    fn new(
+        total: usize,
+        incomplete: bool,
+        position: usize, // Results before the first shown one
+        shown: &[SearchResultEntry],
+        query_string: &str,
+        current_page: &str,
+    ) -> Self {
+        let cursor_query = |name: &str, entry: &SearchResultEntry, position: usize| {
+            let cursor = Cursor {
+                date_slug: entry.date_slug.clone(),
+                line_number: entry.line_number,
+                position,
+            };
+            format!("{}&{}={}", query_string, name, cursor.encode())
+        };
+
+        let last = position.saturating_add(shown.len());
+
+        Self {
+            first: if shown.is_empty() {
+                0
+            } else {
+                position.saturating_add(1)
+            },
+            last,
+            total,
+            incomplete,
+            current_page: format!("{}&{}", query_string, current_page),
+            previous_page: shown
+                .first()
+                .filter(|_| position > 0)
+                .map(|entry| cursor_query("before", entry, position)),
+            next_page: shown
+                .last()
+                .filter(|_| last < total)
+                .map(|entry| cursor_query("after", entry, last - 1)),
+        }
+    }
+}
+
+/// Which page of results to show.
+enum SearchPage {
+    Offset(usize),  // From links made before cursors
+    After(Cursor),  // The results following the cursor's line
+    Before(Cursor), // The results preceding the cursor's line
+}
+
+struct SearchDayOption {
+    pub value: String,
+    pub label: String,
+    pub selected: bool,
+}
+
+const SEARCH_DAY_CHOICES: &[u32] = &[7, 30, 90, 365];
+
+const SEARCH_PAGE_RESULTS: usize = 500;
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct ChannelSearchQuery {
+    search: Option<String>,
+    //action: Option<String>,
+    case: Option<String>,
+    verbatim: Option<String>,
+    word: Option<String>,
+    text: Option<String>,
+    messages_only: Option<String>,
+    days: Option<String>,
+    per_day_limit: Option<String>,
+    date: Option<String>,         // Search only this day
+    page: Option<String>,         // Pages of results from 1
+    offset: Option<String>,       // Number of results to skip; overrides the page
+    after: Option<String>,        // Cursor of the line before the page; overrides the offset
+    before: Option<String>,       // Cursor of the line after the page
+    format: Option<String>,       // "csv" or "json" downloads the page of results
+    nick: Option<String>,         // Nicknames kept in downloads, comma separated
+    exclude_nick: Option<String>, // Nicknames left out of downloads
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SearchExportFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Serialize)]
+struct SearchExport {
+    total: usize,
+    incomplete: bool,
+    offset: usize,
+    results: Vec<SearchExportRow>,
+}
+
+#[derive(Serialize)]
+struct SearchExportRow {
+    channel: String,
+    date_slug: String,
+    line_number: u64,
+    text: String,
+    permalink: String,
+}
+
+/// Serves a page of search results as a download. Per-day limits are left
+/// out, so every line of the page is included.
+fn build_search_export(
+    state: &State,
+    channel: &str,
+    format: SearchExportFormat,
+    export: SearchExport,
+) -> anyhow::Result<Response<Body>> {
+    let (content, mime, extension) = match format {
+        SearchExportFormat::Json => (serde_json::to_vec(&export)?, mime::APPLICATION_JSON, "json"),
+        SearchExportFormat::Csv => {
+            let mut content = String::from("channel,date_slug,line_number,text,permalink\r\n");
+
+            for row in &export.results {
+                content.push_str(&format!(
+                    "{},{},{},{},{}\r\n",
+                    csv_field(&row.channel),
+                    csv_field(&row.date_slug),
+                    row.line_number,
+                    csv_field(&row.text),
+                    csv_field(&row.permalink)
+                ));
+            }
+
+            (content.into_bytes(), mime::TEXT_CSV_UTF_8, "csv")
+        }
+    };
+
+    let mut response = create_response(state, StatusCode::OK, mime, content);
+    response.headers_mut().insert(
+        "Content-Disposition",
+        format!("attachment; filename=\"{}-search.{}\"", channel, extension).parse()?,
+    );
+
+    Ok(response)
+}
+
+/// Whether the line of a search result passes the nickname filter. Lines
+/// that can't be parsed only pass when no nicknames are required.
+fn is_search_result_nick_match(nick_filter: &NickFilter, result: &SearchResultEntry) -> bool {
+    let date = match crate::state::parse_date_slug(&result.date_slug) {
+        Ok(date) => date,
+        Err(_) => return nick_filter.include.is_empty(),
+    };
+
+    match crate::reader::parse_line(&result.raw_line, &date) {
+        Ok(line) => nick_filter.is_match(&line),
+        Err(_) => nick_filter.include.is_empty(),
+    }
+}
+
+/// Quotes a CSV field. Text starting like a formula gets a leading `'` so
+/// spreadsheets show it instead of running it.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(|c: char| "=+-@".contains(c)) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+/// A channel search that passed its checks, with what's needed to run it
+/// and to render its page.
+struct ChannelSearchPlan {
+    params: ChannelParams,
+    query: ChannelSearchQuery,
+    user: Option<String>,
+    days: Option<u32>,
+    per_day_limit: Option<u32>,
+    date: Option<Date<Utc>>,
+    page: SearchPage,
+    export_format: Option<SearchExportFormat>,
+    options: SearchOptions,
+    search: Option<PlannedSearch>, // None without a query or when refused
+    budget_notice: Option<String>,
+    is_refused: bool,
+}
+
+struct PlannedSearch {
+    parameters: SearchParameters,
+    since: Option<Date<Utc>>,
+    until: Option<Date<Utc>>,
+    limit: usize,
+}
+
+/// Checks access and the budget and renders the page on the blocking
+/// thread pool, awaiting only the search in between.
+pub async fn channel_search(state: State) -> (State, anyhow::Result<Response<Body>>) {
+    let (state, plan) = run_blocking(state, plan_channel_search).await;
+    let plan = match plan {
+        Ok(Ok(plan)) => plan,
+        Ok(Err(response)) => return (state, Ok(response)),
+        Err(error) => return (state, Err(error)),
+    };
+    let matches = match &plan.search {
+        Some(search) => {
+            let search_start = Instant::now();
+            let matches = AppState::borrow_from(&state)
+                .search_channel(
+                    &plan.params.channel,
+                    &search.parameters.query,
+                    search.parameters.to_options(),
+                    search.since,
+                    search.until,
+                    search.limit,
+                )
+                .await;
+            record_phase(&state, "search", search_start.elapsed());
+
+            match matches {
+                Ok(matches) => Some(matches),
+                Err(error) => return (state, Err(error)),
+            }
+        }
+        None => None,
+    };
+
+    run_blocking(state, move |state| {
+        render_channel_search(state, plan, matches)
+    })
+    .await
+}
+
+fn plan_channel_search(
+    state: &mut State,
+) -> anyhow::Result<Result<ChannelSearchPlan, Response<Body>>> {
+    let params = ChannelParams::take_from(state);
+
+    if !user_has_access(state, &params.channel)? {
+        return Ok(Err(build_auth_response(state)));
+    }
+
+    let query = ChannelSearchQuery::take_from(state);
+    let app_state = AppState::borrow_from(state);
+
+    if !app_state.get_channel_metadata(&params.channel)?.searchable {
+        return Ok(Err(create_empty_response(state, StatusCode::FORBIDDEN)));
+    }
+
+    let user = if app_state.search_history_store.is_enabled() {
+        authenticated_user(state)?
+    } else {
+        None
+    };
+
+    let defaults = &app_state.search_defaults;
+    let days = match parse_search_days(query.days.as_deref(), defaults.days) {
+        Some(days) => days,
+        None => return Ok(Err(create_empty_response(state, StatusCode::BAD_REQUEST))),
+    };
+    let per_day_limit = match query.per_day_limit.as_deref() {
+        None | Some("") => defaults.per_day_limit,
+        Some("all") => None,
+        Some(limit) => match limit.parse::<u32>() {
+            Ok(limit) => Some(limit),
+            Err(_) => return Ok(Err(create_empty_response(state, StatusCode::BAD_REQUEST))),
+        },
+    };
+    let date = match query.date.as_deref() {
+        None | Some("") => None,
+        Some(date) if is_date_string_ok(date) => Some(crate::state::parse_date_slug(date)?),
+        Some(_) => return Ok(Err(create_empty_response(state, StatusCode::BAD_REQUEST))),
+    };
+    let page = match (
+        query.after.as_deref().filter(|value| !value.is_empty()),
+        query.before.as_deref().filter(|value| !value.is_empty()),
+        query.offset.as_deref().filter(|value| !value.is_empty()),
+        query.page.as_deref().filter(|value| !value.is_empty()),
+    ) {
+        (Some(after), _, _, _) => Cursor::decode(after)
+            .filter(|cursor| is_date_string_ok(&cursor.date_slug))
+            .map(SearchPage::After),
+        (_, Some(before), _, _) => Cursor::decode(before)
+            .filter(|cursor| is_date_string_ok(&cursor.date_slug))
+            .map(SearchPage::Before),
+        (_, _, Some(offset), _) => offset.parse::<usize>().ok().map(SearchPage::Offset),
+        (_, _, _, Some(page)) => page
+            .parse::<usize>()
+            .ok()
+            .filter(|page| *page >= 1)
+            .and_then(|page| (page - 1).checked_mul(SEARCH_PAGE_RESULTS))
+            .map(SearchPage::Offset),
+        _ => Some(SearchPage::Offset(0)),
+    };
+    let page = match page {
+        Some(page) => page,
+        None => return Ok(Err(create_empty_response(state, StatusCode::BAD_REQUEST))),
+    };
+    let export_format = match query.format.as_deref() {
+        None | Some("") | Some("html") => None,
+        Some("csv") if query.search.is_some() => Some(SearchExportFormat::Csv),
+        Some("json") if query.search.is_some() => Some(SearchExportFormat::Json),
+        Some(_) => return Ok(Err(create_empty_response(state, StatusCode::BAD_REQUEST))),
+    };
+
+    // A submitted search carries its own checkboxes; defaults only fill in a blank form
+    let options = if query.search.is_some() {
+        SearchOptions {
+            case_sensitive: query.case.as_deref() == Some("on"),
+            verbatim: query.verbatim.as_deref() == Some("on"),
+            whole_word: query.word.as_deref() == Some("on"),
+            text_only: query.text.as_deref() == Some("on"),
+            messages_only: query.messages_only.as_deref() == Some("on"),
+        }
+    } else {
+        SearchOptions {
+            case_sensitive: defaults.case_sensitive,
+            verbatim: defaults.verbatim,
+            whole_word: defaults.whole_word,
+            text_only: defaults.text_only,
+            messages_only: defaults.messages_only,
+        }
+    };
+
+    let parameters = query.search.as_ref().map(|search| SearchParameters {
+        channel: params.channel.clone(),
+        query: search.clone(),
+        case_sensitive: options.case_sensitive,
+        verbatim: options.verbatim,
+        whole_word: options.whole_word,
+        text_only: options.text_only,
+        messages_only: options.messages_only,
+    });
+    let (since, until) = match date {
+        Some(date) => (Some(date), Some(date)),
+        None => (days.and_then(search_days_start), None),
+    };
+    let mut budget_notice = None;
+    let mut is_refused = false;
+    let since = match &parameters {
+        Some(parameters) => match apply_search_budget(state, since, |app_state| {
+            app_state.get_search_day_costs(
+                &params.channel,
+                &parameters.query,
+                parameters.to_options(),
+                since,
+                until,
+            )
+        })? {
+            Ok((since, notice)) => {
+                budget_notice = notice;
+                since
+            }
+            Err(notice) => {
+                budget_notice = Some(notice);
+                is_refused = true;
+                since
+            }
+        },
+        None => since,
+    };
+
+    let search = match parameters.filter(|_| !is_refused) {
+        Some(parameters) => {
+            let (until, limit) = match &page {
+                SearchPage::Offset(offset) => (until, offset.saturating_add(SEARCH_PAGE_RESULTS)),
+                // Newer days are skipped, and at most a line per line number
+                // precedes the cursor on its own day
+                SearchPage::After(cursor) => {
+                    let cursor_date = crate::state::parse_date_slug(&cursor.date_slug)?;
+
+                    (
+                        Some(until.map_or(cursor_date, |until| until.min(cursor_date))),
+                        (cursor.line_number as usize).saturating_add(SEARCH_PAGE_RESULTS),
+                    )
+                }
+                // Today's log may have grown since, so every match is kept to
+                // find the ones just before the cursor
+                SearchPage::Before(_) => (until, usize::MAX),
+            };
+
+            Some(PlannedSearch {
+                parameters,
+                since,
+                until,
+                limit,
+            })
+        }
+        None => None,
+    };
+
+    Ok(Ok(ChannelSearchPlan {
+        params,
+        query,
+        user,
+        days,
+        per_day_limit,
+        date,
+        page,
+        export_format,
+        options,
+        search,
+        budget_notice,
+        is_refused,
+    }))
+}
+
+fn render_channel_search(
+    state: &mut State,
+    plan: ChannelSearchPlan,
+    matches: Option<SearchMatches>,
+) -> anyhow::Result<Response<Body>> {
+    let ChannelSearchPlan {
+        params,
+        query,
+        user,
+        days,
+        per_day_limit,
+        date,
+        page,
+        export_format,
+        options,
+        search,
+        budget_notice,
+        is_refused,
+    } = plan;
+    let app_state = AppState::borrow_from(state);
+
+    let (search_results, pagination) = if let (Some(search), Some(matches)) = (search, matches) {
+        let parameters = search.parameters;
+
+        // Pages repeat the form's choices as given
+        let mut page_query_string = parameters.to_query_string();
+
+        for (name, value) in &[
+            ("days", &query.days),
+            ("per_day_limit", &query.per_day_limit),
+            ("date", &query.date),
+        ] {
+            if let Some(value) = value.as_deref().filter(|value| !value.is_empty()) {
+                page_query_string.push_str(&format!(
+                    "&{}={}",
+                    name,
+                    percent_encoding::utf8_percent_encode(
+                        value,
+                        percent_encoding::NON_ALPHANUMERIC
+                    )
+                ));
+            }
+        }
+        // Matches are in the order of cursors, so the lines on either side
+        // of a cursor are next to each other
+        let (position, total, skip, take) = match &page {
+            SearchPage::Offset(offset) => (*offset, matches.total, *offset, SEARCH_PAGE_RESULTS),
+            SearchPage::After(cursor) => {
+                let skipped = matches
+                    .entries
+                    .iter()
+                    .take_while(|entry| {
+                        cursor.compare_line(&entry.date_slug, entry.line_number)
+                            != Ordering::Greater
+                    })
+                    .count();
+
+                // Positions come from the client, so they may be anything
+                let first = cursor.position.saturating_add(1);
+
+                (
+                    first,
+                    first.saturating_add(matches.total).saturating_sub(skipped),
+                    skipped,
+                    SEARCH_PAGE_RESULTS,
+                )
+            }
+            SearchPage::Before(cursor) => {
+                let preceding = matches
+                    .entries
+                    .iter()
+                    .take_while(|entry| {
+                        cursor.compare_line(&entry.date_slug, entry.line_number) == Ordering::Less
+                    })
+                    .count();
+                let start = preceding.saturating_sub(SEARCH_PAGE_RESULTS);
+
+                (start, matches.total, start, preceding - start)
+            }
+        };
+        let current_page = match (&query.after, &query.before) {
+            (Some(after), _) if !after.is_empty() => format!("after={}", after),
+            (_, Some(before)) if !before.is_empty() => format!("before={}", before),
+            _ => format!("offset={}", position),
+        };
+        let search_results = matches
+            .entries
+            .into_iter()
+            .skip(skip)
+            .take(take)
+            .collect::<Vec<SearchResultEntry>>();
+        let pagination = SearchPagination::new(
+            total,
+            matches.incomplete,
+            position,
+            &search_results,
+            &page_query_string,
+            &current_page,
+        );
+
+        if let Some(format) = export_format {
+            let nick_filter = NickFilter::new(query.nick.as_deref(), query.exclude_nick.as_deref());
+            let results = search_results
+                .into_iter()
+                .filter(|result| {
+                    nick_filter.is_empty() || is_search_result_nick_match(&nick_filter, result)
+                })
+                .map(|result| SearchExportRow {
+                    permalink: public_url(
+                        state,
+                        &format!(
+                            "/bin/irclogger_log/{}/?date={}&sel={}#l{}",
+                            params.channel,
+                            percent_encoding::utf8_percent_encode(
+                                &result.date_slug,
+                                percent_encoding::NON_ALPHANUMERIC
+                            ),
+                            result.line_number,
+                            result.line_number
+                        ),
+                    ),
+                    channel: params.channel.clone(),
+                    date_slug: result.date_slug,
+                    line_number: result.line_number,
+                    text: result.raw_line,
+                })
+                .collect();
+            let export = SearchExport {
+                total: pagination.total,
+                incomplete: pagination.incomplete,
+                offset: position,
+                results,
+            };
+
+            return build_search_export(state, &params.channel, format, export);
+        }
+
+        let search_results = match date {
+            Some(_) => limit_results_per_day(search_results, None, ""),
+            None => {
+                limit_results_per_day(search_results, per_day_limit, &parameters.to_query_string())
+            }
+        };
+
+        if let Some(user) = &user {
+            app_state
+                .search_history_store
+                .record_search(user, parameters)?;
+        }
+
+        (search_results, Some(pagination))
+    } else {
+        (Vec::new(), None)
+    };
+
+    let saved_searches = match &user {
+        Some(user) => app_state
+            .search_history_store
+            .get_searches(user, &params.channel)?,
+        None => Vec::new(),
+    };
+
+    let template = ChannelSearchTemplate {
+        channel_name: params.channel.clone(),
+        has_results: query.search.is_some(),
+        results: search_results,
+        date: date.and(query.date),
+        pagination,
+        budget_notice,
+        saved_searches,
+        saved_search_alerts: app_state.saved_search_alerts,
+        uses_agrep: app_state.search_backend == SearchBackend::Agrep,
+        search: query.search.unwrap_or_default(),
+        case_sensitive: options.case_sensitive,
+        verbatim: options.verbatim,
+        whole_word: options.whole_word,
+        text_only: options.text_only,
+        messages_only: options.messages_only,
+        day_options: make_search_day_options(days),
+        color_theme: viewer_color_theme(state),
+        path_prefix: path_prefix(state),
+    };
+
+    let mut response = render_template(state, template)?;
+
+    if is_refused {
+        *response.status_mut() = StatusCode::UNPROCESSABLE_ENTITY;
+    }
+
+    Ok(response)
+}
+
+/// Collapses matches beyond the limit on each day into a row linking to a
+/// search of only that day. Results must be grouped by day, and a day split
+/// across pages is limited on each page.
+fn limit_results_per_day(
+    results: Vec<SearchResultEntry>,
+    limit: Option<u32>,
+    query_string: &str,
+) -> Vec<SearchResultRow> {
+    let mut rows = Vec::new();
+    let mut current_day = String::new();
+    let mut day_count = 0;
+
+    for result in results {
+        if result.date_slug != current_day {
+            current_day = result.date_slug.clone();
+            day_count = 0;
+        }
+
+        day_count += 1;
+
+        match limit {
+            Some(limit) if day_count > limit as usize => match rows.last_mut() {
+                Some(SearchResultRow::More(collapsed)) => collapsed.count += 1,
+                _ => rows.push(SearchResultRow::More(CollapsedMatches {
+                    query_string: format!(
+                        "{}&days=all&date={}",
+                        query_string,
+                        percent_encoding::utf8_percent_encode(
+                            &result.date_slug,
+                            percent_encoding::NON_ALPHANUMERIC
+                        )
+                    ),
+                    date_slug: result.date_slug,
+                    count: 1,
+                })),
+            },
+            _ => rows.push(SearchResultRow::Line(result)),
+        }
+    }
+
+    rows
+}
+
+#[derive(Template)]
+#[template(path = "global_search.html")]
+struct GlobalSearchTemplate {
+    pub has_results: bool,
+    pub channels: Vec<GlobalSearchChannel>,
+    pub search: String,
+    pub query_string: String, // Repeats the search on a channel's search page
+    pub budget_notice: Option<String>, // Why the search covered fewer days or none
+    pub case_sensitive: bool,
+    pub verbatim: bool,
+    pub whole_word: bool,
+    pub text_only: bool,
+    pub messages_only: bool,
+    pub day_options: Vec<SearchDayOption>,
+    pub color_theme: ColorTheme,
+    pub path_prefix: String,
+}
+
+struct GlobalSearchChannel {
+    pub channel_name: String,
+    pub results: Vec<SearchResultEntry>,
+    pub more_count: usize, // Matches left out, shown on the channel's search page
+}
+
+const GLOBAL_SEARCH_CHANNEL_RESULTS: usize = 20;
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct GlobalSearchQuery {
+    search: Option<String>,
+    case: Option<String>,
+    verbatim: Option<String>,
+    word: Option<String>,
+    text: Option<String>,
+    messages_only: Option<String>,
+    days: Option<String>,
+}
+
+/// A search of every channel the requester can read, checked against the
+/// budget, with what's needed to run it and to render its page.
+struct GlobalSearchPlan {
+    query: GlobalSearchQuery,
+    days: Option<u32>,
+    parameters: SearchParameters,
+    channel_names: Vec<String>, // Channels to search, none when refused
+    since: Option<Date<Utc>>,
+    budget_notice: Option<String>,
+    is_refused: bool,
+}
+
+/// Searches every channel the requester can read, grouping matches by
+/// channel. Access checks and rendering run on the blocking thread pool.
+pub async fn global_search(state: State) -> (State, anyhow::Result<Response<Body>>) {
+    let (state, plan) = run_blocking(state, plan_global_search).await;
+    let plan = match plan {
+        Ok(Ok(plan)) => plan,
+        Ok(Err(response)) => return (state, Ok(response)),
+        Err(error) => return (state, Err(error)),
+    };
+    let mut channels = Vec::new();
+
+    for channel_name in &plan.channel_names {
+        let search_start = Instant::now();
+        let matches = AppState::borrow_from(&state)
+            .search_channel(
+                channel_name,
+                &plan.parameters.query,
+                plan.parameters.to_options(),
+                plan.since,
+                None,
+                GLOBAL_SEARCH_CHANNEL_RESULTS,
+            )
+            .await;
+        record_phase(&state, "search", search_start.elapsed());
+        let matches = match matches {
+            Ok(matches) => matches,
+            Err(error) => return (state, Err(error)),
+        };
+
+        if matches.total == 0 {
+            continue;
+        }
+
+        channels.push(GlobalSearchChannel {
+            channel_name: channel_name.clone(),
+            more_count: matches.total - matches.entries.len(),
+            results: matches.entries,
+        });
+    }
+
+    run_blocking(state, move |state| {
+        render_global_search(state, plan, channels)
+    })
+    .await
+}
+
+fn plan_global_search(
+    state: &mut State,
+) -> anyhow::Result<Result<GlobalSearchPlan, Response<Body>>> {
+    let query = GlobalSearchQuery::take_from(state);
+    let app_state = AppState::borrow_from(state);
+    let defaults = &app_state.search_defaults;
+    let days = match parse_search_days(query.days.as_deref(), defaults.days) {
+        Some(days) => days,
+        None => return Ok(Err(create_empty_response(state, StatusCode::BAD_REQUEST))),
+    };
+
+    // A submitted search carries its own checkboxes; defaults only fill in a blank form
+    let mut parameters = SearchParameters {
+        channel: String::new(),
+        query: query.search.clone().unwrap_or_default(),
+        case_sensitive: defaults.case_sensitive,
+        verbatim: defaults.verbatim,
+        whole_word: defaults.whole_word,
+        text_only: defaults.text_only,
+        messages_only: defaults.messages_only,
+    };
+
+    if query.search.is_some() {
+        parameters.case_sensitive = query.case.as_deref() == Some("on");
+        parameters.verbatim = query.verbatim.as_deref() == Some("on");
+        parameters.whole_word = query.word.as_deref() == Some("on");
+        parameters.text_only = query.text.as_deref() == Some("on");
+        parameters.messages_only = query.messages_only.as_deref() == Some("on");
+    }
+
+    let mut channel_names = Vec::new();
+    let mut since = days.and_then(search_days_start);
+    let mut budget_notice = None;
+    let mut is_refused = false;
+
+    if query.search.is_some() {
+        for channel in app_state.get_channels()? {
+            if check_user_access(state, &channel.name)? {
+                channel_names.push(channel.name);
+            }
+        }
+
+        // The budget covers every channel together
+        since = match apply_search_budget(state, since, |app_state| {
+            let mut day_costs = Vec::new();
+
+            for channel_name in &channel_names {
+                day_costs.extend(app_state.get_search_day_costs(
+                    channel_name,
+                    &parameters.query,
+                    parameters.to_options(),
+                    since,
+                    None,
+                )?);
+            }
+
+            Ok(day_costs)
+        })? {
+            Ok((since, notice)) => {
+                budget_notice = notice;
+                since
+            }
+            Err(notice) => {
+                budget_notice = Some(notice);
+                is_refused = true;
+                channel_names.clear();
+                since
+            }
+        };
+    }
+
+    Ok(Ok(GlobalSearchPlan {
+        query,
+        days,
+        parameters,
+        channel_names,
+        since,
+        budget_notice,
+        is_refused,
+    }))
+}
+
+fn render_global_search(
+    state: &mut State,
+    plan: GlobalSearchPlan,
+    channels: Vec<GlobalSearchChannel>,
+) -> anyhow::Result<Response<Body>> {
+    let GlobalSearchPlan {
+        query,
+        days,
+        parameters,
+        budget_notice,
+        is_refused,
+        ..
+    } = plan;
+    let mut query_string = parameters.to_query_string();
+
+    if let Some(days) = query.days.as_deref().filter(|days| !days.is_empty()) {
+        query_string.push_str(&format!(
+            "&days={}",
+            percent_encoding::utf8_percent_encode(days, percent_encoding::NON_ALPHANUMERIC)
+        ));
+    }
+
+    let template = GlobalSearchTemplate {
+        has_results: query.search.is_some(),
+        channels,
+        search: parameters.query,
+        query_string,
+        budget_notice,
+        case_sensitive: parameters.case_sensitive,
+        verbatim: parameters.verbatim,
+        whole_word: parameters.whole_word,
+        text_only: parameters.text_only,
+        messages_only: parameters.messages_only,
+        day_options: make_search_day_options(days),
+        color_theme: viewer_color_theme(state),
+        path_prefix: path_prefix(state),
+    };
+    let mut response = render_template(state, template)?;
+
+    if is_refused {
+        *response.status_mut() = StatusCode::UNPROCESSABLE_ENTITY;
+    }
+
+    Ok(response)
+}
+
+type BudgetedSince = (Option<Date<Utc>>, Option<String>); // Date to search from and a notice
+
+/// Checks a search reaching back to `since` against the search budget.
+/// Returns the date to search from with a notice if it was moved, or a
+/// notice refusing the search.
+fn apply_search_budget<F>(
+    state: &State,
+    since: Option<Date<Utc>>,
+    day_costs: F,
+) -> anyhow::Result<Result<BudgetedSince, String>>
+where
+    F: FnOnce(&AppState) -> anyhow::Result<Vec<(Date<Utc>, u64)>>,
+{
+    let app_state = AppState::borrow_from(state);
+    let budget = match &app_state.search_budget {
+        Some(budget) => budget,
+        None => return Ok(Ok((since, None))),
+    };
+    let day_costs = time_phase(state, "estimate", || day_costs(app_state))?;
+    let refusal = "This search would read more of the logs than one search may. \
+        Choose fewer days, or use fewer operators and repetitions."
+        .to_string();
+
+    Ok(
+        match crate::search::find_search_coverage(day_costs, budget.max_bytes) {
+            SearchCoverage::All => Ok((since, None)),
+            SearchCoverage::Since(date) if budget.action == SearchBudgetAction::Narrow => Ok((
+                Some(date),
+                Some(format!(
+                    "This search would read more of the logs than one search may, \
+                    so only days since {} were searched. Choose a day to search older logs.",
+                    date.format("%Y-%m-%d")
+                )),
+            )),
+            _ => Err(refusal),
+        },
+    )
+}
+
+/// Reads the `days` of a search form, where `Some(None)` searches every day.
+/// Returns `None` for values that aren't a number of days or reach back
+/// further than dates go.
+fn parse_search_days(value: Option<&str>, default: Option<u32>) -> Option<Option<u32>> {
+    let days = match value {
+        None | Some("") => default,
+        Some("all") => None,
+        Some(days) => Some(days.parse::<u32>().ok()?),
+    };
+
+    match days {
+        Some(days) => search_days_start(days).map(|_| Some(days)),
+        None => Some(None),
+    }
+}
+
+/// The first day of a search of the most recent days.
+fn search_days_start(days: u32) -> Option<Date<Utc>> {
+    Utc::today().checked_sub_signed(chrono::Duration::days(i64::from(days)))
+}
+
+fn make_search_day_options(selected: Option<u32>) -> Vec<SearchDayOption> {
+    let mut choices = SEARCH_DAY_CHOICES.to_vec();
+
+    if let Some(days) = selected {
+        if !choices.contains(&days) {
+            choices.push(days);
+            choices.sort_unstable();
+        }
+    }
+
+    let mut options = choices
+        .into_iter()
+        .map(|days| SearchDayOption {
+            value: days.to_string(),
+            label: format!("Last {} days", days),
+            selected: selected == Some(days),
+        })
+        .collect::<Vec<SearchDayOption>>();
+
+    options.push(SearchDayOption {
+        value: "all".to_string(),
+        label: "All dates".to_string(),
+        selected: selected.is_none(),
+    });
+
+    options
+}
+
+#[derive(Deserialize)]
+struct SavedSearchForm {
+    search: String,
+    case: Option<String>,
+    verbatim: Option<String>,
+    word: Option<String>,
+    text: Option<String>,
+    messages_only: Option<String>,
+    action: String,
+}
+
+pub async fn update_saved_search(mut state: State) -> (State, anyhow::Result<Response<Body>>) {
+    let result = update_saved_search_impl(&mut state).await;
+    (state, result)
+}
+
+async fn update_saved_search_impl(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let params = ChannelParams::take_from(state);
+
+    if !user_has_access(state, &params.channel)? {
+        return Ok(build_auth_response(state));
+    }
+
+    let user = match authenticated_user(state)? {
+        Some(user) => user,
+        None => return Ok(build_auth_response(state)),
+    };
+
+    let form: SavedSearchForm = match read_form(state).await? {
+        Ok(form) => form,
+        Err(status) => return Ok(create_empty_response(state, status)),
+    };
+
+    let parameters = SearchParameters {
+        channel: params.channel.clone(),
+        query: form.search,
+        case_sensitive: form.case.as_deref() == Some("on"),
+        verbatim: form.verbatim.as_deref() == Some("on"),
+        whole_word: form.word.as_deref() == Some("on"),
+        text_only: form.text.as_deref() == Some("on"),
+        messages_only: form.messages_only.as_deref() == Some("on"),
+    };
+    let app_state = AppState::borrow_from(state);
+    let store = &app_state.search_history_store;
+
+    match form.action.as_str() {
+        "pin" => store.set_pinned(&user, &parameters, true)?,
+        "unpin" => store.set_pinned(&user, &parameters, false)?,
+        "alert" if app_state.saved_search_alerts => store.set_alert(&user, &parameters, true)?,
+        "unalert" => store.set_alert(&user, &parameters, false)?,
+        "delete" => store.remove_search(&user, &parameters)?,
+        _ => return Ok(create_empty_response(state, StatusCode::BAD_REQUEST)),
+    }
+
+    build_see_other_response(
+        state,
+        &format!("/bin/irclogger_log_search/{}", params.channel),
+    )
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct ChannelDiffQuery {
+    date: String,
+}
+
+pub async fn channel_diff(mut state: State) -> (State, anyhow::Result<Response<Body>>) {
+    let result = channel_diff_impl(&mut state).await;
+    (state, result)
+}
+
+async fn channel_diff_impl(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let params = ChannelParams::take_from(state);
+
+    if !user_has_access(state, &params.channel)? {
+        return Ok(build_auth_response(state));
+    }
+
+    let query = ChannelDiffQuery::take_from(state);
+
+    if !is_date_string_ok(&query.date) {
+        return Ok(create_empty_response(state, StatusCode::BAD_REQUEST));
+    }
+
+    let app_state = AppState::borrow_from(state);
+    let upstream_url = match &app_state.mirror_upstream_url {
+        Some(url_template) => {
+            crate::mirror::format_upstream_url(url_template, &params.channel, &query.date)
+        }
+        None => return Ok(create_empty_response(state, StatusCode::NOT_FOUND)),
+    };
+    let local = app_state.get_raw_log(&params.channel, &query.date).ok();
+
+    let upstream =
+        crate::mirror::fetch_upstream_day(&upstream_url, app_state.mirror_max_day_size).await?;
+    let report = crate::mirror::compare_day(
+        &params.channel,
+        &query.date,
+        &upstream_url,
+        local.as_deref(),
+        upstream.as_deref(),
+    );
+
+    Ok(create_response(
+        state,
+        StatusCode::OK,
+        mime::APPLICATION_JSON,
+        serde_json::to_vec_pretty(&report)?,
+    ))
+}
+
+#[derive(Deserialize)]
+struct AnnotationForm {
+    date: String,
+    line: u64,
+    note: String,
+}
+
+pub async fn annotate_line(mut state: State) -> (State, anyhow::Result<Response<Body>>) {
+    let result = annotate_line_impl(&mut state).await;
+    (state, result)
+}
+
+async fn annotate_line_impl(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let params = ChannelParams::take_from(state);
+
+    if !user_has_access(state, &params.channel)? {
+        return Ok(build_auth_response(state));
+    }
+
+    let author = match authenticated_user(state)? {
+        Some(author) => author,
+        None => return Ok(build_auth_response(state)),
+    };
+
+    let form: AnnotationForm = match read_form(state).await? {
+        Ok(form) => form,
+        Err(status) => return Ok(create_empty_response(state, status)),
+    };
+    let note = form.note.trim();
+
+    if !is_date_string_ok(&form.date)
+        || form.line == 0
+        || note.is_empty()
+        || note.chars().count() > MAX_NOTE_LENGTH
+    {
+        return Ok(create_empty_response(state, StatusCode::BAD_REQUEST));
+    }
+
+    let app_state = AppState::borrow_from(state);
+    app_state.annotation_store.add_annotation(
+        &params.channel,
+        &form.date,
+        Annotation {
+            line_number: form.line,
+            author,
+            note: note.to_string(),
+            created: Utc::now(),
+        },
+    )?;
+
+    build_see_other_response(
+        state,
+        &format!(
+            "/bin/irclogger_log/{channel}/?date={date}&sel={line}#l{line}",
+            channel = params.channel,
+            date = form.date,
+            line = form.line
+        ),
+    )
+}
+
+#[derive(Template)]
+#[template(path = "bookmarks.html")]
+struct BookmarksTemplate {
+    user: String,
+    bookmarks: Vec<Bookmark>,
+    color_theme: ColorTheme,
+    path_prefix: String,
+}
+
+pub fn bookmarks(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let app_state = AppState::borrow_from(state);
+
+    if !app_state.bookmark_store.is_enabled() {
+        return Ok(create_empty_response(state, StatusCode::NOT_FOUND));
+    }
+
+    let user = match authenticated_user(state)? {
+        Some(user) => user,
+        None => return Ok(build_auth_response(state)),
+    };
+
+    let mut bookmarks = app_state.bookmark_store.get_bookmarks(&user)?;
+    bookmarks.reverse();
+
+    let template = BookmarksTemplate {
+        user,
+        bookmarks,
+        color_theme: viewer_color_theme(state),
+        path_prefix: path_prefix(state),
+    };
+
+    render_template(state, template)
+}
+
+#[derive(Deserialize)]
+struct BookmarkForm {
+    channel: String,
+    date: String,
+    line: u64,
+    label: Option<String>,
+}
+
+pub async fn add_bookmark(mut state: State) -> (State, anyhow::Result<Response<Body>>) {
+    let result = add_bookmark_impl(&mut state).await;
+    (state, result)
+}
+
+async fn add_bookmark_impl(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let user = match authenticated_user(state)? {
+        Some(user) => user,
+        None => return Ok(build_auth_response(state)),
+    };
+
+    let form: BookmarkForm = match read_form(state).await? {
+        Ok(form) => form,
+        Err(status) => return Ok(create_empty_response(state, status)),
+    };
+    let label = form.label.as_deref().unwrap_or_default().trim().to_string();
+
+    if !is_channel_name_ok(&form.channel)
+        || !is_date_string_ok(&form.date)
+        || form.line == 0
+        || label.chars().count() > MAX_LABEL_LENGTH
+    {
+        return Ok(create_empty_response(state, StatusCode::BAD_REQUEST));
+    }
+
+    if !user_has_access(state, &form.channel)? {
+        return Ok(build_auth_response(state));
+    }
+
+    let app_state = AppState::borrow_from(state);
+    app_state.bookmark_store.add_bookmark(
+        &user,
+        Bookmark {
+            channel: form.channel,
+            date_slug: form.date,
+            line_number: form.line,
+            label,
+            created: Utc::now(),
+        },
+    )?;
+
+    build_see_other_response(state, "/bin/irclogger_bookmarks")
+}
+
+#[derive(Deserialize)]
+struct DeleteBookmarkForm {
+    channel: String,
+    date: String,
+    line: u64,
+}
+
+pub async fn delete_bookmark(mut state: State) -> (State, anyhow::Result<Response<Body>>) {
+    let result = delete_bookmark_impl(&mut state).await;
+    (state, result)
+}
+
+async fn delete_bookmark_impl(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let user = match authenticated_user(state)? {
+        Some(user) => user,
+        None => return Ok(build_auth_response(state)),
+    };
 
-use crate::{
-    reader::{LogLine, LogLineContent},
-    state::{AppState, ChannelDailyEntry, ChannelInfo, SearchResultEntry},
-};
+    let form: DeleteBookmarkForm = match read_form(state).await? {
+        Ok(form) => form,
+        Err(status) => return Ok(create_empty_response(state, status)),
+    };
 
-fn render_template<T: Template>(state: &mut State, template: T) -> anyhow::Result<Response<Body>> {
-    let content = template.render()?;
+    let app_state = AppState::borrow_from(state);
+    app_state
+        .bookmark_store
+        .remove_bookmark(&user, &form.channel, &form.date, form.line)?;
 
-    Ok(create_response(
-        state,
-        StatusCode::OK,
-        mime::TEXT_HTML_UTF_8,
-        content.into_bytes(),
-    ))
+    build_see_other_response(state, "/bin/irclogger_bookmarks")
 }
 
-#[derive(Deserialize, StateData, StaticResponseExtender)]
-pub struct ChannelParams {
-    channel: String,
+const MAX_FORM_BYTES: usize = 16 * 1024;
+
+/// Reads a form posted from one of the site's pages. Forms posted from
+/// other sites are refused, since the browser sends the login along with
+/// them, and so are bodies larger than any of the forms.
+async fn read_form<T: DeserializeOwned>(
+    state: &mut State,
+) -> anyhow::Result<Result<T, StatusCode>> {
+    let headers = HeaderMap::borrow_from(state);
+    let public_url = AppState::borrow_from(state).public_url.as_deref();
+
+    if !is_same_origin_request(headers, public_url) {
+        return Ok(Err(StatusCode::FORBIDDEN));
+    }
+
+    let content = match read_body(state, MAX_FORM_BYTES).await? {
+        Ok(content) => content,
+        Err(status) => return Ok(Err(status)),
+    };
+
+    Ok(serde_urlencoded::from_bytes(&content).map_err(|_| StatusCode::BAD_REQUEST))
 }
 
-#[derive(Template)]
-#[template(path = "index.html")]
-struct IndexTemplate {
-    channels: Vec<ChannelInfo>,
-    message: String,
+/// Reads a request body, refusing it with `413 Payload Too Large` once it's
+/// declared or found to be longer than `max_bytes`.
+async fn read_body(
+    state: &mut State,
+    max_bytes: usize,
+) -> anyhow::Result<Result<Vec<u8>, StatusCode>> {
+    let declared_length = HeaderMap::borrow_from(state)
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+
+    if declared_length.is_some_and(|length| length > max_bytes as u64) {
+        return Ok(Err(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+
+    let mut body = Body::take_from(state);
+    let mut content = Vec::new();
+
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+
+        if content.len() + chunk.len() > max_bytes {
+            return Ok(Err(StatusCode::PAYLOAD_TOO_LARGE));
+        }
+
+        content.extend_from_slice(&chunk);
+    }
+
+    Ok(Ok(content))
 }
 
-pub fn index(state: &mut State) -> anyhow::Result<Response<Body>> {
-    let app_state = AppState::borrow_from(state);
-    let channels = app_state.get_channels()?;
-    let message = app_state.get_custom_message()?;
+/// Whether a request came from one of the site's pages, by its Origin
+/// header or else its Referer, matching the Host header or the public URL.
+/// Requests with neither come from scripts rather than browsers and are
+/// allowed.
+fn is_same_origin_request(headers: &HeaderMap, public_url: Option<&str>) -> bool {
+    let source = match headers
+        .get(header::ORIGIN)
+        .or_else(|| headers.get(header::REFERER))
+    {
+        Some(source) => source,
+        None => return true,
+    };
+    let source = match source
+        .to_str()
+        .ok()
+        .and_then(|source| reqwest::Url::parse(source).ok())
+    {
+        Some(source) => source,
+        None => return false,
+    };
+    let source_host = match (source.host_str(), source.port()) {
+        (Some(host), Some(port)) => format!("{}:{}", host, port),
+        (Some(host), None) => host.to_string(),
+        (None, _) => return false,
+    };
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok());
 
-    let template = IndexTemplate { channels, message };
-    let response = render_template(state, template)?;
+    host.is_some_and(|host| host.eq_ignore_ascii_case(&source_host))
+        || public_url
+            .and_then(|public_url| reqwest::Url::parse(public_url).ok())
+            .is_some_and(|public_url| public_url.origin() == source.origin())
+}
+
+/// Redirects to a page of the site, given by its path without the prefix.
+fn build_see_other_response(state: &State, location: &str) -> anyhow::Result<Response<Body>> {
+    let mut response = create_empty_response(state, StatusCode::SEE_OTHER);
+    response
+        .headers_mut()
+        .insert("Location", site_url(state, location).parse()?);
 
     Ok(response)
 }
 
 #[derive(Template)]
-#[template(path = "channel_index.html")]
-struct ChannelIndexTemplate {
-    channel_name: String,
-    entries: Vec<ChannelDailyEntry>,
+#[template(path = "preferences.html")]
+struct PreferencesTemplate {
+    thumbnails: String,
+    hide_status: bool,
+    hidden_nicks: String,
+    max_hidden_nicks: usize,
+    timezone: String,
+    color_theme: ColorTheme,
+    path_prefix: String,
 }
 
-pub fn channel_daily_index(state: &mut State) -> anyhow::Result<Response<Body>> {
-    let params = ChannelParams::take_from(state);
+pub fn preferences(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let app_state = AppState::borrow_from(state);
 
-    if !user_has_access(state, &params.channel)? {
-        return Ok(build_auth_response(state));
+    if !app_state.preference_signer.is_enabled() {
+        return Ok(create_empty_response(state, StatusCode::NOT_FOUND));
     }
 
-    let app_state = AppState::borrow_from(state);
-    let entries = app_state.get_channel_daily_entries(&params.channel)?;
+    let preferences = get_preferences(state);
+    let template = PreferencesTemplate {
+        thumbnails: match preferences.thumbnails {
+            Some(true) => "on",
+            Some(false) => "off",
+            None => "",
+        }
+        .to_string(),
+        hide_status: preferences.hide_status,
+        hidden_nicks: preferences.hidden_nicks.join("\n"),
+        max_hidden_nicks: crate::preferences::MAX_HIDDEN_NICKS,
+        timezone: preferences.timezone.unwrap_or_default(),
+        color_theme: viewer_color_theme(state),
+        path_prefix: path_prefix(state),
+    };
 
-    let template = ChannelIndexTemplate {
-        channel_name: params.channel,
-        entries,
+    render_template(state, template)
+}
+
+#[derive(Deserialize)]
+struct PreferencesForm {
+    thumbnails: Option<String>,
+    hide_status: Option<String>,
+    hidden_nicks: Option<String>,
+    timezone: Option<String>,
+}
+
+pub async fn update_preferences(mut state: State) -> (State, anyhow::Result<Response<Body>>) {
+    let result = update_preferences_impl(&mut state).await;
+    (state, result)
+}
+
+async fn update_preferences_impl(state: &mut State) -> anyhow::Result<Response<Body>> {
+    if !AppState::borrow_from(state).preference_signer.is_enabled() {
+        return Ok(create_empty_response(state, StatusCode::NOT_FOUND));
+    }
+
+    let form: PreferencesForm = match read_form(state).await? {
+        Ok(form) => form,
+        Err(status) => return Ok(create_empty_response(state, status)),
     };
-    let response = render_template(state, template)?;
+
+    let mut preferences = get_preferences(state);
+    preferences.thumbnails = match form.thumbnails.as_deref() {
+        Some("on") => Some(true),
+        Some("off") => Some(false),
+        _ => None,
+    };
+    preferences.hide_status = form.hide_status.as_deref() == Some("on");
+    preferences.hidden_nicks = form
+        .hidden_nicks
+        .unwrap_or_default()
+        .lines()
+        .map(str::to_string)
+        .collect();
+    preferences.timezone = form.timezone;
+    preferences.normalize();
+
+    let mut response = build_see_other_response(state, "/bin/irclogger_preferences")?;
+    append_preferences_cookie(state, &mut response, &preferences)?;
 
     Ok(response)
 }
 
-#[derive(Deserialize, StateData, StaticResponseExtender)]
-pub struct ChannelLinesQuery {
-    pub date: String,
-    sel: Option<String>,
-    raw: Option<String>,
-}
+pub fn export_preferences(state: &mut State) -> anyhow::Result<Response<Body>> {
+    if !AppState::borrow_from(state).preference_signer.is_enabled() {
+        return Ok(create_empty_response(state, StatusCode::NOT_FOUND));
+    }
 
-#[derive(Template)]
-#[template(path = "channel_lines.html")]
-struct ChannelLinesTemplate {
-    pub channel_name: String,
-    pub lines: Vec<LogOutputLine>,
-    pub date_slug: String,
-    pub selected_line_number: u64,
+    let preferences = get_preferences(state);
+    let mut response = create_response(
+        state,
+        StatusCode::OK,
+        mime::APPLICATION_JSON,
+        serde_json::to_vec_pretty(&preferences)?,
+    );
+    response.headers_mut().insert(
+        "Content-Disposition",
+        "attachment; filename=\"irclogger-preferences.json\"".parse()?,
+    );
+
+    Ok(response)
 }
 
-struct LogOutputLine {
-    pub date: DateTime<Utc>,
-    pub nickname: String,
-    pub text: String,
-    pub line_number: u64,
+#[derive(Deserialize)]
+struct ImportPreferencesForm {
+    json: String,
 }
 
-pub fn channel_lines(state: &mut State) -> anyhow::Result<Response<Body>> {
-    let params = ChannelParams::take_from(state);
+pub async fn import_preferences(mut state: State) -> (State, anyhow::Result<Response<Body>>) {
+    let result = import_preferences_impl(&mut state).await;
+    (state, result)
+}
 
-    if !user_has_access(state, &params.channel)? {
-        return Ok(build_auth_response(state));
+async fn import_preferences_impl(state: &mut State) -> anyhow::Result<Response<Body>> {
+    if !AppState::borrow_from(state).preference_signer.is_enabled() {
+        return Ok(create_empty_response(state, StatusCode::NOT_FOUND));
     }
 
-    let query = ChannelLinesQuery::take_from(state);
+    let form: ImportPreferencesForm = match read_form(state).await? {
+        Ok(form) => form,
+        Err(status) => return Ok(create_empty_response(state, status)),
+    };
+    let mut preferences: Preferences = match serde_json::from_str(&form.json) {
+        Ok(preferences) => preferences,
+        Err(_) => return Ok(create_empty_response(state, StatusCode::BAD_REQUEST)),
+    };
+    preferences.normalize();
 
-    if !is_date_string_ok(&query.date) {
-        return Ok(create_empty_response(state, StatusCode::BAD_REQUEST));
+    let mut response = build_see_other_response(state, "/bin/irclogger_preferences")?;
+    append_preferences_cookie(state, &mut response, &preferences)?;
+
+    Ok(response)
+}
+
+#[derive(Template)]
+#[template(path = "admin.html")]
+struct AdminTemplate {
+    tasks: Vec<AdminTaskRow>,
+    lenient_parsing: bool,
+    parse_problems: Vec<ParseProblemRecord>,
+    hidden_channels_enabled: bool,
+    hidden_channels: Vec<HiddenChannel>,
+    color_theme: ColorTheme,
+    path_prefix: String,
+}
+
+struct AdminTaskRow {
+    name: String,
+    schedule: String,
+    last_run: String,
+    last_duration: String,
+    last_error: String,
+    run_count: u64,
+}
+
+pub fn admin_index(state: &mut State) -> anyhow::Result<Response<Body>> {
+    if !is_admin(state)? {
+        return Ok(build_auth_response(state));
     }
 
     let app_state = AppState::borrow_from(state);
+    let tasks = app_state
+        .scheduler_status
+        .get_tasks()
+        .into_iter()
+        .map(|task| AdminTaskRow {
+            name: task.name,
+            schedule: task.schedule,
+            last_run: task
+                .last_run
+                .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_default(),
+            last_duration: task
+                .last_duration
+                .map(|duration| format!("{:.1}s", duration.as_secs_f64()))
+                .unwrap_or_default(),
+            last_error: task.last_error.unwrap_or_default(),
+            run_count: task.run_count,
+        })
+        .collect();
 
-    if let Some("on") = query.raw.as_deref() {
-        let response = create_response(
-            state,
-            StatusCode::OK,
-            mime::TEXT_PLAIN_UTF_8,
-            app_state.get_raw_log(&params.channel, &query.date)?,
-        );
+    let template = AdminTemplate {
+        tasks,
+        lenient_parsing: app_state.lenient_parsing,
+        parse_problems: app_state.parse_problems.get_problems(),
+        hidden_channels_enabled: app_state.hidden_channels.is_enabled(),
+        hidden_channels: app_state.hidden_channels.get_hidden_channels()?,
+        color_theme: viewer_color_theme(state),
+        path_prefix: path_prefix(state),
+    };
 
-        return Ok(response);
+    render_template(state, template)
+}
+
+pub fn admin_parse_problems(state: &mut State) -> anyhow::Result<Response<Body>> {
+    if !is_admin(state)? {
+        return Ok(build_auth_response(state));
     }
 
-    let lines = app_state.get_log_lines(&params.channel, &query.date)?;
-    let lines = make_output_lines(&lines);
+    let problems = AppState::borrow_from(state).parse_problems.get_problems();
 
-    let template = ChannelLinesTemplate {
-        channel_name: params.channel.clone(),
-        lines,
-        date_slug: query.date.clone(),
-        selected_line_number: query
-            .sel
-            .and_then(|s| s.parse::<u64>().ok())
-            .unwrap_or(u64::MAX),
-    };
+    Ok(create_response(
+        state,
+        StatusCode::OK,
+        mime::APPLICATION_JSON,
+        serde_json::to_vec(&problems)?,
+    ))
+}
 
-    let mut response = render_template(state, template)?;
-    let headers = HeaderMap::borrow_from(state);
-    let host = match headers.get("host") {
-        Some(host) => host.to_str().unwrap(),
-        None => "",
+pub async fn admin_self_test(mut state: State) -> (State, anyhow::Result<Response<Body>>) {
+    let result = admin_self_test_impl(&mut state).await;
+    (state, result)
+}
+
+async fn admin_self_test_impl(state: &mut State) -> anyhow::Result<Response<Body>> {
+    if !is_admin(state)? {
+        return Ok(build_auth_response(state));
+    }
+
+    let search_backend = AppState::borrow_from(state).search_backend;
+    let report = crate::selftest::run_self_test(search_backend).await?;
+    let status = if report.passed {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
     };
 
-    response.headers_mut().append(
-        "Link",
-        format!(
-            "<https://{host}/bin/irclogger_log/{channel}/?date={date_slug}>; rel=\"canonical\"",
-            host = host,
-            channel = params.channel,
-            date_slug = query.date
-        )
-        .parse()
-        .unwrap(),
-    );
+    Ok(create_response(
+        state,
+        status,
+        mime::APPLICATION_JSON,
+        serde_json::to_vec(&report)?,
+    ))
+}
 
-    Ok(response)
+#[derive(Deserialize)]
+struct ReindexForm {
+    channel: String,
+    date: String, // Date slug of the day that was written
 }
 
-fn is_date_string_ok(date: &str) -> bool {
-    lazy_static! {
-        static ref PATTERN: Regex = Regex::new(r"^\d\d\d\d-\d\d-\d\d,\w+$").unwrap();
-    }
+/// Lets the logger announce that a day's log changed, so the viewer catches
+/// up with it without waiting for the scheduled search index update.
+pub async fn admin_reindex(mut state: State) -> (State, anyhow::Result<Response<Body>>) {
+    let form = match read_reindex_form(&mut state).await {
+        Ok(Ok(form)) => form,
+        Ok(Err(response)) => return (state, Ok(response)),
+        Err(error) => return (state, Err(error)),
+    };
 
-    PATTERN.is_match(date)
+    // Indexing the day reads and parses its log
+    run_blocking(state, move |state| {
+        let app_state = AppState::borrow_from(state);
+        time_phase(state, "index", || {
+            app_state.refresh_day(&form.channel, &form.date)
+        })?;
+
+        Ok(create_empty_response(state, StatusCode::NO_CONTENT))
+    })
+    .await
 }
 
-fn make_output_lines(lines: &[LogLine]) -> Vec<LogOutputLine> {
-    let mut output_lines = Vec::new();
+async fn read_reindex_form(
+    state: &mut State,
+) -> anyhow::Result<Result<ReindexForm, Response<Body>>> {
+    if !is_admin(state)? {
+        return Ok(Err(build_auth_response(state)));
+    }
 
-    for (line_number, line) in lines.iter().enumerate() {
-        let line_number = line_number as u64 + 1;
-        let output_line = match &line.content {
-            LogLineContent::Status(text) => LogOutputLine {
-                date: line.date,
-                nickname: String::new(),
-                text: text.clone(),
-                line_number,
-            },
-            LogLineContent::Message { nickname, text } => LogOutputLine {
-                date: line.date,
-                nickname: nickname.clone(),
-                text: text.clone(),
-                line_number,
-            },
-        };
+    let form: ReindexForm = match read_form(state).await? {
+        Ok(form) => form,
+        Err(status) => return Ok(Err(create_empty_response(state, status))),
+    };
 
-        output_lines.push(output_line);
+    if !is_channel_name_ok(&form.channel) || !is_date_string_ok(&form.date) {
+        return Ok(Err(create_empty_response(state, StatusCode::BAD_REQUEST)));
     }
 
-    output_lines
+    Ok(Ok(form))
 }
 
-#[derive(Template)]
-#[template(path = "channel_search.html")]
-struct ChannelSearchTemplate {
-    pub channel_name: String,
-    pub has_results: bool,
-    pub results: Vec<SearchResultEntry>,
+#[derive(Deserialize)]
+struct HiddenChannelForm {
+    action: String, // "hide" or "restore"
+    channel: String,
+    #[serde(default)]
+    reason: String,
 }
 
-#[derive(Deserialize, StateData, StaticResponseExtender)]
-pub struct ChannelSearchQuery {
-    search: Option<String>,
-    //action: Option<String>,
-    case: Option<String>,
-    verbatim: Option<String>,
-    word: Option<String>,
+pub async fn update_hidden_channel(mut state: State) -> (State, anyhow::Result<Response<Body>>) {
+    let result = update_hidden_channel_impl(&mut state).await;
+    (state, result)
 }
 
-pub fn channel_search(state: &mut State) -> anyhow::Result<Response<Body>> {
-    let params = ChannelParams::take_from(state);
-
-    if !user_has_access(state, &params.channel)? {
+async fn update_hidden_channel_impl(state: &mut State) -> anyhow::Result<Response<Body>> {
+    if !is_admin(state)? {
         return Ok(build_auth_response(state));
     }
 
-    let query = ChannelSearchQuery::take_from(state);
+    let admin = authenticated_user(state)?.unwrap_or_default();
+    let form: HiddenChannelForm = match read_form(state).await? {
+        Ok(form) => form,
+        Err(status) => return Ok(create_empty_response(state, status)),
+    };
+    let reason = form.reason.trim();
+
+    if !is_channel_name_ok(&form.channel) || reason.chars().count() > MAX_REASON_LENGTH {
+        return Ok(create_empty_response(state, StatusCode::BAD_REQUEST));
+    }
+
     let app_state = AppState::borrow_from(state);
 
-    let search_results = if query.search.is_some() {
-        app_state.search_channel(
-            &params.channel,
-            query.search.as_deref().unwrap_or_default(),
-            query.case.unwrap_or_default() == "on",
-            query.verbatim.unwrap_or_default() == "on",
-            query.word.unwrap_or_default() == "on",
-        )?
-    } else {
-        Vec::new()
+    if !app_state.hidden_channels.is_enabled() {
+        return Ok(create_empty_response(state, StatusCode::NOT_FOUND));
+    }
+
+    match form.action.as_str() {
+        "hide" => app_state.hidden_channels.hide_channel(HiddenChannel {
+            channel: form.channel,
+            reason: reason.to_string(),
+            hidden_by: admin,
+            hidden_at: Utc::now(),
+        })?,
+        "restore" => app_state.hidden_channels.restore_channel(&form.channel)?,
+        _ => return Ok(create_empty_response(state, StatusCode::BAD_REQUEST)),
+    }
+
+    build_see_other_response(state, "/admin")
+}
+
+#[derive(Template)]
+#[template(path = "channel_hidden.html")]
+struct ChannelHiddenTemplate {
+    channel_name: String,
+    reason: String,
+    color_theme: ColorTheme,
+    path_prefix: String,
+}
+
+/// Answers every request for the pages of a hidden channel with 410 Gone
+/// and the reason, or returns `None` for other requests.
+pub fn hidden_channel_response(state: &mut State) -> anyhow::Result<Option<Response<Body>>> {
+    let channel = match (
+        ChannelParams::try_borrow_from(state),
+        BadgeParams::try_borrow_from(state),
+    ) {
+        (Some(ChannelParams { channel }), _) | (_, Some(BadgeParams { channel, .. })) => {
+            channel.clone()
+        }
+        _ => return Ok(None),
+    };
+    let hidden = match AppState::borrow_from(state)
+        .hidden_channels
+        .get_hidden_channel(&channel)?
+    {
+        Some(hidden) => hidden,
+        None => return Ok(None),
     };
 
-    let template = ChannelSearchTemplate {
-        channel_name: params.channel.clone(),
-        has_results: query.search.is_some(),
-        results: search_results,
+    let template = ChannelHiddenTemplate {
+        channel_name: hidden.channel,
+        reason: hidden.reason,
+        color_theme: viewer_color_theme(state),
+        path_prefix: path_prefix(state),
     };
+    let mut response = render_template(state, template)?;
+    *response.status_mut() = StatusCode::GONE;
 
-    let response = render_template(state, template)?;
+    Ok(Some(response))
+}
 
-    Ok(response)
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct ProxyImageQuery {
+    url: String,
+}
+
+pub async fn proxy_image(mut state: State) -> (State, anyhow::Result<Response<Body>>) {
+    let query = ProxyImageQuery::take_from(&mut state);
+    let image_proxy = AppState::borrow_from(&state).image_proxy.clone();
+
+    if !image_proxy.is_url_allowed(&query.url) {
+        let response = create_empty_response(&state, StatusCode::FORBIDDEN);
+        return (state, Ok(response));
+    }
+
+    let response = match image_proxy.get_image(&query.url).await {
+        Ok(image) => {
+            let mut response =
+                create_response(&state, StatusCode::OK, image.content_type, image.data);
+            let headers = response.headers_mut();
+            headers.insert("Cache-Control", "public, max-age=86400".parse().unwrap());
+            headers.insert("X-Content-Type-Options", "nosniff".parse().unwrap());
+            headers.insert(
+                "Content-Security-Policy",
+                "default-src 'none'".parse().unwrap(),
+            );
+            response
+        }
+        Err(_) => create_empty_response(&state, StatusCode::BAD_GATEWAY),
+    };
+
+    (state, Ok(response))
 }
 
 pub fn redirect_channel_daily_index(state: &mut State) -> anyhow::Result<Response<Body>> {
@@ -250,7 +3834,7 @@ pub fn redirect_channel_daily_index(state: &mut State) -> anyhow::Result<Respons
 
     response.headers_mut().insert(
         "Location",
-        format!("/bin/irclogger_logs/{}", params.channel,).parse()?,
+        site_url(state, &format!("/bin/irclogger_logs/{}", params.channel)).parse()?,
     );
 
     Ok(response)
@@ -263,10 +3847,13 @@ pub fn redirect_channel_lines(state: &mut State) -> anyhow::Result<Response<Body
 
     response.headers_mut().insert(
         "Location",
-        format!(
-            "/bin/irclogger_log/{}/?{}",
-            params.channel,
-            uri.query().unwrap_or_default()
+        site_url(
+            state,
+            &format!(
+                "/bin/irclogger_log/{}/?{}",
+                params.channel,
+                uri.query().unwrap_or_default()
+            ),
         )
         .parse()?,
     );
@@ -281,10 +3868,13 @@ pub fn redirect_channel_search(state: &mut State) -> anyhow::Result<Response<Bod
 
     response.headers_mut().insert(
         "Location",
-        format!(
-            "/bin/irclogger_log_search/{}/?{}",
-            params.channel,
-            uri.query().unwrap_or_default()
+        site_url(
+            state,
+            &format!(
+                "/bin/irclogger_log_search/{}/?{}",
+                params.channel,
+                uri.query().unwrap_or_default()
+            ),
         )
         .parse()?,
     );
@@ -293,15 +3883,45 @@ pub fn redirect_channel_search(state: &mut State) -> anyhow::Result<Response<Bod
 }
 
 fn user_has_access(state: &mut State, channel: &str) -> anyhow::Result<bool> {
+    let state = &*state;
+
+    time_phase(state, "auth", || check_user_access(state, channel))
+}
+
+fn check_user_access(state: &State, channel: &str) -> anyhow::Result<bool> {
     let app_state = AppState::borrow_from(state);
 
     if app_state.is_channel_private(channel)? {
+        if app_state.demo_mode {
+            return Ok(false);
+        }
+
         let headers = state.borrow::<HeaderMap>();
 
         if let Some(value) = headers.get("Authorization") {
             match Credentials::from_header(value.to_str().unwrap_or_default().to_string()) {
-                Ok(credentials) => Ok(channel == credentials.user_id
-                    && app_state.is_password_ok(channel, &credentials.password)?),
+                Ok(credentials) => {
+                    // Checked even for another channel's name so that a wrong
+                    // name takes as long to reject as a wrong password
+                    let password_ok =
+                        app_state.is_password_ok(&credentials.user_id, &credentials.password)?;
+                    let access_ok = crate::state::constant_time_eq(
+                        channel.as_bytes(),
+                        credentials.user_id.as_bytes(),
+                    ) && password_ok;
+
+                    if access_ok {
+                        if let Some(url) = app_state.get_channel_metadata(channel)?.access_webhook {
+                            app_state.access_audit.record_login(
+                                &url,
+                                channel,
+                                &credentials.user_id,
+                            );
+                        }
+                    }
+
+                    Ok(access_ok)
+                }
                 Err(_) => Ok(false),
             }
         } else {
@@ -312,7 +3932,45 @@ fn user_has_access(state: &mut State, channel: &str) -> anyhow::Result<bool> {
     }
 }
 
+fn is_admin(state: &State) -> anyhow::Result<bool> {
+    let app_state = AppState::borrow_from(state);
+
+    match authenticated_user(state)? {
+        Some(user) => Ok(app_state.admin_users.contains(&user)),
+        None => Ok(false),
+    }
+}
+
+fn authenticated_user(state: &State) -> anyhow::Result<Option<String>> {
+    let app_state = AppState::borrow_from(state);
+
+    if app_state.demo_mode {
+        return Ok(None);
+    }
+    let headers = HeaderMap::borrow_from(state);
+
+    if let Some(value) = headers.get("Authorization") {
+        match Credentials::from_header(value.to_str().unwrap_or_default().to_string()) {
+            Ok(credentials) => {
+                if app_state.is_password_ok(&credentials.user_id, &credentials.password)? {
+                    Ok(Some(credentials.user_id))
+                } else {
+                    Ok(None)
+                }
+            }
+            Err(_) => Ok(None),
+        }
+    } else {
+        Ok(None)
+    }
+}
+
 fn build_auth_response(state: &mut State) -> Response<Body> {
+    // Demo instances don't reveal that gated content exists
+    if AppState::borrow_from(state).demo_mode {
+        return create_empty_response(state, StatusCode::NOT_FOUND);
+    }
+
     let mut response = create_response(
         state,
         StatusCode::UNAUTHORIZED,
@@ -328,3 +3986,6 @@ fn build_auth_response(state: &mut State) -> Response<Body> {
 
     response
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,699 @@
+//! JSON versions of the channel list, channel index and day view for bots
+//! and scripts, nickname suggestions for forms, a description of the archive
+//! for discovery tools, and daily message counts for Grafana.
+
+use std::cmp::Ordering;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use gotham::{
+    helpers::http::response::{create_empty_response, create_response},
+    hyper::{Body, Response, StatusCode},
+    state::{FromState, State},
+};
+use gotham_derive::{StateData, StaticResponseExtender};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    build_auth_response, is_date_string_ok, read_body, user_has_access, ChannelParams, NickFilter,
+};
+use crate::{
+    app::run_blocking,
+    cursor::Cursor,
+    reader::LogLine,
+    state::{AppState, ChannelDailyEntry},
+    timing::time_phase,
+};
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct ApiLogQuery {
+    date: String,
+    nick: Option<String>, // Comma-separated nicknames whose messages are kept
+    exclude_nick: Option<String>, // Comma-separated nicknames whose messages are left out
+    after: Option<String>, // Cursor from `next_cursor`; only later lines are given
+    limit: Option<String>, // Most lines to give
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct ApiNicksQuery {
+    prefix: Option<String>, // Start of the nicknames, in any case
+}
+
+const MAX_NICK_SUGGESTIONS: usize = 20;
+
+const DEFAULT_TAIL_LINES: usize = 100;
+const MAX_TAIL_LINES: usize = 1000;
+
+const MAX_GRAFANA_QUERY_BYTES: usize = 64 * 1024;
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+pub struct ApiTailQuery {
+    n: Option<String>, // Number of lines, 100 by default
+}
+
+/// A query from Grafana's JSON datasource, where each target is a channel.
+#[derive(Deserialize)]
+struct GrafanaQuery {
+    range: GrafanaRange,
+    targets: Vec<GrafanaTarget>,
+}
+
+#[derive(Deserialize)]
+struct GrafanaRange {
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+struct GrafanaTarget {
+    target: String,
+}
+
+#[derive(Serialize)]
+struct GrafanaSeries {
+    target: String,
+    datapoints: Vec<(u64, i64)>, // Message count and milliseconds since the epoch of each day
+}
+
+#[derive(Serialize)]
+struct ApiLog {
+    channel: String,
+    date_slug: String,
+    lines: Vec<ApiLogLine>,
+    next_cursor: Option<String>, // Continues after the last line, if more may follow
+}
+
+#[derive(Serialize)]
+struct ApiLogLine {
+    line_number: u64,
+    #[serde(flatten)]
+    line: LogLine,
+}
+
+#[derive(Serialize)]
+struct ApiTailLine {
+    date_slug: String,
+    #[serde(flatten)]
+    line: LogLine,
+}
+
+/// Served at `/.well-known/irclog-archive.json`.
+#[derive(Serialize)]
+struct ArchiveDescription {
+    software: &'static str,
+    version: u32,
+    date_slug_format: &'static str,
+    channels: Vec<ArchiveChannel>,
+    formats: &'static [ArchiveFormat],
+    api: ArchiveEndpoints,
+    feeds: ArchiveFeeds,
+}
+
+#[derive(Serialize)]
+struct ArchiveChannel {
+    name: String,
+    is_private: bool,
+    searchable: bool,
+    public_stats: bool,
+    first_date: Option<NaiveDate>, // Left out for private channels
+    last_date: Option<NaiveDate>,
+    day_count: Option<usize>,
+    url: String,
+}
+
+#[derive(Serialize)]
+struct ArchiveFormat {
+    name: &'static str,
+    media_type: &'static str,
+    url_template: &'static str,
+}
+
+#[derive(Serialize)]
+struct ArchiveEndpoints {
+    channels: &'static str,
+    days: &'static str,
+    log: &'static str,
+    stats: &'static str,
+}
+
+#[derive(Serialize)]
+struct ArchiveFeeds {
+    new_channels: &'static str,
+    daily_digest: &'static str,
+}
+
+const ARCHIVE_FORMATS: &[ArchiveFormat] = &[
+    ArchiveFormat {
+        name: "html",
+        media_type: "text/html",
+        url_template: "/bin/irclogger_log/{channel}/?date={date_slug}",
+    },
+    ArchiveFormat {
+        name: "raw",
+        media_type: "text/plain",
+        url_template: "/bin/irclogger_log/{channel}/?date={date_slug}&raw=on",
+    },
+    ArchiveFormat {
+        name: "json",
+        media_type: "application/json",
+        url_template: "/api/channels/{channel}/log?date={date_slug}",
+    },
+];
+
+fn build_json_response<T: Serialize>(state: &State, value: &T) -> anyhow::Result<Response<Body>> {
+    Ok(create_response(
+        state,
+        StatusCode::OK,
+        mime::APPLICATION_JSON,
+        serde_json::to_vec(value)?,
+    ))
+}
+
+pub fn channels(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let channels = AppState::borrow_from(state).get_channels()?;
+
+    build_json_response(state, &channels)
+}
+
+pub fn archive_description(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let app_state = AppState::borrow_from(state);
+    let mut channels = Vec::new();
+
+    for channel in app_state.get_channels()? {
+        let metadata = app_state.get_channel_metadata(&channel.name)?;
+        let searchable = metadata.searchable;
+        let public_stats = metadata.public_stats && app_state.public_stats.is_some();
+        let url = format!("/bin/irclogger_logs/{}", channel.name);
+
+        if channel.is_private {
+            channels.push(ArchiveChannel {
+                name: channel.name,
+                is_private: true,
+                searchable,
+                public_stats,
+                first_date: None,
+                last_date: None,
+                day_count: None,
+                url,
+            });
+            continue;
+        }
+
+        // Date slugs are sorted newest first
+        let date_slugs = app_state.get_channel_log_date_slugs(&channel.name)?;
+        let to_date = |date_slug: &String| {
+            crate::state::parse_date_slug(date_slug)
+                .ok()
+                .map(|date| date.naive_utc())
+        };
+
+        channels.push(ArchiveChannel {
+            first_date: date_slugs.last().and_then(to_date),
+            last_date: date_slugs.first().and_then(to_date),
+            day_count: Some(date_slugs.len()),
+            name: channel.name,
+            is_private: false,
+            searchable,
+            public_stats,
+            url,
+        });
+    }
+
+    channels.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+
+    let description = ArchiveDescription {
+        software: "irclogger-viewer",
+        version: 1,
+        date_slug_format: "%Y-%m-%d,%a",
+        channels,
+        formats: ARCHIVE_FORMATS,
+        api: ArchiveEndpoints {
+            channels: "/api/channels",
+            days: "/api/channels/{channel}/days",
+            log: "/api/channels/{channel}/log?date={date_slug}",
+            stats: "/api/channels/{channel}/stats",
+        },
+        feeds: ArchiveFeeds {
+            new_channels: "/bin/irclogger_channels.rss",
+            daily_digest: "/bin/irclogger_digest/{channel}",
+        },
+    };
+
+    let mut response = build_json_response(state, &description)?;
+    response
+        .headers_mut()
+        .insert("Cache-Control", "public, max-age=3600".parse()?);
+
+    Ok(response)
+}
+
+/// Serves noisy daily counts without authentication for channels that opt in.
+pub fn channel_stats(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let params = ChannelParams::take_from(state);
+    let app_state = AppState::borrow_from(state);
+
+    let config = match &app_state.public_stats {
+        Some(config) => config,
+        None => return Ok(create_empty_response(state, StatusCode::NOT_FOUND)),
+    };
+
+    if !app_state
+        .get_channel_metadata(&params.channel)?
+        .public_stats
+        || app_state.demo_mode && app_state.is_channel_private(&params.channel)?
+    {
+        return Ok(create_empty_response(state, StatusCode::NOT_FOUND));
+    }
+
+    let stats = time_phase(state, "count", || {
+        crate::stats::build_channel_stats(app_state, config, &params.channel)
+    })?;
+
+    build_json_response(state, &stats)
+}
+
+pub fn channel_days(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let params = ChannelParams::take_from(state);
+
+    if !user_has_access(state, &params.channel)? {
+        return Ok(build_auth_response(state));
+    }
+
+    let app_state = AppState::borrow_from(state);
+    let entries = time_phase(state, "count", || {
+        app_state.get_channel_daily_entries(&params.channel)
+    })?;
+
+    build_json_response(state, &entries)
+}
+
+/// Suggests nicknames that sent messages in the channel, most recently seen
+/// first, for autocompletion. The nicknames are collected by the search index.
+pub fn channel_nicks(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let params = ChannelParams::take_from(state);
+
+    if !user_has_access(state, &params.channel)? {
+        return Ok(build_auth_response(state));
+    }
+
+    let query = ApiNicksQuery::take_from(state);
+    let nicknames = AppState::borrow_from(state)
+        .search_index
+        .as_ref()
+        .and_then(|search_index| {
+            search_index.find_nicknames(
+                &params.channel,
+                query.prefix.as_deref().unwrap_or_default().trim(),
+                MAX_NICK_SUGGESTIONS,
+            )
+        });
+
+    match nicknames {
+        Some(nicknames) => build_json_response(state, &nicknames),
+        None => Ok(create_empty_response(state, StatusCode::NOT_FOUND)),
+    }
+}
+
+pub fn channel_log(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let params = ChannelParams::take_from(state);
+
+    if !user_has_access(state, &params.channel)? {
+        return Ok(build_auth_response(state));
+    }
+
+    let query = ApiLogQuery::take_from(state);
+
+    if !is_date_string_ok(&query.date) {
+        return Ok(create_empty_response(state, StatusCode::BAD_REQUEST));
+    }
+
+    let app_state = AppState::borrow_from(state);
+
+    if let Some(removed) = app_state.get_removed_day(&params.channel, &query.date)? {
+        let mut response = build_json_response(state, &removed)?;
+        *response.status_mut() = StatusCode::GONE;
+
+        return Ok(response);
+    }
+
+    // An unfiltered finished day always gets the same response, which
+    // archival mode keeps next to the log and serves without parsing
+    let archived = app_state.day_json_files
+        && [&query.nick, &query.exclude_nick, &query.after, &query.limit]
+            .iter()
+            .all(|value| value.as_deref().is_none_or(str::is_empty))
+        && crate::state::parse_date_slug(&query.date)?.succ() < Utc::today();
+
+    if archived {
+        if let Some(content) = time_phase(state, "fs read", || {
+            app_state.read_day_json(&params.channel, &query.date)
+        })? {
+            return Ok(create_response(
+                state,
+                StatusCode::OK,
+                mime::APPLICATION_JSON,
+                content,
+            ));
+        }
+    }
+
+    let after = match query.after.as_deref() {
+        None | Some("") => None,
+        Some(after) => {
+            match Cursor::decode(after).filter(|cursor| cursor.date_slug == query.date) {
+                Some(cursor) => Some(cursor),
+                None => return Ok(create_empty_response(state, StatusCode::BAD_REQUEST)),
+            }
+        }
+    };
+    let limit = match query.limit.as_deref() {
+        None | Some("") => None,
+        Some(limit) => match limit.parse::<usize>() {
+            Ok(limit) if limit > 0 => Some(limit),
+            _ => return Ok(create_empty_response(state, StatusCode::BAD_REQUEST)),
+        },
+    };
+
+    let lines = time_phase(state, "parse", || {
+        app_state.get_log_lines(&params.channel, &query.date)
+    })?;
+    let nick_filter = NickFilter::new(query.nick.as_deref(), query.exclude_nick.as_deref());
+
+    // Line numbers are counted before filtering so they match the day view
+    let mut lines = lines
+        .into_iter()
+        .enumerate()
+        .map(|(index, line)| ApiLogLine {
+            line_number: index as u64 + 1,
+            line,
+        })
+        .filter(|line| {
+            after.as_ref().is_none_or(|cursor| {
+                cursor.compare_line(&query.date, line.line_number) == Ordering::Greater
+            }) && nick_filter.is_match(&line.line)
+        })
+        .collect::<Vec<ApiLogLine>>();
+    let truncated = limit.is_some_and(|limit| lines.len() > limit);
+
+    if let Some(limit) = limit {
+        lines.truncate(limit);
+    }
+
+    // Today's log keeps growing, so its last line can be continued from too
+    let is_today = crate::state::parse_date_slug(&query.date)? == Utc::today();
+    let position = after
+        .map_or(0, |cursor| cursor.position.saturating_add(1))
+        .saturating_add(lines.len());
+    let next_cursor = lines.last().filter(|_| truncated || is_today).map(|line| {
+        Cursor {
+            date_slug: query.date.clone(),
+            line_number: line.line_number,
+            position: position - 1,
+        }
+        .encode()
+    });
+    let log = ApiLog {
+        channel: params.channel,
+        date_slug: query.date,
+        lines,
+        next_cursor,
+    };
+    let content = serde_json::to_vec(&log)?;
+
+    // The log directory may be read-only, which only costs the speedup
+    if archived {
+        if let Err(error) = app_state.write_day_json(&log.channel, &log.date_slug, &content) {
+            tracing::warn!(
+                "Couldn't save JSON of {} {}: {:#}",
+                log.channel,
+                log.date_slug,
+                error
+            );
+        }
+    }
+
+    Ok(create_response(
+        state,
+        StatusCode::OK,
+        mime::APPLICATION_JSON,
+        content,
+    ))
+}
+
+/// The latest lines of a channel across days, for activity dashboards.
+pub fn channel_tail(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let params = ChannelParams::take_from(state);
+
+    if !user_has_access(state, &params.channel)? {
+        return Ok(build_auth_response(state));
+    }
+
+    let query = ApiTailQuery::take_from(state);
+    let count = match query.n.as_deref() {
+        None | Some("") => DEFAULT_TAIL_LINES,
+        Some(count) => match count.parse::<usize>() {
+            Ok(count) if count > 0 && count <= MAX_TAIL_LINES => count,
+            _ => return Ok(create_empty_response(state, StatusCode::BAD_REQUEST)),
+        },
+    };
+
+    let app_state = AppState::borrow_from(state);
+    let lines = time_phase(state, "parse", || {
+        app_state.get_recent_lines(&params.channel, count)
+    })?;
+    let lines = lines
+        .into_iter()
+        .map(|(date_slug, line)| ApiTailLine { date_slug, line })
+        .collect::<Vec<ApiTailLine>>();
+
+    build_json_response(state, &lines)
+}
+
+/// Answers the connection test of Grafana's JSON datasource, which is
+/// pointed at `/api/grafana`.
+pub fn grafana_status(state: &mut State) -> anyhow::Result<Response<Body>> {
+    Ok(create_response(
+        state,
+        StatusCode::OK,
+        mime::TEXT_PLAIN_UTF_8,
+        "OK",
+    ))
+}
+
+/// Lists the channels the requester can read as metrics.
+pub fn grafana_search(state: &mut State) -> anyhow::Result<Response<Body>> {
+    let channels = AppState::borrow_from(state).get_channels()?;
+    let mut names = Vec::new();
+
+    for channel in channels {
+        if user_has_access(state, &channel.name)? {
+            names.push(channel.name);
+        }
+    }
+
+    build_json_response(state, &names)
+}
+
+/// Reads the query on the runtime and gathers the counts of its channels on
+/// the blocking thread pool.
+pub async fn grafana_query(mut state: State) -> (State, anyhow::Result<Response<Body>>) {
+    let body = match read_body(&mut state, MAX_GRAFANA_QUERY_BYTES).await {
+        Ok(Ok(body)) => body,
+        Ok(Err(status)) => {
+            let response = create_empty_response(&state, status);
+            return (state, Ok(response));
+        }
+        Err(error) => return (state, Err(error)),
+    };
+    let query: GrafanaQuery = match serde_json::from_slice(&body) {
+        Ok(query) => query,
+        Err(_) => {
+            let response = create_empty_response(&state, StatusCode::BAD_REQUEST);
+            return (state, Ok(response));
+        }
+    };
+
+    run_blocking(state, move |state| grafana_series(state, query)).await
+}
+
+fn grafana_series(state: &mut State, query: GrafanaQuery) -> anyhow::Result<Response<Body>> {
+    let channels = AppState::borrow_from(state).get_channels()?;
+    let mut series = Vec::new();
+
+    for target in query.targets {
+        // Hidden and unknown channels aren't listed, so they're left out
+        if !channels.iter().any(|channel| channel.name == target.target) {
+            continue;
+        }
+
+        if !user_has_access(state, &target.target)? {
+            return Ok(build_auth_response(state));
+        }
+
+        let app_state = AppState::borrow_from(state);
+        let entries = time_phase(state, "count", || {
+            app_state.get_channel_daily_entries(&target.target)
+        })?;
+
+        series.push(GrafanaSeries {
+            datapoints: daily_datapoints(&entries, query.range.from, query.range.to),
+            target: target.target,
+        });
+    }
+
+    build_json_response(state, &series)
+}
+
+/// Points of the days that overlap the range, oldest first, each at the
+/// start of its day.
+fn daily_datapoints(
+    entries: &[ChannelDailyEntry],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Vec<(u64, i64)> {
+    let mut datapoints = entries
+        .iter()
+        .map(|entry| (entry.message_count, entry.date.and_hms(0, 0, 0)))
+        .filter(|(_, start)| *start <= to && *start + chrono::Duration::days(1) > from)
+        .map(|(message_count, start)| (message_count, start.timestamp_millis()))
+        .collect::<Vec<(u64, i64)>>();
+    datapoints.sort_unstable_by_key(|(_, time)| *time);
+
+    datapoints
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::{daily_datapoints, ApiLog, ApiLogLine, NickFilter};
+    use crate::state::ChannelDailyEntry;
+
+    #[test]
+    fn log_serializes_with_line_numbers() {
+        let date = Utc.ymd(2021, 1, 4);
+        let lines = crate::reader::parse_lines(
+            "[00:01] *** alice has joined #archiveteam\n\n[00:02] <alice> hello\n".as_bytes(),
+            &date,
+        )
+        .unwrap();
+        let log = ApiLog {
+            channel: "archiveteam".to_string(),
+            date_slug: "2021-01-04,Mon".to_string(),
+            lines: lines
+                .into_iter()
+                .enumerate()
+                .map(|(index, line)| ApiLogLine {
+                    line_number: index as u64 + 1,
+                    line,
+                })
+                .collect(),
+            next_cursor: None,
+        };
+
+        assert_eq!(
+            serde_json::to_value(&log).unwrap(),
+            serde_json::json!({
+                "channel": "archiveteam",
+                "date_slug": "2021-01-04,Mon",
+                "lines": [
+                    {
+                        "line_number": 1,
+                        "date": "2021-01-04T00:01:00Z",
+                        "content": {"status": "alice has joined #archiveteam"},
+                    },
+                    {
+                        "line_number": 2,
+                        "date": "2021-01-04T00:02:00Z",
+                        "content": {"message": {"nickname": "alice", "text": "hello"}},
+                    },
+                ],
+                "next_cursor": null,
+            })
+        );
+    }
+
+    #[test]
+    fn nick_filter_selects_senders() {
+        let date = Utc.ymd(2021, 1, 4);
+        let lines = crate::reader::parse_lines(
+            "[00:01] *** alice has joined\n[00:02] <Alice> hello\n[00:03] * alice waves\n[00:04] <bob> hi\n"
+                .as_bytes(),
+            &date,
+        )
+        .unwrap();
+        let matches = |filter: NickFilter| {
+            lines
+                .iter()
+                .map(|line| filter.is_match(line))
+                .collect::<Vec<bool>>()
+        };
+
+        assert_eq!(
+            matches(NickFilter::new(None, None)),
+            vec![true, true, true, true]
+        );
+        assert_eq!(
+            matches(NickFilter::new(Some("alice"), None)),
+            vec![false, true, true, false]
+        );
+        assert_eq!(
+            matches(NickFilter::new(Some("alice, BOB"), None)),
+            vec![false, true, true, true]
+        );
+        assert_eq!(
+            matches(NickFilter::new(None, Some("alice"))),
+            vec![true, false, false, true]
+        );
+        assert_eq!(
+            matches(NickFilter::new(Some("ALICE"), None).with_status_mentions(true)),
+            vec![true, true, true, false]
+        );
+    }
+
+    #[test]
+    fn daily_entry_serializes_date() {
+        let entry = ChannelDailyEntry {
+            date_slug: "2021-01-04,Mon".to_string(),
+            date: Utc.ymd(2021, 1, 4),
+            message_count: 1,
+            first_message: Some("<alice> hello".to_string()),
+            last_message: None,
+        };
+
+        assert_eq!(
+            serde_json::to_value(&entry).unwrap(),
+            serde_json::json!({
+                "date_slug": "2021-01-04,Mon",
+                "date": "2021-01-04",
+                "message_count": 1,
+                "first_message": "<alice> hello",
+                "last_message": null,
+            })
+        );
+    }
+
+    #[test]
+    fn datapoints_cover_range_oldest_first() {
+        let entry = |day, message_count| ChannelDailyEntry {
+            date_slug: String::new(),
+            date: Utc.ymd(2021, 1, day),
+            message_count,
+            first_message: None,
+            last_message: None,
+        };
+        // Date slugs are sorted newest first
+        let entries = vec![entry(6, 3), entry(5, 2), entry(4, 1)];
+
+        assert_eq!(
+            daily_datapoints(
+                &entries,
+                Utc.ymd(2021, 1, 4).and_hms(12, 0, 0),
+                Utc.ymd(2021, 1, 5).and_hms(12, 0, 0)
+            ),
+            vec![
+                (1, Utc.ymd(2021, 1, 4).and_hms(0, 0, 0).timestamp_millis()),
+                (2, Utc.ymd(2021, 1, 5).and_hms(0, 0, 0).timestamp_millis()),
+            ]
+        );
+    }
+}
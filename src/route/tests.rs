@@ -0,0 +1,846 @@
+//! Snapshot tests rendering each template from fixture data.
+//!
+//! Review changes with `cargo insta review` after running the tests.
+
+use askama::Template;
+use chrono::{Date, DateTime, Locale, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+
+use super::*;
+
+const DAY_LOG: &str = "\
+[00:01] *** alice has joined #archiveteam
+[00:02] <alice> hello, see https://example.com/a?b=1&c=<2>
+[00:03] * bob waves
+[09:30] <[bob]> <b>not bold</b> & \"quoted\"
+[12:00] *** bob is now known as bob_
+[23:59] <carol> good night
+";
+
+fn fixture_date() -> Date<Utc> {
+    Utc.ymd(2021, 1, 4)
+}
+
+fn fixture_datetime() -> DateTime<Utc> {
+    Utc.ymd(2021, 1, 5).and_hms(6, 7, 8)
+}
+
+fn fixture_dates() -> DateFormatter {
+    DateFormatter::new(Locale::en_US)
+}
+
+fn fixture_lines() -> Vec<LogOutputLine> {
+    let lines = crate::reader::parse_lines(DAY_LOG.as_bytes(), &fixture_date()).unwrap();
+    let mut output_lines = make_output_lines(&lines, Tz::UTC);
+
+    output_lines[1].link_previews.push(LinkPreview {
+        url: "https://example.com/a?b=1&c=<2>".to_string(),
+        title: "Example <Domain>".to_string(),
+    });
+    output_lines[1].thumbnails.push(Thumbnail {
+        url: "https://images.example.com/cat.png".to_string(),
+        proxy_url: "/proxy/image?url=https%3A%2F%2Fimages.example.com%2Fcat.png".to_string(),
+    });
+    output_lines[3].annotations.push(Annotation {
+        line_number: 4,
+        author: "alice".to_string(),
+        note: "Markup should be <escaped>".to_string(),
+        created: fixture_datetime(),
+    });
+
+    output_lines
+}
+
+#[test]
+fn index() {
+    let template = IndexTemplate {
+        channels: vec![
+            ChannelInfo {
+                name: "archiveteam".to_string(),
+                is_private: false,
+            },
+            ChannelInfo {
+                name: "secret".to_string(),
+                is_private: true,
+            },
+        ],
+        message: "<p>Custom <em>message</em></p>".to_string(),
+        preferences_enabled: true,
+        summary: IndexSummary::new(&ArchiveSummary {
+            channel_count: 2,
+            day_count: 1461,
+            line_count: 12_345_678,
+            oldest_date: Some(Utc.ymd(2017, 1, 1)),
+            newest_date: Some(fixture_date()),
+        }),
+        dates: fixture_dates(),
+        color_theme: ColorTheme::Light,
+        path_prefix: String::new(),
+    };
+
+    insta::assert_snapshot!(template.render().unwrap());
+}
+
+#[test]
+fn index_empty() {
+    let template = IndexTemplate {
+        channels: Vec::new(),
+        message: String::new(),
+        preferences_enabled: false,
+        summary: None,
+        dates: fixture_dates(),
+        color_theme: ColorTheme::Light,
+        path_prefix: String::new(),
+    };
+
+    insta::assert_snapshot!(template.render().unwrap());
+}
+
+#[test]
+fn links_follow_path_prefix() {
+    let template = IndexTemplate {
+        channels: vec![ChannelInfo {
+            name: "archiveteam".to_string(),
+            is_private: false,
+        }],
+        message: String::new(),
+        preferences_enabled: true,
+        summary: None,
+        dates: fixture_dates(),
+        color_theme: ColorTheme::Light,
+        path_prefix: "/irc".to_string(),
+    };
+    let content = template.render().unwrap();
+
+    // Askama escapes the slash
+    assert!(content.contains("href=\"&#x2f;irc/bin/irclogger_logs/archiveteam\""));
+    assert!(content.contains("href=\"&#x2f;irc/static/style.css\""));
+    assert!(!content.contains("=\"/"));
+}
+
+#[test]
+fn channels_feed() {
+    let template = ChannelsFeedTemplate {
+        base_url: "https://example.com".to_string(),
+        items: vec![ChannelsFeedItem {
+            name: "archiveteam".to_string(),
+            resumed: true,
+            active_since: fixture_date(),
+            pub_date: fixture_datetime().to_rfc2822(),
+        }],
+    };
+
+    insta::assert_snapshot!(template.render().unwrap());
+}
+
+#[test]
+fn digest_feed() {
+    let template = DigestFeedTemplate {
+        base_url: "https://example.com".to_string(),
+        channel_name: "archiveteam".to_string(),
+        items: vec![DigestFeedItem {
+            digest: DailyDigest {
+                channel: "archiveteam".to_string(),
+                date_slug: "2021-01-04,Mon".to_string(),
+                date: fixture_date(),
+                message_count: 4,
+                active_nicks: vec![("alice".to_string(), 2), ("[bob]".to_string(), 1)],
+                top_links: vec![("https://example.com/a?b=1&c=<2>".to_string(), 1)],
+                first_line: Some("<alice> hello".to_string()),
+                last_line: Some("<carol> good night".to_string()),
+            },
+            pub_date: fixture_datetime().to_rfc2822(),
+        }],
+        dates: fixture_dates(),
+    };
+
+    insta::assert_snapshot!(template.render().unwrap());
+}
+
+#[test]
+fn channel_index() {
+    let template = ChannelIndexTemplate {
+        channel_name: "archiveteam".to_string(),
+        years: group_daily_entries(
+            vec![
+                ChannelDailyEntry {
+                    date_slug: "2021-01-05,Tue".to_string(),
+                    date: Utc.ymd(2021, 1, 5),
+                    message_count: 0,
+                    first_message: None,
+                    last_message: None,
+                },
+                ChannelDailyEntry {
+                    date_slug: "2021-01-04,Mon".to_string(),
+                    date: fixture_date(),
+                    message_count: 4,
+                    first_message: Some(
+                        "<alice> hello, see https://example.com/a?b=1&c=<2>".to_string(),
+                    ),
+                    last_message: Some("<carol> good night".to_string()),
+                },
+                ChannelDailyEntry {
+                    date_slug: "2020-12-31,Thu".to_string(),
+                    date: Utc.ymd(2020, 12, 31),
+                    message_count: 7,
+                    first_message: None,
+                    last_message: None,
+                },
+                ChannelDailyEntry {
+                    date_slug: "2020-11-30,Mon".to_string(),
+                    date: Utc.ymd(2020, 11, 30),
+                    message_count: 2,
+                    first_message: None,
+                    last_message: None,
+                },
+            ],
+            &fixture_dates(),
+        ),
+        searchable: true,
+        dates: fixture_dates(),
+        color_theme: ColorTheme::Light,
+        path_prefix: String::new(),
+    };
+
+    insta::assert_snapshot!(template.render().unwrap());
+}
+
+#[test]
+fn channel_calendar() {
+    let template = ChannelCalendarTemplate {
+        channel_name: "archiveteam".to_string(),
+        month_label: "January 2021".to_string(),
+        weekdays: fixture_dates().weekday_names(),
+        weeks: make_calendar_weeks(
+            NaiveDate::from_ymd(2021, 1, 1),
+            vec![
+                ChannelDailyEntry {
+                    date_slug: "2021-01-05,Tue".to_string(),
+                    date: Utc.ymd(2021, 1, 5),
+                    message_count: 0,
+                    first_message: None,
+                    last_message: None,
+                },
+                ChannelDailyEntry {
+                    date_slug: "2021-01-04,Mon".to_string(),
+                    date: fixture_date(),
+                    message_count: 4,
+                    first_message: None,
+                    last_message: None,
+                },
+                ChannelDailyEntry {
+                    date_slug: "2020-12-31,Thu".to_string(),
+                    date: Utc.ymd(2020, 12, 31),
+                    message_count: 7,
+                    first_message: None,
+                    last_message: None,
+                },
+            ],
+        ),
+        previous_month: Some("year=2020&month=12".to_string()),
+        next_month: None,
+        color_theme: ColorTheme::Light,
+        path_prefix: String::new(),
+    };
+
+    insta::assert_snapshot!(template.render().unwrap());
+}
+
+#[test]
+fn channel_compare() {
+    let counts = |entries: &[(&str, u64)]| {
+        entries
+            .iter()
+            .map(|(nickname, count)| (nickname.to_string(), *count))
+            .collect::<HashMap<String, u64>>()
+    };
+
+    let template = ChannelCompareTemplate {
+        channel_name: "archiveteam".to_string(),
+        from: "2021-01-04,Mon".to_string(),
+        to: "2021-01-05,Tue".to_string(),
+        date_slugs: vec!["2021-01-05,Tue".to_string(), "2021-01-04,Mon".to_string()],
+        comparison: Some(DayComparison::new(
+            &counts(&[("alice", 2), ("[bob]", 1), ("carol", 1)]),
+            &counts(&[("alice", 7), ("<dave>", 3)]),
+        )),
+        message_change: "+6".to_string(),
+        color_theme: ColorTheme::Light,
+        path_prefix: String::new(),
+    };
+
+    insta::assert_snapshot!(template.render().unwrap());
+}
+
+#[test]
+fn channel_lines() {
+    let template = ChannelLinesTemplate {
+        channel_name: "archiveteam".to_string(),
+        lines: fixture_lines(),
+        date_slug: "2021-01-04,Mon".to_string(),
+        date_label: fixture_dates().long_date(fixture_date()),
+        selected_line_number: 4,
+        thumbnails_available: true,
+        thumbnails_enabled: true,
+        annotations_enabled: true,
+        bookmarks_enabled: true,
+        has_selection: true,
+        searchable: true,
+        previous_day: Some("2021-01-03,Sun".to_string()),
+        next_day: None,
+        navigation_json: DayNavigation {
+            channel: "archiveteam",
+            date_slug: "2021-01-04,Mon",
+            line_numbers: vec![1, 2, 3, 4, 5, 6],
+            selected_line: Some(4),
+            previous_day: Some("2021-01-03,Sun"),
+            next_day: Some("</script>"),
+            path_prefix: "",
+            live_url: None,
+            utc_offset_minutes: 0,
+        }
+        .to_script_json()
+        .unwrap(),
+        compact: false,
+        page: 2,
+        page_count: 3,
+        earlier_cursor: String::new(),
+        later_cursor: String::new(),
+        dates: fixture_dates(),
+        nick: None,
+        nick_query: String::new(),
+        timezone: "UTC".to_string(),
+        color_theme: ColorTheme::Light,
+        path_prefix: String::new(),
+    };
+
+    insta::assert_snapshot!(template.render().unwrap());
+}
+
+#[test]
+fn channel_lines_minimal() {
+    let template = ChannelLinesTemplate {
+        channel_name: "archiveteam".to_string(),
+        lines: Vec::new(),
+        date_slug: "2021-01-04,Mon".to_string(),
+        date_label: fixture_dates().long_date(fixture_date()),
+        selected_line_number: 0,
+        thumbnails_available: false,
+        thumbnails_enabled: false,
+        annotations_enabled: false,
+        bookmarks_enabled: false,
+        has_selection: false,
+        searchable: false,
+        previous_day: None,
+        next_day: None,
+        navigation_json: "{}".to_string(),
+        compact: false,
+        page: 1,
+        page_count: 1,
+        earlier_cursor: String::new(),
+        later_cursor: String::new(),
+        dates: fixture_dates(),
+        nick: None,
+        nick_query: String::new(),
+        timezone: "UTC".to_string(),
+        color_theme: ColorTheme::Light,
+        path_prefix: String::new(),
+    };
+
+    insta::assert_snapshot!(template.render().unwrap());
+}
+
+#[test]
+fn channel_lines_nick_filter() {
+    let filter = NickFilter::new(Some("BOB"), None).with_status_mentions(true);
+    let log_lines = crate::reader::parse_lines(DAY_LOG.as_bytes(), &fixture_date()).unwrap();
+    let mut lines = fixture_lines();
+    lines.retain(|line| filter.is_match(&log_lines[line.line_number as usize - 1]));
+
+    let template = ChannelLinesTemplate {
+        channel_name: "archiveteam".to_string(),
+        lines,
+        date_slug: "2021-01-04,Mon".to_string(),
+        date_label: fixture_dates().long_date(fixture_date()),
+        selected_line_number: u64::MAX,
+        thumbnails_available: false,
+        thumbnails_enabled: false,
+        annotations_enabled: false,
+        bookmarks_enabled: false,
+        has_selection: false,
+        searchable: true,
+        previous_day: Some("2021-01-03,Sun".to_string()),
+        next_day: None,
+        navigation_json: "{}".to_string(),
+        compact: false,
+        page: 1,
+        page_count: 2,
+        earlier_cursor: String::new(),
+        later_cursor: String::new(),
+        dates: fixture_dates(),
+        nick: Some("BOB".to_string()),
+        nick_query: "&nick=BOB&nick_status=on".to_string(),
+        timezone: "UTC".to_string(),
+        color_theme: ColorTheme::Light,
+        path_prefix: String::new(),
+    };
+
+    insta::assert_snapshot!(template.render().unwrap());
+}
+
+#[test]
+fn channel_lines_compact() {
+    let template = ChannelLinesTemplate {
+        channel_name: "archiveteam".to_string(),
+        lines: fixture_lines(),
+        date_slug: "2021-01-04,Mon".to_string(),
+        date_label: fixture_dates().long_date(fixture_date()),
+        selected_line_number: 4,
+        thumbnails_available: false,
+        thumbnails_enabled: false,
+        annotations_enabled: false,
+        bookmarks_enabled: false,
+        has_selection: true,
+        searchable: true,
+        previous_day: Some("2021-01-03,Sun".to_string()),
+        next_day: Some("2021-01-05,Tue".to_string()),
+        navigation_json: "{}".to_string(),
+        compact: true,
+        page: 1,
+        page_count: 2,
+        earlier_cursor: String::new(),
+        later_cursor: String::new(),
+        dates: fixture_dates(),
+        nick: None,
+        nick_query: String::new(),
+        timezone: "UTC".to_string(),
+        color_theme: ColorTheme::Light,
+        path_prefix: String::new(),
+    };
+
+    insta::assert_snapshot!(template.render().unwrap());
+}
+
+#[test]
+fn channel_lines_clean() {
+    let lines = fixture_lines();
+    let mut pages = vec![Vec::new(), Vec::new()];
+
+    for (index, line) in lines.into_iter().enumerate() {
+        pages[index / 3].push(line);
+    }
+
+    let template = ChannelLinesCleanTemplate {
+        channel_name: "archiveteam".to_string(),
+        date_slug: "2021-01-04,Mon".to_string(),
+        pages,
+    };
+
+    insta::assert_snapshot!(template.render().unwrap());
+}
+
+#[test]
+fn channel_lines_source() {
+    let raw_log = format!("{}\n[xx:yy] broken line\n", DAY_LOG);
+    let template = ChannelLinesSourceTemplate {
+        channel_name: "archiveteam".to_string(),
+        path_prefix: String::new(),
+        date_slug: "2021-01-04,Mon".to_string(),
+        lines: make_source_lines(raw_log.as_bytes()).unwrap(),
+    };
+
+    insta::assert_snapshot!(template.render().unwrap());
+}
+
+#[test]
+fn day_preparing() {
+    let template = DayPreparingTemplate {
+        channel_name: "archiveteam".to_string(),
+        date_slug: "2021-01-04,Mon".to_string(),
+        refresh_seconds: DAY_PREPARING_REFRESH_SECONDS,
+        color_theme: ColorTheme::Light,
+        path_prefix: String::new(),
+    };
+
+    insta::assert_snapshot!(template.render().unwrap());
+}
+
+#[test]
+fn channel_continuous() {
+    let lines = fixture_lines()
+        .into_iter()
+        .enumerate()
+        .map(|(index, line)| ContinuousOutputLine {
+            date_slug: "2021-01-04,Mon".to_string(),
+            starts_day: index == 0,
+            line,
+        })
+        .collect();
+
+    let template = ChannelContinuousTemplate {
+        channel_name: "archiveteam".to_string(),
+        date_slug: "2021-01-04,Mon".to_string(),
+        lines,
+        next: Some(ContinuousPosition {
+            date_slug: "2021-01-05,Tue".to_string(),
+            line_number: 1,
+        }),
+        searchable: true,
+        color_theme: ColorTheme::Light,
+        path_prefix: String::new(),
+    };
+
+    insta::assert_snapshot!(template.render().unwrap());
+}
+
+#[test]
+fn channel_search() {
+    let results = || {
+        vec![
+            SearchResultEntry {
+                date_slug: "2021-01-05,Tue".to_string(),
+                line_number: 2,
+                raw_line: "[00:02] <alice> not bold".to_string(),
+            },
+            SearchResultEntry {
+                date_slug: "2021-01-04,Mon".to_string(),
+                line_number: 4,
+                raw_line: "[09:30] <[bob]> <b>not bold</b> & \"quoted\"".to_string(),
+            },
+            SearchResultEntry {
+                date_slug: "2021-01-04,Mon".to_string(),
+                line_number: 5,
+                raw_line: "[09:31] <[bob]> still not bold".to_string(),
+            },
+            SearchResultEntry {
+                date_slug: "2021-01-04,Mon".to_string(),
+                line_number: 6,
+                raw_line: "[09:32] <[bob]> not bold again".to_string(),
+            },
+        ]
+    };
+    let template = ChannelSearchTemplate {
+        channel_name: "archiveteam".to_string(),
+        has_results: true,
+        results: limit_results_per_day(results(), Some(1), "search=not%20bold"),
+        date: None,
+        pagination: Some(SearchPagination::new(
+            1234,
+            true,
+            500,
+            &results(),
+            "search=not%20bold&days=60",
+            "offset=500",
+        )),
+        budget_notice: None,
+        saved_searches: vec![SavedSearch {
+            parameters: SearchParameters {
+                channel: "archiveteam".to_string(),
+                query: "not bold".to_string(),
+                case_sensitive: true,
+                verbatim: false,
+                whole_word: true,
+                text_only: false,
+                messages_only: true,
+            },
+            pinned: true,
+            last_used: fixture_datetime(),
+            alert: true,
+        }],
+        saved_search_alerts: true,
+        uses_agrep: true,
+        search: "not bold".to_string(),
+        case_sensitive: true,
+        verbatim: false,
+        whole_word: true,
+        text_only: true,
+        messages_only: true,
+        day_options: make_search_day_options(Some(60)),
+        color_theme: ColorTheme::Light,
+        path_prefix: String::new(),
+    };
+
+    insta::assert_snapshot!(template.render().unwrap());
+}
+
+#[test]
+fn channel_search_no_results() {
+    let template = ChannelSearchTemplate {
+        channel_name: "archiveteam".to_string(),
+        has_results: false,
+        results: Vec::new(),
+        date: Some("2021-01-04,Mon".to_string()),
+        pagination: None,
+        budget_notice: None,
+        saved_searches: Vec::new(),
+        saved_search_alerts: false,
+        uses_agrep: false,
+        search: String::new(),
+        case_sensitive: false,
+        verbatim: false,
+        whole_word: false,
+        text_only: false,
+        messages_only: false,
+        day_options: make_search_day_options(None),
+        color_theme: ColorTheme::Light,
+        path_prefix: String::new(),
+    };
+
+    insta::assert_snapshot!(template.render().unwrap());
+}
+
+#[test]
+fn global_search() {
+    let template = GlobalSearchTemplate {
+        has_results: true,
+        channels: vec![
+            GlobalSearchChannel {
+                channel_name: "archiveteam".to_string(),
+                results: vec![SearchResultEntry {
+                    date_slug: "2021-01-04,Mon".to_string(),
+                    line_number: 4,
+                    raw_line: "[09:30] <[bob]> <b>not bold</b> & \"quoted\"".to_string(),
+                }],
+                more_count: 12,
+            },
+            GlobalSearchChannel {
+                channel_name: "secret".to_string(),
+                results: vec![SearchResultEntry {
+                    date_slug: "2021-01-05,Tue".to_string(),
+                    line_number: 1,
+                    raw_line: "[00:01] <carol> not bold either".to_string(),
+                }],
+                more_count: 0,
+            },
+        ],
+        search: "not bold".to_string(),
+        query_string: "search=not%20bold&word=on".to_string(),
+        budget_notice: None,
+        case_sensitive: false,
+        verbatim: false,
+        whole_word: true,
+        text_only: false,
+        messages_only: false,
+        day_options: make_search_day_options(None),
+        color_theme: ColorTheme::Light,
+        path_prefix: String::new(),
+    };
+
+    insta::assert_snapshot!(template.render().unwrap());
+}
+
+#[test]
+fn bookmarks() {
+    let template = BookmarksTemplate {
+        user: "alice".to_string(),
+        bookmarks: vec![Bookmark {
+            channel: "archiveteam".to_string(),
+            date_slug: "2021-01-04,Mon".to_string(),
+            line_number: 4,
+            label: "<script>alert(1)</script>".to_string(),
+            created: fixture_datetime(),
+        }],
+        color_theme: ColorTheme::Light,
+        path_prefix: String::new(),
+    };
+
+    insta::assert_snapshot!(template.render().unwrap());
+}
+
+#[test]
+fn admin() {
+    let template = AdminTemplate {
+        tasks: vec![
+            AdminTaskRow {
+                name: "stale channel alert".to_string(),
+                schedule: "0 * * * *".to_string(),
+                last_run: "2021-01-05 06:07:08 UTC".to_string(),
+                last_duration: "12 ms".to_string(),
+                last_error: String::new(),
+                run_count: 3,
+            },
+            AdminTaskRow {
+                name: "daily digest".to_string(),
+                schedule: "0 6 * * *".to_string(),
+                last_run: "never".to_string(),
+                last_duration: String::new(),
+                last_error: "SMTP <connection> refused".to_string(),
+                run_count: 0,
+            },
+        ],
+        lenient_parsing: true,
+        parse_problems: vec![ParseProblemRecord {
+            channel: "archiveteam".to_string(),
+            date_slug: "2021-01-04,Mon".to_string(),
+            line_number: 2,
+            reason: "no timestamp and nickname".to_string(),
+            text: "<garbage & more>".to_string(),
+            last_seen: fixture_datetime(),
+        }],
+        hidden_channels_enabled: true,
+        hidden_channels: vec![HiddenChannel {
+            channel: "archivebot".to_string(),
+            reason: "Takedown request <pending>".to_string(),
+            hidden_by: "admin".to_string(),
+            hidden_at: fixture_datetime(),
+        }],
+        color_theme: ColorTheme::Light,
+        path_prefix: String::new(),
+    };
+
+    insta::assert_snapshot!(template.render().unwrap());
+}
+
+#[test]
+fn preferences() {
+    let template = PreferencesTemplate {
+        thumbnails: "off".to_string(),
+        hide_status: true,
+        hidden_nicks: "ChanServ\n<bot>".to_string(),
+        max_hidden_nicks: crate::preferences::MAX_HIDDEN_NICKS,
+        timezone: "Europe/Paris".to_string(),
+        color_theme: ColorTheme::Light,
+        path_prefix: String::new(),
+    };
+
+    insta::assert_snapshot!(template.render().unwrap());
+}
+
+#[test]
+fn nick_card() {
+    let template = NickCardTemplate::new(
+        NickCard {
+            nickname: "[bob]".to_string(),
+            message_count: 2,
+            first_seen: Some(Utc.ymd(2017, 1, 1)),
+            last_seen: Some(fixture_date()),
+        },
+        "",
+        "archiveteam",
+        "2021-01-04,Mon",
+        fixture_date(),
+        &fixture_dates(),
+    );
+
+    insta::assert_snapshot!(template.render().unwrap());
+}
+
+#[test]
+fn channel_widget() {
+    let template = ChannelWidgetTemplate {
+        channel_name: "archiveteam".to_string(),
+        path_prefix: String::new(),
+        lines: fixture_lines()
+            .into_iter()
+            .map(|line| WidgetLine {
+                date_slug: "2021-01-04,Mon".to_string(),
+                line,
+            })
+            .collect(),
+    };
+
+    insta::assert_snapshot!(template.render().unwrap());
+}
+
+#[test]
+fn csv_fields_are_quoted() {
+    assert_eq!(csv_field("plain"), "\"plain\"");
+    assert_eq!(csv_field("say \"hi\", bye"), "\"say \"\"hi\"\", bye\"");
+    assert_eq!(csv_field("=1+1"), "\"'=1+1\"");
+}
+
+#[test]
+fn search_exports_follow_nick_filters() {
+    let results = DAY_LOG
+        .lines()
+        .enumerate()
+        .map(|(index, line)| SearchResultEntry {
+            date_slug: "2021-01-04,Mon".to_string(),
+            line_number: index as u64 + 1,
+            raw_line: line.to_string(),
+        })
+        .collect::<Vec<SearchResultEntry>>();
+    let matches = |filter: &NickFilter| {
+        results
+            .iter()
+            .filter(|result| is_search_result_nick_match(filter, result))
+            .map(|result| result.line_number)
+            .collect::<Vec<u64>>()
+    };
+
+    assert_eq!(
+        matches(&NickFilter::new(Some("Alice,bob"), None)),
+        vec![2, 3]
+    );
+    assert_eq!(
+        matches(&NickFilter::new(None, Some("alice"))),
+        vec![1, 3, 4, 5, 6]
+    );
+}
+
+#[test]
+fn search_days_are_checked() {
+    assert_eq!(parse_search_days(None, Some(90)), Some(Some(90)));
+    assert_eq!(parse_search_days(Some("all"), Some(90)), Some(None));
+    assert_eq!(parse_search_days(Some("7"), None), Some(Some(7)));
+    assert_eq!(parse_search_days(Some("week"), None), None);
+    assert_eq!(parse_search_days(Some("4000000000"), None), None);
+}
+
+#[test]
+fn forged_cursor_positions_saturate() {
+    let shown = vec![SearchResultEntry {
+        date_slug: "2021-01-04,Mon".to_string(),
+        line_number: 4,
+        raw_line: "[09:30] <[bob]> <b>not bold</b>".to_string(),
+    }];
+    let pagination = SearchPagination::new(10, false, usize::MAX, &shown, "search=bold", "");
+
+    assert_eq!(pagination.first, usize::MAX);
+    assert_eq!(pagination.last, usize::MAX);
+    assert!(pagination.next_page.is_none());
+}
+
+#[test]
+fn forms_from_other_origins_are_refused() {
+    let headers = |pairs: &[(header::HeaderName, &str)]| {
+        let mut headers = HeaderMap::new();
+
+        for (name, value) in pairs {
+            headers.insert(name.clone(), value.parse().unwrap());
+        }
+
+        headers
+    };
+    let public_url = Some("https://logs.example.org/irc/");
+
+    assert!(is_same_origin_request(&headers(&[]), None));
+    assert!(is_same_origin_request(
+        &headers(&[
+            (header::HOST, "localhost:8080"),
+            (header::ORIGIN, "http://localhost:8080"),
+        ]),
+        None
+    ));
+    assert!(is_same_origin_request(
+        &headers(&[
+            (header::HOST, "127.0.0.1:8080"),
+            (
+                header::REFERER,
+                "https://logs.example.org/irc/bin/irclogger_bookmarks"
+            ),
+        ]),
+        public_url
+    ));
+    assert!(!is_same_origin_request(
+        &headers(&[
+            (header::HOST, "logs.example.org"),
+            (header::ORIGIN, "https://attacker.example"),
+        ]),
+        public_url
+    ));
+    assert!(!is_same_origin_request(
+        &headers(&[(header::HOST, "logs.example.org"), (header::ORIGIN, "null")]),
+        public_url
+    ));
+}
@@ -0,0 +1,254 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+
+use crate::{
+    config::Config,
+    digest::DailyDigestConfig,
+    monitor::{SavedSearchAlertConfig, SeenMatches, StaleChannelAlertConfig},
+    purge::{CachePurgeConfig, LogFingerprint},
+    state::AppState,
+    tenant::TenantMiddleware,
+};
+
+/// A cron-like schedule in the `minute hour day-of-month month day-of-week`
+/// format, supporting `*`, `*/step`, ranges and comma separated lists.
+#[derive(Clone)]
+pub struct Schedule {
+    expression: String,
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days_of_month: Vec<bool>,
+    months: Vec<bool>,
+    days_of_week: Vec<bool>,
+}
+
+impl Schedule {
+    pub fn parse(expression: &str) -> anyhow::Result<Self> {
+        let fields = expression.split_whitespace().collect::<Vec<&str>>();
+
+        if fields.len() != 5 {
+            anyhow::bail!("Schedule '{}' must have 5 fields", expression);
+        }
+
+        Ok(Self {
+            expression: expression.to_string(),
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days_of_month: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            days_of_week: parse_field(fields[4], 0, 6)?,
+        })
+    }
+
+    pub fn matches(&self, time: &DateTime<Utc>) -> bool {
+        self.minutes[time.minute() as usize]
+            && self.hours[time.hour() as usize]
+            && self.days_of_month[time.day() as usize]
+            && self.months[time.month() as usize]
+            && self.days_of_week[time.weekday().num_days_from_sunday() as usize]
+    }
+}
+
+fn parse_field(field: &str, min: usize, max: usize) -> anyhow::Result<Vec<bool>> {
+    let mut values = vec![false; max + 1];
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<usize>()?),
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (start.parse::<usize>()?, end.parse::<usize>()?)
+        } else {
+            let value = range.parse::<usize>()?;
+            (value, value)
+        };
+
+        if start < min || end > max || start > end || step == 0 {
+            anyhow::bail!("Invalid schedule field '{}'", field);
+        }
+
+        for value in (start..=end).step_by(step) {
+            values[value] = true;
+        }
+    }
+
+    Ok(values)
+}
+
+pub enum TaskKind {
+    StaleChannelCheck {
+        config: StaleChannelAlertConfig,
+        alerted_channels: HashSet<String>,
+    },
+    SavedSearchAlerts {
+        config: SavedSearchAlertConfig,
+        seen_matches: SeenMatches,
+    },
+    DailyDigest(DailyDigestConfig),
+    CachePurge {
+        config: CachePurgeConfig,
+        fingerprints: HashMap<String, LogFingerprint>,
+    },
+    SearchIndexUpdate,
+}
+
+pub struct ScheduledTask {
+    pub name: String,
+    pub schedule: Schedule,
+    pub kind: TaskKind,
+}
+
+#[derive(Clone)]
+pub struct TaskStatus {
+    pub name: String,
+    pub schedule: String,
+    pub last_run: Option<DateTime<Utc>>,
+    pub last_duration: Option<Duration>,
+    pub last_error: Option<String>,
+    pub run_count: u64,
+}
+
+/// Status of the scheduled tasks shared with the admin page.
+#[derive(Clone, Default)]
+pub struct SchedulerStatus {
+    tasks: Arc<Mutex<Vec<TaskStatus>>>,
+}
+
+impl SchedulerStatus {
+    pub fn get_tasks(&self) -> Vec<TaskStatus> {
+        self.tasks.lock().unwrap().clone()
+    }
+}
+
+pub fn build_tasks(config: &Config) -> anyhow::Result<Vec<ScheduledTask>> {
+    let mut tasks = Vec::new();
+
+    if let Some(alert_config) = &config.stale_channel_alert {
+        tasks.push(ScheduledTask {
+            name: "stale_channel_check".to_string(),
+            schedule: Schedule::parse(&alert_config.schedule)?,
+            kind: TaskKind::StaleChannelCheck {
+                config: alert_config.clone(),
+                alerted_channels: HashSet::new(),
+            },
+        });
+    }
+
+    if let Some(alert_config) = &config.saved_search_alert {
+        tasks.push(ScheduledTask {
+            name: "saved_search_alerts".to_string(),
+            schedule: Schedule::parse(&alert_config.schedule)?,
+            kind: TaskKind::SavedSearchAlerts {
+                config: alert_config.clone(),
+                seen_matches: HashMap::new(),
+            },
+        });
+    }
+
+    if let Some(digest_config) = &config.daily_digest {
+        tasks.push(ScheduledTask {
+            name: "daily_digest".to_string(),
+            schedule: Schedule::parse(&digest_config.schedule)?,
+            kind: TaskKind::DailyDigest(digest_config.clone()),
+        });
+    }
+
+    if let Some(purge_config) = &config.cache_purge {
+        tasks.push(ScheduledTask {
+            name: "cache_purge".to_string(),
+            schedule: Schedule::parse(&purge_config.schedule)?,
+            kind: TaskKind::CachePurge {
+                config: purge_config.clone(),
+                fingerprints: HashMap::new(),
+            },
+        });
+    }
+
+    if let Some(search_index_config) = &config.search_index {
+        tasks.push(ScheduledTask {
+            name: "search_index_update".to_string(),
+            schedule: Schedule::parse(&search_index_config.schedule)?,
+            kind: TaskKind::SearchIndexUpdate,
+        });
+    }
+
+    Ok(tasks)
+}
+
+/// Runs the tasks on the main site's state, taken again for every run so
+/// they follow config reloads.
+pub async fn run_scheduler(sites: TenantMiddleware, mut tasks: Vec<ScheduledTask>) {
+    let status = sites.default_app_state().scheduler_status;
+
+    *status.tasks.lock().unwrap() = tasks
+        .iter()
+        .map(|task| TaskStatus {
+            name: task.name.clone(),
+            schedule: task.schedule.expression.clone(),
+            last_run: None,
+            last_duration: None,
+            last_error: None,
+            run_count: 0,
+        })
+        .collect();
+
+    loop {
+        tokio::time::sleep(duration_until_next_minute()).await;
+
+        let now = Utc::now();
+        let app_state = sites.default_app_state();
+
+        for (index, task) in tasks.iter_mut().enumerate() {
+            if !task.schedule.matches(&now) {
+                continue;
+            }
+
+            let start = Instant::now();
+            let result = run_task(&app_state, &mut task.kind).await;
+
+            let mut task_statuses = status.tasks.lock().unwrap();
+            let task_status = &mut task_statuses[index];
+            task_status.last_run = Some(now);
+            task_status.last_duration = Some(start.elapsed());
+            task_status.run_count += 1;
+            task_status.last_error = match result {
+                Ok(()) => None,
+                Err(error) => Some(format!("{:#}", error)),
+            };
+        }
+    }
+}
+
+async fn run_task(app_state: &AppState, kind: &mut TaskKind) -> anyhow::Result<()> {
+    match kind {
+        TaskKind::StaleChannelCheck {
+            config,
+            alerted_channels,
+        } => crate::monitor::check_stale_channels(app_state, config, alerted_channels).await,
+        TaskKind::SavedSearchAlerts {
+            config,
+            seen_matches,
+        } => crate::monitor::check_saved_searches(app_state, config, seen_matches).await,
+        TaskKind::DailyDigest(config) => crate::digest::send_daily_digests(app_state, config).await,
+        TaskKind::CachePurge {
+            config,
+            fingerprints,
+        } => crate::purge::purge_changed_logs(app_state, config, fingerprints).await,
+        TaskKind::SearchIndexUpdate => crate::search_index::update_search_index(app_state).await,
+    }
+}
+
+fn duration_until_next_minute() -> Duration {
+    let now = Utc::now();
+    let elapsed = Duration::new(u64::from(now.second()), now.nanosecond() % 1_000_000_000);
+
+    Duration::from_secs(60).saturating_sub(elapsed)
+}
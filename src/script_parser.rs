@@ -0,0 +1,163 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use chrono::{Date, NaiveTime, Utc};
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+
+use crate::reader::{LogLine, LogLineContent};
+
+// Stops scripts that loop forever; plenty for matching one line
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// A channel's own line parser for an unusual log format, written in
+/// [Rhai](https://rhai.rs).
+///
+/// The script defines `parse(line)`, which returns a map of `time` (`HH:MM`
+/// or `HH:MM:SS`), `nick`, `text` and `kind` (`message`, `action` or
+/// `status`), or `()` for a line it can't parse.
+pub struct ScriptParser {
+    engine: Engine,
+    ast: AST,
+}
+
+impl ScriptParser {
+    pub fn compile(source: &str) -> anyhow::Result<Self> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+
+        let ast = engine.compile(source)?;
+
+        Ok(Self { engine, ast })
+    }
+
+    pub fn parse_line(&self, line: &str, log_date: &Date<Utc>) -> Result<LogLine, String> {
+        let result: Dynamic = self
+            .engine
+            .call_fn(&mut Scope::new(), &self.ast, "parse", (line.to_string(),))
+            .map_err(|error| format!("script error: {}", error))?;
+
+        if result.is::<()>() {
+            return Err("not recognized by the script".to_string());
+        }
+
+        let fields = result
+            .try_cast::<Map>()
+            .ok_or_else(|| "script didn't return a map".to_string())?;
+        let field = |name: &str| {
+            fields
+                .get(name)
+                .and_then(|value| value.clone().into_string().ok())
+                .unwrap_or_default()
+        };
+
+        let time = field("time");
+        let time = NaiveTime::parse_from_str(&time, "%H:%M:%S")
+            .or_else(|_| NaiveTime::parse_from_str(&time, "%H:%M"))
+            .map_err(|_| format!("invalid time from script: {}", time))?;
+        let nickname = field("nick");
+        let text = field("text");
+
+        let content = match field("kind").as_str() {
+            "status" => LogLineContent::Status(text),
+            _ if nickname.is_empty() => return Err("no nick from script".to_string()),
+            "" | "message" => LogLineContent::Message { nickname, text },
+            // Stored like irclogger's `* nickname text` actions
+            "action" => LogLineContent::Message {
+                nickname: "*".to_string(),
+                text: format!("{} {}", nickname, text),
+            },
+            kind => return Err(format!("unknown kind from script: {}", kind)),
+        };
+
+        Ok(LogLine {
+            date: log_date.and_time(time).unwrap(),
+            content,
+        })
+    }
+}
+
+type CachedParser = (SystemTime, Arc<ScriptParser>); // Modification time of the script
+
+/// Compiled scripts by path, compiled again when the file changes.
+#[derive(Clone, Default)]
+pub struct ScriptParserCache {
+    parsers: Arc<Mutex<HashMap<PathBuf, CachedParser>>>,
+}
+
+impl ScriptParserCache {
+    pub fn get(&self, path: &Path) -> anyhow::Result<Arc<ScriptParser>> {
+        let modified = std::fs::metadata(path)?.modified()?;
+        let mut parsers = self.parsers.lock().unwrap();
+
+        if let Some((cached_modified, parser)) = parsers.get(path) {
+            if *cached_modified == modified {
+                return Ok(parser.clone());
+            }
+        }
+
+        let parser = Arc::new(
+            ScriptParser::compile(&std::fs::read_to_string(path)?)
+                .map_err(|error| anyhow::anyhow!("Parser script {}: {}", path.display(), error))?,
+        );
+        parsers.insert(path.to_path_buf(), (modified, parser.clone()));
+
+        Ok(parser)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::ScriptParser;
+    use crate::reader::LogLineContent;
+
+    // Lines like "12:30:05 alice: hello" and "12:31:00 -- bob left"
+    const SCRIPT: &str = r#"
+        fn parse(line) {
+            let time = line.sub_string(0, 8);
+            let rest = line.sub_string(9);
+
+            if rest.starts_with("-- ") {
+                return #{ time: time, text: rest.sub_string(3), kind: "status" };
+            }
+
+            let colon = rest.index_of(": ");
+
+            if colon < 0 {
+                return ();
+            }
+
+            #{ time: time, nick: rest.sub_string(0, colon), text: rest.sub_string(colon + 2) }
+        }
+    "#;
+
+    #[test]
+    fn parses_lines_with_script() {
+        let parser = ScriptParser::compile(SCRIPT).unwrap();
+        let date = Utc.ymd(2021, 1, 4);
+
+        let line = parser.parse_line("12:30:05 alice: hello", &date).unwrap();
+        assert_eq!(line.date, date.and_hms(12, 30, 5));
+        assert!(matches!(
+            line.content,
+            LogLineContent::Message { nickname, text } if nickname == "alice" && text == "hello"
+        ));
+
+        let line = parser.parse_line("12:31:00 -- bob left", &date).unwrap();
+        assert!(matches!(line.content, LogLineContent::Status(text) if text == "bob left"));
+
+        assert!(parser.parse_line("12:32:00 garbage", &date).is_err());
+    }
+
+    #[test]
+    fn stops_scripts_that_never_finish() {
+        let parser = ScriptParser::compile("fn parse(line) { loop {} }").unwrap();
+
+        assert!(parser.parse_line("", &Utc.ymd(2021, 1, 4)).is_err());
+    }
+}
@@ -0,0 +1,596 @@
+use std::{
+    cmp::Reverse,
+    io::{BufRead, BufReader, Cursor},
+    path::Path,
+    process::Stdio,
+    time::{Duration, Instant},
+};
+
+use chrono::{Date, Utc};
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+use tokio::{io::AsyncReadExt, process::Command};
+
+use crate::{reader::LineKind, state::SearchResultEntry};
+
+const SEARCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchBackend {
+    #[default]
+    Native, // Built-in search that works on every platform
+    Agrep, // External agrep binary with approximate matching
+}
+
+/// Limits how much of the logs one search may scan, so a broad regular
+/// expression over a whole archive can't tie up a shared instance.
+#[derive(Clone, Deserialize)]
+pub struct SearchBudget {
+    pub max_bytes: u64, // Log bytes one search may scan, counted again for each costly query part
+    #[serde(default)]
+    pub action: SearchBudgetAction,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchBudgetAction {
+    #[default]
+    Narrow, // Search only the newest days that fit
+    Refuse, // Search nothing and ask for fewer days
+}
+
+/// How far back a search can go within the budget.
+#[derive(Debug, PartialEq)]
+pub enum SearchCoverage {
+    All,
+    Since(Date<Utc>), // Only days from this date on fit
+    Nothing,          // Not even the newest day fits
+}
+
+#[derive(Clone, Copy)]
+pub struct SearchOptions {
+    pub case_sensitive: bool,
+    pub verbatim: bool,
+    pub whole_word: bool,
+    pub text_only: bool, // Match message text only, not timestamps, nicknames or status lines
+    pub messages_only: bool, // Leave out joins, parts, quits and other status lines
+}
+
+/// Lines matching a search, newest day first. Only the first lines up to a
+/// limit are kept, but every match is counted.
+#[derive(Default)]
+pub struct SearchMatches {
+    pub entries: Vec<SearchResultEntry>,
+    pub total: usize,
+    pub incomplete: bool, // Stopped early, so more lines may match than counted
+}
+
+impl SearchMatches {
+    fn push(&mut self, entry: SearchResultEntry, limit: usize) {
+        if self.entries.len() < limit {
+            self.entries.push(entry);
+        }
+
+        self.total += 1;
+    }
+
+    /// Combines the matches of other days, keeping the first lines of both.
+    pub fn merge(&mut self, other: SearchMatches, limit: usize) {
+        self.entries.extend(other.entries);
+        self.entries.sort_by(|a, b| {
+            b.date_slug
+                .cmp(&a.date_slug)
+                .then(a.line_number.cmp(&b.line_number))
+        });
+        self.entries.truncate(limit);
+        self.total += other.total;
+        self.incomplete |= other.incomplete;
+    }
+}
+
+/// Estimates how many times a scan reads each line for a query. Every term
+/// is matched separately, and repetitions make a regular expression slower
+/// to match, so each of them counts as another read.
+pub fn query_weight(query: &str, options: SearchOptions) -> u64 {
+    if options.verbatim {
+        return 1;
+    }
+
+    let weight = query
+        .split([',', ';'])
+        .filter(|term| !term.is_empty())
+        .map(|term| 1 + term.matches(['*', '+', '{']).count() as u64)
+        .sum::<u64>();
+
+    weight.max(1)
+}
+
+/// Finds the oldest date a search can reach within `max_cost`, given the
+/// cost of each day it would scan. Days are taken newest first, so a search
+/// that doesn't fit still covers recent logs.
+pub fn find_search_coverage(mut day_costs: Vec<(Date<Utc>, u64)>, max_cost: u64) -> SearchCoverage {
+    day_costs.sort_unstable_by_key(|(date, _)| Reverse(*date));
+
+    let mut total: u64 = 0;
+    let mut last_date = None;
+    let mut index = 0;
+
+    // Days of several channels share dates, so they're added together
+    while index < day_costs.len() {
+        let date = day_costs[index].0;
+
+        while index < day_costs.len() && day_costs[index].0 == date {
+            total = total.saturating_add(day_costs[index].1);
+            index += 1;
+        }
+
+        if total > max_cost {
+            return match last_date {
+                Some(date) => SearchCoverage::Since(date),
+                None => SearchCoverage::Nothing,
+            };
+        }
+
+        last_date = Some(date);
+    }
+
+    SearchCoverage::All
+}
+
+/// Searches the given days of a channel, newest first as given, keeping the
+/// first `limit` matching lines and stopping at the timeout.
+///
+/// With agrep, uncompressed days are searched by agrep and compressed days
+/// natively. Native searches decompress and match on the blocking thread
+/// pool, while agrep is awaited as a child process.
+pub async fn search_logs(
+    backend: SearchBackend,
+    channel_dir: &Path,
+    date_slugs: &[String],
+    query: &str,
+    options: SearchOptions,
+    limit: usize,
+) -> anyhow::Result<SearchMatches> {
+    // agrep only sees whole lines
+    let uses_agrep = backend == SearchBackend::Agrep && !options.text_only;
+    let (native_matches, agrep_slugs) = {
+        let channel_dir = channel_dir.to_path_buf();
+        let date_slugs = date_slugs.to_vec();
+        let query = query.to_string();
+
+        tokio::task::spawn_blocking(move || -> anyhow::Result<_> {
+            let (native_slugs, agrep_slugs): (Vec<String>, Vec<String>) = if uses_agrep {
+                date_slugs.into_iter().partition(|date_slug| {
+                    crate::reader::is_compressed(&crate::reader::find_log_file(
+                        &channel_dir,
+                        date_slug,
+                    ))
+                })
+            } else {
+                (date_slugs, Vec::new())
+            };
+            let native_matches = if uses_agrep && native_slugs.is_empty() {
+                None
+            } else {
+                Some(search_native(
+                    &channel_dir,
+                    &native_slugs,
+                    &query,
+                    options,
+                    limit,
+                )?)
+            };
+
+            Ok((native_matches, agrep_slugs))
+        })
+        .await??
+    };
+
+    match native_matches {
+        Some(mut matches) if !agrep_slugs.is_empty() => {
+            matches.merge(
+                search_agrep(channel_dir, &agrep_slugs, query, options, limit).await?,
+                limit,
+            );
+
+            Ok(matches)
+        }
+        Some(matches) => Ok(matches),
+        None => search_agrep(channel_dir, &agrep_slugs, query, options, limit).await,
+    }
+}
+
+fn search_native(
+    channel_dir: &Path,
+    date_slugs: &[String],
+    query: &str,
+    options: SearchOptions,
+    limit: usize,
+) -> anyhow::Result<SearchMatches> {
+    let start = Instant::now();
+    let matcher = QueryMatcher::new(query, options)?;
+    let mut matches = SearchMatches::default();
+
+    for date_slug in date_slugs {
+        if start.elapsed() > SEARCH_TIMEOUT {
+            matches.incomplete = true;
+            break;
+        }
+
+        let file = crate::reader::open_log(&crate::reader::find_log_file(channel_dir, date_slug))?;
+        let file = DecodeReaderBytesBuilder::new()
+            .encoding(Some(encoding_rs::UTF_8))
+            .build(file);
+
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+
+            if !matcher.is_match(&line) {
+                continue;
+            }
+
+            matches.push(
+                SearchResultEntry {
+                    date_slug: date_slug.clone(),
+                    line_number: index as u64 + 1,
+                    raw_line: line,
+                },
+                limit,
+            );
+        }
+    }
+
+    Ok(matches)
+}
+
+/// Matches lines like agrep does without approximate matching.
+///
+/// Unless verbatim, `,` separates alternatives and `;` separates terms that
+/// must all appear, and each term is a regular expression. Terms that aren't
+/// valid expressions are matched literally.
+pub(crate) struct QueryMatcher {
+    alternatives: Vec<Vec<Regex>>,
+    text_only: bool,
+    messages_only: bool,
+}
+
+impl QueryMatcher {
+    pub(crate) fn new(query: &str, options: SearchOptions) -> anyhow::Result<Self> {
+        let mut alternatives = Vec::new();
+
+        if options.verbatim {
+            if !query.is_empty() {
+                alternatives.push(vec![build_term_regex(&regex::escape(query), options)?]);
+            }
+        } else {
+            for alternative in query.split(',') {
+                let terms = alternative
+                    .split(';')
+                    .filter(|term| !term.is_empty())
+                    .map(|term| {
+                        build_term_regex(term, options)
+                            .or_else(|_| build_term_regex(&regex::escape(term), options))
+                    })
+                    .collect::<Result<Vec<Regex>, _>>()?;
+
+                if !terms.is_empty() {
+                    alternatives.push(terms);
+                }
+            }
+        }
+
+        Ok(Self {
+            alternatives,
+            text_only: options.text_only,
+            messages_only: options.messages_only,
+        })
+    }
+
+    pub(crate) fn is_match(&self, line: &str) -> bool {
+        if self.messages_only && crate::reader::classify_line(line) != LineKind::Message {
+            return false;
+        }
+
+        let line = if self.text_only {
+            match crate::reader::message_text(line) {
+                Some(text) => text,
+                None => return false,
+            }
+        } else {
+            line
+        };
+
+        self.alternatives
+            .iter()
+            .any(|terms| terms.iter().all(|term| term.is_match(line)))
+    }
+}
+
+fn build_term_regex(pattern: &str, options: SearchOptions) -> Result<Regex, regex::Error> {
+    let pattern = if options.whole_word {
+        format!(r"(?:^|[^\w])(?:{})(?:$|[^\w])", pattern)
+    } else {
+        pattern.to_string()
+    };
+
+    RegexBuilder::new(&pattern)
+        .case_insensitive(!options.case_sensitive)
+        .build()
+}
+
+async fn search_agrep(
+    channel_dir: &Path,
+    date_slugs: &[String],
+    query: &str,
+    options: SearchOptions,
+    limit: usize,
+) -> anyhow::Result<SearchMatches> {
+    let mut process = Command::new("agrep");
+
+    if !options.case_sensitive {
+        process.arg("-i0");
+    }
+
+    if options.verbatim {
+        process.arg("-k");
+    }
+
+    if options.whole_word {
+        process.arg("-w");
+    }
+
+    process.arg("-n").arg(query);
+
+    // Relative names keep drive letters and other colons out of the output
+    for date_slug in date_slugs {
+        process.arg(format!("{}.log", date_slug));
+    }
+
+    let (output, timed_out) = run_with_timeout(
+        process
+            .current_dir(channel_dir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null()),
+    )
+    .await?;
+    let output = DecodeReaderBytesBuilder::new()
+        .encoding(Some(encoding_rs::UTF_8))
+        .build(Cursor::new(output));
+    let output = BufReader::new(output);
+    let mut matches = SearchMatches {
+        incomplete: timed_out,
+        ..SearchMatches::default()
+    };
+
+    for line in output.lines() {
+        let line = line?;
+
+        // Like grep, agrep leaves out the file name when searching one file
+        let (date_slug, rest) = if date_slugs.len() == 1 {
+            (date_slugs[0].as_str(), line.as_str())
+        } else {
+            match line.split_once(".log:") {
+                Some(parts) => parts,
+                None => continue,
+            }
+        };
+
+        if let Some((line_number, raw_line)) = rest.split_once(':') {
+            if options.messages_only && crate::reader::classify_line(raw_line) != LineKind::Message
+            {
+                continue;
+            }
+
+            matches.push(
+                SearchResultEntry {
+                    date_slug: date_slug.to_string(),
+                    line_number: line_number.trim().parse::<u64>()?,
+                    raw_line: raw_line.to_string(),
+                },
+                limit,
+            );
+        }
+    }
+
+    Ok(matches)
+}
+
+/// Runs a process and returns its standard output and whether it was killed
+/// for running longer than the search timeout.
+async fn run_with_timeout(command: &mut Command) -> anyhow::Result<(Vec<u8>, bool)> {
+    let mut child = command.kill_on_drop(true).spawn().map_err(|error| {
+        if error.kind() == std::io::ErrorKind::NotFound {
+            anyhow::anyhow!("agrep is not installed; use the native search backend instead")
+        } else {
+            error.into()
+        }
+    })?;
+
+    let mut stdout = child.stdout.take().unwrap();
+    let reader = tokio::spawn(async move {
+        let mut output = Vec::new();
+        stdout.read_to_end(&mut output).await.map(|_| output)
+    });
+    let timed_out = match tokio::time::timeout(SEARCH_TIMEOUT, child.wait()).await {
+        Ok(status) => {
+            status?;
+            false
+        }
+        Err(_) => {
+            child.kill().await?;
+            true
+        }
+    };
+
+    let output = reader
+        .await
+        .map_err(|_| anyhow::anyhow!("Search output reader panicked"))??;
+
+    Ok((output, timed_out))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::{find_search_coverage, query_weight, QueryMatcher, SearchCoverage, SearchOptions};
+
+    fn options(case_sensitive: bool, verbatim: bool, whole_word: bool) -> SearchOptions {
+        SearchOptions {
+            case_sensitive,
+            verbatim,
+            whole_word,
+            text_only: false,
+            messages_only: false,
+        }
+    }
+
+    fn is_match(query: &str, options: SearchOptions, line: &str) -> bool {
+        QueryMatcher::new(query, options).unwrap().is_match(line)
+    }
+
+    #[test]
+    fn search_coverage_stops_at_budget() {
+        let day = |day| Utc.ymd(2021, 1, day);
+        let day_costs = vec![(day(4), 10), (day(6), 10), (day(5), 5), (day(6), 10)];
+
+        assert_eq!(
+            find_search_coverage(day_costs.clone(), 35),
+            SearchCoverage::All
+        );
+        assert_eq!(
+            find_search_coverage(day_costs.clone(), 30),
+            SearchCoverage::Since(day(5))
+        );
+        assert_eq!(find_search_coverage(day_costs, 19), SearchCoverage::Nothing);
+
+        assert_eq!(query_weight("doh", options(false, false, false)), 1);
+        assert_eq!(query_weight("a.*b;c+,d", options(false, false, false)), 5);
+        assert_eq!(query_weight("a.*b;c+,d", options(false, true, false)), 1);
+    }
+
+    #[test]
+    fn matches_substring_ignoring_case() {
+        let options = options(false, false, false);
+
+        assert!(is_match("wind", options, "[00:01] <alice> The WINDows box"));
+        assert!(!is_match("wind", options, "[00:01] <alice> hello"));
+    }
+
+    #[test]
+    fn matches_case_sensitively() {
+        let options = options(true, false, false);
+
+        assert!(is_match("Wind", options, "[00:01] <alice> Windows"));
+        assert!(!is_match("Wind", options, "[00:01] <alice> windows"));
+    }
+
+    #[test]
+    fn matches_whole_words() {
+        let options = options(false, false, true);
+
+        assert!(is_match("win", options, "[00:01] <alice> we win"));
+        assert!(is_match("win", options, "[00:01] <alice> win, again"));
+        assert!(!is_match("win", options, "[00:01] <alice> the wind"));
+        assert!(!is_match("win", options, "[00:01] <alice> win_32"));
+    }
+
+    #[test]
+    fn matches_any_alternative() {
+        let options = options(false, false, false);
+
+        assert!(is_match("cat,dog", options, "[00:01] <alice> a dog"));
+        assert!(is_match("cat,dog", options, "[00:01] <alice> a cat"));
+        assert!(!is_match("cat,dog", options, "[00:01] <alice> a bird"));
+    }
+
+    #[test]
+    fn matches_all_terms() {
+        let options = options(false, false, false);
+
+        assert!(is_match("cat;dog", options, "[00:01] <alice> dog and cat"));
+        assert!(!is_match("cat;dog", options, "[00:01] <alice> just a cat"));
+        assert!(is_match("cat;dog,bird", options, "[00:01] <alice> a bird"));
+    }
+
+    #[test]
+    fn matches_regular_expressions() {
+        let options = options(false, false, false);
+
+        assert!(is_match(
+            "^\\[00:0.\\] <alice>",
+            options,
+            "[00:05] <alice> hi"
+        ));
+        assert!(!is_match(
+            "^\\[00:0.\\] <alice>",
+            options,
+            "[00:15] <alice> hi"
+        ));
+    }
+
+    #[test]
+    fn matches_invalid_expressions_literally() {
+        let options = options(false, false, false);
+
+        assert!(is_match("f(x", options, "[00:01] <alice> call f(x)"));
+        assert!(!is_match("f(x", options, "[00:01] <alice> call fx"));
+    }
+
+    #[test]
+    fn matches_verbatim_without_operators() {
+        let options = options(false, true, false);
+
+        assert!(is_match("a,b;c.", options, "[00:01] <alice> see a,b;c."));
+        assert!(!is_match("a,b;c.", options, "[00:01] <alice> see a"));
+        assert!(!is_match("a.c", options, "[00:01] <alice> abc"));
+    }
+
+    #[test]
+    fn matches_message_text_only() {
+        let options = SearchOptions {
+            text_only: true,
+            ..options(false, false, false)
+        };
+
+        assert!(is_match("12:30", options, "[09:00] <alice> meet at 12:30"));
+        assert!(!is_match("12:30", options, "[12:30] <alice> hello"));
+        assert!(!is_match("alice", options, "[12:30] <alice> hello"));
+        assert!(!is_match("joined", options, "[12:30] *** alice has joined"));
+        assert!(is_match("^hello", options, "[12:30] <alice> hello"));
+    }
+
+    #[test]
+    fn matches_messages_only() {
+        let options = SearchOptions {
+            messages_only: true,
+            ..options(false, false, false)
+        };
+
+        assert!(is_match("alice", options, "[12:30] <alice> hello"));
+        assert!(is_match("alice", options, "[12:30] * alice waves"));
+        assert!(!is_match("alice", options, "[12:30] *** alice has joined"));
+    }
+
+    #[test]
+    fn empty_query_matches_nothing() {
+        assert!(!is_match(
+            "",
+            options(false, false, false),
+            "[00:01] <alice> hi"
+        ));
+        assert!(!is_match(
+            "",
+            options(false, true, false),
+            "[00:01] <alice> hi"
+        ));
+        assert!(!is_match(
+            ",;",
+            options(false, false, false),
+            "[00:01] <alice> hi"
+        ));
+    }
+}
@@ -0,0 +1,957 @@
+//! Stores of indexed log lines behind the search index.
+//!
+//! The index decides which days to add and remove, and checks the lines an
+//! engine finds against the whole query, so an engine only has to find lines
+//! by their words. Days are identified by channel and date slug, and dated by
+//! days since the common era so they can be filtered by range.
+
+use std::{ops::RangeInclusive, path::Path, sync::Mutex, time::Duration};
+
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tantivy::{
+    collector::TopDocs,
+    directory::MmapDirectory,
+    doc,
+    query::{BooleanQuery, Occur, PhraseQuery, Query, RangeQuery, TermQuery},
+    schema::{Field, IndexRecordOption, Schema, FAST, INDEXED, STORED, STRING, TEXT},
+    Index, IndexReader, IndexWriter, ReloadPolicy, Term,
+};
+
+use crate::{search_index::SearchIndexConfig, state::SearchResultEntry};
+
+const WRITER_HEAP_SIZE: usize = 50_000_000;
+const SQLITE_FILENAME: &str = "lines.sqlite3";
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+const HTTP_BATCH_SIZE: usize = 1000; // Lines sent to an external engine per request
+const MEILISEARCH_MAX_HITS: usize = 1000; // MeiliSearch's default maxTotalHits
+const ELASTICSEARCH_MAX_HITS: usize = 10000; // Elasticsearch's default max_result_window
+
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchEngineKind {
+    #[default]
+    Tantivy, // Built-in index in the index directory
+    Sqlite,        // SQLite FTS5 table in the index directory
+    Meilisearch,   // MeiliSearch server at engine_url
+    Elasticsearch, // Elasticsearch or OpenSearch server at engine_url
+}
+
+/// Lines an engine found, newest day first.
+pub struct FoundLines {
+    pub lines: Vec<SearchResultEntry>,
+    pub incomplete: bool, // More lines have the words than were returned
+}
+
+pub trait SearchEngine: Send + Sync {
+    /// Adds the lines of a day, given with their line numbers.
+    fn add_day(
+        &self,
+        channel: &str,
+        date_slug: &str,
+        date: u64,
+        lines: &[(u64, String)],
+    ) -> anyhow::Result<()>;
+
+    fn remove_day(&self, channel: &str, date_slug: &str) -> anyhow::Result<()>;
+
+    fn remove_channel(&self, channel: &str) -> anyhow::Result<()>;
+
+    /// Makes the changes since the last commit searchable.
+    fn commit(&self) -> anyhow::Result<()>;
+
+    /// Finds up to `limit` lines of a channel between the dates that have
+    /// every word of the query, or its words in order for a phrase. Returns
+    /// `None` if the query has no words.
+    fn find_lines(
+        &self,
+        channel: &str,
+        query: &str,
+        phrase: bool,
+        dates: RangeInclusive<u64>,
+        limit: usize,
+    ) -> anyhow::Result<Option<FoundLines>>;
+}
+
+pub fn open_engine(config: &SearchIndexConfig) -> anyhow::Result<Box<dyn SearchEngine>> {
+    let url = || {
+        config
+            .engine_url
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("search_index.engine_url is required"))
+    };
+
+    Ok(match config.engine {
+        SearchEngineKind::Tantivy => Box::new(TantivyEngine::open(&config.directory)?),
+        SearchEngineKind::Sqlite => {
+            Box::new(SqliteEngine::open(&config.directory.join(SQLITE_FILENAME))?)
+        }
+        SearchEngineKind::Meilisearch => Box::new(MeilisearchEngine {
+            server: HttpServer::new(url()?, &config.engine_index, config.engine_api_key.clone())?,
+        }),
+        SearchEngineKind::Elasticsearch => Box::new(ElasticsearchEngine {
+            server: HttpServer::new(url()?, &config.engine_index, config.engine_api_key.clone())?,
+        }),
+    })
+}
+
+/// Splits a query into the words that the SQLite and external engines
+/// index, lowercase.
+fn query_words(query: &str) -> Vec<String> {
+    query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+struct TantivyFields {
+    channel: Field,
+    day: Field, // "channel/date_slug", for replacing a day's lines
+    date_slug: Field,
+    date: Field, // Newest sorted first
+    line_number: Field,
+    text: Field,
+}
+
+struct TantivyEngine {
+    index: Index,
+    reader: IndexReader,
+    fields: TantivyFields,
+    writer: Mutex<Option<IndexWriter>>, // Open from the first change until the commit
+}
+
+impl TantivyEngine {
+    fn open(directory: &Path) -> anyhow::Result<Self> {
+        let mut schema_builder = Schema::builder();
+        let fields = TantivyFields {
+            channel: schema_builder.add_text_field("channel", STRING),
+            day: schema_builder.add_text_field("day", STRING),
+            date_slug: schema_builder.add_text_field("date_slug", STORED),
+            date: schema_builder.add_u64_field("date", INDEXED | FAST),
+            line_number: schema_builder.add_u64_field("line_number", STORED),
+            text: schema_builder.add_text_field("text", TEXT | STORED),
+        };
+
+        std::fs::create_dir_all(directory)?;
+
+        let index = Index::open_or_create(MmapDirectory::open(directory)?, schema_builder.build())?;
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()?;
+
+        Ok(Self {
+            index,
+            reader,
+            fields,
+            writer: Mutex::new(None),
+        })
+    }
+
+    fn with_writer<F>(&self, func: F) -> anyhow::Result<()>
+    where
+        F: FnOnce(&mut IndexWriter),
+    {
+        let mut writer = self.writer.lock().unwrap();
+
+        if writer.is_none() {
+            *writer = Some(self.index.writer(WRITER_HEAP_SIZE)?);
+        }
+
+        func(writer.as_mut().unwrap());
+
+        Ok(())
+    }
+
+    fn day_term(&self, channel: &str, date_slug: &str) -> Term {
+        Term::from_field_text(self.fields.day, &format!("{}/{}", channel, date_slug))
+    }
+
+    fn tokenize(&self, text: &str) -> anyhow::Result<Vec<Term>> {
+        let analyzer = self.index.tokenizer_for_field(self.fields.text)?;
+        let mut stream = analyzer.token_stream(text);
+        let mut terms = Vec::new();
+
+        while stream.advance() {
+            terms.push(Term::from_field_text(
+                self.fields.text,
+                &stream.token().text,
+            ));
+        }
+
+        Ok(terms)
+    }
+}
+
+impl SearchEngine for TantivyEngine {
+    fn add_day(
+        &self,
+        channel: &str,
+        date_slug: &str,
+        date: u64,
+        lines: &[(u64, String)],
+    ) -> anyhow::Result<()> {
+        let day = format!("{}/{}", channel, date_slug);
+
+        self.with_writer(|writer| {
+            for (line_number, line) in lines {
+                writer.add_document(doc!(
+                    self.fields.channel => channel,
+                    self.fields.day => day.as_str(),
+                    self.fields.date_slug => date_slug,
+                    self.fields.date => date,
+                    self.fields.line_number => *line_number,
+                    self.fields.text => line.as_str(),
+                ));
+            }
+        })
+    }
+
+    fn remove_day(&self, channel: &str, date_slug: &str) -> anyhow::Result<()> {
+        let term = self.day_term(channel, date_slug);
+
+        self.with_writer(|writer| {
+            writer.delete_term(term);
+        })
+    }
+
+    fn remove_channel(&self, channel: &str) -> anyhow::Result<()> {
+        let term = Term::from_field_text(self.fields.channel, channel);
+
+        self.with_writer(|writer| {
+            writer.delete_term(term);
+        })
+    }
+
+    fn commit(&self) -> anyhow::Result<()> {
+        if let Some(mut writer) = self.writer.lock().unwrap().take() {
+            writer.commit()?;
+            self.reader.reload()?;
+        }
+
+        Ok(())
+    }
+
+    fn find_lines(
+        &self,
+        channel: &str,
+        query: &str,
+        phrase: bool,
+        dates: RangeInclusive<u64>,
+        limit: usize,
+    ) -> anyhow::Result<Option<FoundLines>> {
+        let terms = self.tokenize(query)?;
+
+        if terms.is_empty() {
+            return Ok(None);
+        }
+
+        let text_query: Box<dyn Query> = if phrase && terms.len() > 1 {
+            Box::new(PhraseQuery::new(terms))
+        } else {
+            Box::new(BooleanQuery::new(
+                terms
+                    .into_iter()
+                    .map(|term| {
+                        let query: Box<dyn Query> =
+                            Box::new(TermQuery::new(term, IndexRecordOption::Basic));
+                        (Occur::Must, query)
+                    })
+                    .collect(),
+            ))
+        };
+        let channel_query = TermQuery::new(
+            Term::from_field_text(self.fields.channel, channel),
+            IndexRecordOption::Basic,
+        );
+        let date_query = RangeQuery::new_u64(self.fields.date, *dates.start()..*dates.end() + 1);
+        let index_query = BooleanQuery::new(vec![
+            (Occur::Must, text_query),
+            (Occur::Must, Box::new(channel_query) as Box<dyn Query>),
+            (Occur::Must, Box::new(date_query) as Box<dyn Query>),
+        ]);
+
+        let searcher = self.reader.searcher();
+        let candidates = searcher.search(
+            &index_query,
+            &TopDocs::with_limit(limit + 1).order_by_u64_field(self.fields.date),
+        )?;
+        let incomplete = candidates.len() > limit;
+        let mut lines = Vec::new();
+
+        for (_date, address) in candidates.into_iter().take(limit) {
+            let document = searcher.doc(address)?;
+
+            lines.push(SearchResultEntry {
+                date_slug: document
+                    .get_first(self.fields.date_slug)
+                    .and_then(|value| value.text())
+                    .unwrap_or_default()
+                    .to_string(),
+                line_number: document
+                    .get_first(self.fields.line_number)
+                    .and_then(|value| value.u64_value())
+                    .unwrap_or_default(),
+                raw_line: document
+                    .get_first(self.fields.text)
+                    .and_then(|value| value.text())
+                    .unwrap_or_default()
+                    .to_string(),
+            });
+        }
+
+        Ok(Some(FoundLines { lines, incomplete }))
+    }
+}
+
+/// Lines in an FTS5 table, with their days in a regular table so a day can
+/// be replaced without scanning the whole index.
+struct SqliteEngine {
+    connection: Mutex<Connection>, // In a transaction from the first change until the commit
+}
+
+impl SqliteEngine {
+    fn open(path: &Path) -> anyhow::Result<Self> {
+        if let Some(directory) = path.parent() {
+            std::fs::create_dir_all(directory)?;
+        }
+
+        let connection = Connection::open(path)?;
+
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS lines (
+                id INTEGER PRIMARY KEY,
+                channel TEXT NOT NULL,
+                date_slug TEXT NOT NULL,
+                date INTEGER NOT NULL,
+                line_number INTEGER NOT NULL,
+                text TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS lines_day ON lines (channel, date_slug);
+            CREATE VIRTUAL TABLE IF NOT EXISTS lines_text
+                USING fts5(text, content = 'lines', content_rowid = 'id');
+            CREATE TRIGGER IF NOT EXISTS lines_insert AFTER INSERT ON lines BEGIN
+                INSERT INTO lines_text (rowid, text) VALUES (new.id, new.text);
+            END;
+            CREATE TRIGGER IF NOT EXISTS lines_delete AFTER DELETE ON lines BEGIN
+                INSERT INTO lines_text (lines_text, rowid, text)
+                    VALUES ('delete', old.id, old.text);
+            END;",
+        )?;
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn change<F>(&self, func: F) -> anyhow::Result<()>
+    where
+        F: FnOnce(&Connection) -> rusqlite::Result<()>,
+    {
+        let connection = self.connection.lock().unwrap();
+
+        if connection.is_autocommit() {
+            connection.execute_batch("BEGIN")?;
+        }
+
+        Ok(func(&connection)?)
+    }
+}
+
+impl SearchEngine for SqliteEngine {
+    fn add_day(
+        &self,
+        channel: &str,
+        date_slug: &str,
+        date: u64,
+        lines: &[(u64, String)],
+    ) -> anyhow::Result<()> {
+        self.change(|connection| {
+            let mut statement = connection.prepare_cached(
+                "INSERT INTO lines (channel, date_slug, date, line_number, text)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+
+            for (line_number, line) in lines {
+                statement.execute(params![
+                    channel,
+                    date_slug,
+                    date as i64,
+                    *line_number as i64,
+                    line
+                ])?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn remove_day(&self, channel: &str, date_slug: &str) -> anyhow::Result<()> {
+        self.change(|connection| {
+            connection.execute(
+                "DELETE FROM lines WHERE channel = ?1 AND date_slug = ?2",
+                params![channel, date_slug],
+            )?;
+
+            Ok(())
+        })
+    }
+
+    fn remove_channel(&self, channel: &str) -> anyhow::Result<()> {
+        self.change(|connection| {
+            connection.execute("DELETE FROM lines WHERE channel = ?1", params![channel])?;
+
+            Ok(())
+        })
+    }
+
+    fn commit(&self) -> anyhow::Result<()> {
+        let connection = self.connection.lock().unwrap();
+
+        if !connection.is_autocommit() {
+            connection.execute_batch("COMMIT")?;
+        }
+
+        Ok(())
+    }
+
+    fn find_lines(
+        &self,
+        channel: &str,
+        query: &str,
+        phrase: bool,
+        dates: RangeInclusive<u64>,
+        limit: usize,
+    ) -> anyhow::Result<Option<FoundLines>> {
+        let words = query_words(query);
+
+        if words.is_empty() {
+            return Ok(None);
+        }
+
+        // Words are alphanumeric, so quoting them is enough to keep FTS5
+        // from reading them as operators
+        let text_query = if phrase {
+            format!("\"{}\"", words.join(" "))
+        } else {
+            words
+                .iter()
+                .map(|word| format!("\"{}\"", word))
+                .collect::<Vec<String>>()
+                .join(" ")
+        };
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare_cached(
+            "SELECT lines.date_slug, lines.line_number, lines.text
+            FROM lines_text JOIN lines ON lines.id = lines_text.rowid
+            WHERE lines_text MATCH ?1 AND lines.channel = ?2 AND lines.date BETWEEN ?3 AND ?4
+            ORDER BY lines.date DESC
+            LIMIT ?5",
+        )?;
+        let mut lines = statement
+            .query_map(
+                params![
+                    text_query,
+                    channel,
+                    *dates.start() as i64,
+                    *dates.end() as i64,
+                    (limit + 1) as i64
+                ],
+                |row| {
+                    Ok(SearchResultEntry {
+                        date_slug: row.get(0)?,
+                        line_number: row.get::<_, i64>(1)? as u64,
+                        raw_line: row.get(2)?,
+                    })
+                },
+            )?
+            .collect::<rusqlite::Result<Vec<SearchResultEntry>>>()?;
+        let incomplete = lines.len() > limit;
+        lines.truncate(limit);
+
+        Ok(Some(FoundLines { lines, incomplete }))
+    }
+}
+
+/// A line as stored in an external engine.
+#[derive(Serialize, Deserialize)]
+struct EngineDocument {
+    id: String,
+    channel: String,
+    date_slug: String,
+    date: u64,
+    line_number: u64,
+    text: String,
+}
+
+impl EngineDocument {
+    fn new(channel: &str, date_slug: &str, date: u64, line_number: u64, text: &str) -> Self {
+        Self {
+            // Engines limit the characters of IDs, so names are hashed
+            id: format!(
+                "{:x}",
+                Sha256::new()
+                    .chain(channel)
+                    .chain([0])
+                    .chain(date_slug)
+                    .chain([0])
+                    .chain(line_number.to_string())
+                    .finalize()
+            ),
+            channel: channel.to_string(),
+            date_slug: date_slug.to_string(),
+            date,
+            line_number,
+            text: text.to_string(),
+        }
+    }
+
+    fn into_result_entry(self) -> SearchResultEntry {
+        SearchResultEntry {
+            date_slug: self.date_slug,
+            line_number: self.line_number,
+            raw_line: self.text,
+        }
+    }
+}
+
+/// An index on a search server reached over HTTP.
+///
+/// The index is updated from scheduled tasks and searched from request
+/// handlers, which both run where blocking is allowed, so requests are
+/// waited on in place.
+struct HttpServer {
+    client: reqwest::Client,
+    url: String, // Base URL without a trailing slash
+    index: String,
+    api_key: Option<String>,
+    prepared: Mutex<bool>, // Whether the index was set up since startup
+}
+
+impl HttpServer {
+    fn new(url: String, index: &str, api_key: Option<String>) -> anyhow::Result<Self> {
+        Ok(Self {
+            client: reqwest::Client::builder().timeout(HTTP_TIMEOUT).build()?,
+            url,
+            index: index.to_string(),
+            api_key,
+            prepared: Mutex::new(false),
+        })
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.client.request(method, format!("{}{}", self.url, path))
+    }
+
+    /// Sends a request and returns the JSON body of the response, which is
+    /// `null` if there's none.
+    fn send(&self, request: reqwest::RequestBuilder) -> anyhow::Result<serde_json::Value> {
+        tokio::task::block_in_place(|| {
+            tokio::runtime::Handle::current().block_on(async {
+                let response = request.send().await?;
+                let status = response.status();
+                let body = response.bytes().await?;
+
+                if !status.is_success() {
+                    anyhow::bail!(
+                        "Search engine answered {}: {}",
+                        status,
+                        String::from_utf8_lossy(&body)
+                    );
+                }
+
+                Ok::<_, anyhow::Error>(
+                    serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null),
+                )
+            })
+        })
+    }
+
+    /// Runs the setup once per process before the first change.
+    fn prepare<F>(&self, setup: F) -> anyhow::Result<()>
+    where
+        F: FnOnce() -> anyhow::Result<()>,
+    {
+        let mut prepared = self.prepared.lock().unwrap();
+
+        if !*prepared {
+            setup()?;
+            *prepared = true;
+        }
+
+        Ok(())
+    }
+}
+
+/// Quotes a value for a MeiliSearch filter expression.
+fn meilisearch_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+struct MeilisearchEngine {
+    server: HttpServer,
+}
+
+impl MeilisearchEngine {
+    fn authorize(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.server.api_key {
+            Some(api_key) => request.bearer_auth(api_key),
+            None => request,
+        }
+    }
+
+    fn send(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        body: &serde_json::Value,
+    ) -> anyhow::Result<serde_json::Value> {
+        let path = format!("/indexes/{}{}", self.server.index, path);
+
+        self.server.send(
+            self.authorize(self.server.request(method, &path))
+                .json(body),
+        )
+    }
+
+    fn prepare(&self) -> anyhow::Result<()> {
+        self.server.prepare(|| {
+            self.send(
+                reqwest::Method::PATCH,
+                "/settings",
+                &serde_json::json!({
+                    "filterableAttributes": ["channel", "date_slug", "date"],
+                    "sortableAttributes": ["date"],
+                    "searchableAttributes": ["text"],
+                }),
+            )?;
+
+            Ok(())
+        })
+    }
+
+    fn delete_matching(&self, filter: String) -> anyhow::Result<()> {
+        self.prepare()?;
+        self.send(
+            reqwest::Method::POST,
+            "/documents/delete",
+            &serde_json::json!({ "filter": filter }),
+        )?;
+
+        Ok(())
+    }
+}
+
+/// MeiliSearch applies changes in the order they're sent, a moment after
+/// they're accepted, so there's nothing to commit.
+impl SearchEngine for MeilisearchEngine {
+    fn add_day(
+        &self,
+        channel: &str,
+        date_slug: &str,
+        date: u64,
+        lines: &[(u64, String)],
+    ) -> anyhow::Result<()> {
+        self.prepare()?;
+
+        for batch in lines.chunks(HTTP_BATCH_SIZE) {
+            let documents = batch
+                .iter()
+                .map(|(line_number, line)| {
+                    EngineDocument::new(channel, date_slug, date, *line_number, line)
+                })
+                .collect::<Vec<EngineDocument>>();
+
+            self.send(
+                reqwest::Method::POST,
+                "/documents?primaryKey=id",
+                &serde_json::to_value(&documents)?,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn remove_day(&self, channel: &str, date_slug: &str) -> anyhow::Result<()> {
+        self.delete_matching(format!(
+            "channel = {} AND date_slug = {}",
+            meilisearch_string(channel),
+            meilisearch_string(date_slug)
+        ))
+    }
+
+    fn remove_channel(&self, channel: &str) -> anyhow::Result<()> {
+        self.delete_matching(format!("channel = {}", meilisearch_string(channel)))
+    }
+
+    fn commit(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn find_lines(
+        &self,
+        channel: &str,
+        query: &str,
+        phrase: bool,
+        dates: RangeInclusive<u64>,
+        limit: usize,
+    ) -> anyhow::Result<Option<FoundLines>> {
+        let words = query_words(query);
+
+        if words.is_empty() {
+            return Ok(None);
+        }
+
+        let text_query = if phrase {
+            format!("\"{}\"", words.join(" "))
+        } else {
+            words.join(" ")
+        };
+        let limit = limit.min(MEILISEARCH_MAX_HITS);
+        let response = self.send(
+            reqwest::Method::POST,
+            "/search",
+            &serde_json::json!({
+                "q": text_query,
+                "filter": format!(
+                    "channel = {} AND date >= {} AND date <= {}",
+                    meilisearch_string(channel),
+                    dates.start(),
+                    dates.end()
+                ),
+                "sort": ["date:desc"],
+                "matchingStrategy": "all",
+                "limit": limit,
+            }),
+        )?;
+        let hits: Vec<EngineDocument> = serde_json::from_value(response["hits"].clone())?;
+        let total = response["estimatedTotalHits"].as_u64().unwrap_or_default() as usize;
+
+        Ok(Some(FoundLines {
+            incomplete: total > hits.len(),
+            lines: hits
+                .into_iter()
+                .map(EngineDocument::into_result_entry)
+                .collect(),
+        }))
+    }
+}
+
+struct ElasticsearchEngine {
+    server: HttpServer,
+}
+
+impl ElasticsearchEngine {
+    fn authorize(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.server.api_key {
+            Some(api_key) => request.header("Authorization", format!("ApiKey {}", api_key)),
+            None => request,
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let path = format!("/{}{}", self.server.index, path);
+
+        self.authorize(self.server.request(method, &path))
+    }
+
+    fn send(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> anyhow::Result<serde_json::Value> {
+        let request = self.request(method, path);
+
+        self.server.send(match body {
+            Some(body) => request.json(&body),
+            None => request,
+        })
+    }
+
+    fn prepare(&self) -> anyhow::Result<()> {
+        self.server.prepare(|| {
+            let exists = self.send(reqwest::Method::HEAD, "", None).is_ok();
+
+            if !exists {
+                let settings = serde_json::json!({
+                    "mappings": {
+                        "properties": {
+                            "channel": { "type": "keyword" },
+                            "date_slug": { "type": "keyword" },
+                            "date": { "type": "integer" },
+                            "line_number": { "type": "long" },
+                            "text": { "type": "text" },
+                        }
+                    }
+                });
+                self.send(reqwest::Method::PUT, "", Some(settings))?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn delete_matching(&self, filters: serde_json::Value) -> anyhow::Result<()> {
+        self.prepare()?;
+
+        let query = serde_json::json!({ "query": { "bool": { "filter": filters } } });
+        self.send(
+            reqwest::Method::POST,
+            "/_delete_by_query?conflicts=proceed",
+            Some(query),
+        )?;
+
+        Ok(())
+    }
+}
+
+impl SearchEngine for ElasticsearchEngine {
+    fn add_day(
+        &self,
+        channel: &str,
+        date_slug: &str,
+        date: u64,
+        lines: &[(u64, String)],
+    ) -> anyhow::Result<()> {
+        self.prepare()?;
+
+        for batch in lines.chunks(HTTP_BATCH_SIZE) {
+            let mut body = String::new();
+
+            for (line_number, line) in batch {
+                let document = EngineDocument::new(channel, date_slug, date, *line_number, line);
+                body.push_str(&serde_json::json!({ "index": { "_id": document.id } }).to_string());
+                body.push('\n');
+                body.push_str(&serde_json::to_string(&document)?);
+                body.push('\n');
+            }
+
+            let response = self.server.send(
+                self.request(reqwest::Method::POST, "/_bulk")
+                    .header("Content-Type", "application/x-ndjson")
+                    .body(body),
+            )?;
+
+            if response["errors"].as_bool().unwrap_or_default() {
+                anyhow::bail!("Search engine rejected lines of {} {}", channel, date_slug);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove_day(&self, channel: &str, date_slug: &str) -> anyhow::Result<()> {
+        self.delete_matching(serde_json::json!([
+            { "term": { "channel": channel } },
+            { "term": { "date_slug": date_slug } },
+        ]))
+    }
+
+    fn remove_channel(&self, channel: &str) -> anyhow::Result<()> {
+        self.delete_matching(serde_json::json!([{ "term": { "channel": channel } }]))
+    }
+
+    fn commit(&self) -> anyhow::Result<()> {
+        self.send(reqwest::Method::POST, "/_refresh", None)?;
+
+        Ok(())
+    }
+
+    fn find_lines(
+        &self,
+        channel: &str,
+        query: &str,
+        phrase: bool,
+        dates: RangeInclusive<u64>,
+        limit: usize,
+    ) -> anyhow::Result<Option<FoundLines>> {
+        if query_words(query).is_empty() {
+            return Ok(None);
+        }
+
+        let text_query = if phrase {
+            serde_json::json!({ "match_phrase": { "text": query } })
+        } else {
+            serde_json::json!({ "match": { "text": { "query": query, "operator": "and" } } })
+        };
+        let search = serde_json::json!({
+            "size": limit.min(ELASTICSEARCH_MAX_HITS),
+            "sort": [{ "date": "desc" }],
+            "track_total_hits": true,
+            "query": {
+                "bool": {
+                    "must": [text_query],
+                    "filter": [
+                        { "term": { "channel": channel } },
+                        { "range": { "date": { "gte": dates.start(), "lte": dates.end() } } },
+                    ],
+                }
+            },
+        });
+        let response = self.send(reqwest::Method::POST, "/_search", Some(search))?;
+        let total = response["hits"]["total"]["value"]
+            .as_u64()
+            .unwrap_or_default() as usize;
+        let mut lines = Vec::new();
+
+        for hit in response["hits"]["hits"].as_array().into_iter().flatten() {
+            let document: EngineDocument = serde_json::from_value(hit["_source"].clone())?;
+            lines.push(document.into_result_entry());
+        }
+
+        Ok(Some(FoundLines {
+            incomplete: total > lines.len(),
+            lines,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{query_words, SearchEngine, SqliteEngine};
+
+    #[test]
+    fn sqlite_engine_finds_and_replaces_days() {
+        let directory = tempfile::tempdir().unwrap();
+        let engine = SqliteEngine::open(&directory.path().join("lines.sqlite3")).unwrap();
+        let lines = vec![
+            (1, "[00:01] <alice> hello world".to_string()),
+            (3, "[00:02] <bob> world, hello".to_string()),
+        ];
+        engine
+            .add_day("archiveteam", "2021-01-04,Mon", 100, &lines)
+            .unwrap();
+        engine
+            .add_day("archiveteam", "2021-01-05,Tue", 101, &lines[..1])
+            .unwrap();
+        engine.commit().unwrap();
+
+        let found = |phrase| {
+            engine
+                .find_lines("archiveteam", "Hello world", phrase, 100..=101, 10)
+                .unwrap()
+                .unwrap()
+                .lines
+                .into_iter()
+                .map(|line| (line.date_slug, line.line_number))
+                .collect::<Vec<(String, u64)>>()
+        };
+
+        assert_eq!(found(true).len(), 2);
+        assert_eq!(found(false).len(), 3);
+        assert_eq!(found(false)[0], ("2021-01-05,Tue".to_string(), 1));
+
+        engine.remove_day("archiveteam", "2021-01-04,Mon").unwrap();
+        engine.commit().unwrap();
+
+        assert_eq!(found(false), vec![("2021-01-05,Tue".to_string(), 1)]);
+        assert!(engine
+            .find_lines("archiveteam", "*", false, 100..=101, 10)
+            .unwrap()
+            .is_none());
+        assert_eq!(query_words("Hello, wörld!"), vec!["hello", "wörld"]);
+    }
+}
@@ -0,0 +1,262 @@
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_db::AppDatabase,
+    datafile::{read_json, write_json},
+    search::SearchOptions,
+};
+
+pub const MAX_RECENT_SEARCHES: usize = 20;
+
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SearchParameters {
+    pub channel: String,
+    pub query: String,
+    pub case_sensitive: bool,
+    pub verbatim: bool,
+    pub whole_word: bool,
+    #[serde(default)]
+    pub text_only: bool,
+    #[serde(default)]
+    pub messages_only: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SavedSearch {
+    pub parameters: SearchParameters,
+    pub pinned: bool,
+    pub last_used: DateTime<Utc>,
+    #[serde(default)]
+    pub alert: bool, // New matches are sent to the saved search alert; only kept while pinned
+}
+
+/// Remembers each user's recent searches and the ones they pinned, in the
+/// app database if there is one.
+#[derive(Clone)]
+pub struct SearchHistoryStore {
+    path: Option<PathBuf>,
+    database: Option<AppDatabase>,
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl SearchHistoryStore {
+    pub fn new(data_directory: Option<PathBuf>, database: Option<AppDatabase>) -> Self {
+        Self {
+            path: data_directory.map(|directory| directory.join("search_history.json")),
+            database,
+            write_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.path.is_some() || self.database.is_some()
+    }
+
+    /// Returns the user's searches for a channel, pinned first, most recent first.
+    pub fn get_searches(&self, user: &str, channel: &str) -> anyhow::Result<Vec<SavedSearch>> {
+        if let Some(database) = &self.database {
+            return database.get_searches(user, channel);
+        }
+
+        let mut searches = self
+            .read_all()?
+            .remove(user)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|search| search.parameters.channel == channel)
+            .collect::<Vec<SavedSearch>>();
+
+        searches.sort_by(|a, b| {
+            b.pinned
+                .cmp(&a.pinned)
+                .then_with(|| b.last_used.cmp(&a.last_used))
+        });
+
+        Ok(searches)
+    }
+
+    pub fn record_search(&self, user: &str, parameters: SearchParameters) -> anyhow::Result<()> {
+        if let Some(database) = &self.database {
+            return database.record_search(user, &parameters);
+        }
+
+        let _guard = self.write_lock.lock().unwrap();
+        let mut history = self.read_all()?;
+        let searches = history.entry(user.to_string()).or_default();
+
+        match searches
+            .iter_mut()
+            .find(|search| search.parameters == parameters)
+        {
+            Some(search) => search.last_used = Utc::now(),
+            None => searches.push(SavedSearch {
+                parameters,
+                pinned: false,
+                last_used: Utc::now(),
+                alert: false,
+            }),
+        }
+
+        searches.sort_by_key(|search| Reverse(search.last_used));
+
+        let mut recent_count = 0;
+        searches.retain(|search| {
+            if search.pinned {
+                true
+            } else {
+                recent_count += 1;
+                recent_count <= MAX_RECENT_SEARCHES
+            }
+        });
+
+        self.write_all(&history)
+    }
+
+    pub fn set_pinned(
+        &self,
+        user: &str,
+        parameters: &SearchParameters,
+        pinned: bool,
+    ) -> anyhow::Result<()> {
+        if let Some(database) = &self.database {
+            return database.set_pinned(user, parameters, pinned);
+        }
+
+        self.update(user, |searches| {
+            for search in searches.iter_mut() {
+                if &search.parameters == parameters {
+                    search.pinned = pinned;
+                    search.alert = search.alert && pinned;
+                }
+            }
+        })
+    }
+
+    /// Subscribes a pinned search to alerts of new matches, or unsubscribes it.
+    pub fn set_alert(
+        &self,
+        user: &str,
+        parameters: &SearchParameters,
+        alert: bool,
+    ) -> anyhow::Result<()> {
+        if let Some(database) = &self.database {
+            return database.set_alert(user, parameters, alert);
+        }
+
+        self.update(user, |searches| {
+            for search in searches.iter_mut() {
+                if &search.parameters == parameters {
+                    search.alert = alert && search.pinned;
+                }
+            }
+        })
+    }
+
+    /// Returns every user's pinned searches that have alerts.
+    pub fn get_alert_searches(&self) -> anyhow::Result<Vec<(String, SearchParameters)>> {
+        if let Some(database) = &self.database {
+            return database.get_alert_searches();
+        }
+
+        let mut alert_searches = Vec::new();
+
+        for (user, searches) in self.read_all()? {
+            for search in searches {
+                if search.pinned && search.alert {
+                    alert_searches.push((user.clone(), search.parameters));
+                }
+            }
+        }
+
+        Ok(alert_searches)
+    }
+
+    pub fn remove_search(&self, user: &str, parameters: &SearchParameters) -> anyhow::Result<()> {
+        if let Some(database) = &self.database {
+            return database.remove_search(user, parameters);
+        }
+
+        self.update(user, |searches| {
+            searches.retain(|search| &search.parameters != parameters);
+        })
+    }
+
+    fn update<F>(&self, user: &str, func: F) -> anyhow::Result<()>
+    where
+        F: FnOnce(&mut Vec<SavedSearch>),
+    {
+        let _guard = self.write_lock.lock().unwrap();
+        let mut history = self.read_all()?;
+
+        if let Some(searches) = history.get_mut(user) {
+            func(searches);
+        }
+
+        self.write_all(&history)
+    }
+
+    fn read_all(&self) -> anyhow::Result<HashMap<String, Vec<SavedSearch>>> {
+        match &self.path {
+            Some(path) => read_json(path),
+            None => Ok(HashMap::new()),
+        }
+    }
+
+    fn write_all(&self, history: &HashMap<String, Vec<SavedSearch>>) -> anyhow::Result<()> {
+        let path = self
+            .path
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Search history is not enabled"))?;
+
+        write_json(path, history)
+    }
+}
+
+impl SearchParameters {
+    pub fn to_query_string(&self) -> String {
+        let mut query_string = format!(
+            "search={}",
+            percent_encoding::utf8_percent_encode(&self.query, percent_encoding::NON_ALPHANUMERIC)
+        );
+
+        if self.case_sensitive {
+            query_string.push_str("&case=on");
+        }
+
+        if self.verbatim {
+            query_string.push_str("&verbatim=on");
+        }
+
+        if self.whole_word {
+            query_string.push_str("&word=on");
+        }
+
+        if self.text_only {
+            query_string.push_str("&text=on");
+        }
+
+        if self.messages_only {
+            query_string.push_str("&messages_only=on");
+        }
+
+        query_string
+    }
+
+    pub fn to_options(&self) -> SearchOptions {
+        SearchOptions {
+            case_sensitive: self.case_sensitive,
+            verbatim: self.verbatim,
+            whole_word: self.whole_word,
+            text_only: self.text_only,
+            messages_only: self.messages_only,
+        }
+    }
+}
@@ -0,0 +1,594 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use chrono::Datelike;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    search::{QueryMatcher, SearchMatches, SearchOptions},
+    search_engine::{SearchEngine, SearchEngineKind},
+    state::AppState,
+};
+
+const MAX_CANDIDATES: usize = 10000; // Lines fetched from the index to check against the query
+const INDEXED_DAYS_FILENAME: &str = "indexed_days.json";
+const NICKNAMES_FILENAME: &str = "nicknames.json";
+
+#[derive(Clone, Deserialize)]
+pub struct SearchIndexConfig {
+    pub directory: PathBuf, // Writable directory for the index files and the list of indexed days
+    #[serde(default = "default_schedule")]
+    pub schedule: String, // Cron-like schedule of index updates
+    #[serde(default)]
+    pub engine: SearchEngineKind, // "tantivy", "sqlite", "meilisearch" or "elasticsearch"
+    pub engine_url: Option<String>, // Base URL of the MeiliSearch or Elasticsearch server
+    pub engine_api_key: Option<String>, // Sent as a bearer token to MeiliSearch or an API key to Elasticsearch
+    #[serde(default = "default_engine_index")]
+    pub engine_index: String, // Index name on the MeiliSearch or Elasticsearch server
+}
+
+fn default_schedule() -> String {
+    "* * * * *".to_string()
+}
+
+fn default_engine_index() -> String {
+    "irclogger".to_string()
+}
+
+/// A nickname that sent messages in a channel's indexed days.
+#[derive(Clone, Deserialize, Serialize)]
+struct KnownNickname {
+    nickname: String,  // As written on the day it was last seen
+    last_seen: String, // Date slug
+}
+
+/// Full-text index of log lines, kept in one of the search engines, and the
+/// nicknames of each channel.
+///
+/// The index matches whole words, so it only answers queries without
+/// operators or regular expressions. Days are tracked by their log ETag and
+/// searched by scanning until the next update indexes them.
+pub struct SearchIndex {
+    engine: Box<dyn SearchEngine>,
+    directory: PathBuf,
+    indexed_days: Mutex<HashMap<String, HashMap<String, String>>>, // Channel to date slug to ETag
+    nicknames: Mutex<HashMap<String, HashMap<String, KnownNickname>>>, // Channel to lowercase nickname
+    writer_lock: Mutex<()>, // Held while updating, since engines take one batch of changes at a time
+}
+
+impl SearchIndex {
+    pub fn open(config: &SearchIndexConfig) -> anyhow::Result<Self> {
+        let directory = &config.directory;
+
+        std::fs::create_dir_all(directory)?;
+
+        let engine = crate::search_engine::open_engine(config)?;
+        let nicknames = match std::fs::read(directory.join(NICKNAMES_FILENAME)) {
+            Ok(content) => Some(serde_json::from_slice(&content)?),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => None,
+            Err(error) => return Err(error.into()),
+        };
+        // Days indexed before nicknames were kept are indexed again for theirs
+        let (indexed_days, nicknames) = match nicknames {
+            Some(nicknames) => match std::fs::read(directory.join(INDEXED_DAYS_FILENAME)) {
+                Ok(content) => (serde_json::from_slice(&content)?, nicknames),
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                    (HashMap::new(), HashMap::new())
+                }
+                Err(error) => return Err(error.into()),
+            },
+            None => (HashMap::new(), HashMap::new()),
+        };
+
+        Ok(Self {
+            engine,
+            directory: directory.clone(),
+            indexed_days: Mutex::new(indexed_days),
+            nicknames: Mutex::new(nicknames),
+            writer_lock: Mutex::new(()),
+        })
+    }
+
+    /// Returns whether the day was indexed with the log's current ETag.
+    pub fn is_current(&self, channel: &str, date_slug: &str, etag: &str) -> bool {
+        self.indexed_days
+            .lock()
+            .unwrap()
+            .get(channel)
+            .and_then(|days| days.get(date_slug))
+            .is_some_and(|indexed_etag| indexed_etag == etag)
+    }
+
+    /// Returns nicknames of the channel starting with the prefix, compared
+    /// case insensitively, most recently seen first. Returns `None` if the
+    /// channel isn't indexed.
+    pub fn find_nicknames(&self, channel: &str, prefix: &str, limit: usize) -> Option<Vec<String>> {
+        let nicknames = self.nicknames.lock().unwrap();
+        let prefix = prefix.to_lowercase();
+        let mut found = nicknames
+            .get(channel)?
+            .iter()
+            .filter(|(key, _)| key.starts_with(&prefix))
+            .map(|(_, known)| known)
+            .collect::<Vec<&KnownNickname>>();
+
+        found.sort_by(|a, b| {
+            b.last_seen
+                .cmp(&a.last_seen)
+                .then_with(|| a.nickname.cmp(&b.nickname))
+        });
+
+        Some(
+            found
+                .into_iter()
+                .take(limit)
+                .map(|known| known.nickname.clone())
+                .collect(),
+        )
+    }
+
+    /// Returns whether the nickname sent messages in the channel's indexed
+    /// days, or `None` if the channel isn't indexed.
+    pub fn is_known_nickname(&self, channel: &str, nickname: &str) -> Option<bool> {
+        self.nicknames
+            .lock()
+            .unwrap()
+            .get(channel)
+            .map(|nicknames| nicknames.contains_key(&nickname.to_lowercase()))
+    }
+
+    /// Searches the given indexed days, newest first, keeping the first
+    /// `limit` matching lines. Returns `None` if the query can't be answered
+    /// by the index.
+    pub fn search(
+        &self,
+        channel: &str,
+        date_slugs: &[String],
+        query: &str,
+        options: SearchOptions,
+        limit: usize,
+    ) -> anyhow::Result<Option<SearchMatches>> {
+        if !is_query_indexable(query, options) || date_slugs.is_empty() {
+            return Ok(None);
+        }
+
+        let mut dates = Vec::new();
+
+        for date_slug in date_slugs {
+            dates.push(date_value(crate::state::parse_date_slug(date_slug)?));
+        }
+
+        let min_date = dates.iter().copied().min().unwrap();
+        let max_date = dates.iter().copied().max().unwrap();
+        let found = match self.engine.find_lines(
+            channel,
+            query,
+            options.verbatim,
+            min_date..=max_date,
+            MAX_CANDIDATES,
+        )? {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        let date_slugs = date_slugs.iter().collect::<HashSet<&String>>();
+        let matcher = QueryMatcher::new(query, options)?;
+        let mut search_results = found
+            .lines
+            .into_iter()
+            .filter(|line| date_slugs.contains(&line.date_slug) && matcher.is_match(&line.raw_line))
+            .collect::<Vec<_>>();
+
+        search_results.sort_by(|a, b| {
+            b.date_slug
+                .cmp(&a.date_slug)
+                .then(a.line_number.cmp(&b.line_number))
+        });
+
+        let total = search_results.len();
+        search_results.truncate(limit);
+
+        Ok(Some(SearchMatches {
+            entries: search_results,
+            total,
+            incomplete: found.incomplete,
+        }))
+    }
+
+    /// Indexes days that are new or changed since the last update and
+    /// removes days whose logs were deleted.
+    pub fn update(&self, app_state: &AppState) -> anyhow::Result<()> {
+        let _writer_guard = self.writer_lock.lock().unwrap();
+        let mut indexed_days = self.indexed_days.lock().unwrap().clone();
+        let mut nicknames = self.nicknames.lock().unwrap().clone();
+        let mut changed = false;
+        let mut channel_names = HashSet::new();
+
+        for channel in app_state.get_channels()? {
+            if !app_state.get_channel_metadata(&channel.name)?.searchable {
+                continue;
+            }
+
+            let date_slugs = app_state.get_channel_log_date_slugs(&channel.name)?;
+            let days = indexed_days.entry(channel.name.clone()).or_default();
+            let channel_nicknames = nicknames.entry(channel.name.clone()).or_default();
+            let removed_slugs = days
+                .keys()
+                .filter(|date_slug| !date_slugs.contains(date_slug))
+                .cloned()
+                .collect::<Vec<String>>();
+
+            for date_slug in removed_slugs {
+                self.engine.remove_day(&channel.name, &date_slug)?;
+                days.remove(&date_slug);
+                changed = true;
+            }
+
+            for date_slug in date_slugs {
+                let etag = app_state.get_log_etag(&channel.name, &date_slug)?;
+
+                if days.get(&date_slug) == Some(&etag) {
+                    continue;
+                }
+
+                self.index_day(app_state, &channel.name, &date_slug, channel_nicknames)?;
+                days.insert(date_slug, etag);
+                changed = true;
+            }
+
+            channel_names.insert(channel.name);
+        }
+
+        let removed_channels = indexed_days
+            .keys()
+            .filter(|channel| !channel_names.contains(*channel))
+            .cloned()
+            .collect::<Vec<String>>();
+
+        for channel in removed_channels {
+            self.engine.remove_channel(&channel)?;
+            indexed_days.remove(&channel);
+            nicknames.remove(&channel);
+            changed = true;
+        }
+
+        if !changed {
+            return Ok(());
+        }
+
+        self.commit(indexed_days, nicknames)
+    }
+
+    /// Indexes one day again, or removes it if its log is gone, without
+    /// looking at the channel's other days.
+    pub fn update_day(
+        &self,
+        app_state: &AppState,
+        channel: &str,
+        date_slug: &str,
+    ) -> anyhow::Result<()> {
+        let _writer_guard = self.writer_lock.lock().unwrap();
+        let mut indexed_days = self.indexed_days.lock().unwrap().clone();
+        let mut nicknames = self.nicknames.lock().unwrap().clone();
+        let days = indexed_days.entry(channel.to_string()).or_default();
+        let channel_nicknames = nicknames.entry(channel.to_string()).or_default();
+        let is_indexed = app_state.get_channel_metadata(channel)?.searchable
+            && app_state.get_log_path(channel, date_slug)?.is_file();
+
+        if is_indexed {
+            let etag = app_state.get_log_etag(channel, date_slug)?;
+
+            if days.get(date_slug) == Some(&etag) {
+                return Ok(());
+            }
+
+            self.index_day(app_state, channel, date_slug, channel_nicknames)?;
+            days.insert(date_slug.to_string(), etag);
+        } else if days.remove(date_slug).is_some() {
+            self.engine.remove_day(channel, date_slug)?;
+        } else {
+            return Ok(());
+        }
+
+        self.commit(indexed_days, nicknames)
+    }
+
+    /// Replaces the day's lines in the index with those of its log, and adds
+    /// the nicknames that sent messages that day.
+    fn index_day(
+        &self,
+        app_state: &AppState,
+        channel: &str,
+        date_slug: &str,
+        nicknames: &mut HashMap<String, KnownNickname>,
+    ) -> anyhow::Result<()> {
+        let date = date_value(crate::state::parse_date_slug(date_slug)?);
+        let raw_log = app_state.get_raw_log(channel, date_slug)?;
+        let lines = crate::reader::read_raw_lines(raw_log.as_slice())?
+            .into_iter()
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(index, line)| (index as u64 + 1, line))
+            .collect::<Vec<(u64, String)>>();
+
+        self.engine.remove_day(channel, date_slug)?;
+        self.engine.add_day(channel, date_slug, date, &lines)?;
+
+        // Days the parser can't read are still searchable, without nicknames
+        for line in app_state
+            .get_log_lines(channel, date_slug)
+            .unwrap_or_default()
+        {
+            let nickname = match crate::reader::message_sender(&line.content) {
+                Some(nickname) if !nickname.is_empty() => nickname,
+                _ => continue,
+            };
+            let known = nicknames
+                .entry(nickname.to_lowercase())
+                .or_insert_with(|| KnownNickname {
+                    nickname: nickname.to_string(),
+                    last_seen: date_slug.to_string(),
+                });
+
+            if known.last_seen.as_str() <= date_slug {
+                known.nickname = nickname.to_string();
+                known.last_seen = date_slug.to_string();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn commit(
+        &self,
+        indexed_days: HashMap<String, HashMap<String, String>>,
+        nicknames: HashMap<String, HashMap<String, KnownNickname>>,
+    ) -> anyhow::Result<()> {
+        self.engine.commit()?;
+
+        // Written first, so a failed write of the days indexes them again
+        std::fs::write(
+            self.directory.join(NICKNAMES_FILENAME),
+            serde_json::to_vec(&nicknames)?,
+        )?;
+        *self.nicknames.lock().unwrap() = nicknames;
+
+        std::fs::write(
+            self.directory.join(INDEXED_DAYS_FILENAME),
+            serde_json::to_vec(&indexed_days)?,
+        )?;
+        *self.indexed_days.lock().unwrap() = indexed_days;
+
+        Ok(())
+    }
+}
+
+/// Whether the index can answer a query, which it can't if the query has
+/// operators or regular expressions.
+pub fn is_query_indexable(query: &str, options: SearchOptions) -> bool {
+    let has_operators = query.contains([',', ';']);
+
+    options.verbatim || (!has_operators && regex::escape(query) == query)
+}
+
+/// Days since the common era, which sort like the dates.
+fn date_value(date: chrono::Date<chrono::Utc>) -> u64 {
+    date.num_days_from_ce().max(0) as u64
+}
+
+pub async fn update_search_index(app_state: &AppState) -> anyhow::Result<()> {
+    match &app_state.search_index {
+        Some(search_index) => tokio::task::block_in_place(|| search_index.update(app_state)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{search::SearchOptions, state::AppState};
+
+    fn build_app_state(directory: &Path) -> AppState {
+        let mut value = crate::config::minimal_config_value(&directory.join("log"), directory);
+        value["search_index"] = serde_json::json!({ "directory": directory.join("index") });
+        let config = crate::config::config_from_value(value).unwrap();
+
+        crate::app::build_app_state(&config)
+    }
+
+    fn write_log(directory: &Path, date_slug: &str, content: &str) {
+        let channel_dir = directory.join("log").join("archiveteam");
+        std::fs::create_dir_all(&channel_dir).unwrap();
+        std::fs::write(channel_dir.join(format!("{}.log", date_slug)), content).unwrap();
+    }
+
+    async fn search(app_state: &AppState, query: &str, whole_word: bool) -> Vec<(String, u64)> {
+        app_state
+            .search_channel(
+                "archiveteam",
+                query,
+                SearchOptions {
+                    case_sensitive: false,
+                    verbatim: false,
+                    whole_word,
+                    text_only: false,
+                    messages_only: false,
+                },
+                None,
+                None,
+                usize::MAX,
+            )
+            .await
+            .unwrap()
+            .entries
+            .into_iter()
+            .map(|entry| (entry.date_slug, entry.line_number))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn indexed_and_unindexed_days_are_searched() {
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::write(directory.path().join("htpasswd"), "").unwrap();
+        write_log(
+            directory.path(),
+            "2021-01-04,Mon",
+            "[00:01] <alice> hello world\n\n[00:02] <bob> Hello there\n",
+        );
+
+        let app_state = build_app_state(directory.path());
+        let search_index = app_state.search_index.clone().unwrap();
+        search_index.update(&app_state).unwrap();
+
+        write_log(
+            directory.path(),
+            "2021-01-05,Tue",
+            "[00:03] <carol> hello again\n",
+        );
+
+        assert_eq!(
+            search(&app_state, "hello", false).await,
+            vec![
+                ("2021-01-05,Tue".to_string(), 1),
+                ("2021-01-04,Mon".to_string(), 1),
+                ("2021-01-04,Mon".to_string(), 3),
+            ]
+        );
+
+        search_index.update(&app_state).unwrap();
+
+        assert!(search_index.is_current(
+            "archiveteam",
+            "2021-01-05,Tue",
+            &app_state
+                .get_log_etag("archiveteam", "2021-01-05,Tue")
+                .unwrap()
+        ));
+        assert_eq!(
+            search(&app_state, "hello", true).await,
+            vec![
+                ("2021-01-05,Tue".to_string(), 1),
+                ("2021-01-04,Mon".to_string(), 1),
+                ("2021-01-04,Mon".to_string(), 3),
+            ]
+        );
+        assert_eq!(
+            search(&app_state, "world", false).await,
+            vec![("2021-01-04,Mon".to_string(), 1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn deleted_days_are_removed() {
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::write(directory.path().join("htpasswd"), "").unwrap();
+        write_log(
+            directory.path(),
+            "2021-01-04,Mon",
+            "[00:01] <alice> hello\n",
+        );
+        write_log(
+            directory.path(),
+            "2021-01-05,Tue",
+            "[00:01] <alice> hello\n",
+        );
+
+        let app_state = build_app_state(directory.path());
+        let search_index = app_state.search_index.clone().unwrap();
+        search_index.update(&app_state).unwrap();
+
+        std::fs::remove_file(
+            directory
+                .path()
+                .join("log")
+                .join("archiveteam")
+                .join("2021-01-04,Mon.log"),
+        )
+        .unwrap();
+        search_index.update(&app_state).unwrap();
+
+        assert_eq!(
+            search(&app_state, "hello", false).await,
+            vec![("2021-01-05,Tue".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn single_days_are_updated() {
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::write(directory.path().join("htpasswd"), "").unwrap();
+        write_log(
+            directory.path(),
+            "2021-01-04,Mon",
+            "[00:01] <alice> hello\n",
+        );
+
+        let app_state = build_app_state(directory.path());
+        let search_index = app_state.search_index.clone().unwrap();
+        search_index
+            .update_day(&app_state, "archiveteam", "2021-01-04,Mon")
+            .unwrap();
+        let etag = app_state
+            .get_log_etag("archiveteam", "2021-01-04,Mon")
+            .unwrap();
+
+        assert!(search_index.is_current("archiveteam", "2021-01-04,Mon", &etag));
+
+        std::fs::remove_file(
+            directory
+                .path()
+                .join("log")
+                .join("archiveteam")
+                .join("2021-01-04,Mon.log"),
+        )
+        .unwrap();
+        search_index
+            .update_day(&app_state, "archiveteam", "2021-01-04,Mon")
+            .unwrap();
+
+        assert!(!search_index.is_current("archiveteam", "2021-01-04,Mon", &etag));
+    }
+
+    #[test]
+    fn nicknames_are_found_by_prefix() {
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::write(directory.path().join("htpasswd"), "").unwrap();
+        write_log(
+            directory.path(),
+            "2021-01-04,Mon",
+            "[00:01] <alice> hello\n[00:02] <Albert> hi\n[00:03] <bob> hey\n",
+        );
+        write_log(
+            directory.path(),
+            "2021-01-05,Tue",
+            "[00:01] <Alice> hello again\n",
+        );
+
+        let app_state = build_app_state(directory.path());
+        let search_index = app_state.search_index.clone().unwrap();
+
+        assert_eq!(search_index.find_nicknames("archiveteam", "al", 10), None);
+
+        search_index.update(&app_state).unwrap();
+
+        assert_eq!(
+            search_index.find_nicknames("archiveteam", "AL", 10),
+            Some(vec!["Alice".to_string(), "Albert".to_string()])
+        );
+        assert_eq!(
+            search_index.find_nicknames("archiveteam", "", 1),
+            Some(vec!["Alice".to_string()])
+        );
+        assert_eq!(
+            search_index.is_known_nickname("archiveteam", "BOB"),
+            Some(true)
+        );
+        assert_eq!(
+            search_index.is_known_nickname("archiveteam", "carol"),
+            Some(false)
+        );
+    }
+}
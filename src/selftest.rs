@@ -0,0 +1,204 @@
+use std::{
+    collections::HashMap,
+    net::Ipv4Addr,
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use reqwest::StatusCode;
+use serde::Serialize;
+
+use crate::{
+    access_log::AccessLogMiddleware, ip_filter::IpFilterMiddleware, search::SearchBackend,
+    tenant::TenantMiddleware,
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const CHANNEL: &str = "selftest";
+const PRIVATE_CHANNEL: &str = "selftestprivate";
+const DATE_SLUG: &str = "2021-01-04,Mon";
+const MARKER: &str = "selftestmarker";
+
+static RUN_COUNT: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Serialize)]
+pub struct SelfTestReport {
+    pub passed: bool,
+    pub stages: Vec<SelfTestStage>,
+}
+
+#[derive(Serialize)]
+pub struct SelfTestStage {
+    pub name: &'static str,
+    pub passed: bool,
+    pub detail: String, // What went wrong, empty when passed
+}
+
+/// Serves a synthetic site with known logs on a free local port and
+/// requests its pages through the same router, middleware and handlers as
+/// the real site, checking each response.
+pub async fn run_self_test(search_backend: SearchBackend) -> anyhow::Result<SelfTestReport> {
+    let directory = std::env::temp_dir().join(format!(
+        "irclogger-viewer-selftest-{}-{}",
+        std::process::id(),
+        RUN_COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = run_stages(&directory, search_backend).await;
+    let _ = std::fs::remove_dir_all(&directory);
+
+    let stages = result?;
+
+    Ok(SelfTestReport {
+        passed: stages.iter().all(|stage| stage.passed),
+        stages,
+    })
+}
+
+async fn run_stages(
+    directory: &Path,
+    search_backend: SearchBackend,
+) -> anyhow::Result<Vec<SelfTestStage>> {
+    let log_content = format!("[00:01] <alice> hello {}\n[00:02] <bob> bye\n", MARKER);
+    let log_directory = directory.join("log");
+    std::fs::create_dir_all(log_directory.join(CHANNEL))?;
+    std::fs::create_dir_all(log_directory.join(PRIVATE_CHANNEL))?;
+    std::fs::write(
+        log_directory
+            .join(CHANNEL)
+            .join(format!("{}.log", DATE_SLUG)),
+        &log_content,
+    )?;
+    std::fs::write(
+        log_directory
+            .join(PRIVATE_CHANNEL)
+            .join(format!("{}.log", DATE_SLUG)),
+        &log_content,
+    )?;
+    // Only listed so the channel is private; no login is attempted
+    std::fs::write(
+        directory.join("passwords"),
+        format!("{}:!\n", PRIVATE_CHANNEL),
+    )?;
+    std::fs::write(directory.join("message.html"), "")?;
+
+    let config = crate::config::config_from_value(serde_json::json!({
+        "chat_log_directory": log_directory,
+        "apache_password_file": directory.join("passwords"),
+        "custom_message_html_file": directory.join("message.html"),
+        "web_server_port_number": 0,
+    }))?;
+    let mut app_state = crate::app::build_app_state(&config);
+    app_state.search_backend = search_backend;
+
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()?;
+    let router = crate::app::build_routes(
+        TenantMiddleware::new(app_state, HashMap::new()),
+        AccessLogMiddleware::new(None),
+        IpFilterMiddleware::default(),
+    );
+    let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+    let base_url = format!("http://{}", listener.local_addr()?);
+    let server = tokio::spawn(gotham::bind_server(
+        listener,
+        router,
+        futures_util::future::ok,
+    ));
+
+    let day_url = format!("/bin/irclogger_log/{}?date={}", CHANNEL, DATE_SLUG);
+    let search_url = format!(
+        "/bin/irclogger_log_search/{}?search={}&action=search",
+        CHANNEL, MARKER
+    );
+    let raw_url = format!("{}&raw=on", day_url);
+    let private_url = format!("/bin/irclogger_logs/{}", PRIVATE_CHANNEL);
+
+    let stages = vec![
+        check(
+            &client,
+            &base_url,
+            "index",
+            "/bin/irclogger_logs",
+            |status, body| {
+                expect_status(status, StatusCode::OK)?;
+                expect_text(body, CHANNEL)
+            },
+        )
+        .await,
+        check(&client, &base_url, "day", &day_url, |status, body| {
+            expect_status(status, StatusCode::OK)?;
+            expect_text(body, MARKER)
+        })
+        .await,
+        check(&client, &base_url, "search", &search_url, |status, body| {
+            expect_status(status, StatusCode::OK)?;
+            expect_text(body, &format!("hello {}", MARKER))
+        })
+        .await,
+        check(&client, &base_url, "raw", &raw_url, |status, body| {
+            expect_status(status, StatusCode::OK)?;
+
+            if body == log_content {
+                Ok(())
+            } else {
+                Err("raw log differs from the file".to_string())
+            }
+        })
+        .await,
+        check(&client, &base_url, "auth", &private_url, |status, _| {
+            expect_status(status, StatusCode::UNAUTHORIZED)
+        })
+        .await,
+    ];
+
+    server.abort();
+
+    Ok(stages)
+}
+
+async fn check<F>(
+    client: &reqwest::Client,
+    base_url: &str,
+    name: &'static str,
+    path: &str,
+    check_response: F,
+) -> SelfTestStage
+where
+    F: FnOnce(StatusCode, &str) -> Result<(), String>,
+{
+    let result = match client.get(format!("{}{}", base_url, path)).send().await {
+        Ok(response) => {
+            let status = response.status();
+
+            match response.text().await {
+                Ok(body) => check_response(status, &body),
+                Err(error) => Err(error.to_string()),
+            }
+        }
+        Err(error) => Err(error.to_string()),
+    };
+
+    SelfTestStage {
+        name,
+        passed: result.is_ok(),
+        detail: result.err().unwrap_or_default(),
+    }
+}
+
+fn expect_status(status: StatusCode, expected: StatusCode) -> Result<(), String> {
+    if status == expected {
+        Ok(())
+    } else {
+        Err(format!("status {} instead of {}", status, expected))
+    }
+}
+
+fn expect_text(body: &str, text: &str) -> Result<(), String> {
+    if body.contains(text) {
+        Ok(())
+    } else {
+        Err(format!("response doesn't contain {:?}", text))
+    }
+}
@@ -1,25 +1,60 @@
 use std::{
-    ffi::OsStr,
-    io::{BufRead, BufReader, Cursor},
+    cmp::Reverse,
+    collections::HashMap,
+    io::Read,
     path::PathBuf,
+    sync::Arc,
+    time::{Duration as StdDuration, SystemTime},
 };
 
-use chrono::{Date, NaiveDate, Utc};
-use encoding_rs_io::DecodeReaderBytesBuilder;
+use chrono::{Date, Duration, Locale, NaiveDate, Utc};
+use chrono_tz::Tz;
 use gotham_derive::StateData;
+use serde::{Serialize, Serializer};
+use sha2::{Digest, Sha256};
 
-use crate::reader::LogLine;
+use crate::{
+    access_audit::AccessAudit,
+    annotation::AnnotationStore,
+    bookmark::BookmarkStore,
+    cache::{LruMap, TimedCache, TimedMap},
+    config::{ColorTheme, Density, SearchDefaults},
+    count_file::{CountFile, COUNT_FILE_NAME},
+    day_cache::{DayCache, DaySummary},
+    hidden_channel::HiddenChannelStore,
+    hydrate::LogHydrator,
+    message::MessageSources,
+    metadata::ChannelMetadata,
+    page_template::PageTemplates,
+    parse_report::ParseProblemReport,
+    preferences::PreferenceSigner,
+    preview::LinkPreviewer,
+    proxy::ImageProxy,
+    ratelimit::{IpNetwork, RateLimiter},
+    reader::LogLine,
+    scheduler::SchedulerStatus,
+    script_parser::{ScriptParser, ScriptParserCache},
+    search::{SearchBackend, SearchBudget, SearchMatches, SearchOptions},
+    search_history::SearchHistoryStore,
+    search_index::SearchIndex,
+    stats::PublicStatsConfig,
+    tombstone::{parse_removed_days, RemovedDay},
+};
 
+#[derive(Serialize)]
 pub struct ChannelInfo {
     pub name: String,
     pub is_private: bool,
 }
 
-#[derive(PartialEq, Eq, PartialOrd, Ord)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Serialize)]
 pub struct ChannelDailyEntry {
     pub date_slug: String,
+    #[serde(serialize_with = "serialize_date")]
     pub date: Date<Utc>,
     pub message_count: u64,
+    pub first_message: Option<String>,
+    pub last_message: Option<String>,
 }
 
 #[derive(Clone, StateData)]
@@ -27,6 +62,111 @@ pub struct AppState {
     pub chat_log_directory: PathBuf,
     pub apache_password_file: PathBuf,
     pub custom_message_html_file: PathBuf,
+    pub message_sources: MessageSources, // Parts of the homepage message besides the site's file
+    pub theme_file: Option<PathBuf>,
+    pub static_directory: Option<PathBuf>, // Overrides the built-in static files
+    pub page_templates: Option<PageTemplates>, // Override built-in pages
+    pub widget_embed_origins: Vec<String>, // Allowed to frame the widget, which is off without any
+    pub public_url: Option<String>,        // Base of absolute links; else links are relative
+    pub path_prefix: String,               // Start of site links, such as "/irc"; empty at the root
+    pub link_previewer: LinkPreviewer,
+    pub image_proxy: ImageProxy,
+    pub mirror_upstream_url: Option<String>,
+    pub mirror_max_day_size: usize, // Bytes
+    pub annotation_store: AnnotationStore,
+    pub bookmark_store: BookmarkStore,
+    pub search_history_store: SearchHistoryStore,
+    pub saved_search_alerts: bool, // Pinned searches can be subscribed to alerts
+    pub hidden_channels: HiddenChannelStore,
+    pub access_audit: AccessAudit,
+    pub channel_activity_cache: TimedCache<Vec<ChannelActivity>>,
+    pub archive_summary_cache: TimedCache<ArchiveSummary>,
+    pub channel_privacy_cache: TimedMap<String, bool>,
+    pub credential_cache: TimedMap<Vec<u8>, ()>, // SHA-256 of user and password that matched
+    pub admin_users: Vec<String>,
+    pub scheduler_status: SchedulerStatus,
+    pub slow_request_threshold: Option<StdDuration>,
+    pub search_defaults: SearchDefaults,
+    pub search_budget: Option<SearchBudget>,
+    pub preference_signer: PreferenceSigner,
+    pub search_backend: SearchBackend,
+    pub search_index: Option<Arc<SearchIndex>>,
+    pub day_density: Density,
+    pub color_theme: ColorTheme,
+    pub day_view_page_lines: usize,
+    pub past_day_max_age: StdDuration,
+    pub day_json_files: bool,
+    pub count_status_lines: bool,
+    pub lenient_parsing: bool,
+    pub parse_problems: ParseProblemReport, // Filled in lenient parsing mode
+    pub parsed_day_cache: LruMap<ParsedDayKey, Arc<Vec<LogLine>>>,
+    pub script_parsers: ScriptParserCache,
+    pub locale: Locale, // Used when the client's languages have no locale data
+    pub timezone: Tz,   // Used when the viewer hasn't chosen a timezone
+    pub day_cache: Option<DayCache>,
+    pub channel_count_files: bool,         // Used without a day cache
+    pub log_hydrator: Option<LogHydrator>, // Copies logs off slow storage
+    pub public_stats: Option<PublicStatsConfig>,
+    pub demo_mode: bool,
+    pub rate_limiter: Option<RateLimiter>,
+    pub search_rate_limiter: Option<RateLimiter>, // Applies to search pages on top of rate_limiter
+    pub trusted_proxies: Vec<IpNetwork>,
+}
+
+/// Identifies a parsed day by its log file's modification time and size,
+/// which change as the logger appends to it.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct ParsedDayKey {
+    channel: String,
+    date_slug: String,
+    modified: SystemTime,
+    len: u64,
+}
+
+/// Totals over every listed channel, shown on the front page.
+pub struct ArchiveSummary {
+    pub channel_count: usize,
+    pub day_count: usize,
+    pub line_count: u64,
+    pub oldest_date: Option<Date<Utc>>,
+    pub newest_date: Option<Date<Utc>>,
+}
+
+/// A nickname's activity in a channel, shown when hovering over the
+/// nickname in the day view.
+#[derive(Serialize)]
+pub struct NickCard {
+    pub nickname: String,
+    pub message_count: u64, // On the requested day
+    #[serde(serialize_with = "serialize_optional_date")]
+    pub first_seen: Option<Date<Utc>>, // Days with a message from the nickname
+    #[serde(serialize_with = "serialize_optional_date")]
+    pub last_seen: Option<Date<Utc>>,
+}
+
+pub struct ChannelActivity {
+    pub name: String,
+    pub active_since: Date<Utc>,
+    pub last_date: Date<Utc>,
+    pub resumed: bool,
+}
+
+const CHANNEL_INACTIVITY_DAYS: i64 = 90;
+
+pub struct ContinuousChunk {
+    pub lines: Vec<ContinuousLine>,
+    pub next: Option<ContinuousPosition>,
+}
+
+pub struct ContinuousLine {
+    pub date_slug: String,
+    pub line_number: u64,
+    pub line: LogLine,
+}
+
+pub struct ContinuousPosition {
+    pub date_slug: String,
+    pub line_number: u64,
 }
 
 pub struct SearchResultEntry {
@@ -38,14 +178,28 @@ pub struct SearchResultEntry {
 impl AppState {
     pub fn get_channels(&self) -> anyhow::Result<Vec<ChannelInfo>> {
         let mut channels = Vec::new();
+        let hidden_channels = self.hidden_channels.get_hidden_channels()?;
         let dirs = std::fs::read_dir(&self.chat_log_directory)?;
 
         for entry in dirs {
             let entry = entry?;
             if entry.metadata()?.is_dir() {
                 if let Ok(filename) = entry.file_name().into_string() {
+                    if hidden_channels
+                        .iter()
+                        .any(|hidden| hidden.channel == filename)
+                    {
+                        continue;
+                    }
+
+                    let is_private = self.is_channel_private(&filename)?;
+
+                    if is_private && self.demo_mode {
+                        continue;
+                    }
+
                     channels.push(ChannelInfo {
-                        is_private: self.is_channel_private(&filename)?,
+                        is_private,
                         name: filename,
                     });
                 }
@@ -57,8 +211,14 @@ impl AppState {
         Ok(channels)
     }
 
+    /// Whether a channel needs a password. Both answers are remembered for a
+    /// minute, since every page of a channel checks.
     pub fn is_channel_private(&self, name: &str) -> anyhow::Result<bool> {
-        Ok(!self.is_channel_marked_public(name) && self.is_channel_in_password_file(name)?)
+        self.channel_privacy_cache
+            .get_or_try_insert_with(name.to_string(), || {
+                Ok(!self.is_channel_marked_public(name)
+                    && self.is_channel_in_password_file(name)?)
+            })
     }
 
     pub fn is_channel_marked_public(&self, name: &str) -> bool {
@@ -67,72 +227,386 @@ impl AppState {
         public_path.is_file()
     }
 
+    pub fn get_channel_metadata(&self, name: &str) -> anyhow::Result<ChannelMetadata> {
+        let metadata_path = self.chat_log_directory.join(name).join("METADATA");
+
+        if !metadata_path.is_file() {
+            return Ok(ChannelMetadata::default());
+        }
+
+        ChannelMetadata::parse(&std::fs::read_to_string(metadata_path)?)
+    }
+
     pub fn is_channel_in_password_file(&self, name: &str) -> anyhow::Result<bool> {
         let content = std::fs::read_to_string(&self.apache_password_file)?;
 
-        for line in content.split('\n') {
-            if line.starts_with('#') {
-                // Despite the bash script saving both unprefixed and prefixed
-                // channel names, it's ultimately treated as a comment...
-                continue;
-            } else if let Some((candidate_name, _)) = line.split_once(":") {
-                if name == candidate_name {
-                    return Ok(true);
-                }
-            }
-        }
+        let found = password_file_users(&content).any(|candidate_name| name == candidate_name);
 
-        Ok(false)
+        Ok(found)
     }
 
+    /// Checks a password against the password file. Passwords that match are
+    /// remembered by digest for a minute, so a logged in reader's requests
+    /// don't each read the file and verify a slow hash.
     pub fn is_password_ok(&self, channel_name: &str, password: &str) -> anyhow::Result<bool> {
-        let passwords = std::fs::read_to_string(&self.apache_password_file)?;
-        let passwords = htpasswd_verify::load(&passwords);
+        let digest = Sha256::new()
+            .chain(channel_name)
+            .chain([0])
+            .chain(password)
+            .finalize()
+            .to_vec();
 
-        Ok(passwords.check(channel_name, password))
+        if self.credential_cache.get(&digest).is_some() {
+            return Ok(true);
+        }
+
+        let content = std::fs::read_to_string(&self.apache_password_file)?;
+        let passwords = htpasswd_verify::load(&content);
+        let known_user = password_file_users(&content).fold(None, |found, user| {
+            if constant_time_eq(user.as_bytes(), channel_name.as_bytes()) {
+                Some(user)
+            } else {
+                found
+            }
+        });
+
+        // An unknown user's password is still hashed, against another user's
+        // entry, so that it takes as long to reject as a wrong password
+        let password_ok = match known_user {
+            Some(user) => passwords.check(user, password),
+            None => {
+                if let Some(other_user) = password_file_users(&content).next() {
+                    passwords.check(other_user, password);
+                }
+
+                false
+            }
+        };
+
+        if password_ok {
+            self.credential_cache.insert(digest, ());
+        }
+
+        Ok(password_ok)
     }
 
     pub fn get_channel_daily_entries(&self, name: &str) -> anyhow::Result<Vec<ChannelDailyEntry>> {
         let mut channel_entries = Vec::new();
+        let date_slugs = self.get_channel_log_date_slugs(name)?;
+        let count_file_path = self.chat_log_directory.join(name).join(COUNT_FILE_NAME);
+        let mut count_file = if self.channel_count_files && self.day_cache.is_none() {
+            Some(CountFile::load(&count_file_path))
+        } else {
+            None
+        };
 
-        for date_slug in self.get_channel_log_date_slugs(name)? {
+        for date_slug in date_slugs.iter().cloned() {
             let date = parse_date_slug(&date_slug)?;
-            let log_path = self.get_log_path(name, &date_slug)?;
-            let message_count = crate::reader::count_message_lines(&log_path, &date)?;
+            let summary = match &mut count_file {
+                Some(count_file) => {
+                    let etag = self.get_log_etag(name, &date_slug)?;
+
+                    match count_file.get(&date_slug, &etag, self.count_status_lines) {
+                        Some(summary) => summary,
+                        None => {
+                            let summary = self.summarize_day(name, &date_slug, date)?;
+                            count_file.insert(&date_slug, &etag, self.count_status_lines, &summary);
+                            summary
+                        }
+                    }
+                }
+                None => self.get_day_summary(name, &date_slug)?,
+            };
 
             channel_entries.push(ChannelDailyEntry {
                 date,
                 date_slug,
-                message_count,
+                message_count: summary.message_count,
+                first_message: summary.first_message,
+                last_message: summary.last_message,
             });
         }
 
+        if let Some(count_file) = &mut count_file {
+            count_file.retain_days(&date_slugs);
+
+            // The log directory may be read-only, which only costs the speedup
+            if let Err(error) = count_file.save(&count_file_path) {
+                tracing::warn!("Couldn't save {}: {:#}", count_file_path.display(), error);
+            }
+        }
+
         channel_entries.sort_unstable();
         channel_entries.reverse();
 
         Ok(channel_entries)
     }
 
-    fn get_channel_log_date_slugs(&self, name: &str) -> anyhow::Result<Vec<String>> {
+    /// Counts a day's messages, from the day cache if it's enabled and the
+    /// log hasn't changed since.
+    pub fn get_day_summary(&self, name: &str, date_slug: &str) -> anyhow::Result<DaySummary> {
+        let date = parse_date_slug(date_slug)?;
+
+        match &self.day_cache {
+            Some(day_cache) => {
+                let etag = self.get_log_etag(name, date_slug)?;
+
+                match day_cache.get(name, date_slug, &etag, self.count_status_lines)? {
+                    Some(summary) => Ok(summary),
+                    None => {
+                        let summary = self.summarize_day(name, date_slug, date)?;
+                        day_cache.insert(
+                            name,
+                            date_slug,
+                            &etag,
+                            self.count_status_lines,
+                            &summary,
+                        )?;
+                        Ok(summary)
+                    }
+                }
+            }
+            None => self.summarize_day(name, date_slug, date),
+        }
+    }
+
+    fn summarize_day(
+        &self,
+        name: &str,
+        date_slug: &str,
+        date: Date<Utc>,
+    ) -> anyhow::Result<DaySummary> {
+        let log_path = self.get_log_path(name, date_slug)?;
+        let message_count =
+            crate::reader::count_message_lines(&log_path, &date, self.count_status_lines)?;
+        let (first_message, last_message) = crate::reader::read_message_previews(&log_path)?;
+
+        Ok(DaySummary {
+            message_count,
+            first_message,
+            last_message,
+        })
+    }
+
+    /// Counts each nickname's messages on a day, keyed by lowercase nickname.
+    pub fn get_day_nick_counts(
+        &self,
+        name: &str,
+        date_slug: &str,
+    ) -> anyhow::Result<HashMap<String, u64>> {
+        let day_cache = match &self.day_cache {
+            Some(day_cache) => day_cache,
+            None => return self.count_day_nicks(name, date_slug),
+        };
+        let etag = self.get_log_etag(name, date_slug)?;
+
+        if let Some(counts) = day_cache.get_nick_counts(name, date_slug, &etag)? {
+            return Ok(counts);
+        }
+
+        let counts = self.count_day_nicks(name, date_slug)?;
+        day_cache.insert_nick_counts(name, date_slug, &etag, &counts)?;
+
+        Ok(counts)
+    }
+
+    fn count_day_nicks(&self, name: &str, date_slug: &str) -> anyhow::Result<HashMap<String, u64>> {
+        let mut counts = HashMap::new();
+
+        for line in self.get_log_lines(name, date_slug)? {
+            if let Some(nickname) = crate::reader::message_sender(&line.content) {
+                *counts.entry(nickname.to_lowercase()).or_insert(0) += 1;
+            }
+        }
+
+        Ok(counts)
+    }
+
+    /// Summarizes a nickname's activity up to any day. The first and last days
+    /// seen are found by reading days inward from each end of the channel's
+    /// history, which is only quick with the day cache.
+    pub fn get_nick_card(
+        &self,
+        name: &str,
+        nickname: &str,
+        date_slug: &str,
+    ) -> anyhow::Result<NickCard> {
+        let key = nickname.to_lowercase();
+        let message_count = self
+            .get_day_nick_counts(name, date_slug)?
+            .get(&key)
+            .copied()
+            .unwrap_or(0);
+
+        // Newest first
+        let date_slugs = self.get_channel_log_date_slugs(name)?;
+        let mut last_seen = None;
+        let mut first_seen = None;
+
+        for date_slug in &date_slugs {
+            if self
+                .get_day_nick_counts(name, date_slug)?
+                .contains_key(&key)
+            {
+                last_seen = Some(parse_date_slug(date_slug)?);
+                break;
+            }
+        }
+
+        if last_seen.is_some() {
+            for date_slug in date_slugs.iter().rev() {
+                if self
+                    .get_day_nick_counts(name, date_slug)?
+                    .contains_key(&key)
+                {
+                    first_seen = Some(parse_date_slug(date_slug)?);
+                    break;
+                }
+            }
+        }
+
+        Ok(NickCard {
+            nickname: nickname.to_string(),
+            message_count,
+            first_seen,
+            last_seen,
+        })
+    }
+
+    /// Returns when each channel started logging or resumed logging after a
+    /// long period of inactivity, most recent first.
+    pub fn get_channel_activity(&self) -> anyhow::Result<Arc<Vec<ChannelActivity>>> {
+        self.channel_activity_cache.get_or_try_insert_with(|| {
+            let mut activities = Vec::new();
+
+            for channel in self.get_channels()? {
+                let mut dates = self
+                    .get_channel_log_date_slugs(&channel.name)?
+                    .iter()
+                    .map(|date_slug| parse_date_slug(date_slug))
+                    .collect::<anyhow::Result<Vec<Date<Utc>>>>()?;
+                dates.sort_unstable();
+
+                let last_date = match dates.last() {
+                    Some(date) => *date,
+                    None => continue,
+                };
+                let mut active_since = dates[0];
+                let mut resumed = false;
+
+                for window in dates.windows(2) {
+                    if window[1].signed_duration_since(window[0])
+                        > Duration::days(CHANNEL_INACTIVITY_DAYS)
+                    {
+                        active_since = window[1];
+                        resumed = true;
+                    }
+                }
+
+                activities.push(ChannelActivity {
+                    name: channel.name,
+                    active_since,
+                    last_date,
+                    resumed,
+                });
+            }
+
+            activities.sort_unstable_by_key(|activity| Reverse(activity.active_since));
+
+            Ok(activities)
+        })
+    }
+
+    /// Counts the channels, days and lines of the archive. Counting reads
+    /// every log unless the day cache is enabled, so the totals are cached.
+    pub fn get_archive_summary(&self) -> anyhow::Result<Arc<ArchiveSummary>> {
+        self.archive_summary_cache.get_or_try_insert_with(|| {
+            let mut summary = ArchiveSummary {
+                channel_count: 0,
+                day_count: 0,
+                line_count: 0,
+                oldest_date: None,
+                newest_date: None,
+            };
+
+            for channel in self.get_channels()? {
+                let entries = self.get_channel_daily_entries(&channel.name)?;
+
+                summary.channel_count += 1;
+                summary.day_count += entries.len();
+                summary.line_count += entries.iter().map(|entry| entry.message_count).sum::<u64>();
+
+                // Entries are newest first
+                if let Some(entry) = entries.last() {
+                    if summary.oldest_date.is_none_or(|date| entry.date < date) {
+                        summary.oldest_date = Some(entry.date);
+                    }
+                }
+
+                if let Some(entry) = entries.first() {
+                    if summary.newest_date.is_none_or(|date| entry.date > date) {
+                        summary.newest_date = Some(entry.date);
+                    }
+                }
+            }
+
+            Ok(summary)
+        })
+    }
+
+    pub fn get_channel_last_modified(&self, name: &str) -> anyhow::Result<Option<SystemTime>> {
+        let mut last_modified = None;
+
+        for date_slug in self.get_channel_log_date_slugs(name)? {
+            let modified = std::fs::metadata(self.get_log_path(name, &date_slug)?)?.modified()?;
+
+            if last_modified.is_none_or(|last_modified| modified > last_modified) {
+                last_modified = Some(modified);
+            }
+        }
+
+        Ok(last_modified)
+    }
+
+    pub fn find_date_slug(&self, name: &str, date: Date<Utc>) -> anyhow::Result<Option<String>> {
+        for date_slug in self.get_channel_log_date_slugs(name)? {
+            if parse_date_slug(&date_slug)? == date {
+                return Ok(Some(date_slug));
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub fn get_channel_log_date_slugs(&self, name: &str) -> anyhow::Result<Vec<String>> {
         let channel_dir = self.chat_log_directory.join(name);
         let mut date_slugs = Vec::new();
 
         for entry in std::fs::read_dir(channel_dir)? {
-            let entry = entry?;
+            // Names that aren't valid Unicode couldn't be requested by a URL anyway
+            let file_name = entry?.file_name();
+            let file_name = match file_name.to_str() {
+                Some(file_name) => file_name,
+                None => continue,
+            };
+            let date_slug = match file_name
+                .strip_suffix(".log")
+                .or_else(|| file_name.strip_suffix(".log.gz"))
+            {
+                Some(date_slug) => date_slug,
+                None => continue,
+            };
 
-            if let Some("log") = entry.path().extension().and_then(OsStr::to_str) {
-                let date_slug = entry
-                    .path()
-                    .file_stem()
-                    .unwrap()
-                    .to_string_lossy()
-                    .to_string();
-                date_slugs.push(date_slug)
+            // Skip stray files so one bad name doesn't break every page of the channel
+            if parse_date_slug(date_slug).is_ok() {
+                date_slugs.push(date_slug.to_string())
             }
         }
 
         date_slugs.sort_unstable();
         date_slugs.reverse();
+        // A day may be both compressed and not while it's being compressed
+        date_slugs.dedup();
 
         Ok(date_slugs)
     }
@@ -140,109 +614,807 @@ impl AppState {
     pub fn get_raw_log(&self, name: &str, date_slug: &str) -> anyhow::Result<Vec<u8>> {
         let log_path = self.get_log_path(name, date_slug)?;
 
-        Ok(std::fs::read(log_path)?)
+        Ok(crate::reader::read_log(&log_path)?)
+    }
+
+    /// Returns an ETag that changes whenever the log file is appended to or replaced.
+    pub fn get_log_etag(&self, name: &str, date_slug: &str) -> anyhow::Result<String> {
+        let metadata = std::fs::metadata(self.get_log_path(name, date_slug)?)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+
+        Ok(format!(
+            "\"{:x}-{:x}.{:x}\"",
+            metadata.len(),
+            modified.as_secs(),
+            modified.subsec_nanos()
+        ))
     }
 
+    /// Catches up with a day's log that was just written, replaced or
+    /// deleted: the day's parsed lines and summaries are dropped, totals over
+    /// the archive are counted again on next use and the day is indexed
+    /// again. A log replaced within the same second at the same size would
+    /// otherwise look unchanged to the caches of single days.
+    pub fn refresh_day(&self, name: &str, date_slug: &str) -> anyhow::Result<()> {
+        self.parsed_day_cache
+            .retain(|key| key.channel != name || key.date_slug != date_slug);
+
+        if let Some(day_cache) = &self.day_cache {
+            day_cache.remove_day(name, date_slug)?;
+        } else if self.channel_count_files {
+            let count_file_path = self.chat_log_directory.join(name).join(COUNT_FILE_NAME);
+            let mut count_file = CountFile::load(&count_file_path);
+            count_file.remove_day(date_slug);
+
+            if let Err(error) = count_file.save(&count_file_path) {
+                tracing::warn!("Couldn't save {}: {:#}", count_file_path.display(), error);
+            }
+        }
+
+        self.channel_activity_cache.clear();
+        self.archive_summary_cache.clear();
+
+        if let Some(search_index) = &self.search_index {
+            search_index.update_day(self, name, date_slug)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the lines of a day, parsing the log only if the parsed lines
+    /// of the file as it is now aren't in the cache.
     pub fn get_log_lines(&self, name: &str, date_slug: &str) -> anyhow::Result<Vec<LogLine>> {
-        let date = parse_date_slug(date_slug)?;
         let log_path = self.get_log_path(name, date_slug)?;
+        let metadata = std::fs::metadata(&log_path)?;
+        let key = ParsedDayKey {
+            channel: name.to_string(),
+            date_slug: date_slug.to_string(),
+            modified: metadata.modified()?,
+            len: metadata.len(),
+        };
+        let lines = self.parsed_day_cache.get_or_try_insert_with(key, || {
+            Ok(Arc::new(self.parse_log_lines(
+                name,
+                date_slug,
+                crate::reader::open_log(&log_path)?,
+            )?))
+        })?;
 
-        crate::reader::read_lines(&log_path, &date)
+        Ok(lines.as_ref().clone())
     }
 
-    fn get_log_path(&self, name: &str, date_slug: &str) -> anyhow::Result<PathBuf> {
-        let log_path = self
-            .chat_log_directory
-            .join(name)
-            .join(format!("{}.log", date_slug));
+    /// Returns the last lines of a channel's logs, oldest first, with the
+    /// date slug of each. Only the ends of the newest days are read.
+    ///
+    /// Line numbers aren't known without reading whole days, so unparsable
+    /// lines kept in lenient mode aren't added to the parse problem report.
+    pub fn get_recent_lines(
+        &self,
+        name: &str,
+        count: usize,
+    ) -> anyhow::Result<Vec<(String, LogLine)>> {
+        let script_parser = self.get_script_parser(name)?;
+        let mut days = Vec::new();
+        let mut found = 0;
+
+        for date_slug in self.get_channel_log_date_slugs(name)? {
+            if found >= count {
+                break;
+            }
+
+            let date = parse_date_slug(&date_slug)?;
+            let tail =
+                crate::reader::read_log_tail(&self.get_log_path(name, &date_slug)?, count - found)?;
+            let (lines, _) = crate::reader::parse_lines_with(
+                tail.as_slice(),
+                &date,
+                self.lenient_parsing,
+                |line, date| match &script_parser {
+                    Some(script_parser) => script_parser.parse_line(line, date),
+                    None => crate::reader::parse_line(line, date),
+                },
+            )?;
+
+            found += lines.len();
+            days.push((date_slug, lines));
+        }
+
+        Ok(days
+            .into_iter()
+            .rev()
+            .flat_map(|(date_slug, lines)| {
+                lines.into_iter().map(move |line| (date_slug.clone(), line))
+            })
+            .collect())
+    }
+
+    /// Parses a day's log. In lenient mode, unparsable lines are kept and
+    /// added to the parse problem report.
+    pub fn parse_log_lines<R: Read>(
+        &self,
+        name: &str,
+        date_slug: &str,
+        reader: R,
+    ) -> anyhow::Result<Vec<LogLine>> {
+        let date = parse_date_slug(date_slug)?;
+        let script_parser = self.get_script_parser(name)?;
+        let (lines, problems) =
+            crate::reader::parse_lines_with(reader, &date, self.lenient_parsing, |line, date| {
+                match &script_parser {
+                    Some(script_parser) => script_parser.parse_line(line, date),
+                    None => crate::reader::parse_line(line, date),
+                }
+            })?;
+
+        if !problems.is_empty() {
+            self.parse_problems.record(name, date_slug, problems);
+        }
+
+        Ok(lines)
+    }
+
+    /// Returns the channel's own line parser if its metadata names one.
+    pub fn get_script_parser(&self, name: &str) -> anyhow::Result<Option<Arc<ScriptParser>>> {
+        match self.get_channel_metadata(name)?.parser {
+            Some(file_name) => Ok(Some(
+                self.script_parsers
+                    .get(&self.chat_log_directory.join(name).join(file_name))?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_continuous_lines(
+        &self,
+        name: &str,
+        date_slug: &str,
+        start_line_number: u64,
+        max_lines: usize,
+    ) -> anyhow::Result<ContinuousChunk> {
+        let mut date_slugs = self.get_channel_log_date_slugs(name)?;
+        date_slugs.reverse();
+
+        let mut lines = Vec::new();
+
+        for current_slug in date_slugs
+            .into_iter()
+            .filter(|slug| slug.as_str() >= date_slug)
+        {
+            let first_line_number = if current_slug == date_slug {
+                start_line_number
+            } else {
+                1
+            };
+
+            for (index, line) in self
+                .get_log_lines(name, &current_slug)?
+                .into_iter()
+                .enumerate()
+            {
+                let line_number = index as u64 + 1;
+
+                if line_number < first_line_number {
+                    continue;
+                }
+
+                if lines.len() == max_lines {
+                    return Ok(ContinuousChunk {
+                        lines,
+                        next: Some(ContinuousPosition {
+                            date_slug: current_slug,
+                            line_number,
+                        }),
+                    });
+                }
+
+                lines.push(ContinuousLine {
+                    date_slug: current_slug.clone(),
+                    line_number,
+                    line,
+                });
+            }
+        }
+
+        Ok(ContinuousChunk { lines, next: None })
+    }
+
+    /// Returns the path of a day's log, or of its local copy if the logs are
+    /// on cold storage and it's been copied.
+    pub fn get_log_path(&self, name: &str, date_slug: &str) -> anyhow::Result<PathBuf> {
+        let log_path = crate::reader::find_log_file(&self.chat_log_directory.join(name), date_slug);
+
+        if let Some(log_hydrator) = &self.log_hydrator {
+            if let Some(copy_path) = log_hydrator.get_copy(name, &log_path) {
+                return Ok(copy_path);
+            }
+        }
 
         Ok(log_path)
     }
 
+    /// Whether a finished day's log is still only on cold storage, and starts
+    /// copying it if so. Today's log keeps changing, so it's always read
+    /// where it is.
+    pub fn is_day_cold(&self, name: &str, date_slug: &str) -> anyhow::Result<bool> {
+        let log_hydrator = match &self.log_hydrator {
+            Some(log_hydrator) => log_hydrator,
+            None => return Ok(false),
+        };
+
+        if parse_date_slug(date_slug)?.succ() >= Utc::today() {
+            return Ok(false);
+        }
+
+        let log_path = crate::reader::find_log_file(&self.chat_log_directory.join(name), date_slug);
+
+        if !log_path.is_file() || log_hydrator.get_copy(name, &log_path).is_some() {
+            return Ok(false);
+        }
+
+        log_hydrator.start_copy(name, &log_path);
+
+        Ok(true)
+    }
+
+    /// Returns the saved JSON rendition of a day's log, unless the log has
+    /// changed since it was written.
+    pub fn read_day_json(&self, name: &str, date_slug: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let json_path = self.get_day_json_path(name, date_slug);
+        let json_modified = match std::fs::metadata(&json_path) {
+            Ok(metadata) => metadata.modified()?,
+            Err(_) => return Ok(None),
+        };
+        let log_modified = std::fs::metadata(self.get_log_path(name, date_slug)?)?.modified()?;
+
+        if json_modified < log_modified {
+            return Ok(None);
+        }
+
+        Ok(Some(std::fs::read(json_path)?))
+    }
+
+    /// Saves a day's JSON rendition through a temporary file, so that it's
+    /// never read half written.
+    pub fn write_day_json(
+        &self,
+        name: &str,
+        date_slug: &str,
+        content: &[u8],
+    ) -> anyhow::Result<()> {
+        let json_path = self.get_day_json_path(name, date_slug);
+        let temp_path = json_path.with_extension("json.tmp");
+
+        std::fs::write(&temp_path, content)?;
+        std::fs::rename(temp_path, json_path)?;
+
+        Ok(())
+    }
+
+    fn get_day_json_path(&self, name: &str, date_slug: &str) -> PathBuf {
+        self.chat_log_directory
+            .join(name)
+            .join(format!("{}.json", date_slug))
+    }
+
+    /// Returns why a day's log is gone if the channel lists it as removed
+    /// and its file doesn't exist anymore.
+    pub fn get_removed_day(
+        &self,
+        name: &str,
+        date_slug: &str,
+    ) -> anyhow::Result<Option<RemovedDay>> {
+        let removed_path = self.chat_log_directory.join(name).join("REMOVED");
+
+        if !removed_path.is_file() || self.get_log_path(name, date_slug)?.exists() {
+            return Ok(None);
+        }
+
+        let date = parse_date_slug(date_slug)?.naive_utc();
+        let removed_days = parse_removed_days(&std::fs::read_to_string(removed_path)?)?;
+
+        Ok(removed_days.into_iter().find(|day| day.date == date))
+    }
+
     pub fn get_custom_message(&self) -> anyhow::Result<String> {
-        Ok(std::fs::read_to_string(&self.custom_message_html_file)?)
+        if self.demo_mode {
+            return Ok(String::new());
+        }
+
+        self.message_sources
+            .assemble(&self.custom_message_html_file)
     }
 
-    pub fn search_channel(
+    pub async fn get_theme(&self) -> anyhow::Result<String> {
+        match &self.theme_file {
+            Some(path) => Ok(tokio::fs::read_to_string(path).await?),
+            None => Ok(String::new()),
+        }
+    }
+
+    pub async fn search_channel(
         &self,
         channel_name: &str,
         query: &str,
-        case_sensitive: bool,
-        verbatim: bool,
-        whole_word: bool,
-    ) -> anyhow::Result<Vec<SearchResultEntry>> {
+        options: SearchOptions,
+        since: Option<Date<Utc>>,
+        until: Option<Date<Utc>>,
+        limit: usize,
+    ) -> anyhow::Result<SearchMatches> {
+        let (indexed_matches, date_slugs) = {
+            let app_state = self.clone();
+            let channel_name = channel_name.to_string();
+            let query = query.to_string();
+
+            tokio::task::spawn_blocking(move || {
+                app_state.search_channel_index(&channel_name, &query, options, since, until, limit)
+            })
+            .await??
+        };
         let channel_dir = self.chat_log_directory.join(channel_name);
-        let date_slugs = self.get_channel_log_date_slugs(channel_name)?;
-        let log_files = date_slugs
-            .iter()
-            .map(|slug| channel_dir.join(format!("{}.log", slug)))
-            .collect::<Vec<PathBuf>>();
 
-        let mut process = std::process::Command::new("timeout");
-        process.arg("10s").arg("agrep");
+        match indexed_matches {
+            Some(mut matches) => {
+                if !date_slugs.is_empty() {
+                    matches.merge(
+                        crate::search::search_logs(
+                            self.search_backend,
+                            &channel_dir,
+                            &date_slugs,
+                            query,
+                            options,
+                            limit,
+                        )
+                        .await?,
+                        limit,
+                    );
+                }
 
-        if !case_sensitive {
-            process.arg("-i0");
+                Ok(matches)
+            }
+            None => {
+                crate::search::search_logs(
+                    self.search_backend,
+                    &channel_dir,
+                    &date_slugs,
+                    query,
+                    options,
+                    limit,
+                )
+                .await
+            }
         }
+    }
 
-        if verbatim {
-            process.arg("-k");
+    /// Finds the days a search covers and answers the ones the index is
+    /// current for. Returns the index's matches, if it answered, and the
+    /// days left to scan. Reads metadata and the index from disk, so it
+    /// belongs on the blocking thread pool.
+    fn search_channel_index(
+        &self,
+        channel_name: &str,
+        query: &str,
+        options: SearchOptions,
+        since: Option<Date<Utc>>,
+        until: Option<Date<Utc>>,
+        limit: usize,
+    ) -> anyhow::Result<(Option<SearchMatches>, Vec<String>)> {
+        if !self.get_channel_metadata(channel_name)?.searchable {
+            return Ok((Some(SearchMatches::default()), Vec::new()));
         }
 
-        if whole_word {
-            process.arg("-w");
+        let date_slugs = self.get_search_date_slugs(channel_name, since, until)?;
+
+        if date_slugs.is_empty() {
+            return Ok((Some(SearchMatches::default()), Vec::new()));
         }
 
-        process.arg("-n").arg(query);
+        if let Some(search_index) = &self.search_index {
+            let mut indexed_slugs = Vec::new();
+            let mut unindexed_slugs = Vec::new();
 
-        for path in log_files {
-            process.arg(path);
-        }
+            for date_slug in date_slugs.iter().cloned() {
+                let etag = self.get_log_etag(channel_name, &date_slug)?;
 
-        let output = process.output()?;
-        let output = DecodeReaderBytesBuilder::new()
-            .encoding(Some(encoding_rs::UTF_8))
-            .build(Cursor::new(output.stdout));
-        let output = BufReader::new(output);
-        let mut search_results = Vec::new();
+                if search_index.is_current(channel_name, &date_slug, &etag) {
+                    indexed_slugs.push(date_slug);
+                } else {
+                    unindexed_slugs.push(date_slug);
+                }
+            }
 
-        for (count, line) in output.lines().enumerate() {
-            if count == 10000 {
-                search_results.push(SearchResultEntry {
-                    date_slug: String::new(),
-                    line_number: 0,
-                    raw_line: "(max search results exceed)".to_string(),
-                });
-                break;
+            if let Some(matches) =
+                search_index.search(channel_name, &indexed_slugs, query, options, limit)?
+            {
+                return Ok((Some(matches), unindexed_slugs));
             }
+        }
 
-            let line = line?;
-            let parts = line.splitn(3, ':');
-            let parts = parts.collect::<Vec<&str>>();
+        Ok((None, date_slugs))
+    }
 
-            if parts.len() == 3 {
-                let file_path = parts[0];
-                let line_number = parts[1].trim().parse::<u64>()?;
-                let raw_line = parts[2];
-                let file_path = PathBuf::from(file_path);
-                let date_slug = file_path.file_stem().unwrap_or_default().to_string_lossy();
+    fn get_search_date_slugs(
+        &self,
+        channel_name: &str,
+        since: Option<Date<Utc>>,
+        until: Option<Date<Utc>>,
+    ) -> anyhow::Result<Vec<String>> {
+        let mut date_slugs = self.get_channel_log_date_slugs(channel_name)?;
 
-                search_results.push(SearchResultEntry {
-                    date_slug: date_slug.to_string(),
-                    line_number,
-                    raw_line: raw_line.to_string(),
-                });
+        if since.is_some() || until.is_some() {
+            date_slugs.retain(|slug| {
+                parse_date_slug(slug).is_ok_and(|date| {
+                    since.is_none_or(|since| date >= since)
+                        && until.is_none_or(|until| date <= until)
+                })
+            });
+        }
+
+        Ok(date_slugs)
+    }
+
+    /// Returns what a search would cost on each day it scans: the size of
+    /// the log times the query's weight. Days the index answers cost
+    /// nothing, and compressed logs count at their size on disk.
+    pub fn get_search_day_costs(
+        &self,
+        channel_name: &str,
+        query: &str,
+        options: SearchOptions,
+        since: Option<Date<Utc>>,
+        until: Option<Date<Utc>>,
+    ) -> anyhow::Result<Vec<(Date<Utc>, u64)>> {
+        if !self.get_channel_metadata(channel_name)?.searchable {
+            return Ok(Vec::new());
+        }
+
+        let weight = crate::search::query_weight(query, options);
+        let search_index = self
+            .search_index
+            .as_ref()
+            .filter(|_| crate::search_index::is_query_indexable(query, options));
+        let mut day_costs = Vec::new();
+
+        for date_slug in self.get_search_date_slugs(channel_name, since, until)? {
+            if let Some(search_index) = search_index {
+                let etag = self.get_log_etag(channel_name, &date_slug)?;
+
+                if search_index.is_current(channel_name, &date_slug, &etag) {
+                    continue;
+                }
             }
+
+            let size = std::fs::metadata(self.get_log_path(channel_name, &date_slug)?)?.len();
+            day_costs.push((parse_date_slug(&date_slug)?, size.saturating_mul(weight)));
         }
 
-        Ok(search_results)
+        Ok(day_costs)
     }
 }
 
-fn parse_date_slug(date_slug: &str) -> anyhow::Result<Date<Utc>> {
-    let date_string = date_slug.split_once(",").unwrap().0;
+pub(crate) fn serialize_date<S: Serializer>(
+    date: &Date<Utc>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    date.naive_utc().serialize(serializer)
+}
+
+fn serialize_optional_date<S: Serializer>(
+    date: &Option<Date<Utc>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    date.map(|date| date.naive_utc()).serialize(serializer)
+}
+
+pub fn parse_date_slug(date_slug: &str) -> anyhow::Result<Date<Utc>> {
+    let (date_string, _weekday) = date_slug
+        .split_once(',')
+        .ok_or_else(|| anyhow::anyhow!("Date slug without weekday: {}", date_slug))?;
     Ok(Date::from_utc(
         NaiveDate::parse_from_str(date_string, "%Y-%m-%d")?,
         Utc,
     ))
 }
+
+fn password_file_users(content: &str) -> impl Iterator<Item = &str> {
+    content
+        .split('\n')
+        // Despite the bash script saving both unprefixed and prefixed
+        // channel names, it's ultimately treated as a comment...
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, _)| name)
+}
+
+/// Compares secrets in a time that depends only on their lengths.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .fold(0, |difference, (x, y)| difference | (x ^ y))
+            == 0
+}
+
+/// Formats a date like irclogger's log file names (`date +%F,%a` in the C locale).
+///
+/// Logs written under other locales have other weekday names, so slugs read
+/// from disk should be matched by date with `parse_date_slug` instead.
+pub fn format_date_slug(date: Date<Utc>) -> String {
+    date.format("%Y-%m-%d,%a").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use chrono::{Date, NaiveDate, TimeZone, Utc};
+    use proptest::prelude::*;
+
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::{format_date_slug, parse_date_slug};
+    use crate::route::is_date_string_ok;
+
+    // Abbreviated weekday names as printed by `date +%a` under various locales
+    const WEEKDAY_NAMES: &[&str] = &[
+        "Mon", "Tue", "Sun", "Mo", "Di", "So", "lun.", "mar.", "dim.", "lun", "mié", "dom", "Пн",
+        "Вт", "Вс", "月", "火", "日", "周一", "週二", "ma", "zo", "pon", "nie",
+    ];
+
+    fn date_strategy() -> impl Strategy<Value = Date<Utc>> {
+        // 1970-01-01 to 2100-12-31
+        (719_163i32..767_376)
+            .prop_map(|days| Date::from_utc(NaiveDate::from_num_days_from_ce(days), Utc))
+    }
+
+    fn weekday_name_strategy() -> impl Strategy<Value = &'static str> {
+        prop::sample::select(WEEKDAY_NAMES)
+    }
+
+    fn build_app_state(chat_log_directory: &std::path::Path) -> super::AppState {
+        let config = crate::config::config_from_value(crate::config::minimal_config_value(
+            chat_log_directory,
+            chat_log_directory,
+        ))
+        .unwrap();
+
+        crate::app::build_app_state(&config)
+    }
+
+    proptest! {
+        #[test]
+        fn formatted_slug_round_trips(date in date_strategy()) {
+            let date_slug = format_date_slug(date);
+
+            prop_assert!(is_date_string_ok(&date_slug));
+            prop_assert_eq!(parse_date_slug(&date_slug).unwrap(), date);
+        }
+
+        #[test]
+        fn any_weekday_name_parses(date in date_strategy(), weekday in weekday_name_strategy()) {
+            let date_slug = format!("{},{}", date.format("%Y-%m-%d"), weekday);
+
+            prop_assert!(is_date_string_ok(&date_slug));
+            prop_assert_eq!(parse_date_slug(&date_slug).unwrap(), date);
+        }
+
+        #[test]
+        fn accepted_strings_parse(input in "\\PC{0,30}") {
+            // Must never panic, and anything the routes accept must parse
+            let parsed = parse_date_slug(&input);
+
+            if is_date_string_ok(&input) {
+                prop_assert!(parsed.is_ok());
+            }
+        }
+
+        #[test]
+        fn accepted_strings_near_slugs_parse(input in "[0-9]{4}-[0-9]{2}-[0-9]{2},?[\\w./]{0,6}") {
+            let parsed = parse_date_slug(&input);
+
+            if is_date_string_ok(&input) {
+                prop_assert!(parsed.is_ok());
+                prop_assert!(!input.contains('/'));
+            }
+        }
+
+        #[test]
+        fn discovered_files_round_trip(
+            entries in prop::collection::btree_map(date_strategy(), weekday_name_strategy(), 0..20),
+            junk in prop::collection::btree_set("[a-z]{1,8}", 0..5),
+        ) {
+            let directory = tempfile::tempdir().unwrap();
+            let channel_dir = directory.path().join("channel");
+            std::fs::create_dir(&channel_dir).unwrap();
+
+            for (date, weekday) in &entries {
+                let file_name = format!("{},{}.log", date.format("%Y-%m-%d"), weekday);
+                std::fs::write(channel_dir.join(file_name), b"").unwrap();
+            }
+
+            for name in &junk {
+                std::fs::write(channel_dir.join(format!("{}.log", name)), b"").unwrap();
+                std::fs::write(channel_dir.join(format!("{}.txt", name)), b"").unwrap();
+            }
+
+            let app_state = build_app_state(directory.path());
+            let date_slugs = app_state.get_channel_log_date_slugs("channel").unwrap();
+            let dates = date_slugs
+                .iter()
+                .map(|date_slug| parse_date_slug(date_slug).unwrap())
+                .collect::<Vec<Date<Utc>>>();
+
+            // Newest first, with exactly one slug per log file and junk skipped
+            prop_assert_eq!(dates.len(), entries.len());
+            prop_assert!(dates.windows(2).all(|window| window[0] > window[1]));
+            prop_assert_eq!(
+                dates.iter().copied().collect::<BTreeSet<Date<Utc>>>(),
+                entries.keys().copied().collect::<BTreeSet<Date<Utc>>>()
+            );
+
+            for (date, weekday) in &entries {
+                let date_slug = app_state.find_date_slug("channel", *date).unwrap().unwrap();
+
+                prop_assert_eq!(date_slug, format!("{},{}", date.format("%Y-%m-%d"), weekday));
+            }
+        }
+    }
+
+    #[test]
+    fn compressed_logs_are_read() {
+        let directory = tempfile::tempdir().unwrap();
+        let channel_dir = directory.path().join("channel");
+        std::fs::create_dir(&channel_dir).unwrap();
+        std::fs::write(channel_dir.join("2021-01-05,Tue.log"), "[00:01] <bob> hi\n").unwrap();
+
+        let mut encoder = GzEncoder::new(
+            std::fs::File::create(channel_dir.join("2021-01-04,Mon.log.gz")).unwrap(),
+            Compression::default(),
+        );
+        encoder
+            .write_all(b"[00:01] <alice> hello\n[00:02] <alice> bye\n")
+            .unwrap();
+        encoder.finish().unwrap();
+
+        let app_state = build_app_state(directory.path());
+
+        assert_eq!(
+            app_state.get_channel_log_date_slugs("channel").unwrap(),
+            vec!["2021-01-05,Tue", "2021-01-04,Mon"]
+        );
+        assert_eq!(
+            app_state
+                .get_log_lines("channel", "2021-01-04,Mon")
+                .unwrap()
+                .len(),
+            2
+        );
+
+        let entries = app_state.get_channel_daily_entries("channel").unwrap();
+        assert_eq!(entries[1].message_count, 2);
+        assert_eq!(entries[1].first_message.as_deref(), Some("<alice> hello"));
+        assert_eq!(entries[1].last_message.as_deref(), Some("<alice> bye"));
+    }
+
+    #[test]
+    fn removed_days_are_only_reported_without_their_log() {
+        let directory = tempfile::tempdir().unwrap();
+        let channel_dir = directory.path().join("channel");
+        std::fs::create_dir(&channel_dir).unwrap();
+        std::fs::write(
+            channel_dir.join("REMOVED"),
+            "2021-01-04 Retention\n2021-01-05\n",
+        )
+        .unwrap();
+        std::fs::write(channel_dir.join("2021-01-05,Tue.log"), "[00:01] <bob> hi\n").unwrap();
+
+        let app_state = build_app_state(directory.path());
+        let removed = app_state
+            .get_removed_day("channel", "2021-01-04,Mon")
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(removed.reason, "Retention");
+        assert!(app_state
+            .get_removed_day("channel", "2021-01-05,Tue")
+            .unwrap()
+            .is_none());
+        assert!(app_state
+            .get_removed_day("channel", "2021-01-06,Wed")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn nick_card_counts_messages_and_actions() {
+        let directory = tempfile::tempdir().unwrap();
+        let channel_dir = directory.path().join("channel");
+        std::fs::create_dir(&channel_dir).unwrap();
+        std::fs::write(
+            channel_dir.join("2021-01-04,Mon.log"),
+            "[00:01] <Alice> hello\n[00:02] * alice waves\n[00:03] *** bob has joined #channel\n",
+        )
+        .unwrap();
+        std::fs::write(channel_dir.join("2021-01-05,Tue.log"), "[00:01] <bob> hi\n").unwrap();
+        std::fs::write(
+            channel_dir.join("2021-01-06,Wed.log"),
+            "[00:01] <alice> hi\n",
+        )
+        .unwrap();
+
+        let app_state = build_app_state(directory.path());
+        let card = app_state
+            .get_nick_card("channel", "ALICE", "2021-01-04,Mon")
+            .unwrap();
+
+        assert_eq!(card.message_count, 2);
+        assert_eq!(card.first_seen, Some(Utc.ymd(2021, 1, 4)));
+        assert_eq!(card.last_seen, Some(Utc.ymd(2021, 1, 6)));
+
+        let card = app_state
+            .get_nick_card("channel", "carol", "2021-01-05,Tue")
+            .unwrap();
+
+        assert_eq!(card.message_count, 0);
+        assert_eq!(card.first_seen, None);
+        assert_eq!(card.last_seen, None);
+    }
+
+    #[test]
+    fn rejects_unknown_users_and_compares_in_constant_time() {
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::write(
+            directory.path().join("htpasswd"),
+            "# #channel:x\nchannel:$apr1$Wq2v8c0p$RIXLQgocvQb45RMr8c71q/\n",
+        )
+        .unwrap();
+
+        let app_state = build_app_state(directory.path());
+
+        assert!(app_state.is_channel_in_password_file("channel").unwrap());
+        assert!(!app_state.is_channel_in_password_file("#channel").unwrap());
+        assert!(!app_state.is_password_ok("other", "secret").unwrap());
+        assert!(!app_state.is_password_ok("channel", "wrong").unwrap());
+        assert!(app_state.is_password_ok("channel", "secret").unwrap());
+
+        assert!(super::constant_time_eq(b"channel", b"channel"));
+        assert!(!super::constant_time_eq(b"channel", b"channe1"));
+        assert!(!super::constant_time_eq(b"channel", b"channel2"));
+    }
+
+    #[test]
+    fn day_json_is_saved_next_to_the_log() {
+        let directory = tempfile::tempdir().unwrap();
+        let channel_dir = directory.path().join("channel");
+        std::fs::create_dir(&channel_dir).unwrap();
+        let log_path = channel_dir.join("2021-01-04,Mon.log");
+        std::fs::write(&log_path, "[00:01] <alice> hello\n").unwrap();
+
+        let app_state = build_app_state(directory.path());
+
+        assert_eq!(
+            app_state
+                .read_day_json("channel", "2021-01-04,Mon")
+                .unwrap(),
+            None
+        );
+
+        app_state
+            .write_day_json("channel", "2021-01-04,Mon", b"{}")
+            .unwrap();
+        assert_eq!(
+            app_state
+                .read_day_json("channel", "2021-01-04,Mon")
+                .unwrap(),
+            Some(b"{}".to_vec())
+        );
+        assert_eq!(
+            app_state.get_channel_log_date_slugs("channel").unwrap(),
+            vec!["2021-01-04,Mon".to_string()]
+        );
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+
+use chrono::NaiveDate;
+use hmac::{Hmac, Mac, NewMac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::state::AppState;
+
+#[derive(Clone, Deserialize)]
+pub struct PublicStatsConfig {
+    pub noise_secret: String, // Keeps the noise added to counts unpredictable
+    #[serde(default = "default_epsilon")]
+    pub epsilon: f64, // Privacy parameter of each count; smaller adds more noise
+}
+
+fn default_epsilon() -> f64 {
+    1.0
+}
+
+/// Daily activity of a channel without any text, published for channels
+/// whose metadata sets `public_stats=true`.
+#[derive(Serialize)]
+pub struct ChannelStats {
+    pub channel: String,
+    pub epsilon: f64,
+    pub days: Vec<DailyStats>,
+}
+
+#[derive(Serialize)]
+pub struct DailyStats {
+    pub date: NaiveDate,
+    pub messages: u64,
+    pub active_nicks: u64,
+}
+
+/// Counts messages and distinct nicknames per day, oldest first, with
+/// Laplace noise added to each count.
+///
+/// The noise of a count is derived from the secret, channel, day and kind of
+/// count, so repeating a request returns the same values instead of
+/// averaging the noise away.
+pub fn build_channel_stats(
+    app_state: &AppState,
+    config: &PublicStatsConfig,
+    channel: &str,
+) -> anyhow::Result<ChannelStats> {
+    let mut days = Vec::new();
+
+    for date_slug in app_state.get_channel_log_date_slugs(channel)?.iter().rev() {
+        let date = crate::state::parse_date_slug(date_slug)?;
+        let nick_counts = match app_state.get_day_nick_counts(channel, date_slug) {
+            Ok(nick_counts) => nick_counts,
+            Err(_) => continue,
+        };
+        let messages = nick_counts.values().sum::<u64>();
+        let key = format!("{}/{}", channel, date_slug);
+
+        days.push(DailyStats {
+            date: date.naive_utc(),
+            messages: add_noise(config, &format!("{}/messages", key), messages as usize),
+            active_nicks: add_noise(config, &format!("{}/nicks", key), nick_counts.len()),
+        });
+    }
+
+    Ok(ChannelStats {
+        channel: channel.to_string(),
+        epsilon: config.epsilon,
+        days,
+    })
+}
+
+/// How the participants of a channel changed from one day to another, with
+/// nicknames in lowercase as counted per day.
+pub struct DayComparison {
+    pub messages_before: u64,
+    pub messages_after: u64,
+    pub appeared: Vec<NickChange>,    // Only active on the later day
+    pub disappeared: Vec<NickChange>, // Only active on the earlier day
+    pub continued: Vec<NickChange>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct NickChange {
+    pub nickname: String,
+    pub before: u64,
+    pub after: u64,
+}
+
+impl DayComparison {
+    /// Compares the messages per nickname of two days, listing the most
+    /// active nicknames first.
+    pub fn new(before: &HashMap<String, u64>, after: &HashMap<String, u64>) -> Self {
+        let mut appeared = Vec::new();
+        let mut disappeared = Vec::new();
+        let mut continued = Vec::new();
+
+        for (nickname, count) in after {
+            let change = NickChange {
+                nickname: nickname.clone(),
+                before: before.get(nickname).copied().unwrap_or(0),
+                after: *count,
+            };
+
+            if before.contains_key(nickname) {
+                continued.push(change);
+            } else {
+                appeared.push(change);
+            }
+        }
+
+        for (nickname, count) in before {
+            if !after.contains_key(nickname) {
+                disappeared.push(NickChange {
+                    nickname: nickname.clone(),
+                    before: *count,
+                    after: 0,
+                });
+            }
+        }
+
+        for changes in [&mut appeared, &mut disappeared, &mut continued].iter_mut() {
+            changes.sort_unstable_by(|a, b| {
+                (b.before + b.after)
+                    .cmp(&(a.before + a.after))
+                    .then(a.nickname.cmp(&b.nickname))
+            });
+        }
+
+        Self {
+            messages_before: before.values().sum(),
+            messages_after: after.values().sum(),
+            appeared,
+            disappeared,
+            continued,
+        }
+    }
+}
+
+fn add_noise(config: &PublicStatsConfig, key: &str, count: usize) -> u64 {
+    let noisy =
+        count as f64 + laplace_noise(config.noise_secret.as_bytes(), key, 1.0 / config.epsilon);
+
+    noisy.round().max(0.0) as u64
+}
+
+/// Samples Laplace(0, scale) using the HMAC of the key as the random source.
+fn laplace_noise(secret: &[u8], key: &str, scale: f64) -> f64 {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(key.as_bytes());
+
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&mac.finalize().into_bytes()[..8]);
+
+    // Uniform in (-0.5, 0.5), never reaching the ends
+    let uniform = ((u64::from_le_bytes(bytes) >> 11) as f64 + 0.5) / (1u64 << 53) as f64 - 0.5;
+
+    -scale * uniform.signum() * (1.0 - 2.0 * uniform.abs()).ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{add_noise, laplace_noise, DayComparison, NickChange, PublicStatsConfig};
+
+    #[test]
+    fn noise_is_repeatable_per_key() {
+        let first = laplace_noise(b"secret", "archiveteam/2021-01-04,Mon/messages", 1.0);
+        let second = laplace_noise(b"secret", "archiveteam/2021-01-04,Mon/messages", 1.0);
+        let other = laplace_noise(b"secret", "archiveteam/2021-01-05,Tue/messages", 1.0);
+
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn noise_averages_near_zero() {
+        let samples = (0..10000)
+            .map(|index| laplace_noise(b"secret", &index.to_string(), 2.0))
+            .collect::<Vec<f64>>();
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        let mean_absolute =
+            samples.iter().map(|sample| sample.abs()).sum::<f64>() / samples.len() as f64;
+
+        assert!(mean.abs() < 0.2, "mean {}", mean);
+        // The mean absolute deviation of Laplace(0, b) is b
+        assert!(
+            (mean_absolute - 2.0).abs() < 0.2,
+            "mean absolute {}",
+            mean_absolute
+        );
+    }
+
+    #[test]
+    fn large_epsilon_keeps_counts() {
+        let config = PublicStatsConfig {
+            noise_secret: "secret".to_string(),
+            epsilon: 1e9,
+        };
+
+        for count in 0..100 {
+            assert_eq!(add_noise(&config, &count.to_string(), count), count as u64);
+        }
+    }
+
+    fn change(nickname: &str, before: u64, after: u64) -> NickChange {
+        NickChange {
+            nickname: nickname.to_string(),
+            before,
+            after,
+        }
+    }
+
+    #[test]
+    fn day_comparison_splits_participants() {
+        let counts = |entries: &[(&str, u64)]| {
+            entries
+                .iter()
+                .map(|(nickname, count)| (nickname.to_string(), *count))
+                .collect::<HashMap<String, u64>>()
+        };
+        let comparison = DayComparison::new(
+            &counts(&[("alice", 5), ("bob", 2), ("carol", 1)]),
+            &counts(&[("alice", 1), ("dave", 3), ("erin", 3)]),
+        );
+
+        assert_eq!(comparison.messages_before, 8);
+        assert_eq!(comparison.messages_after, 7);
+        assert_eq!(
+            comparison.appeared,
+            vec![change("dave", 0, 3), change("erin", 0, 3)]
+        );
+        assert_eq!(
+            comparison.disappeared,
+            vec![change("bob", 2, 0), change("carol", 1, 0)]
+        );
+        assert_eq!(comparison.continued, vec![change("alice", 5, 1)]);
+    }
+}
@@ -0,0 +1,111 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, RwLock},
+};
+
+use gotham::{
+    handler::HandlerFuture,
+    hyper::HeaderMap,
+    middleware::{Middleware, NewMiddleware},
+    state::{FromState, State},
+};
+use serde::Deserialize;
+
+use crate::state::AppState;
+
+#[derive(Deserialize)]
+pub struct TenantConfig {
+    pub chat_log_directory: PathBuf,
+    pub apache_password_file: PathBuf,
+    pub custom_message_html_file: PathBuf,
+    pub message_html: Option<String>, // Shown below the tenant's message file
+    pub theme: Option<PathBuf>,       // CSS file served after the built-in styles
+    pub public_url: Option<String>, // Base of links to the tenant's pages in feeds and Link headers
+    pub path_prefix: Option<String>, // Path of the tenant's site links; else that of public_url
+    pub data_directory: Option<PathBuf>, // Kept separate so channel names can't collide between tenants
+}
+
+/// Puts the `AppState` of the tenant matching the request's Host header into
+/// the state, falling back to the top-level config for other hosts.
+///
+/// The states can be changed while serving, such as when the config is
+/// reloaded.
+#[derive(Clone)]
+pub struct TenantMiddleware {
+    sites: Arc<RwLock<Sites>>,
+}
+
+struct Sites {
+    default: AppState,
+    tenants: HashMap<String, AppState>,
+}
+
+impl TenantMiddleware {
+    pub fn new(default: AppState, tenants: HashMap<String, AppState>) -> Self {
+        Self {
+            sites: Arc::new(RwLock::new(Sites { default, tenants })),
+        }
+    }
+
+    /// Changes the state of the main site and of the tenants by hostname.
+    pub fn update<F>(&self, func: F)
+    where
+        F: FnOnce(&mut AppState, &mut HashMap<String, AppState>),
+    {
+        let mut sites = self.sites.write().unwrap();
+        let sites = &mut *sites;
+
+        func(&mut sites.default, &mut sites.tenants);
+    }
+
+    /// The state of the main site as it is now, for work outside of requests.
+    pub fn default_app_state(&self) -> AppState {
+        self.sites.read().unwrap().default.clone()
+    }
+
+    fn find_app_state(&self, state: &State) -> AppState {
+        let host = HeaderMap::borrow_from(state)
+            .get("host")
+            .and_then(|host| host.to_str().ok())
+            .map(normalize_host);
+        let sites = self.sites.read().unwrap();
+
+        host.and_then(|host| sites.tenants.get(&host))
+            .unwrap_or(&sites.default)
+            .clone()
+    }
+}
+
+impl Middleware for TenantMiddleware {
+    fn call<Chain>(self, mut state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>> + Send + 'static,
+    {
+        let app_state = self.find_app_state(&state);
+        state.put(app_state);
+
+        chain(state)
+    }
+}
+
+impl NewMiddleware for TenantMiddleware {
+    type Instance = Self;
+
+    fn new_middleware(&self) -> anyhow::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+/// Lowercases a hostname and removes any port so config keys can be plain names.
+pub fn normalize_host(host: &str) -> String {
+    let host = host.trim();
+    let host = match host.rsplit_once(':') {
+        // Leave bracketed IPv6 addresses without a port alone
+        Some((name, port)) if !port.contains(']') => name,
+        _ => host,
+    };
+
+    host.to_lowercase()
+}
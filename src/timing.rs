@@ -0,0 +1,107 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use gotham::{
+    hyper::{Body, Method, Response, Uri},
+    state::{FromState, State},
+};
+use gotham_derive::StateData;
+
+use crate::state::AppState;
+
+/// Time spent in each phase of handling a request, used to report slow requests.
+#[derive(StateData)]
+pub struct RequestTimings {
+    start: Instant,
+    phases: Mutex<Vec<(&'static str, Duration)>>,
+}
+
+impl RequestTimings {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            phases: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn record(&self, phase: &'static str, duration: Duration) {
+        let mut phases = self.phases.lock().unwrap();
+
+        match phases.iter_mut().find(|(name, _)| *name == phase) {
+            Some((_, total)) => *total += duration,
+            None => phases.push((phase, duration)),
+        }
+    }
+}
+
+/// Runs the function and adds its duration to the named phase of the request.
+pub fn time_phase<T, F>(state: &State, phase: &'static str, func: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    let start = Instant::now();
+    let result = func();
+    record_phase(state, phase, start.elapsed());
+
+    result
+}
+
+/// Adds a duration to the named phase of the request, for phases that await
+/// and so can't be timed with `time_phase`.
+pub fn record_phase(state: &State, phase: &'static str, duration: Duration) {
+    if let Some(timings) = RequestTimings::try_borrow_from(state) {
+        timings.record(phase, duration);
+    }
+}
+
+pub fn start_request(state: &mut State) {
+    state.put(RequestTimings::new());
+}
+
+/// Logs the request's method, path, status and duration, along with the
+/// time spent in each phase when it took longer than the slow request threshold.
+pub fn finish_request(state: &State, response: &Response<Body>) {
+    let timings = match RequestTimings::try_borrow_from(state) {
+        Some(timings) => timings,
+        None => return,
+    };
+    let total = timings.start.elapsed();
+    let method = Method::borrow_from(state);
+    let path = Uri::borrow_from(state).path();
+    let status = response.status().as_u16();
+    let is_slow = match AppState::borrow_from(state).slow_request_threshold {
+        Some(threshold) => total >= threshold,
+        None => false,
+    };
+
+    if !is_slow {
+        tracing::info!(
+            method = %method,
+            path,
+            status,
+            duration_ms = total.as_millis() as u64,
+            "Request"
+        );
+        return;
+    }
+
+    let phases = timings
+        .phases
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(name, duration)| format!("{}={}ms", name, duration.as_millis()))
+        .collect::<Vec<String>>()
+        .join(" ");
+
+    tracing::warn!(
+        method = %method,
+        path,
+        status,
+        duration_ms = total.as_millis() as u64,
+        phases = phases.as_str(),
+        "Slow request"
+    );
+}
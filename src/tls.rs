@@ -0,0 +1,45 @@
+use std::{fs::File, io::BufReader, path::Path};
+
+use rustls::{internal::pemfile, Certificate, NoClientAuth, PrivateKey, ServerConfig};
+
+/// Builds the HTTPS config from a PEM certificate chain and private key,
+/// such as the `fullchain.pem` and `privkey.pem` of Let's Encrypt.
+pub fn load_server_config(
+    certificate_path: &Path,
+    key_path: &Path,
+) -> anyhow::Result<ServerConfig> {
+    let certificates = read_certificates(certificate_path)?;
+    let key = read_private_key(key_path)?;
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.set_single_cert(certificates, key)?;
+
+    Ok(config)
+}
+
+fn read_certificates(path: &Path) -> anyhow::Result<Vec<Certificate>> {
+    let certificates = pemfile::certs(&mut BufReader::new(File::open(path)?))
+        .map_err(|_| anyhow::anyhow!("Couldn't read certificates in {}", path.display()))?;
+
+    if certificates.is_empty() {
+        anyhow::bail!("No certificates in {}", path.display());
+    }
+
+    Ok(certificates)
+}
+
+fn read_private_key(path: &Path) -> anyhow::Result<PrivateKey> {
+    let read_error = || anyhow::anyhow!("Couldn't read the private key in {}", path.display());
+
+    // PKCS #8 keys first, then the older RSA format
+    let mut keys = pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(path)?))
+        .map_err(|_| read_error())?;
+
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut BufReader::new(File::open(path)?))
+            .map_err(|_| read_error())?;
+    }
+
+    keys.into_iter()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("No private key in {}", path.display()))
+}
@@ -0,0 +1,61 @@
+use chrono::NaiveDate;
+use serde::Serialize;
+
+/// A day whose log was removed on purpose, such as by a retention policy.
+///
+/// Removed days are listed in a `REMOVED` file in the channel's log
+/// directory, one per line as `YYYY-MM-DD` and an optional reason shown to
+/// visitors. Lines starting with `#` are comments.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct RemovedDay {
+    pub date: NaiveDate,
+    pub reason: String,
+}
+
+pub fn parse_removed_days(content: &str) -> anyhow::Result<Vec<RemovedDay>> {
+    let mut days = Vec::new();
+
+    for line in content.lines() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (date, reason) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map_err(|_| anyhow::anyhow!("Invalid date in removed days: {}", date))?;
+
+        days.push(RemovedDay {
+            date,
+            reason: reason.trim().to_string(),
+        });
+    }
+
+    Ok(days)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::parse_removed_days;
+
+    #[test]
+    fn parses_dates_and_reasons() {
+        let days = parse_removed_days(
+            "# Removed by the retention policy\n\
+            2021-01-04 Older than two years\n\
+            \n\
+            2021-01-05\n",
+        )
+        .unwrap();
+
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[0].date, NaiveDate::from_ymd(2021, 1, 4));
+        assert_eq!(days[0].reason, "Older than two years");
+        assert_eq!(days[1].reason, "");
+
+        assert!(parse_removed_days("2021-01-04,Mon gone").is_err());
+    }
+}
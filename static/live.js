@@ -0,0 +1,80 @@
+// Appends lines written to today's log to the day view as they arrive.
+(function () {
+    "use strict";
+
+    var dataElement = document.getElementById("navigation-data");
+    var list = document.querySelector("ol.log-lines");
+
+    if (!dataElement || !list || !window.EventSource) {
+        return;
+    }
+
+    var data = JSON.parse(dataElement.textContent);
+
+    if (!data.live_url) {
+        return;
+    }
+
+    function makeLineElement(line) {
+        var item = document.createElement("li");
+        var time = document.createElement("span");
+        var timeElement = document.createElement("time");
+        var permalink = document.createElement("a");
+        var permalinkIcon = document.createElement("sup");
+        var nickname = document.createElement("span");
+        var text = document.createElement("span");
+        var number = line.line_number;
+
+        item.id = "l" + number;
+        item.value = number;
+        time.className = "time";
+        // Shifted so the UTC time reads as the viewer's time
+        var localDate = new Date(Date.parse(line.date) + data.utc_offset_minutes * 60000);
+
+        timeElement.dateTime = line.date;
+        timeElement.textContent = localDate.toISOString().substring(11, 16);
+        permalink.className = "permalink";
+        permalink.href = "?date=" + encodeURIComponent(data.date_slug) + "&sel=" + number + "#l" + number;
+        permalink.setAttribute("aria-label", "Permalink to line " + number);
+        permalinkIcon.textContent = "🔗";
+        nickname.className = "nickname";
+        text.className = "text";
+
+        if (line.content.status !== undefined) {
+            item.className = "line status";
+            text.textContent = line.content.status;
+        } else {
+            var message = line.content.message;
+            item.className = message.nickname === "*" ? "line message action" : "line message";
+            nickname.textContent = message.nickname;
+            text.textContent = message.text;
+        }
+
+        permalink.appendChild(permalinkIcon);
+        time.appendChild(timeElement);
+        time.appendChild(document.createTextNode(" "));
+        time.appendChild(permalink);
+        item.appendChild(time);
+        item.appendChild(nickname);
+        item.appendChild(text);
+
+        return item;
+    }
+
+    var source = new EventSource(data.live_url);
+
+    source.addEventListener("line", function (event) {
+        var line = JSON.parse(event.data);
+
+        if (document.getElementById("l" + line.line_number)) {
+            return;
+        }
+
+        list.appendChild(makeLineElement(line));
+        document.dispatchEvent(new CustomEvent("irclogger:line", { detail: line }));
+    });
+
+    source.addEventListener("end", function () {
+        source.close();
+    });
+})();
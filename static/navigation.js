@@ -0,0 +1,117 @@
+// Keyboard navigation for the day view.
+//
+// j/k: next/previous line, gg/G: first/last line of the day,
+// h/l: previous/next day, Enter: open the permalink of the selected line.
+(function () {
+    "use strict";
+
+    var dataElement = document.getElementById("navigation-data");
+
+    if (!dataElement) {
+        return;
+    }
+
+    var data = JSON.parse(dataElement.textContent);
+    var selectedLine = data.selected_line;
+    var pendingG = false;
+
+    // Lines added by the live tail
+    document.addEventListener("irclogger:line", function (event) {
+        data.line_numbers.push(event.detail.line_number);
+    });
+
+    function findRow(lineNumber) {
+        return document.getElementById("l" + lineNumber);
+    }
+
+    function dayUrl(dateSlug) {
+        return data.path_prefix + "/bin/irclogger_log/" + data.channel + "/?date=" + encodeURIComponent(dateSlug);
+    }
+
+    function select(lineNumber) {
+        var previousRow = selectedLine !== null ? findRow(selectedLine) : null;
+        var row = findRow(lineNumber);
+
+        if (!row) {
+            return;
+        }
+
+        if (previousRow) {
+            previousRow.classList.remove("selected");
+            previousRow.removeAttribute("aria-current");
+        }
+
+        row.classList.add("selected");
+        row.setAttribute("aria-current", "true");
+        row.scrollIntoView({ block: "center" });
+        selectedLine = lineNumber;
+        history.replaceState(null, "", dayUrl(data.date_slug) + "&sel=" + lineNumber + "#l" + lineNumber);
+    }
+
+    function move(offset) {
+        var lineNumbers = data.line_numbers;
+
+        if (lineNumbers.length === 0) {
+            return;
+        }
+
+        var index = lineNumbers.indexOf(selectedLine);
+
+        if (index === -1) {
+            select(offset > 0 ? lineNumbers[0] : lineNumbers[lineNumbers.length - 1]);
+        } else {
+            select(lineNumbers[Math.max(0, Math.min(lineNumbers.length - 1, index + offset))]);
+        }
+    }
+
+    document.addEventListener("keydown", function (event) {
+        var target = event.target;
+
+        if (event.ctrlKey || event.altKey || event.metaKey ||
+            target.isContentEditable || /^(INPUT|TEXTAREA|SELECT|BUTTON)$/.test(target.tagName)) {
+            return;
+        }
+
+        var isG = event.key === "g";
+
+        switch (event.key) {
+            case "j":
+                move(1);
+                break;
+            case "k":
+                move(-1);
+                break;
+            case "g":
+                if (pendingG && data.line_numbers.length > 0) {
+                    select(data.line_numbers[0]);
+                    isG = false;
+                }
+                break;
+            case "G":
+                if (data.line_numbers.length > 0) {
+                    select(data.line_numbers[data.line_numbers.length - 1]);
+                }
+                break;
+            case "h":
+                if (data.previous_day) {
+                    location.href = dayUrl(data.previous_day);
+                }
+                break;
+            case "l":
+                if (data.next_day) {
+                    location.href = dayUrl(data.next_day);
+                }
+                break;
+            case "Enter":
+                if (selectedLine !== null) {
+                    location.href = dayUrl(data.date_slug) + "&sel=" + selectedLine + "#l" + selectedLine;
+                }
+                break;
+            default:
+                return;
+        }
+
+        pendingG = isG;
+        event.preventDefault();
+    });
+})();
@@ -0,0 +1,109 @@
+// Shows a nickname's activity when hovering over or tapping a nickname in
+// the day view.
+(function () {
+    "use strict";
+
+    var dataElement = document.getElementById("navigation-data");
+    var list = document.querySelector("ol.log-lines");
+
+    if (!dataElement || !list || !window.fetch) {
+        return;
+    }
+
+    var data = JSON.parse(dataElement.textContent);
+    var fragments = {};
+    var currentNickname = null;
+    var hideTimer = null;
+    var popup = document.createElement("div");
+
+    popup.className = "nick-card-popup";
+    popup.hidden = true;
+    document.body.appendChild(popup);
+
+    function cardUrl(nickname) {
+        return data.path_prefix + "/bin/irclogger_nick/" + data.channel + "?nick=" + encodeURIComponent(nickname) +
+            "&date=" + encodeURIComponent(data.date_slug);
+    }
+
+    function findNicknameElement(target) {
+        var element = target.closest(".nickname");
+
+        // Actions and status lines don't show a nickname
+        if (!element || element.closest("li.status") || element.closest("li.action")) {
+            return null;
+        }
+
+        return element;
+    }
+
+    function show(element) {
+        var nickname = element.textContent.trim();
+
+        if (!nickname) {
+            return;
+        }
+
+        clearTimeout(hideTimer);
+        currentNickname = nickname;
+
+        if (!fragments[nickname]) {
+            fragments[nickname] = fetch(cardUrl(nickname), { credentials: "same-origin" })
+                .then(function (response) {
+                    if (!response.ok) {
+                        throw new Error(response.statusText);
+                    }
+
+                    return response.text();
+                });
+        }
+
+        fragments[nickname].then(function (html) {
+            var rect = element.getBoundingClientRect();
+
+            if (currentNickname !== nickname) {
+                return;
+            }
+
+            popup.innerHTML = html;
+            popup.style.left = (window.scrollX + rect.left) + "px";
+            popup.style.top = (window.scrollY + rect.bottom) + "px";
+            popup.hidden = false;
+        }, function () {
+            // Retried on the next hover
+            delete fragments[nickname];
+        });
+    }
+
+    function hide() {
+        currentNickname = null;
+        hideTimer = setTimeout(function () {
+            popup.hidden = true;
+        }, 200);
+    }
+
+    list.addEventListener("mouseover", function (event) {
+        var element = findNicknameElement(event.target);
+
+        if (element) {
+            show(element);
+        }
+    });
+
+    list.addEventListener("mouseout", function (event) {
+        if (findNicknameElement(event.target)) {
+            hide();
+        }
+    });
+
+    // Touch screens have no hover, so a tap shows the card and a tap
+    // elsewhere hides it
+    document.addEventListener("click", function (event) {
+        var element = findNicknameElement(event.target);
+
+        if (element) {
+            show(element);
+        } else if (!popup.contains(event.target)) {
+            hide();
+        }
+    });
+}());